@@ -4,8 +4,8 @@ use std::io::Read;
 use clap::{App, Arg};
 use log::info;
 
-use feo3boy::gbz80core;
-use feo3boy::memdev::{BiosRom, Cartridge, GbMmu};
+use feo3boy::cpu::Cpu;
+use feo3boy::memdev::{cartridge_type_name, GbMmu};
 
 fn main() {
     env_logger::init();
@@ -30,31 +30,36 @@ fn main() {
         )
         .get_matches();
 
-    let bios = match argparser.value_of("bios") {
-        Some(filename) => {
-            let mut bios_file = File::open(filename).unwrap();
-            let mut bios = Vec::with_capacity(0x100);
-            bios_file
-                .read_to_end(&mut bios)
-                .expect("Unable to read bios file");
-            BiosRom::try_from_slice(&bios).expect("Bios file was wrong length")
-        }
-        None => BiosRom::default(),
-    };
-
-    let cart = match argparser.value_of("rom") {
-        Some(filename) => {
-            let cart_file = File::open(filename).unwrap();
-            Cartridge::parse(cart_file).unwrap()
-        }
-        None => Cartridge::None,
-    };
-
     // Box to keep it off the stack.
-    let mut mmu = Box::new(GbMmu::new(bios, cart));
-    let mut cpustate = gbz80core::Gbz80State::new();
+    let mut mmu = Box::new(GbMmu::default());
+
+    if let Some(filename) = argparser.value_of("bios") {
+        let mut bios_file = File::open(filename).unwrap();
+        let mut bios = Vec::with_capacity(0x100);
+        bios_file
+            .read_to_end(&mut bios)
+            .expect("Unable to read bios file");
+        mmu.load_bios(&bios).expect("Unable to load bios");
+    }
+
+    if let Some(filename) = argparser.value_of("rom") {
+        let mut cart_file = File::open(filename).unwrap();
+        let mut rom = Vec::new();
+        cart_file
+            .read_to_end(&mut rom)
+            .expect("Unable to read rom file");
+        let header = mmu.load_rom(&rom).expect("Unable to load rom");
+        info!(
+            "Loaded \"{}\" ({})",
+            header.title,
+            cartridge_type_name(header.cartridge_type)
+        );
+    }
+
+    let mut cpu = Cpu::new();
 
+    // Stepping the CPU also ticks the hardware along with it.
     loop {
-        gbz80core::tick((&mut cpustate, &mut *mmu));
+        cpu.step(&mut *mmu);
     }
 }
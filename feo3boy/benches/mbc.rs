@@ -0,0 +1,108 @@
+//! Measures the read and write paths of the MBC1 mapper, as a baseline for optimizing its
+//! address decode.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use feo3boy::memdev::{Addr, Cartridge, Mbc1Rom, MemDevice};
+
+/// Number of 16 KiB rom banks in the test cartridge (1 MiB, so the bank set bits are used).
+const ROM_BANKS: usize = 64;
+
+/// Builds an MBC1 cartridge with 4 ram banks, where the first byte of each rom bank is its index.
+fn mbc1() -> Mbc1Rom {
+    let mut rom = vec![0u8; ROM_BANKS * 0x4000];
+    for (i, bank) in rom.chunks_mut(0x4000).enumerate().skip(1) {
+        bank[0] = i as u8;
+    }
+    // MBC1+RAM+BATTERY, 1 MiB rom, 32 KiB ram.
+    rom[0x147] = 0x03;
+    rom[0x148] = 0x05;
+    rom[0x149] = 0x03;
+    rom[0x14d] = rom[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |x, &h| x.wrapping_sub(h).wrapping_sub(1));
+    match Cartridge::parse(&rom[..]).unwrap() {
+        Cartridge::Mbc1(cart) => cart,
+        _ => unreachable!(),
+    }
+}
+
+fn register_writes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mbc1 register write");
+    let regions = [
+        ("ram enable", 0x0000),
+        ("rom bank", 0x2000),
+        ("bank set", 0x4000),
+        ("banking mode", 0x6000),
+    ];
+    for &(name, base) in regions.iter() {
+        let mut cart = mbc1();
+        let mut value = 0u8;
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                value = value.wrapping_add(1);
+                cart.write(Addr::from(black_box(base + value as u16)), value & 0x1f);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mbc1 read");
+    let mut cart = mbc1();
+    cart.write(0x0000.into(), 0x0a);
+    cart.write(0x2000.into(), 0x05);
+    let regions = [
+        ("fixed rom", 0x0000),
+        ("switchable rom", 0x4000),
+        ("ram", 0x8000),
+    ];
+    for &(name, base) in regions.iter() {
+        let mut offset = 0u16;
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                offset = (offset + 1) & 0x1fff;
+                cart.read(Addr::from(black_box(base + offset)))
+            })
+        });
+    }
+
+    let mut offset = 0u16;
+    group.bench_function("ram write", |b| {
+        b.iter(|| {
+            offset = (offset + 1) & 0x1fff;
+            cart.write(Addr::from(black_box(0x8000 + offset)), offset as u8);
+        })
+    });
+    group.finish();
+}
+
+fn banked_reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mbc1 banked read");
+    let mut cart = mbc1();
+    let mut bank = 0u8;
+    group.bench_function("switch rom bank", |b| {
+        b.iter(|| {
+            bank = bank.wrapping_add(1);
+            cart.write(0x2000.into(), bank & 0x1f);
+            cart.write(0x4000.into(), (bank >> 5) & 0x3);
+            cart.read(Addr::from(black_box(0x4000)))
+        })
+    });
+
+    let mut cart = mbc1();
+    cart.write(0x0000.into(), 0x0a);
+    cart.write(0x6000.into(), 0x01);
+    let mut bank = 0u8;
+    group.bench_function("switch ram bank", |b| {
+        b.iter(|| {
+            bank = bank.wrapping_add(1);
+            cart.write(0x4000.into(), bank & 0x3);
+            cart.read(Addr::from(black_box(0x8000)))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, register_writes, reads, banked_reads);
+criterion_main!(benches);
@@ -0,0 +1,90 @@
+//! Measures `GbMmu` read and write throughput for each region of the memory map, to show the cost
+//! of the address decode on the hot path.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use feo3boy::memdev::{Cartridge, GbMmu};
+
+/// Number of accesses made per iteration, so that results are reported per access.
+const ACCESSES: u16 = 256;
+
+/// Regions to measure, as (name, start address, number of addresses to cycle through).
+///
+/// IO is limited to the scroll and window registers, since writes elsewhere in IO can have side
+/// effects such as starting a DMA transfer.
+const REGIONS: [(&str, u16, &[u16]); 7] = [
+    ("rom", 0x0150, &[]),
+    ("vram", 0x8000, &[]),
+    ("wram", 0xc000, &[]),
+    ("echo", 0xe000, &[]),
+    ("oam", 0xfe00, &[]),
+    ("io", 0xff00, &[0x42, 0x43, 0x4a, 0x4b]),
+    ("hram", 0xff80, &[]),
+];
+
+/// Number of consecutive addresses used in regions which don't give an explicit list. Small
+/// enough to fit in OAM and high ram.
+const SPAN: u16 = 0x40;
+
+/// Builds a `GbMmu` with a rom-only cartridge and the bios disabled, so that low addresses read
+/// the cartridge.
+fn mmu() -> GbMmu {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x14d] = rom[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |x, &h| x.wrapping_sub(h).wrapping_sub(1));
+    let mut mmu = GbMmu::new(Default::default(), Cartridge::parse(&rom[..]).unwrap());
+    mmu.write_raw(0xff50, 1);
+    mmu
+}
+
+/// Gets the addresses accessed in a region, in order.
+fn addresses(start: u16, offsets: &[u16]) -> Vec<u16> {
+    (0..ACCESSES)
+        .map(|i| {
+            if offsets.is_empty() {
+                start + i % SPAN
+            } else {
+                start + offsets[i as usize % offsets.len()]
+            }
+        })
+        .collect()
+}
+
+fn reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mmu read");
+    group.throughput(Throughput::Elements(ACCESSES as u64));
+    let mmu = mmu();
+    for &(name, start, offsets) in REGIONS.iter() {
+        let addrs = addresses(start, offsets);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for &addr in addrs.iter() {
+                    black_box(mmu.read_raw(black_box(addr)));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Writes to each region. The rom-only cartridge ignores writes, so the rom case measures just the
+/// decode.
+fn writes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mmu write");
+    group.throughput(Throughput::Elements(ACCESSES as u64));
+    let mut mmu = mmu();
+    for &(name, start, offsets) in REGIONS.iter() {
+        let addrs = addresses(start, offsets);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for (i, &addr) in addrs.iter().enumerate() {
+                    mmu.write_raw(black_box(addr), i as u8);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, reads, writes);
+criterion_main!(benches);
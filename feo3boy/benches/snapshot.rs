@@ -0,0 +1,62 @@
+//! Compares the per-frame cost of taking a copy-on-write snapshot against copying all ram.
+
+use std::collections::VecDeque;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use feo3boy::memdev::GbMmu;
+
+/// Number of frames kept in the rewind buffer.
+const REWIND_FRAMES: usize = 60;
+
+/// Ranges of ram which a full copy has to save: VRAM, WRAM, OAM, IO and high ram.
+const RAM_RANGES: [(u16, u16); 3] = [(0x8000, 0xdfff), (0xfe00, 0xfe9f), (0xff00, 0xffff)];
+
+/// Simulates a frame's worth of writes, touching a few scattered addresses.
+fn run_frame(mmu: &mut GbMmu, frame: u8) {
+    for addr in (0xc000..0xe000).step_by(0x800) {
+        mmu.write_raw(addr, frame);
+    }
+    mmu.write_raw(0x9800, frame);
+    mmu.write_raw(0xff80, frame);
+}
+
+fn snapshot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rewind frame");
+
+    let mut mmu = GbMmu::default();
+    let mut frame = 0u8;
+    let mut rewind = VecDeque::with_capacity(REWIND_FRAMES);
+    group.bench_function("snapshot", |b| {
+        b.iter(|| {
+            frame = frame.wrapping_add(1);
+            run_frame(&mut mmu, frame);
+            if rewind.len() == REWIND_FRAMES {
+                rewind.pop_front();
+            }
+            rewind.push_back(mmu.snapshot());
+        })
+    });
+
+    let mut mmu = GbMmu::default();
+    let mut frame = 0u8;
+    let mut rewind = VecDeque::with_capacity(REWIND_FRAMES);
+    group.bench_function("full copy", |b| {
+        b.iter(|| {
+            frame = frame.wrapping_add(1);
+            run_frame(&mut mmu, frame);
+            let mut copy = Vec::with_capacity(0x6200);
+            for &(start, end) in RAM_RANGES.iter() {
+                mmu.dump_range(start..=end, &mut copy).unwrap();
+            }
+            if rewind.len() == REWIND_FRAMES {
+                rewind.pop_front();
+            }
+            rewind.push_back(copy);
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, snapshot);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+//! The LR35902 CPU, under a shorter name than [`gbz80core`](crate::gbz80core), where it is
+//! implemented.
+//!
+//! [`Cpu::step`](Gbz80State::step) runs one instruction against any [`CpuMem`] and returns the M
+//! cycles it took. Every memory the CPU can run against implements `CpuMem`, including
+//! [`GbMmu`](crate::memdev::GbMmu) and plain `[u8; N]` arrays.
+
+pub use crate::gbz80core::{CpuMem, Flags, Gbz80State, InterruptMasterState, Regs};
+
+/// The LR35902 CPU: the register file, IME, and whether the CPU is halted or stopped.
+pub type Cpu = Gbz80State;
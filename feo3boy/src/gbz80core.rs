@@ -2,8 +2,8 @@ use std::borrow::BorrowMut;
 
 use bitflags::bitflags;
 
-use crate::interrupts::InterruptFlags;
-use crate::memdev::MemDevice;
+use crate::interrupts::InterruptController;
+use crate::memdev::{Addr, MemDevice};
 pub use opcode::{CBOpcode, CBOperation, Opcode};
 pub use opcode_args::{AluOp, AluUnaryOp, ConditionCode, Operand16, Operand8};
 
@@ -19,11 +19,11 @@ bitflags! {
         const ZERO = 0x80;
         /// operation was a subtraction.
         const SUB = 0x40;
-        /// there was a carry in the middle of the number (bit 4 -> 5 for u8, uncertain which bits
-        /// this is for in u16).
+        /// there was a carry in the middle of the number (bit 3 -> 4 for u8, bit 11 -> 12 for
+        /// `ADD HL,rr`, and bit 3 -> 4 of the low byte for the `SP+i8` instructions).
         const HALFCARRY = 0x20;
-        /// there was a carry out of the top of the number (bit 7 -> carry for u8, presumably bit 15
-        /// -> carry for u16, though not sure).
+        /// there was a carry out of the top of the number (bit 7 -> carry for u8, bit 15 -> carry
+        /// for `ADD HL,rr`, and bit 7 -> 8 of the low byte for the `SP+i8` instructions).
         const CARRY = 0x10;
     }
 }
@@ -130,18 +130,19 @@ impl Regs {
         sp
     }
 
-    /// Returns the current stack pointer and decrements the value.
+    /// Decrements the stack pointer and returns the new value. This is the opposite of `inc_sp`,
+    /// so a byte pushed with `dec_sp` is popped from the same address by `inc_sp`.
     pub fn dec_sp(&mut self) -> u16 {
-        let sp = self.sp;
-        self.sp = sp.wrapping_sub(1);
-        sp
+        self.sp = self.sp.wrapping_sub(1);
+        self.sp
     }
 }
 
 /// State of interrupts on the CPU.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Default)]
 pub enum InterruptMasterState {
     /// Interrupts are disabled.
+    #[default]
     Disabled,
     /// EI was just run, but the effect is delayed until after the next instruction.
     Pending,
@@ -182,12 +183,6 @@ impl InterruptMasterState {
     }
 }
 
-impl Default for InterruptMasterState {
-    fn default() -> Self {
-        Self::Disabled
-    }
-}
-
 /// Internal state of the CPU.
 #[derive(Default, Debug)]
 pub struct Gbz80State {
@@ -195,10 +190,15 @@ pub struct Gbz80State {
     pub regs: Regs,
     /// Interrupt master enable flag, controlled by EI, DI, RETI, and interrupts.
     pub interrupt_master_enable: InterruptMasterState,
-    /// Current pending interrupts. Often referred to as register `IF`.
-    pub interrupt_vector: InterruptFlags,
     /// Whether the CPU is halted.
     pub halted: bool,
+    /// Whether the halt bug was triggered by the last instruction, so the next opcode fetch
+    /// doesn't increment the program counter.
+    pub halt_bug: bool,
+    /// Whether the CPU is stopped by `STOP`. It stays stopped until the memory reports a button
+    /// held with [`joypad_held`](CpuMem::joypad_held), so the embedding loop must keep
+    /// updating the joypad while this is set. The hardware isn't ticked while this is set.
+    pub stopped: bool,
 }
 
 impl Gbz80State {
@@ -206,6 +206,19 @@ impl Gbz80State {
     pub fn new() -> Gbz80State {
         Default::default()
     }
+
+    /// Runs a single instruction against the given memory, and returns the number of M cycles it
+    /// took. Like [`tick`], this services an interrupt instead if one is due, which takes 5 M
+    /// cycles. While the CPU is halted or stopped, this idles for 1 M cycle instead.
+    pub fn step<M: CpuMem>(&mut self, mem: &mut M) -> u32 {
+        let mut ctx = CycleCounter {
+            cpu: self,
+            mem,
+            cycles: 0,
+        };
+        tick::<_, CycleCounter<M>>(&mut ctx);
+        ctx.cycles
+    }
 }
 
 /// Trait which encapsulates everything that the CPU needs in order to execute.
@@ -214,8 +227,8 @@ impl Gbz80State {
 /// independently of any other component of the GameBoy system. That allows the CPU to be run for
 /// other purposes, by swapping in a memory controller that behaves differently.
 pub trait CpuContext {
-    /// Type of MemDevice in this context.
-    type Mem: MemDevice;
+    /// Type of memory in this context.
+    type Mem: CpuMem;
 
     /// Gets the CPU state.
     fn cpustate(&self) -> &Gbz80State;
@@ -235,7 +248,100 @@ pub trait CpuContext {
     fn yield1m(&mut self);
 }
 
+/// Memory the CPU can run against: the memory map, plus the hardware the CPU talks to directly
+/// instead of through memory.
+///
+/// These are kept out of [`MemDevice`], which is just an address space, so wrappers and banks of
+/// memory don't need to know about them. There are deliberately no defaults: memory which has
+/// the hardware must say so, rather than silently losing it when a wrapper forgets to forward.
+pub trait CpuMem: MemDevice {
+    /// Gets the interrupt controller of this memory, if it has one. The CPU services interrupts
+    /// through this rather than reading IE and IF from memory. `None` means there are no
+    /// interrupts, so the CPU never services any.
+    fn interrupt_controller(&self) -> Option<&InterruptController>;
+
+    /// Gets a mutable reference to the interrupt controller of this memory, if it has one.
+    fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController>;
+
+    /// Reads a byte the CPU is fetching as an opcode or immediate operand. This is the same as
+    /// [`read`](MemDevice::read), except that memory which tracks what its accesses are for can
+    /// tell fetches apart from data reads.
+    fn fetch(&mut self, addr: Addr) -> u8;
+
+    /// Returns whether a button is held in a selected joypad column, which wakes the CPU from
+    /// `STOP`, or `None` if this memory has no joypad. Without a joypad nothing could wake the
+    /// CPU, so `STOP` doesn't stop it.
+    fn joypad_held(&self) -> Option<bool>;
+
+    /// Called when the CPU executes `STOP` with no button held. Resets DIV and performs a CGB
+    /// speed switch if one is armed, and returns true if the speed changed. Only called if this
+    /// memory has a joypad.
+    fn stop(&mut self) -> bool;
+
+    /// Advances the hardware behind this memory by 1 M cycle. The CPU contexts call this from
+    /// [`CpuContext::yield1m`], between the memory accesses of an instruction, so timers, DMA
+    /// and the PPU move along with the instruction. It isn't called while the CPU is stopped,
+    /// since `STOP` stops the clock.
+    fn yield1m(&mut self);
+}
+
+/// Plain memory, which has no interrupts or joypad.
+impl<const N: usize> CpuMem for [u8; N] {
+    fn interrupt_controller(&self) -> Option<&InterruptController> {
+        None
+    }
+
+    fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController> {
+        None
+    }
+
+    fn fetch(&mut self, addr: Addr) -> u8 {
+        self.read(addr)
+    }
+
+    fn joypad_held(&self) -> Option<bool> {
+        None
+    }
+
+    fn stop(&mut self) -> bool {
+        false
+    }
+
+    fn yield1m(&mut self) {}
+}
+
+impl<M: CpuMem + ?Sized> CpuMem for Box<M> {
+    fn interrupt_controller(&self) -> Option<&InterruptController> {
+        (**self).interrupt_controller()
+    }
+
+    fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController> {
+        (**self).interrupt_controller_mut()
+    }
+
+    fn fetch(&mut self, addr: Addr) -> u8 {
+        (**self).fetch(addr)
+    }
+
+    fn joypad_held(&self) -> Option<bool> {
+        (**self).joypad_held()
+    }
+
+    fn stop(&mut self) -> bool {
+        (**self).stop()
+    }
+
+    fn yield1m(&mut self) {
+        (**self).yield1m()
+    }
+}
+
 /// Runs a single instruction on the CPU.
+///
+/// Before the instruction, if an interrupt is pending in the memory's
+/// [`interrupt_controller`](CpuMem::interrupt_controller) and IME is set, the interrupt is
+/// serviced instead. A pending interrupt also wakes the CPU from `HALT`, even if IME is not set.
+/// While the CPU is stopped, this idles for 1 M cycle unless a button is held, which wakes it.
 pub fn tick<B, C>(mut ctx: B)
 // Using BorrowMut here allows both `&mut (Gbz80State, M)` and `(&mut Gbz80State, &mut M)` to be
 // passed as the argument.
@@ -245,9 +351,29 @@ where
 {
     let ctx = ctx.borrow_mut();
 
-    // TODO: check interrupt state.
+    if ctx.cpustate().stopped {
+        ctx.yield1m();
+        // Memory without a joypad can't keep the CPU stopped.
+        if !ctx.mem().joypad_held().unwrap_or(true) {
+            return;
+        }
+        ctx.cpustate_mut().stopped = false;
+    }
+
+    let pending = ctx.mem().interrupt_controller().and_then(|ic| ic.pending());
     if ctx.cpustate().halted {
-        return;
+        // Waking up takes a cycle, as does staying halted.
+        ctx.yield1m();
+        if pending.is_none() {
+            return;
+        }
+        ctx.cpustate_mut().halted = false;
+    }
+    if let Some(interrupt) = pending {
+        if ctx.cpustate().interrupt_master_enable.enabled() {
+            opcode::service_interrupt(ctx, interrupt);
+            return;
+        }
     }
 
     let previous_ime = ctx.cpustate().interrupt_master_enable;
@@ -262,7 +388,7 @@ where
 /////////////////////////////////////////
 
 /// Allows a tuple of Gbz80State and any MemDevice to be used as CpuContext.
-impl<M: MemDevice> CpuContext for (Gbz80State, M) {
+impl<M: CpuMem> CpuContext for (Gbz80State, M) {
     type Mem = M;
 
     fn cpustate(&self) -> &Gbz80State {
@@ -281,12 +407,16 @@ impl<M: MemDevice> CpuContext for (Gbz80State, M) {
         &mut self.1
     }
 
-    /// With just a Gbz80State and arbitrary MemDevice, yielding actually does nothing.
-    fn yield1m(&mut self) {}
+    /// Yielding advances the memory's hardware, unless the CPU is stopped.
+    fn yield1m(&mut self) {
+        if !self.0.stopped {
+            self.1.yield1m();
+        }
+    }
 }
 
 /// Allows a tuple of references to Gbz80State and any MemDevice to be used as CpuContext.
-impl<M: MemDevice> CpuContext for (&mut Gbz80State, &mut M) {
+impl<M: CpuMem> CpuContext for (&mut Gbz80State, &mut M) {
     type Mem = M;
 
     fn cpustate(&self) -> &Gbz80State {
@@ -305,13 +435,92 @@ impl<M: MemDevice> CpuContext for (&mut Gbz80State, &mut M) {
         self.1
     }
 
-    /// With just a Gbz80State and arbitrary MemDevice, yielding actually does nothing.
-    fn yield1m(&mut self) {}
+    /// Yielding advances the memory's hardware, unless the CPU is stopped.
+    fn yield1m(&mut self) {
+        if !self.0.stopped {
+            self.1.yield1m();
+        }
+    }
+}
+
+/// Context used by [`Gbz80State::step`], which counts the M cycles the instruction yields for,
+/// and advances the memory's hardware on each of them like the tuple contexts do.
+struct CycleCounter<'a, M> {
+    cpu: &'a mut Gbz80State,
+    mem: &'a mut M,
+    cycles: u32,
+}
+
+impl<'a, M: CpuMem> CpuContext for CycleCounter<'a, M> {
+    type Mem = M;
+
+    fn cpustate(&self) -> &Gbz80State {
+        self.cpu
+    }
+
+    fn cpustate_mut(&mut self) -> &mut Gbz80State {
+        self.cpu
+    }
+
+    fn mem(&self) -> &Self::Mem {
+        self.mem
+    }
+
+    fn mem_mut(&mut self) -> &mut Self::Mem {
+        self.mem
+    }
+
+    fn yield1m(&mut self) {
+        self.cycles += 1;
+        if !self.cpu.stopped {
+            self.mem.yield1m();
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+
     use super::*;
+    use crate::interrupts::{Interrupt, InterruptController, InterruptFlags};
+    use crate::memdev::{AccessKind, Addr, ButtonState, GbMmu, HardwareModel, Watchpoint};
+
+    /// Runs the given program from address 0 for as many instructions as it takes to reach the end
+    /// of the program, after letting `setup` initialize the CPU. Returns the memory and the total
+    /// number of M cycles.
+    fn run(
+        program: &[u8],
+        cpu: &mut Gbz80State,
+        setup: impl FnOnce(&mut Gbz80State, &mut [u8; 0x10000]),
+    ) -> (Box<[u8; 0x10000]>, u32) {
+        let mut mem = Box::new([0u8; 0x10000]);
+        mem[..program.len()].copy_from_slice(program);
+        setup(cpu, &mut mem);
+        let mut cycles = 0;
+        while (cpu.regs.pc as usize) < program.len() {
+            cycles += cpu.step(&mut *mem);
+        }
+        (mem, cycles)
+    }
+
+    /// Runs a single ALU instruction with the given accumulator, B, and flags, and returns the
+    /// resulting accumulator and flags.
+    fn alu(opcode: u8, acc: u8, b: u8, flags: Flags) -> (u8, Flags) {
+        let mut cpu = Gbz80State::new();
+        run(&[opcode], &mut cpu, |cpu, _| {
+            cpu.regs.acc = acc;
+            cpu.regs.b = b;
+            cpu.regs.flags = flags;
+        });
+        (cpu.regs.acc, cpu.regs.flags)
+    }
+
+    const Z: Flags = Flags::ZERO;
+    const N: Flags = Flags::SUB;
+    const H: Flags = Flags::HALFCARRY;
+    const C: Flags = Flags::CARRY;
+    const NONE: Flags = Flags::empty();
 
     #[test]
     fn test_loads_and_alu() {
@@ -347,4 +556,991 @@ mod tests {
             cpustate.regs
         );
     }
+
+    #[test]
+    fn alu_flags() {
+        // (opcode, A, B, flags in, A out, flags out)
+        #[rustfmt::skip]
+        let cases = [
+            // ADD A,B
+            (0x80, 0x3a, 0xc6, NONE, 0x00, Z | H | C),
+            (0x80, 0x0f, 0x01, NONE, 0x10, H),
+            (0x80, 0x80, 0x80, NONE, 0x00, Z | C),
+            // ADC A,B
+            (0x88, 0xff, 0x00, C, 0x00, Z | H | C),
+            (0x88, 0xff, 0x01, C, 0x01, H | C),
+            (0x88, 0x0e, 0x01, C, 0x10, H),
+            (0x88, 0xe1, 0x0f, C, 0xf1, H),
+            (0x88, 0xe1, 0x0f, NONE, 0xf0, H),
+            (0x88, 0xe1, 0x0e, NONE, 0xef, NONE),
+            // SUB A,B
+            (0x90, 0x3e, 0x3e, NONE, 0x00, Z | N),
+            (0x90, 0x3e, 0x0f, NONE, 0x2f, N | H),
+            (0x90, 0x3e, 0x40, NONE, 0xfe, N | C),
+            // SBC A,B
+            (0x98, 0x3b, 0x2a, C, 0x10, N),
+            (0x98, 0x3b, 0x3a, C, 0x00, Z | N),
+            (0x98, 0x3b, 0x4f, C, 0xeb, N | H | C),
+            (0x98, 0x00, 0x00, C, 0xff, N | H | C),
+            (0x98, 0x10, 0x0f, C, 0x00, Z | N | H),
+            // AND A,B
+            (0xa0, 0x5a, 0x3f, C, 0x1a, H),
+            (0xa0, 0x5a, 0x00, NONE, 0x00, Z | H),
+            // XOR A,B
+            (0xa8, 0xff, 0xff, C, 0x00, Z),
+            (0xa8, 0x0f, 0xf0, NONE, 0xff, NONE),
+            // OR A,B
+            (0xb0, 0x5a, 0x00, C, 0x5a, NONE),
+            (0xb0, 0x00, 0x00, NONE, 0x00, Z),
+            // CP A,B
+            (0xb8, 0x3c, 0x2f, NONE, 0x3c, N | H),
+            (0xb8, 0x3c, 0x3c, NONE, 0x3c, Z | N),
+            (0xb8, 0x3c, 0x40, NONE, 0x3c, N | C),
+            // RLCA, RLA, RRCA, RRA always clear ZERO.
+            (0x07, 0x85, 0x00, Z, 0x0b, C),
+            (0x07, 0x00, 0x00, Z, 0x00, NONE),
+            (0x17, 0x95, 0x00, C, 0x2b, C),
+            (0x17, 0x80, 0x00, NONE, 0x00, C),
+            (0x0f, 0x3b, 0x00, NONE, 0x9d, C),
+            (0x1f, 0x81, 0x00, NONE, 0x40, C),
+            (0x1f, 0x01, 0x00, Z, 0x00, C),
+            // DAA
+            (0x27, 0x7d, 0x00, NONE, 0x83, NONE),
+            (0x27, 0x9a, 0x00, NONE, 0x00, Z | C),
+            (0x27, 0x4b, 0x00, N | H, 0x45, N),
+            // CPL
+            (0x2f, 0x35, 0x00, Z | C, 0xca, Z | N | H | C),
+            // SCF
+            (0x37, 0x00, 0x00, Z | N | H, 0x00, Z | C),
+            // CCF
+            (0x3f, 0x00, 0x00, N | H | C, 0x00, NONE),
+            (0x3f, 0x00, 0x00, Z, 0x00, Z | C),
+        ];
+        for &(opcode, acc, b, flags, expected_acc, expected_flags) in cases.iter() {
+            assert_eq!(
+                alu(opcode, acc, b, flags),
+                (expected_acc, expected_flags),
+                "opcode {:02X} with A={:02X} B={:02X} F={:?}",
+                opcode,
+                acc,
+                b,
+                flags
+            );
+        }
+    }
+
+    #[test]
+    fn daa_exhaustive() {
+        let bcd = |n: u32| (n / 10 * 16 + n % 10) as u8;
+        let mut mem = Box::new([0u8; 0x10000]);
+        // ADD A,B then DAA, and SUB A,B then DAA.
+        for &(op, subtract) in [(0x80, false), (0x90, true)].iter() {
+            mem[..2].copy_from_slice(&[op, 0x27]);
+            for a in 0..100 {
+                for b in 0..100 {
+                    let (expected, carry) = if subtract {
+                        ((a + 100 - b) % 100, a < b)
+                    } else {
+                        ((a + b) % 100, a + b >= 100)
+                    };
+                    let mut expected_flags = Flags::check_zero(bcd(expected));
+                    expected_flags |= Flags::check_carry(carry);
+                    if subtract {
+                        expected_flags |= Flags::SUB;
+                    }
+
+                    let mut cpu = Gbz80State::new();
+                    cpu.regs.acc = bcd(a);
+                    cpu.regs.b = bcd(b);
+                    cpu.step(&mut *mem);
+                    cpu.step(&mut *mem);
+                    assert_eq!(
+                        (cpu.regs.acc, cpu.regs.flags),
+                        (bcd(expected), expected_flags),
+                        "{:02X} {} {:02X}",
+                        bcd(a),
+                        if subtract { "-" } else { "+" },
+                        bcd(b)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inc_dec_preserve_carry() {
+        // (opcode, B, flags in, B out, flags out)
+        #[rustfmt::skip]
+        let cases = [
+            (0x04, 0xff, C, 0x00, Z | H | C),
+            (0x04, 0x0f, NONE, 0x10, H),
+            (0x04, 0x01, Z | N, 0x02, NONE),
+            (0x05, 0x01, C, 0x00, Z | N | C),
+            (0x05, 0x00, NONE, 0xff, N | H),
+            (0x05, 0x10, NONE, 0x0f, N | H),
+        ];
+        for &(opcode, b, flags, expected_b, expected_flags) in cases.iter() {
+            let mut cpu = Gbz80State::new();
+            run(&[opcode], &mut cpu, |cpu, _| {
+                cpu.regs.b = b;
+                cpu.regs.flags = flags;
+            });
+            assert_eq!(
+                (cpu.regs.b, cpu.regs.flags),
+                (expected_b, expected_flags),
+                "opcode {:02X} with B={:02X} F={:?}",
+                opcode,
+                b,
+                flags
+            );
+        }
+    }
+
+    #[test]
+    fn add16_half_carry_from_bit_11() {
+        // (opcode, HL, BC, flags in, HL out, flags out)
+        #[rustfmt::skip]
+        let cases = [
+            (0x09, 0x8a23, 0x0605, NONE, 0x9028, H),
+            (0x29, 0x8a23, 0x0000, NONE, 0x1446, H | C),
+            // Zero is never changed.
+            (0x09, 0x0fff, 0x0001, Z | N, 0x1000, Z | H),
+            (0x09, 0x8000, 0x8000, NONE, 0x0000, C),
+            (0x09, 0x00ff, 0x0001, C, 0x0100, NONE),
+        ];
+        for &(opcode, hl, bc, flags, expected_hl, expected_flags) in cases.iter() {
+            let mut cpu = Gbz80State::new();
+            let (_, cycles) = run(&[opcode], &mut cpu, |cpu, _| {
+                cpu.regs.set_hl(hl);
+                cpu.regs.set_bc(bc);
+                cpu.regs.flags = flags;
+            });
+            assert_eq!(cycles, 2);
+            assert_eq!(
+                (cpu.regs.hl(), cpu.regs.flags),
+                (expected_hl, expected_flags),
+                "opcode {:02X} with HL={:04X} BC={:04X} F={:?}",
+                opcode,
+                hl,
+                bc,
+                flags
+            );
+        }
+    }
+
+    #[test]
+    fn sp_offset_flags_from_low_byte() {
+        // (SP, offset, result, flags out). Flags in are always Z | N, which are always cleared.
+        #[rustfmt::skip]
+        let cases = [
+            (0xfff8, 0x02, 0xfffa, NONE),
+            (0x00ff, 0x01, 0x0100, H | C),
+            (0x000f, 0x01, 0x0010, H),
+            (0x0001, 0xff, 0x0000, H | C),
+            (0x1000, 0xff, 0x0fff, NONE),
+            (0xff80, 0x80, 0xff00, C),
+        ];
+        for &(sp, offset, expected, expected_flags) in cases.iter() {
+            // ADD SP,i8
+            let mut cpu = Gbz80State::new();
+            let (_, cycles) = run(&[0xe8, offset], &mut cpu, |cpu, _| {
+                cpu.regs.sp = sp;
+                cpu.regs.flags = Z | N;
+            });
+            assert_eq!(cycles, 4);
+            assert_eq!(
+                (cpu.regs.sp, cpu.regs.flags),
+                (expected, expected_flags),
+                "ADD SP,{:02X} with SP={:04X}",
+                offset,
+                sp
+            );
+
+            // LD HL,SP+i8
+            let mut cpu = Gbz80State::new();
+            let (_, cycles) = run(&[0xf8, offset], &mut cpu, |cpu, _| {
+                cpu.regs.sp = sp;
+                cpu.regs.flags = Z | N;
+            });
+            assert_eq!(cycles, 3);
+            assert_eq!(
+                (cpu.regs.hl(), cpu.regs.sp, cpu.regs.flags),
+                (expected, sp, expected_flags),
+                "LD HL,SP+{:02X} with SP={:04X}",
+                offset,
+                sp
+            );
+        }
+    }
+
+    #[test]
+    fn cycle_counts() {
+        // (program, flags, M cycles)
+        #[rustfmt::skip]
+        let cases: &[(&[u8], Flags, u32)] = &[
+            (&[0x00], NONE, 1),                 // NOP
+            (&[0x06, 0x12], NONE, 2),           // LD B,u8
+            (&[0x01, 0x34, 0x12], NONE, 3),     // LD BC,u16
+            (&[0x77], NONE, 2),                 // LD (HL),A
+            (&[0x34], NONE, 3),                 // INC (HL)
+            (&[0x08, 0x00, 0xc0], NONE, 5),     // LD (u16),SP
+            (&[0x03], NONE, 2),                 // INC BC
+            (&[0x18, 0x00], NONE, 3),           // JR i8
+            (&[0x20, 0x00], NONE, 3),           // JR NZ,i8 (taken)
+            (&[0x20, 0x00], Z, 2),              // JR NZ,i8 (not taken)
+            (&[0xc3, 0x03, 0x00], NONE, 4),     // JP u16
+            (&[0xc2, 0x03, 0x00], Z, 3),        // JP NZ,u16 (not taken)
+            (&[0xcd, 0x03, 0x00], NONE, 6),     // CALL u16
+            (&[0xc4, 0x03, 0x00], Z, 3),        // CALL NZ,u16 (not taken)
+            (&[0xc9], NONE, 4),                 // RET
+            (&[0xc8], Z, 5),                    // RET Z (taken)
+            (&[0xc8], NONE, 2),                 // RET Z (not taken)
+            (&[0xd9], NONE, 4),                 // RETI
+            (&[0xc5], NONE, 4),                 // PUSH BC
+            (&[0xc1], NONE, 3),                 // POP BC
+            (&[0xff], NONE, 4),                 // RST 38h
+            (&[0xe9], NONE, 1),                 // JP HL
+            (&[0xf9], NONE, 2),                 // LD SP,HL
+            (&[0xe0, 0x80], NONE, 3),           // LD (FF00+u8),A
+            (&[0xfa, 0x00, 0xc0], NONE, 4),     // LD A,(u16)
+            (&[0xcb, 0x00], NONE, 2),           // RLC B
+            (&[0xcb, 0x06], NONE, 4),           // RLC (HL)
+            (&[0xcb, 0x46], NONE, 3),           // BIT 0,(HL)
+        ];
+        for &(program, flags, expected) in cases.iter() {
+            let mut cpu = Gbz80State::new();
+            let (_, cycles) = run(program, &mut cpu, |cpu, mem| {
+                cpu.regs.flags = flags;
+                cpu.regs.sp = 0xc000;
+                // Returns and JP HL go to the end of the program.
+                mem[0xc000] = program.len() as u8;
+                cpu.regs.set_hl(program.len() as u16);
+            });
+            assert_eq!(cycles, expected, "{:02X?}", program);
+        }
+    }
+
+    #[test]
+    fn jumps_and_calls() {
+        // JP HL jumps to HL without touching the stack pointer.
+        let mut cpu = Gbz80State::new();
+        let mut mem = [0u8; 0x10000];
+        mem[0] = 0xe9;
+        cpu.regs.set_hl(0x1234);
+        cpu.regs.sp = 0xfffe;
+        cpu.step(&mut mem);
+        assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x1234, 0xfffe));
+
+        // JR goes backwards from the following instruction.
+        mem[0x1234] = 0x18;
+        mem[0x1235] = 0xfe;
+        cpu.step(&mut mem);
+        assert_eq!(cpu.regs.pc, 0x1234);
+
+        // CALL pushes the address of the following instruction, high byte first.
+        mem[0x1234..0x1237].copy_from_slice(&[0xcd, 0x00, 0x20]);
+        assert_eq!(cpu.step(&mut mem), 6);
+        assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x2000, 0xfffc));
+        assert_eq!(&mem[0xfffc..0xfffe], &[0x37, 0x12]);
+
+        // RST does the same with a fixed destination.
+        mem[0x2000] = 0xef;
+        cpu.step(&mut mem);
+        assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x0028, 0xfffa));
+        assert_eq!(&mem[0xfffa..0xfffc], &[0x01, 0x20]);
+
+        // RET and conditional RET pop in the opposite order.
+        mem[0x0028] = 0xc9;
+        cpu.step(&mut mem);
+        assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x2001, 0xfffc));
+        mem[0x2001] = 0xd0;
+        cpu.regs.flags = NONE;
+        cpu.step(&mut mem);
+        assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x1237, 0xfffe));
+    }
+
+    #[test]
+    fn push_pop_af_masks_flags() {
+        let mut cpu = Gbz80State::new();
+        let (mem, _) = run(&[0xc5, 0xf1], &mut cpu, |cpu, _| {
+            cpu.regs.set_bc(0x12ff);
+            cpu.regs.sp = 0xd000;
+        });
+        assert_eq!(&mem[0xcffe..0xd000], &[0xff, 0x12]);
+        assert_eq!((cpu.regs.af(), cpu.regs.sp), (0x12f0, 0xd000));
+    }
+
+    #[test]
+    fn interrupt_master_enable() {
+        let mut mem = [0u8; 0x10000];
+
+        // EI takes effect after the following instruction.
+        mem[..2].copy_from_slice(&[0xfb, 0x00]);
+        let mut cpu = Gbz80State::new();
+        cpu.step(&mut mem);
+        assert_eq!(cpu.interrupt_master_enable, InterruptMasterState::Pending);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.interrupt_master_enable, InterruptMasterState::Enabled);
+
+        // DI right after EI cancels it.
+        mem[..2].copy_from_slice(&[0xfb, 0xf3]);
+        let mut cpu = Gbz80State::new();
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.interrupt_master_enable, InterruptMasterState::Disabled);
+
+        // RETI enables right away.
+        mem[0] = 0xd9;
+        let mut cpu = Gbz80State::new();
+        cpu.regs.sp = 0xc000;
+        cpu.step(&mut mem);
+        assert_eq!(cpu.interrupt_master_enable, InterruptMasterState::Enabled);
+    }
+
+    #[test]
+    fn halt_idles() {
+        let mut mem = [0u8; 0x10000];
+        mem[0] = 0x76;
+        let mut cpu = Gbz80State::new();
+        assert_eq!(cpu.step(&mut mem), 1);
+        assert!(cpu.halted);
+        assert_eq!(cpu.step(&mut mem), 1);
+        assert_eq!(cpu.regs.pc, 1);
+    }
+
+    /// Reference implementation of the CB-prefixed opcodes, written out bit by bit, independently
+    /// of the decoder. Returns the result and flags for the given operand value and flags.
+    fn cb_reference(opcode: u8, val: u8, flags: Flags) -> (u8, Flags) {
+        let carry = flags.contains(C) as u8;
+        let bit = 1 << (opcode >> 3 & 7);
+        let (res, carry_out) = match opcode >> 3 {
+            0 => (val.rotate_left(1), val >> 7),
+            1 => (val.rotate_right(1), val & 1),
+            2 => (val << 1 | carry, val >> 7),
+            3 => (val >> 1 | carry << 7, val & 1),
+            4 => (val << 1, val >> 7),
+            5 => (val >> 1 | val & 0x80, val & 1),
+            6 => (val.rotate_left(4), 0),
+            7 => (val >> 1, val & 1),
+            8..=15 => return (val, Flags::check_zero(val & bit) | H | flags & C),
+            16..=23 => return (val & !bit, flags),
+            _ => return (val | bit, flags),
+        };
+        (
+            res,
+            Flags::check_zero(res) | Flags::check_carry(carry_out != 0),
+        )
+    }
+
+    #[test]
+    fn cb_opcodes() {
+        const HL: u16 = 0xc000;
+        const INPUTS: [u8; 8] = [0x00, 0x01, 0x80, 0x0f, 0xf0, 0xa5, 0x5a, 0xff];
+
+        for opcode in 0..=0xffu8 {
+            let regcode = opcode & 7;
+            let expected_cycles = match (regcode, opcode >> 6) {
+                (6, 1) => 3,
+                (6, _) => 4,
+                _ => 2,
+            };
+            for &val in INPUTS.iter() {
+                for &flags in [NONE, Z | N | H | C].iter() {
+                    let mut cpu = Gbz80State::new();
+                    let (mem, cycles) = run(&[0xcb, opcode], &mut cpu, |cpu, mem| {
+                        cpu.regs.set_hl(HL);
+                        cpu.regs.flags = flags;
+                        match regcode {
+                            0 => cpu.regs.b = val,
+                            1 => cpu.regs.c = val,
+                            2 => cpu.regs.d = val,
+                            3 => cpu.regs.e = val,
+                            4 => cpu.regs.h = val,
+                            5 => cpu.regs.l = val,
+                            6 => mem[HL as usize] = val,
+                            _ => cpu.regs.acc = val,
+                        }
+                    });
+                    let res = match regcode {
+                        0 => cpu.regs.b,
+                        1 => cpu.regs.c,
+                        2 => cpu.regs.d,
+                        3 => cpu.regs.e,
+                        4 => cpu.regs.h,
+                        5 => cpu.regs.l,
+                        6 => mem[HL as usize],
+                        _ => cpu.regs.acc,
+                    };
+                    assert_eq!(
+                        (res, cpu.regs.flags, cycles),
+                        {
+                            let (res, flags) = cb_reference(opcode, val, flags);
+                            (res, flags, expected_cycles)
+                        },
+                        "{} with {:02X} and F={:?}",
+                        CBOpcode::decode(opcode),
+                        val,
+                        flags
+                    );
+                }
+            }
+        }
+    }
+
+    /// Flat memory with an interrupt controller, for testing interrupts.
+    struct InterruptMem {
+        mem: Box<[u8; 0x10000]>,
+        ic: InterruptController,
+    }
+
+    impl InterruptMem {
+        /// Creates memory holding the given program at address 0, with every interrupt enabled
+        /// and none requested.
+        fn new(program: &[u8]) -> Self {
+            let mut mem = Box::new([0u8; 0x10000]);
+            mem[..program.len()].copy_from_slice(program);
+            let mut ic = InterruptController::new();
+            ic.set_ie(InterruptFlags::all());
+            InterruptMem { mem, ic }
+        }
+    }
+
+    impl MemDevice for InterruptMem {
+        fn read(&self, addr: Addr) -> u8 {
+            self.mem[addr.index()]
+        }
+
+        fn write(&mut self, addr: Addr, value: u8) {
+            self.mem[addr.index()] = value;
+        }
+    }
+
+    impl CpuMem for InterruptMem {
+        fn interrupt_controller(&self) -> Option<&InterruptController> {
+            Some(&self.ic)
+        }
+
+        fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController> {
+            Some(&mut self.ic)
+        }
+
+        fn fetch(&mut self, addr: Addr) -> u8 {
+            self.read(addr)
+        }
+
+        fn joypad_held(&self) -> Option<bool> {
+            None
+        }
+
+        fn stop(&mut self) -> bool {
+            false
+        }
+
+        fn yield1m(&mut self) {}
+    }
+
+    #[test]
+    fn interrupt_dispatch_priority() {
+        let mut mem = InterruptMem::new(&[]);
+        mem.mem[0x48] = 0xd9;
+        let mut cpu = Gbz80State::new();
+        cpu.regs.pc = 0x1234;
+        cpu.regs.sp = 0xd000;
+        cpu.interrupt_master_enable.set();
+        mem.ic
+            .request(InterruptFlags::TIMER | InterruptFlags::JOYPAD | InterruptFlags::STAT);
+
+        // The highest priority interrupt goes first, and only its flag is cleared.
+        assert_eq!(cpu.step(&mut mem), 5);
+        assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x0048, 0xcffe));
+        assert_eq!(&mem.mem[0xcffe..0xd000], &[0x34, 0x12]);
+        assert_eq!(mem.ic.if_(), InterruptFlags::TIMER | InterruptFlags::JOYPAD);
+        assert_eq!(cpu.interrupt_master_enable, InterruptMasterState::Disabled);
+
+        // RETI returns and re-enables right away, so the next interrupt is serviced next.
+        assert_eq!(cpu.step(&mut mem), 4);
+        assert_eq!((cpu.regs.pc, cpu.regs.sp), (0x1234, 0xd000));
+        assert_eq!(cpu.step(&mut mem), 5);
+        assert_eq!(cpu.regs.pc, Interrupt::Timer.vector());
+        assert_eq!(mem.ic.if_(), InterruptFlags::JOYPAD);
+    }
+
+    #[test]
+    fn interrupts_need_ime_and_ie() {
+        let mut mem = InterruptMem::new(&[0x00, 0x00]);
+        let mut cpu = Gbz80State::new();
+        mem.ic.request(InterruptFlags::VBLANK);
+        assert_eq!(cpu.step(&mut mem), 1);
+        assert_eq!(cpu.regs.pc, 1);
+
+        cpu.interrupt_master_enable.set();
+        mem.ic.set_ie(InterruptFlags::TIMER);
+        assert_eq!(cpu.step(&mut mem), 1);
+        assert_eq!(cpu.regs.pc, 2);
+        assert_eq!(mem.ic.if_(), InterruptFlags::VBLANK);
+    }
+
+    #[test]
+    fn ei_delays_interrupts() {
+        // EI enables interrupts only after the following instruction.
+        let mut mem = InterruptMem::new(&[0xfb, 0x00, 0x00]);
+        mem.ic.request(InterruptFlags::VBLANK);
+        let mut cpu = Gbz80State::new();
+        cpu.regs.sp = 0xd000;
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.regs.pc, 2);
+        assert_eq!(cpu.step(&mut mem), 5);
+        assert_eq!(cpu.regs.pc, 0x40);
+        assert_eq!(&mem.mem[0xcffe..0xd000], &[0x02, 0x00]);
+
+        // EI then DI never lets an interrupt in.
+        let mut mem = InterruptMem::new(&[0xfb, 0xf3, 0x00, 0x00]);
+        mem.ic.request(InterruptFlags::VBLANK);
+        let mut cpu = Gbz80State::new();
+        for _ in 0..4 {
+            cpu.step(&mut mem);
+        }
+        assert_eq!(cpu.regs.pc, 4);
+        assert_eq!(mem.ic.if_(), InterruptFlags::VBLANK);
+    }
+
+    #[test]
+    fn interrupts_wake_halt() {
+        // With IME set, the interrupt is serviced after waking, and returns after the HALT.
+        let mut mem = InterruptMem::new(&[0x76, 0x00]);
+        let mut cpu = Gbz80State::new();
+        cpu.regs.sp = 0xd000;
+        cpu.interrupt_master_enable.set();
+        cpu.step(&mut mem);
+        assert_eq!(cpu.step(&mut mem), 1);
+        assert!(cpu.halted);
+        mem.ic.request(InterruptFlags::SERIAL);
+        assert_eq!(cpu.step(&mut mem), 6);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.regs.pc, 0x58);
+        assert_eq!(&mem.mem[0xcffe..0xd000], &[0x01, 0x00]);
+
+        // Without IME, the CPU just wakes and carries on.
+        let mut mem = InterruptMem::new(&[0x76, 0x00]);
+        let mut cpu = Gbz80State::new();
+        cpu.step(&mut mem);
+        mem.ic.request(InterruptFlags::SERIAL);
+        assert_eq!(cpu.step(&mut mem), 2);
+        assert_eq!(cpu.regs.pc, 2);
+        assert_eq!(mem.ic.if_(), InterruptFlags::SERIAL);
+    }
+
+    #[test]
+    fn halt_bug() {
+        // DI, HALT, INC A, with an interrupt already pending.
+        let mut mem = InterruptMem::new(&[0xf3, 0x76, 0x3c]);
+        mem.ic.request(InterruptFlags::TIMER);
+        let mut cpu = Gbz80State::new();
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.regs.pc, 2);
+        // The byte after HALT is read twice, so INC A runs twice.
+        cpu.step(&mut mem);
+        assert_eq!((cpu.regs.pc, cpu.regs.acc), (2, 1));
+        cpu.step(&mut mem);
+        assert_eq!((cpu.regs.pc, cpu.regs.acc), (3, 2));
+        assert_eq!(mem.ic.if_(), InterruptFlags::TIMER);
+    }
+
+    #[test]
+    fn halt_bug_after_ei() {
+        // EI, HALT, with an interrupt already pending. The interrupt is serviced right after HALT
+        // and returns to it, so it runs again and halts for real the second time.
+        let mut mem = InterruptMem::new(&[0xfb, 0x76, 0x00]);
+        mem.mem[0x50] = 0xd9;
+        mem.ic.request(InterruptFlags::TIMER);
+        let mut cpu = Gbz80State::new();
+        cpu.regs.sp = 0xd000;
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.step(&mut mem), 5);
+        assert_eq!(cpu.regs.pc, 0x50);
+        assert_eq!(&mem.mem[0xcffe..0xd000], &[0x01, 0x00]);
+        // RETI, then HALT again, which halts now that nothing is pending.
+        cpu.step(&mut mem);
+        assert_eq!(cpu.regs.pc, 1);
+        cpu.step(&mut mem);
+        assert!(cpu.halted);
+        assert_eq!(cpu.regs.pc, 2);
+    }
+
+    #[test]
+    fn interrupts_through_mmu() {
+        let mut mmu = GbMmu::default();
+        let mut cpu = Gbz80State::new();
+        cpu.regs.pc = 0xc000;
+        cpu.regs.sp = 0xd000;
+        cpu.interrupt_master_enable.set();
+        mmu.write_raw(0xffff, 0x04);
+        mmu.interrupts_mut().request(InterruptFlags::TIMER);
+        assert_eq!(cpu.step(&mut mmu), 5);
+        assert_eq!(cpu.regs.pc, 0x50);
+        assert_eq!(mmu.read_raw(0xff0f), 0xe0);
+        assert_eq!(mmu.read_raw(0xcfff), 0xc0);
+    }
+
+    #[test]
+    fn execute_watchpoints() {
+        let mut mmu = GbMmu::default();
+        // LD A,(0xC000), which reads its own opcode as data.
+        for (i, &byte) in [0xfa, 0x00, 0xc0].iter().enumerate() {
+            mmu.write_raw(0xc000 + i as u16, byte);
+        }
+        let exec = mmu.add_watchpoint(Watchpoint::execute(0xc000..=0xc0ff));
+        let data = mmu.add_watchpoint(Watchpoint::read(0xc000..=0xc0ff).kind(AccessKind::Data));
+        let mut cpu = Gbz80State::new();
+        cpu.regs.pc = 0xc000;
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.regs.acc, 0xfa);
+        assert_eq!(mmu.access_kind(), AccessKind::Data);
+
+        let hits: Vec<_> = mmu
+            .take_watch_hits()
+            .iter()
+            .map(|hit| (hit.handle, hit.addr))
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                (exec, 0xc000),
+                (exec, 0xc001),
+                (exec, 0xc002),
+                (data, 0xc000),
+            ]
+        );
+    }
+
+    #[test]
+    fn hardware_ticks_between_accesses() {
+        let mut mmu = GbMmu::default();
+        // LD A,(0xFF05); LD B,A; PUSH BC; LD A,(0xFF05)
+        let program = [0xfa, 0x05, 0xff, 0x47, 0xc5, 0xfa, 0x05, 0xff];
+        for (i, &byte) in program.iter().enumerate() {
+            mmu.write_raw(0xc000 + i as u16, byte);
+        }
+        let mut cpu = Gbz80State::new();
+        cpu.regs.pc = 0xc000;
+        cpu.regs.sp = 0xd000;
+        // Reset the divider and count TIMA up every 4 M cycles.
+        mmu.write_raw(0xff04, 0);
+        mmu.write_raw(0xff07, 0x05);
+        assert_eq!(mmu.read_raw(0xff05), 0);
+
+        // TIMA counts up while the operand is fetched, before it is read in the same instruction.
+        assert_eq!(cpu.step(&mut mmu), 4);
+        assert_eq!(cpu.regs.acc, 1);
+        assert_eq!(cpu.step(&mut mmu), 1);
+        assert_eq!(cpu.step(&mut mmu), 4);
+        assert_eq!(cpu.step(&mut mmu), 4);
+        // 13 M cycles in, it has counted up twice more.
+        assert_eq!(cpu.regs.b, 1);
+        assert_eq!(cpu.regs.acc, 3);
+        assert_eq!(mmu.read_raw(0xcfff), 1);
+    }
+
+    /// A memory access recorded by [`Recorder`]: the M cycle it happened on, counting from 1,
+    /// whether it was a write, and the address.
+    type Access = (u32, bool, u16);
+
+    /// CPU context which records the M cycle every memory access lands on.
+    struct Recorder {
+        cpu: Gbz80State,
+        mem: InterruptMem,
+        cycle: u32,
+        accesses: RefCell<Vec<Access>>,
+    }
+
+    impl Recorder {
+        /// Runs one instruction of the given program, which starts at address 0, after letting
+        /// `setup` initialize the CPU and memory. HL starts at 0xC000 and SP at 0xD000. Returns
+        /// the memory accesses and the total number of M cycles.
+        fn run(
+            program: &[u8],
+            setup: impl FnOnce(&mut Gbz80State, &mut InterruptMem),
+        ) -> (Vec<Access>, u32) {
+            let mut recorder = Recorder {
+                cpu: Gbz80State::new(),
+                mem: InterruptMem::new(program),
+                cycle: 0,
+                accesses: RefCell::new(Vec::new()),
+            };
+            recorder.cpu.regs.set_hl(0xc000);
+            recorder.cpu.regs.sp = 0xd000;
+            setup(&mut recorder.cpu, &mut recorder.mem);
+            tick::<_, Recorder>(&mut recorder);
+            (recorder.accesses.into_inner(), recorder.cycle)
+        }
+    }
+
+    impl CpuContext for Recorder {
+        type Mem = Self;
+
+        fn cpustate(&self) -> &Gbz80State {
+            &self.cpu
+        }
+
+        fn cpustate_mut(&mut self) -> &mut Gbz80State {
+            &mut self.cpu
+        }
+
+        fn mem(&self) -> &Self {
+            self
+        }
+
+        fn mem_mut(&mut self) -> &mut Self {
+            self
+        }
+
+        fn yield1m(&mut self) {
+            self.cycle += 1;
+        }
+    }
+
+    impl MemDevice for Recorder {
+        fn read(&self, addr: Addr) -> u8 {
+            self.accesses
+                .borrow_mut()
+                .push((self.cycle, false, addr.raw()));
+            self.mem.read(addr)
+        }
+
+        fn write(&mut self, addr: Addr, value: u8) {
+            self.accesses.get_mut().push((self.cycle, true, addr.raw()));
+            self.mem.write(addr, value);
+        }
+    }
+
+    impl CpuMem for Recorder {
+        fn interrupt_controller(&self) -> Option<&InterruptController> {
+            self.mem.interrupt_controller()
+        }
+
+        fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController> {
+            self.mem.interrupt_controller_mut()
+        }
+
+        fn fetch(&mut self, addr: Addr) -> u8 {
+            self.read(addr)
+        }
+
+        fn joypad_held(&self) -> Option<bool> {
+            self.mem.joypad_held()
+        }
+
+        fn stop(&mut self) -> bool {
+            self.mem.stop()
+        }
+
+        fn yield1m(&mut self) {}
+    }
+
+    const R: bool = false;
+    const W: bool = true;
+
+    #[test]
+    fn memory_access_cycles() {
+        // Opcode, then the accesses it makes and its total length in M cycles.
+        let cases: &[(&[u8], &[Access], u32)] = &[
+            // LD (HL),A
+            (&[0x77], &[(1, R, 0x0000), (2, W, 0xc000)], 2),
+            // LD A,(HL)
+            (&[0x7e], &[(1, R, 0x0000), (2, R, 0xc000)], 2),
+            // INC (HL): read-modify-write on consecutive cycles.
+            (
+                &[0x34],
+                &[(1, R, 0x0000), (2, R, 0xc000), (3, W, 0xc000)],
+                3,
+            ),
+            // LDH (0x80),A
+            (&[0xe0, 0x80], &[(1, R, 0), (2, R, 1), (3, W, 0xff80)], 3),
+            // LD (0xc123),A
+            (
+                &[0xea, 0x23, 0xc1],
+                &[(1, R, 0), (2, R, 1), (3, R, 2), (4, W, 0xc123)],
+                4,
+            ),
+            // LD (0xc123),SP: low byte first.
+            (
+                &[0x08, 0x23, 0xc1],
+                &[
+                    (1, R, 0),
+                    (2, R, 1),
+                    (3, R, 2),
+                    (4, W, 0xc123),
+                    (5, W, 0xc124),
+                ],
+                5,
+            ),
+            // PUSH BC: an internal delay, then the high byte before the low byte.
+            (
+                &[0xc5],
+                &[(1, R, 0x0000), (3, W, 0xcfff), (4, W, 0xcffe)],
+                4,
+            ),
+            // POP BC
+            (
+                &[0xc1],
+                &[(1, R, 0x0000), (2, R, 0xd000), (3, R, 0xd001)],
+                3,
+            ),
+            // CALL 0x1234
+            (
+                &[0xcd, 0x34, 0x12],
+                &[
+                    (1, R, 0),
+                    (2, R, 1),
+                    (3, R, 2),
+                    (5, W, 0xcfff),
+                    (6, W, 0xcffe),
+                ],
+                6,
+            ),
+            // RET
+            (
+                &[0xc9],
+                &[(1, R, 0x0000), (2, R, 0xd000), (3, R, 0xd001)],
+                4,
+            ),
+            // RST 0x08
+            (
+                &[0xcf],
+                &[(1, R, 0x0000), (3, W, 0xcfff), (4, W, 0xcffe)],
+                4,
+            ),
+            // BIT 0,(HL)
+            (&[0xcb, 0x46], &[(1, R, 0), (2, R, 1), (3, R, 0xc000)], 3),
+            // SET 0,(HL)
+            (
+                &[0xcb, 0xc6],
+                &[(1, R, 0), (2, R, 1), (3, R, 0xc000), (4, W, 0xc000)],
+                4,
+            ),
+        ];
+        for &(program, expected, cycles) in cases {
+            let (accesses, total) = Recorder::run(program, |_, _| {});
+            assert_eq!(accesses, expected, "{:02X?}", program);
+            assert_eq!(total, cycles, "{:02X?}", program);
+        }
+    }
+
+    #[test]
+    fn interrupt_dispatch_access_cycles() {
+        let (accesses, total) = Recorder::run(&[0x00], |cpu, mem| {
+            cpu.regs.pc = 0x1234;
+            cpu.interrupt_master_enable.set();
+            mem.ic.request(InterruptFlags::TIMER);
+        });
+        // Two internal delays, then the program counter is pushed high byte first.
+        assert_eq!(accesses, [(3, W, 0xcfff), (4, W, 0xcffe)]);
+        assert_eq!(total, 5);
+    }
+
+    /// Sets up `mmu` to run `program` from 0xc000 with the divider partway through counting.
+    fn stop_setup(mmu: &mut GbMmu, program: &[u8]) -> Gbz80State {
+        for (i, &byte) in program.iter().enumerate() {
+            mmu.write_raw(0xc000 + i as u16, byte);
+        }
+        mmu.tick(1000);
+        assert_ne!(mmu.read_raw(0xff04), 0);
+        let mut cpu = Gbz80State::new();
+        cpu.regs.pc = 0xc000;
+        cpu
+    }
+
+    #[test]
+    fn stop_speed_switch() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        let mut cpu = stop_setup(&mut mmu, &[0x10, 0x00, 0x10]);
+        mmu.write_raw(0xff4d, 0x01);
+        assert_eq!(cpu.step(&mut mmu), 2051);
+        assert!(mmu.io().double_speed());
+        assert_eq!(mmu.read_raw(0xff04), 0);
+        assert!(!cpu.stopped && !cpu.halted);
+        // Without an interrupt pending, the byte after STOP is skipped.
+        assert_eq!(cpu.regs.pc, 0xc002);
+
+        // With one pending, STOP is a single byte.
+        mmu.write_raw(0xff4d, 0x01);
+        mmu.write_raw(0xffff, 0x01);
+        mmu.interrupts_mut().request(InterruptFlags::VBLANK);
+        assert_eq!(cpu.step(&mut mmu), 2051);
+        assert!(!mmu.io().double_speed());
+        assert_eq!(cpu.regs.pc, 0xc003);
+    }
+
+    #[test]
+    fn stop_until_joypad() {
+        let mut mmu = GbMmu::default();
+        let mut cpu = stop_setup(&mut mmu, &[0x10, 0x00, 0x00]);
+        // Select the action buttons.
+        mmu.write_raw(0xff00, 0x10);
+        assert_eq!(cpu.step(&mut mmu), 1);
+        assert!(cpu.stopped);
+        assert_eq!(cpu.regs.pc, 0xc002);
+        assert_eq!(mmu.read_raw(0xff04), 0);
+
+        // Interrupts don't wake the CPU, and neither do buttons in a column which isn't selected.
+        mmu.write_raw(0xffff, 0x01);
+        mmu.interrupts_mut().request(InterruptFlags::VBLANK);
+        mmu.io_mut().set_buttons(ButtonState::UP);
+        for _ in 0..10 {
+            assert_eq!(cpu.step(&mut mmu), 1);
+        }
+        assert!(cpu.stopped);
+        assert_eq!(cpu.regs.pc, 0xc002);
+
+        // Pressing a selected button wakes it up to run the next instruction.
+        mmu.io_mut().set_buttons(ButtonState::A);
+        assert_eq!(cpu.step(&mut mmu), 2);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.regs.pc, 0xc003);
+    }
+
+    #[test]
+    fn stop_without_joypad() {
+        // Plain memory has no joypad to wake the CPU, so STOP just skips its operand.
+        let mut cpu = Gbz80State::new();
+        let (_, cycles) = run(&[0x10, 0x00, 0x3c], &mut cpu, |_, _| {});
+        assert_eq!(cycles, 2);
+        assert!(!cpu.stopped && !cpu.halted);
+        assert_eq!(cpu.regs.acc, 1);
+
+        // With an interrupt pending, it is a single byte.
+        let mut mem = InterruptMem::new(&[0x10, 0x3c]);
+        mem.ic.request(InterruptFlags::TIMER);
+        let mut cpu = Gbz80State::new();
+        assert_eq!(cpu.step(&mut mem), 1);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.regs.pc, 1);
+    }
+
+    #[test]
+    fn stop_with_button_held() {
+        let mut mmu = GbMmu::default();
+        let mut cpu = stop_setup(&mut mmu, &[0x10, 0x00, 0x10, 0x00]);
+        mmu.write_raw(0xff00, 0x10);
+        mmu.io_mut().set_buttons(ButtonState::START);
+        let div = mmu.read_raw(0xff04);
+        // With no interrupt pending, STOP is 2 bytes and halts without touching DIV.
+        assert_eq!(cpu.step(&mut mmu), 1);
+        assert!(cpu.halted && !cpu.stopped);
+        assert_eq!(cpu.regs.pc, 0xc002);
+        assert_eq!(mmu.read_raw(0xff04), div);
+
+        // With one pending, it is a single byte and the CPU keeps running.
+        cpu.halted = false;
+        mmu.write_raw(0xffff, 0x10);
+        assert_eq!(cpu.step(&mut mmu), 1);
+        assert!(!cpu.halted && !cpu.stopped);
+        assert_eq!(cpu.regs.pc, 0xc003);
+        assert_eq!(mmu.read_raw(0xff04), div);
+    }
 }
@@ -2,10 +2,12 @@ use std::fmt;
 
 use log::{debug, trace, warn};
 
-use super::oputils::{add8_flags, offset_addr, rotate_left9, rotate_right9, sub8_flags};
-use super::{AluOp, AluUnaryOp, ConditionCode, CpuContext, Flags, Operand16, Operand8};
-use crate::interrupts::InterruptFlags;
-use crate::memdev::MemDevice;
+use super::oputils::{
+    add8_flags, fetch_cycle, offset_addr, read_cycle, rotate_left9, rotate_right9, sub8_flags,
+    write_cycle,
+};
+use super::{AluOp, AluUnaryOp, ConditionCode, CpuContext, CpuMem, Flags, Operand16, Operand8};
+use crate::interrupts::Interrupt;
 
 // Opcode References:
 // - Decoding: www.z80.info/decoding.htm
@@ -20,7 +22,8 @@ use crate::memdev::MemDevice;
 pub enum Opcode {
     /// No operation.
     Nop,
-    /// Stop -- not sure what this does or how this differs from Halt.
+    /// Stop the CPU until a button is pressed, or perform a CGB speed switch. See [`stop`] for
+    /// the details.
     Stop,
     /// Relative jump. Load a signed immediate for the jump destination, then check the condition,
     /// then jump if the condition is met.
@@ -235,7 +238,13 @@ impl Opcode {
     pub(super) fn load_and_execute(ctx: &mut impl CpuContext) {
         let pc = ctx.cpustate().regs.pc;
         trace!("Loading opcode at {:#6X}", pc);
-        let opcode = Operand8::Immediate.read(ctx);
+        let opcode = if ctx.cpustate().halt_bug {
+            // The halt bug skips incrementing the program counter, so this byte is read again.
+            ctx.cpustate_mut().halt_bug = false;
+            fetch_cycle(ctx, pc)
+        } else {
+            Operand8::Immediate.read(ctx)
+        };
         let opcode = Self::decode(opcode);
         debug!("Executing @ {:#6X}: {}", pc, opcode);
         opcode.execute(ctx);
@@ -245,7 +254,7 @@ impl Opcode {
     fn execute(self, ctx: &mut impl CpuContext) {
         match self {
             Self::Nop => {}
-            Self::Stop => panic!("STOP is bizarre and complicated and not implemented."),
+            Self::Stop => stop(ctx),
             Self::JumpRelative(cond) => jump_relative(ctx, cond),
             Self::Inc8(operand) => inc8(ctx, operand),
             Self::Dec8(operand) => dec8(ctx, operand),
@@ -335,6 +344,8 @@ fn jump_relative(ctx: &mut impl CpuContext, cond: ConditionCode) {
     // JR doesn't set any flags.
     let (dest, _) = offset_addr(base, offset);
     if cond.evaluate(ctx) {
+        // Like the absolute jump, taking the branch adds an extra cycle.
+        ctx.yield1m();
         trace!("Relative jump by {} from {} to {}", offset, base, dest);
         ctx.cpustate_mut().regs.pc = dest;
     } else {
@@ -412,16 +423,20 @@ fn load16(ctx: &mut impl CpuContext, dest: Operand16, source: Operand16) {
 
 /// Implements 16 bit register add into HL. Never sets the zero flag and clears the subtract flag,
 /// but does set carry and half-carry based on the upper byte of the operation (as if it was
-/// performed by running the pseudo-instructions `add l,<arg-low>; adc h,<arg-high>`.
+/// performed by running the pseudo-instructions `add l,<arg-low>; adc h,<arg-high>`), so
+/// half-carry is the carry from bit 11 -> 12.
 fn add16(ctx: &mut impl CpuContext, arg: Operand16) {
     // 16 bit add never modifies the zero flag.
     const MASK: Flags = Flags::all().difference(Flags::ZERO);
 
     let lhs = ctx.cpustate().regs.hl();
-    let rhs = arg.read(ctx); // This will always be a register in practice.
+    // This will always be a register in practice.
+    let rhs = arg.read(ctx);
+    // The ALU only works on 8 bits, so the high byte takes an extra cycle.
+    ctx.yield1m();
 
     let mut flags = Flags::empty();
-    if (lhs & 0x7ff) + (rhs & 0x7ff) > 0x7ff {
+    if (lhs & 0xfff) + (rhs & 0xfff) > 0xfff {
         flags |= Flags::HALFCARRY;
     }
     let (res, carry) = lhs.overflowing_add(rhs);
@@ -431,6 +446,57 @@ fn add16(ctx: &mut impl CpuContext, arg: Operand16) {
     ctx.cpustate_mut().regs.flags.merge(flags, MASK);
 }
 
+/// Number of M cycles the CPU is paused for while switching speeds.
+const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+/// Executes `STOP`, which does one of several things depending on the joypad, pending
+/// interrupts, and whether a CGB speed switch is armed in KEY1:
+///
+/// *   If a button is held in a selected column, nothing happens to DIV or the speed. With an
+///     interrupt pending `STOP` is a 1 byte instruction and the CPU keeps running. Otherwise it
+///     is 2 bytes and the CPU halts.
+/// *   If a speed switch is armed, DIV is reset and the speed switches, which pauses the CPU for
+///     about 2050 M cycles. The clock is stopped during the pause, so DIV stays at 0. `STOP` is
+///     1 byte with an interrupt pending and 2 bytes otherwise.
+/// *   Otherwise DIV is reset and the CPU stops until a button is pressed. `STOP` is 1 byte with
+///     an interrupt pending and 2 bytes otherwise.
+///
+/// A 2 byte `STOP` skips the byte after it without reading it. On hardware, switching speeds
+/// with an interrupt pending and `IME` set hangs the CPU unpredictably; that case is treated as
+/// if `IME` were clear.
+///
+/// If the memory has no joypad, nothing could ever wake the CPU, so `STOP` is ignored apart from
+/// its length.
+fn stop(ctx: &mut impl CpuContext) {
+    let pending = ctx
+        .mem()
+        .interrupt_controller()
+        .is_some_and(|ic| !ic.active().is_empty());
+    if !pending {
+        ctx.cpustate_mut().regs.inc_pc();
+    }
+    match ctx.mem().joypad_held() {
+        None => warn!("STOP with no joypad to wake the CPU, ignoring it"),
+        Some(true) => {
+            if !pending {
+                ctx.cpustate_mut().halted = true;
+            }
+        }
+        Some(false) => {
+            if ctx.mem_mut().stop() {
+                debug!("STOP switched speeds");
+                ctx.cpustate_mut().stopped = true;
+                for _ in 0..SPEED_SWITCH_CYCLES {
+                    ctx.yield1m();
+                }
+                ctx.cpustate_mut().stopped = false;
+            } else {
+                ctx.cpustate_mut().stopped = true;
+            }
+        }
+    }
+}
+
 /// If `IME` is set, just halts the CPU until there is an interrupt available to be serviced. If
 /// there's already an interrupt to be serviced, `HALT` is effectively a `NOP` and excution moved
 /// directly to the interrupt handler then to the next instruction.
@@ -439,29 +505,26 @@ fn add16(ctx: &mut impl CpuContext, arg: Operand16) {
 /// *   If no interrupt is pending, the CPU still halts and resumes the next time an interrupt
 ///     becomes pending, but the interrupt just isn't handled because IME is off.
 /// *   If there are enabled interrupts pending (`[IE] & [IF] != 0`), the bug is triggered:
-///     *   In the normal case, the bug just prevents the program counter from incrementing
-///         properly, so the byte after `HALT` will be read twice. (This presumably means that any
-///         1-byte instruction will execute twice, and any two-byte instruction will read itself as
-///         the following value, but that's somewhat unclear. Its also not clear what happens if an
-///         `RST` is executed, since that's a 1 byte jump. Does overwritting the `PC` avert the bug?
-///         Note that with any normal `CALL`, `JP`, or `JR`, the repeat byte would be used as the
-///         jump target or offset instead.)
+///     *   In the normal case, the CPU doesn't halt, and the next opcode fetch fails to increment
+///         the program counter, so the byte after `HALT` is read twice. A 1 byte instruction runs
+///         twice, and a longer one reads its own opcode as its first argument.
 ///     *   If `EI` executed just before `HALT` such that `IME` would become true after the `HALT`,
-///         the bug is even weirder: the interrupt is serviced as normal, but then the interrupt
-///         returns to `halt` which is executed again.
+///         the interrupt is serviced before that fetch, and returns to the `HALT`, which is
+///         executed again.
 ///
-/// Implementing the behavior of preventing PC increment would require a bunch of complex extra
-/// state which would have to be checked in a bunch of places, so for now this just panics if the
-/// bug would be encountered.
+/// The skipped increment is tracked by [`Gbz80State::halt_bug`](super::Gbz80State::halt_bug).
 fn halt(ctx: &mut impl CpuContext) {
     if ctx.cpustate().interrupt_master_enable.enabled() {
         // No need to special-case interrupts here, since the next `tick` call will un-halt anyway.
         ctx.cpustate_mut().halted = true;
     } else {
-        let enabled_interrupts = InterruptFlags::get_interrupt_enable(ctx.mem());
-        let pending_interrupts = ctx.cpustate().interrupt_vector;
-        if enabled_interrupts.intersects(pending_interrupts) {
-            panic!("Halt-Bug encountered (see method description).");
+        let pending = ctx
+            .mem()
+            .interrupt_controller()
+            .is_some_and(|ic| !ic.active().is_empty());
+        if pending {
+            debug!("HALT bug triggered");
+            ctx.cpustate_mut().halt_bug = true;
         } else {
             // `tick` will un-halt next time ([IE] & [IF] != 0), but will not service the interrupt.
             ctx.cpustate_mut().halted = true;
@@ -540,25 +603,21 @@ fn pop(ctx: &mut impl CpuContext, operand: Operand16) {
 /// waiting 1m between each byte and decrementing the stack pointer by 2.
 fn push_helper(ctx: &mut impl CpuContext, val: u16) {
     let [low, high] = val.to_le_bytes();
-    ctx.yield1m();
     let addr = ctx.cpustate_mut().regs.dec_sp();
-    ctx.mem_mut().write(addr.into(), high);
+    write_cycle(ctx, addr, high);
 
-    ctx.yield1m();
     let addr = ctx.cpustate_mut().regs.dec_sp();
-    ctx.mem_mut().write(addr.into(), low);
+    write_cycle(ctx, addr, low);
 }
 
 /// Pop helper, shared between pop and ret. Pops value from the stack, waiting 1m between each byte
 /// and incrementing the stack pointer by 2.
 fn pop_helper(ctx: &mut impl CpuContext) -> u16 {
-    ctx.yield1m();
     let addr = ctx.cpustate_mut().regs.inc_sp();
-    let low = ctx.mem().read(addr.into());
+    let low = read_cycle(ctx, addr);
 
-    ctx.yield1m();
     let addr = ctx.cpustate_mut().regs.inc_sp();
-    let high = ctx.mem().read(addr.into());
+    let high = read_cycle(ctx, addr);
 
     u16::from_le_bytes([low, high])
 }
@@ -605,10 +664,34 @@ fn address_of_offset_sp(ctx: &mut impl CpuContext) {
     ctx.cpustate_mut().regs.flags = flags;
 }
 
-// Similar to unconditional jump, but using HL as the target address.
+/// Similar to unconditional jump, but using HL as the target address. Unlike `JP u16`, there's no
+/// extra delay.
 fn jump_hl(ctx: &mut impl CpuContext) {
     let regs = &mut ctx.cpustate_mut().regs;
-    regs.sp = regs.hl();
+    regs.pc = regs.hl();
+}
+
+/// Services the given interrupt. Similar to `RST` to the interrupt's vector, but it takes 5 M
+/// cycles, disables interrupts, and clears the interrupt's bit in IF.
+pub(super) fn service_interrupt(ctx: &mut impl CpuContext, interrupt: Interrupt) {
+    debug!("Servicing {:?} interrupt", interrupt);
+    ctx.cpustate_mut().interrupt_master_enable.clear();
+    // There are two cycles of delay before the program counter is pushed, and one after.
+    ctx.yield1m();
+    ctx.yield1m();
+    let mut ret = ctx.cpustate().regs.pc;
+    if ctx.cpustate().halt_bug {
+        // The interrupt came between HALT and the fetch which would have repeated the byte after
+        // it, so it returns to the HALT instead.
+        ctx.cpustate_mut().halt_bug = false;
+        ret = ret.wrapping_sub(1);
+    }
+    push_helper(ctx, ret);
+    if let Some(ic) = ctx.mem_mut().interrupt_controller_mut() {
+        ic.acknowledge(interrupt);
+    }
+    ctx.yield1m();
+    ctx.cpustate_mut().regs.pc = interrupt.vector();
 }
 
 /// Executes the reset instruction. Similar to call with a fixed destination.
@@ -623,7 +706,11 @@ fn reset(ctx: &mut impl CpuContext, dest: u8) {
 // CB Prefixed Opcodes
 //////////////////////
 
-/// Opcodes that come after a CB prefix opcode.
+/// Opcodes that come after a CB prefix opcode. The top two bits select the kind of operation,
+/// the middle three select the shift or bit index, and the low three select the operand.
+///
+/// Including the prefix, these take 2 M cycles on a register and 4 on `(HL)`, except for
+/// `BIT n,(HL)`, which only reads and so takes 3.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct CBOpcode {
     /// The operand to operate on.
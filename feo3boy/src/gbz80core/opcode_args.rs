@@ -1,11 +1,12 @@
 use std::fmt;
-use std::num::Wrapping;
 
 use log::trace;
 
-use super::oputils::{add8_flags, rotate_left9, rotate_right9, sub8_flags};
+use super::oputils::{
+    adc8_flags, add8_flags, fetch_cycle, read_cycle, rotate_left9, rotate_right9, sbc8_flags,
+    sub8_flags, write_cycle,
+};
 use super::{CpuContext, Flags};
-use crate::memdev::MemDevice;
 
 /// ALU Operation type.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -47,12 +48,8 @@ impl AluOp {
                 ctx.cpustate_mut().regs.flags = flags;
             }
             Self::AddCarry => {
-                let (mut res, mut flags) = add8_flags(ctx.cpustate().regs.acc, arg);
-                if ctx.cpustate().regs.flags.contains(Flags::CARRY) {
-                    let (res2, flags2) = add8_flags(res, 1);
-                    res = res2;
-                    flags |= flags2;
-                }
+                let carry = ctx.cpustate().regs.flags.contains(Flags::CARRY);
+                let (res, flags) = adc8_flags(ctx.cpustate().regs.acc, arg, carry);
                 ctx.cpustate_mut().regs.acc = res;
                 ctx.cpustate_mut().regs.flags = flags;
             }
@@ -62,12 +59,8 @@ impl AluOp {
                 ctx.cpustate_mut().regs.flags = flags;
             }
             Self::SubCarry => {
-                let (mut res, mut flags) = sub8_flags(ctx.cpustate().regs.acc, arg);
-                if ctx.cpustate().regs.flags.contains(Flags::CARRY) {
-                    let (res2, flags2) = sub8_flags(res, 1);
-                    res = res2;
-                    flags |= flags2;
-                }
+                let carry = ctx.cpustate().regs.flags.contains(Flags::CARRY);
+                let (res, flags) = sbc8_flags(ctx.cpustate().regs.acc, arg, carry);
                 ctx.cpustate_mut().regs.acc = res;
                 ctx.cpustate_mut().regs.flags = flags;
             }
@@ -120,8 +113,10 @@ pub enum AluUnaryOp {
     RotateRight8,
     /// 9-bit left rotate. Bit 0 goes to carry and carry goes to bit 7.
     RotateRight9,
-    /// Helper for doing binary-coded-decimal. Adjusts the hex didgits to keep both nybbles in range
-    /// 0..=9 by adding 0x06 and/or 0x60 to push the digit to the next nybble.
+    /// Helper for doing binary-coded-decimal. Adjusts the hex digits to keep both nybbles in range
+    /// 0..=9 by adding 0x06 and/or 0x60 to push the digit to the next nybble. After a subtraction
+    /// (SUB set), instead subtracts 0x06 and/or 0x60 according to HALFCARRY and CARRY. Clears
+    /// HALFCARRY, and sets CARRY if the decimal result carried or borrowed out of the top digit.
     DecimalAdjust,
     /// Sets the carry flag.
     SetCarryFlag,
@@ -320,50 +315,41 @@ impl Operand8 {
             Self::L => ctx.cpustate().regs.l,
             Self::AddrHL => {
                 let addr = ctx.cpustate().regs.hl();
-                ctx.yield1m();
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
             Self::AddrBC => {
                 let addr = ctx.cpustate().regs.bc();
-                ctx.yield1m();
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
             Self::AddrDE => {
                 let addr = ctx.cpustate().regs.de();
-                ctx.yield1m();
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
             Self::AddrHLInc => {
                 let addr = ctx.cpustate().regs.hl();
-                ctx.yield1m();
                 ctx.cpustate_mut().regs.set_hl(addr.wrapping_add(1));
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
             Self::AddrHLDec => {
                 let addr = ctx.cpustate().regs.hl();
-                ctx.yield1m();
                 ctx.cpustate_mut().regs.set_hl(addr.wrapping_sub(1));
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
             Self::Immediate => {
                 let addr = ctx.cpustate_mut().regs.inc_pc();
-                ctx.yield1m();
-                ctx.mem().read(addr.into())
+                fetch_cycle(ctx, addr)
             }
             Self::AddrImmediate => {
                 let addr = Operand16::Immediate.read(ctx);
-                ctx.yield1m();
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
             Self::AddrRelC => {
                 let addr = 0xFF00 + ctx.cpustate().regs.c as u16;
-                ctx.yield1m();
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
             Self::AddrRelImmediate => {
                 let addr = 0xFF00 + Self::Immediate.read(ctx) as u16;
-                ctx.yield1m();
-                ctx.mem().read(addr.into())
+                read_cycle(ctx, addr)
             }
         }
     }
@@ -381,46 +367,38 @@ impl Operand8 {
             Self::L => ctx.cpustate_mut().regs.l = val,
             Self::AddrHL => {
                 let addr = ctx.cpustate().regs.hl();
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
             Self::AddrBC => {
                 let addr = ctx.cpustate().regs.bc();
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
             Self::AddrDE => {
                 let addr = ctx.cpustate().regs.de();
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
             Self::AddrHLInc => {
                 let addr = ctx.cpustate().regs.hl();
-                ctx.yield1m();
                 ctx.cpustate_mut().regs.set_hl(addr.wrapping_add(1));
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
             Self::AddrHLDec => {
                 let addr = ctx.cpustate().regs.hl();
-                ctx.yield1m();
                 ctx.cpustate_mut().regs.set_hl(addr.wrapping_sub(1));
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
             Self::Immediate => panic!("Immediates cannot be used as store destinations"),
             Self::AddrImmediate => {
                 let addr = Operand16::Immediate.read(ctx);
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
             Self::AddrRelC => {
                 let addr = 0xFF00 + ctx.cpustate().regs.c as u16;
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
             Self::AddrRelImmediate => {
                 let addr = 0xFF00 + Self::Immediate.read(ctx) as u16;
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.into(), val)
+                write_cycle(ctx, addr, val)
             }
         }
     }
@@ -528,12 +506,9 @@ impl Operand16 {
             Self::Immediate => panic!("Immediates cannot be used as store destinations"),
             Self::AddrImmediate => {
                 let [low, high] = val.to_le_bytes();
-                let mut addr = Wrapping(Self::Immediate.read(ctx));
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.0.into(), low);
-                addr += Wrapping(1u16);
-                ctx.yield1m();
-                ctx.mem_mut().write(addr.0.into(), high);
+                let addr = Self::Immediate.read(ctx);
+                write_cycle(ctx, addr, low);
+                write_cycle(ctx, addr.wrapping_add(1), high);
             }
         }
     }
@@ -1,12 +1,36 @@
 //! Utilities for implementing operations.
 
-use super::Flags;
+use super::{CpuContext, CpuMem, Flags};
+use crate::memdev::MemDevice;
+
+/// Performs a memory read cycle: yields for 1 M cycle, then reads the given address. Every
+/// memory access the CPU makes goes through this or [`write_cycle`], so each access lands on the
+/// M cycle it takes on hardware, with the clock already advanced past the cycles before it.
+pub(super) fn read_cycle(ctx: &mut impl CpuContext, addr: u16) -> u8 {
+    ctx.yield1m();
+    ctx.mem().read(addr.into())
+}
+
+/// Performs an instruction fetch cycle: yields for 1 M cycle, then fetches the byte at the given
+/// address with [`CpuMem::fetch`]. Opcodes and immediate operands are read through this rather
+/// than [`read_cycle`].
+pub(super) fn fetch_cycle(ctx: &mut impl CpuContext, addr: u16) -> u8 {
+    ctx.yield1m();
+    ctx.mem_mut().fetch(addr.into())
+}
+
+/// Performs a memory write cycle: yields for 1 M cycle, then writes the given address.
+pub(super) fn write_cycle(ctx: &mut impl CpuContext, addr: u16, val: u8) {
+    ctx.yield1m();
+    ctx.mem_mut().write(addr.into(), val);
+}
 
 /// Get the result of applying an 8 bit offset to a 16 bit address.
 /// * ZERO is never set.
 /// * SUB is never set.
 /// * CARRY is set based on the low order byte.
 /// * HALFCARRY is set based on the lower order byte.
+///
 /// Carries are determined as if adding (using two's compliment).
 pub(super) fn offset_addr(addr: u16, offset: i8) -> (u16, Flags) {
     // Perform sign-extension, then treat as u16.
@@ -34,12 +58,21 @@ pub(super) fn offset_addr(addr: u16, offset: i8) -> (u16, Flags) {
 /// * CARRY is set if the result overflowed.
 /// * HALFCARRY is set if there was an overflow from bit 3 -> 4.
 pub(super) fn add8_flags(a: u8, b: u8) -> (u8, Flags) {
+    adc8_flags(a, b, false)
+}
+
+/// Perform 8 bit addition with an incoming carry, as `a + b + carry`. Flags are the same as for
+/// `add8_flags`, but computed over all three inputs at once, so the carry can cause either
+/// CARRY or HALFCARRY, and ZERO only reflects the final result.
+pub(super) fn adc8_flags(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let carry = carry as u8;
     let mut flags = Flags::empty();
-    if (a & 0xf) + (b & 0xf) > 0xf {
+    if (a & 0xf) + (b & 0xf) + carry > 0xf {
         flags |= Flags::HALFCARRY;
     }
-    let (res, carry) = a.overflowing_add(b);
-    flags |= Flags::check_zero(res) | Flags::check_carry(carry);
+    let sum = a as u16 + b as u16 + carry as u16;
+    let res = sum as u8;
+    flags |= Flags::check_zero(res) | Flags::check_carry(sum > 0xff);
     (res, flags)
 }
 
@@ -49,12 +82,19 @@ pub(super) fn add8_flags(a: u8, b: u8) -> (u8, Flags) {
 /// * CARRY is set if the result underflowed (b > a).
 /// * HALFCARRY is set if there was a borrow from bit 4 -> 3.
 pub(super) fn sub8_flags(a: u8, b: u8) -> (u8, Flags) {
+    sbc8_flags(a, b, false)
+}
+
+/// Perform 8 bit subtraction with an incoming borrow, as `a - b - carry`. Flags are the same as
+/// for `sub8_flags`, but computed over all three inputs at once.
+pub(super) fn sbc8_flags(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let carry = carry as u8;
     let mut flags = Flags::SUB;
-    if (a & 0xf) < (b & 0xf) {
+    if (a & 0xf) < (b & 0xf) + carry {
         flags |= Flags::HALFCARRY;
     }
-    let (res, carry) = a.overflowing_sub(b);
-    flags |= Flags::check_zero(res) | Flags::check_carry(carry);
+    let res = a.wrapping_sub(b).wrapping_sub(carry);
+    flags |= Flags::check_zero(res) | Flags::check_carry((a as u16) < b as u16 + carry as u16);
     (res, flags)
 }
 
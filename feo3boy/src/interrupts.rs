@@ -25,6 +25,17 @@ bitflags! {
 }
 
 impl InterruptFlags {
+    /// Gets the highest priority interrupt in this set, if any. Lower bits have higher priority,
+    /// so VBLANK is serviced first and JOYPAD last.
+    pub fn highest_priority(self) -> Option<InterruptFlags> {
+        let bits = self.bits;
+        if bits == 0 {
+            None
+        } else {
+            Some(Self::from_bits_truncate(bits & bits.wrapping_neg()))
+        }
+    }
+
     /// Reads the byte at 0xffff and converts it to `InterruptFlags`. Normally 0xffff is the
     /// location of the interrupt enable register.
     pub fn get_interrupt_enable(mem: &impl MemDevice) -> Self {
@@ -38,11 +49,144 @@ impl InterruptFlags {
     }
 }
 
+/// A single interrupt, in priority order.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Interrupt {
+    /// Vertical blanking of the display.
+    VBlank,
+    /// LCD status interrupt.
+    Stat,
+    /// Timer counter overflow.
+    Timer,
+    /// Serial transfer complete.
+    Serial,
+    /// Joypad button pressed.
+    Joypad,
+}
+
+impl Interrupt {
+    /// All interrupts, from highest to lowest priority.
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Gets the flag bit for this interrupt in IE and IF.
+    pub fn flag(self) -> InterruptFlags {
+        match self {
+            Interrupt::VBlank => InterruptFlags::VBLANK,
+            Interrupt::Stat => InterruptFlags::STAT,
+            Interrupt::Timer => InterruptFlags::TIMER,
+            Interrupt::Serial => InterruptFlags::SERIAL,
+            Interrupt::Joypad => InterruptFlags::JOYPAD,
+        }
+    }
+
+    /// Gets the address the CPU jumps to when servicing this interrupt.
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * self as u16
+    }
+}
+
+/// The interrupt enable (IE) and interrupt flag (IF) registers, which together decide which
+/// interrupt, if any, the CPU should service next.
+///
+/// The controller is owned by [`MemMappedIo`](crate::memdev::MemMappedIo), which maps IF to
+/// 0xFF0F and requests interrupts for the IO devices. The [`GbMmu`](crate::memdev::GbMmu) maps IE
+/// at 0xFFFF to the same controller, and the CPU reaches it through
+/// [`CpuMem::interrupt_controller`](crate::gbz80core::CpuMem::interrupt_controller) to service
+/// interrupts.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InterruptController {
+    /// Interrupt enable (IE).
+    enable: InterruptFlags,
+    /// Interrupt flags (IF), the requested interrupts.
+    flags: InterruptFlags,
+}
+
+impl InterruptController {
+    /// Constructs a new `InterruptController` with nothing enabled or requested.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Requests the given interrupts by setting them in IF.
+    pub fn request(&mut self, flags: InterruptFlags) {
+        self.flags |= flags;
+    }
+
+    /// Gets the highest priority interrupt which is both enabled and requested.
+    pub fn pending(&self) -> Option<Interrupt> {
+        let active = self.active();
+        Interrupt::ALL
+            .iter()
+            .copied()
+            .find(|interrupt| active.contains(interrupt.flag()))
+    }
+
+    /// Clears the interrupt's bit in IF, as happens when the CPU services it.
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flags.remove(interrupt.flag());
+    }
+
+    /// Gets the interrupts which are both enabled and requested.
+    pub fn active(&self) -> InterruptFlags {
+        self.enable & self.flags
+    }
+
+    /// Gets the interrupt enable register (IE).
+    pub fn ie(&self) -> InterruptFlags {
+        self.enable
+    }
+
+    /// Sets the interrupt enable register (IE).
+    pub fn set_ie(&mut self, enable: InterruptFlags) {
+        self.enable = enable;
+    }
+
+    /// Gets the interrupt flag register (IF).
+    pub fn if_(&self) -> InterruptFlags {
+        self.flags
+    }
+
+    /// Sets the interrupt flag register (IF).
+    pub fn set_if(&mut self, flags: InterruptFlags) {
+        self.flags = flags;
+    }
+
+    /// Gets the value the CPU reads from IF. The upper 3 bits are unused and always read 1.
+    pub fn read_if(&self) -> u8 {
+        self.flags.bits | 0xe0
+    }
+
+    /// Handles a CPU write to IF, keeping only the low 5 bits.
+    pub fn write_if(&mut self, value: u8) {
+        self.flags = InterruptFlags::from_bits_truncate(value);
+    }
+
+    /// Gets the value the CPU reads from IE.
+    pub fn read_ie(&self) -> u8 {
+        self.enable.bits
+    }
+
+    /// Handles a CPU write to IE, keeping only the low 5 bits.
+    pub fn write_ie(&mut self, value: u8) {
+        self.enable = InterruptFlags::from_bits_truncate(value);
+    }
+}
+
 /// The Interrupt Enable (IE) register. Implements MemDevice.
 #[derive(Copy, Clone, Debug, Default)]
 pub struct InterruptEnable(pub InterruptFlags);
 
 impl MemDevice for InterruptEnable {
+    fn size(&self) -> usize {
+        1
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         assert!(
             addr.relative() == 0,
@@ -61,3 +205,62 @@ impl MemDevice for InterruptEnable {
         self.0 = InterruptFlags::from_bits_truncate(value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_order() {
+        let order = [
+            InterruptFlags::VBLANK,
+            InterruptFlags::STAT,
+            InterruptFlags::TIMER,
+            InterruptFlags::SERIAL,
+            InterruptFlags::JOYPAD,
+        ];
+        let mut pending = InterruptFlags::all();
+        for &flag in order.iter() {
+            assert_eq!(pending.highest_priority(), Some(flag));
+            pending.remove(flag);
+        }
+        assert_eq!(pending.highest_priority(), None);
+
+        for (&interrupt, &flag) in Interrupt::ALL.iter().zip(order.iter()) {
+            assert_eq!(interrupt.flag(), flag);
+        }
+        assert_eq!(Interrupt::VBlank.vector(), 0x40);
+        assert_eq!(Interrupt::Joypad.vector(), 0x60);
+        assert_eq!(
+            (InterruptFlags::JOYPAD | InterruptFlags::TIMER).highest_priority(),
+            Some(InterruptFlags::TIMER)
+        );
+    }
+
+    #[test]
+    fn controller() {
+        let mut ic = InterruptController::new();
+        ic.request(InterruptFlags::TIMER | InterruptFlags::JOYPAD);
+        // Nothing is pending until it is enabled.
+        assert_eq!(ic.pending(), None);
+        ic.write_ie(0xff);
+        assert_eq!(ic.read_ie(), 0x1f);
+        assert_eq!(ic.pending(), Some(Interrupt::Timer));
+
+        // Higher priority interrupts go first, regardless of when they were requested.
+        ic.request(InterruptFlags::VBLANK);
+        assert_eq!(ic.pending(), Some(Interrupt::VBlank));
+        ic.acknowledge(Interrupt::VBlank);
+        assert_eq!(ic.pending(), Some(Interrupt::Timer));
+        ic.acknowledge(Interrupt::Timer);
+        assert_eq!(ic.pending(), Some(Interrupt::Joypad));
+        assert_eq!(ic.read_if(), 0xf0);
+
+        // Disabled interrupts stay requested, but aren't pending.
+        ic.set_ie(InterruptFlags::VBLANK);
+        assert_eq!(ic.pending(), None);
+        assert_eq!(ic.if_(), InterruptFlags::JOYPAD);
+        ic.write_if(0);
+        assert_eq!(ic.read_if(), 0xe0);
+    }
+}
@@ -1,3 +1,6 @@
+pub mod cpu;
 pub mod gbz80core;
 pub mod interrupts;
 pub mod memdev;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testutils;
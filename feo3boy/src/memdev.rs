@@ -1,13 +1,81 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::{Deref, DerefMut, RangeInclusive};
+use std::rc::Rc;
 
+use bitflags::bitflags;
+use log::{debug, warn};
 use thiserror::Error;
 
-use crate::interrupts::{InterruptEnable, InterruptFlags};
+use crate::gbz80core::CpuMem;
+use crate::interrupts::{InterruptController, InterruptFlags};
 
-pub use cartridge::{Cartridge, Mbc1Rom, ParseCartridgeError, RamBank, RomBank};
+pub use apu::Apu;
+pub use cartridge::{
+    cartridge_type_name, ram_size_name, rom_size_name, Cartridge, CartridgeHeader, CgbSupport,
+    Huc1Rom, IntegrityWarning, Mbc1Builder, Mbc1Rom, Mmm01Rom, ParseCartridgeError, PocketCamera,
+    RamBank, RomBank, SaveSignature, VolatileState, VolatileStateError,
+};
+pub use cgbpalette::{PaletteRam, Rgb555};
+pub use cheats::{CheatCode, CheatEngine, CheatHandle, CheatParseError};
+pub use chunked::ChunkedRam;
+pub use components::{Component, IoDevice, NullApu, NullPpu, NullTimer};
+pub use hdma::Hdma;
+pub use hex::HexParseError;
+pub use infrared::InfraredPort;
+pub use iosnapshot::{IoRegisterValue, IoSnapshot};
+pub use joypad::{ButtonState, Joypad};
+pub use lcd::{Lcd, LcdMode, LcdcFlags};
+pub use model::{CompatMode, HardwareModel};
+pub use oam::{Oam, ObjPriorityMode, ObjectPriority, Sprite, SpriteFlags};
+pub use palette::{DmgPalette, Shade};
+pub use patch::{GameGenieParseError, Patch, PatchHandle, Patched};
+pub use raminit::RamInit;
+pub use serial::{BufferedSerial, Disconnected, Serial, SerialBackend, SerialTiming};
+pub use sgb::SgbPacket;
+#[cfg(any(test, feature = "mmu-stats"))]
+pub use stats::{AccessStats, Region};
+pub use timer::Timer;
+pub use watch::{AccessKind, WatchHandle, WatchHit, Watchpoint};
 
+use ioregs::{io_register, IoRegister};
+use regions::*;
+#[cfg(any(test, feature = "mmu-stats"))]
+use stats::StatsCollector;
+use watch::Watchpoints;
+
+mod apu;
 mod cartridge;
+mod cgbpalette;
+mod cheats;
+mod chunked;
+mod components;
+mod hdma;
+mod hex;
+mod infrared;
+mod ioregs;
+mod iosnapshot;
+mod joypad;
+mod lcd;
+mod model;
+mod oam;
+mod palette;
+mod patch;
+mod raminit;
+pub mod regions;
+mod serial;
+mod sgb;
+#[cfg(any(test, feature = "mmu-stats"))]
+mod stats;
+mod timer;
+mod watch;
+
+/// An inclusive range of system addresses. Inclusive so that ranges can reach 0xFFFF.
+pub type AddrRange = RangeInclusive<u16>;
 
 /// A memory address within system memory. Provides both the raw address and relative address so
 /// that devices can report both raw and relative addresses in error messages.
@@ -51,13 +119,45 @@ impl Addr {
     }
 
     /// Constructs a new address, offsetting the relative address by the specified amount.
+    ///
+    /// The shift must not be larger than the relative address. Devices call this on every access
+    /// with a shift that their address decode already guarantees is in range, so this is only
+    /// checked in debug builds. Use [`checked_offset_by`](Addr::checked_offset_by) where the
+    /// shift isn't known to be in range.
+    #[inline]
     pub fn offset_by(&self, shift: u16) -> Self {
-        assert!(shift <= self.relative, "Attempting to offset with overflow");
+        debug_assert!(shift <= self.relative, "Attempting to offset with overflow");
         Addr {
             raw: self.raw,
-            relative: self.relative - shift,
+            relative: self.relative.wrapping_sub(shift),
         }
     }
+
+    /// Constructs a new address, offsetting the relative address by the specified amount, or
+    /// returns `None` if the shift is larger than the relative address.
+    pub fn checked_offset_by(&self, shift: u16) -> Option<Self> {
+        Some(Addr {
+            raw: self.raw,
+            relative: self.relative.checked_sub(shift)?,
+        })
+    }
+
+    /// Constructs an address with the given raw address, relative to a device which starts at
+    /// `offset`. This is the same address a device mapped at `offset` receives, which makes it
+    /// convenient for testing devices directly.
+    ///
+    /// The offset must not be larger than the raw address, which is only checked in debug builds,
+    /// like [`offset_by`](Addr::offset_by). Use [`checked_with_offset`](Addr::checked_with_offset)
+    /// where the offset isn't known to be in range.
+    pub fn with_offset(raw: u16, offset: u16) -> Self {
+        Addr::from(raw).offset_by(offset)
+    }
+
+    /// Constructs an address with the given raw address, relative to a device which starts at
+    /// `offset`, or returns `None` if the offset is larger than the raw address.
+    pub fn checked_with_offset(raw: u16, offset: u16) -> Option<Self> {
+        Addr::from(raw).checked_offset_by(offset)
+    }
 }
 
 impl fmt::Display for Addr {
@@ -73,6 +173,21 @@ pub trait MemDevice {
 
     /// Write the byte at the sepcified address.
     fn write(&mut self, addr: Addr, data: u8);
+
+    /// Size of the device's address space: the device accepts relative addresses in
+    /// `0..size()`. The default of `usize::MAX` means the size is unknown or unbounded.
+    fn size(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Reads every address of the device, in order. Since addresses are 16 bits, at most the
+    /// first 0x10000 bytes are read, which is also what's read from devices with unknown size.
+    fn read_all(&self) -> Vec<u8> {
+        let len = self.size().min(0x10000);
+        (0..len)
+            .map(|addr| self.read(Addr::from(addr as u16)))
+            .collect()
+    }
 }
 
 /// Wraps a memory device to make it read-only.
@@ -93,6 +208,10 @@ impl<M> ReadOnly<M> {
 }
 
 impl<M: MemDevice> MemDevice for ReadOnly<M> {
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         self.0.read(addr)
     }
@@ -103,11 +222,89 @@ impl<M: MemDevice> MemDevice for ReadOnly<M> {
     }
 }
 
+/// Wraps a memory device to make part of it read-only, such as a ram with a header that
+/// shouldn't change.
+///
+/// Reads always go to the inner device. Writes to relative addresses in the protected range,
+/// given as an inclusive `(start, end)` pair, are dropped, or panic if
+/// [`panic_on_write`](PartialReadOnly::panic_on_write) is set, which helps find the code making
+/// the write in tests. Writes outside the range go to the inner device.
+#[derive(Copy, Clone, Debug)]
+pub struct PartialReadOnly<M> {
+    /// The wrapped device.
+    mem: M,
+    /// Start and end of the protected range, inclusive.
+    range: (u16, u16),
+    /// Whether writes to the protected range panic instead of being dropped.
+    panic_on_write: bool,
+}
+
+impl<M> PartialReadOnly<M> {
+    /// Constructs a device which wraps the given memory, protecting the relative addresses from
+    /// `range.0` to `range.1` inclusive. Writes to them are dropped.
+    pub fn new(mem: M, range: (u16, u16)) -> Self {
+        Self {
+            mem,
+            range,
+            panic_on_write: false,
+        }
+    }
+
+    /// Sets whether writes to the protected range panic instead of being dropped.
+    pub fn panic_on_write(mut self, panic_on_write: bool) -> Self {
+        self.panic_on_write = panic_on_write;
+        self
+    }
+
+    /// Gets the start and end of the protected range, inclusive.
+    pub fn range(&self) -> (u16, u16) {
+        self.range
+    }
+
+    /// Gets the inner memory device.
+    pub fn inner(&self) -> &M {
+        &self.mem
+    }
+
+    /// Unwraps the inner memory device and returns it. This allows writes to the whole device
+    /// again.
+    pub fn into_inner(self) -> M {
+        self.mem
+    }
+}
+
+impl<M: MemDevice> MemDevice for PartialReadOnly<M> {
+    fn size(&self) -> usize {
+        self.mem.size()
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        self.mem.read(addr)
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        let (start, end) = self.range;
+        if (start..=end).contains(&addr.relative()) {
+            if self.panic_on_write {
+                panic!("Write of {:#04X} to read-only address {}", value, addr);
+            }
+            // Read the address to allow the wrapped device to validate the address range.
+            self.mem.read(addr);
+        } else {
+            self.mem.write(addr, value);
+        }
+    }
+}
+
 /// A rom which does bounds checks, but contains no actual memory (always returns 0, ignores
 /// writes).
 pub struct NullRom<const N: usize>;
 
 impl<const N: usize> MemDevice for NullRom<N> {
+    fn size(&self) -> usize {
+        N
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         assert!(
             addr.index() < N,
@@ -128,6 +325,44 @@ impl<const N: usize> MemDevice for NullRom<N> {
     }
 }
 
+/// Two memory devices placed one after the other. Relative addresses below the split (the third
+/// field) go to the first device, and the rest go to the second device, offset by the split.
+///
+/// This is a lightweight way to build a simple memory map, such as a rom followed by a ram,
+/// without a full MMU.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Concat<A, B>(pub A, pub B, pub u16);
+
+impl<A, B> Concat<A, B> {
+    /// Constructs a device which maps relative addresses below `split` to `first` and the rest
+    /// to `second`.
+    pub fn new(first: A, second: B, split: u16) -> Self {
+        Self(first, second, split)
+    }
+}
+
+impl<A: MemDevice, B: MemDevice> MemDevice for Concat<A, B> {
+    fn size(&self) -> usize {
+        (self.2 as usize).saturating_add(self.1.size())
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        if addr.relative() < self.2 {
+            self.0.read(addr)
+        } else {
+            self.1.read(addr.offset_by(self.2))
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        if addr.relative() < self.2 {
+            self.0.write(addr, value)
+        } else {
+            self.1.write(addr.offset_by(self.2), value)
+        }
+    }
+}
+
 /// Rom for the bios, which is swapped out once started.
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug)]
@@ -146,6 +381,12 @@ impl BiosRom {
     pub fn try_from_slice(data: &[u8]) -> Result<Self, BiosSizeError> {
         Self::try_from(data)
     }
+
+    /// Constructs a `BiosRom` from a string of exactly 512 hex digits. Whitespace is ignored, so
+    /// the digits can be grouped however is most readable. Mostly useful for test fixtures.
+    pub fn from_hex(hex: &str) -> Result<Self, BiosLoadError> {
+        Ok(Self::try_from_slice(&hex::parse_hex(hex)?)?)
+    }
 }
 
 impl Default for BiosRom {
@@ -155,6 +396,10 @@ impl Default for BiosRom {
 }
 
 impl MemDevice for BiosRom {
+    fn size(&self) -> usize {
+        0x100
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         self.0.read(addr)
     }
@@ -170,6 +415,46 @@ impl MemDevice for BiosRom {
 #[error("Expected exactly 256 bytes, got {0}")]
 pub struct BiosSizeError(pub usize);
 
+/// Error when loading a [`BiosRom`] from a hex string.
+#[derive(Clone, Debug, Error)]
+pub enum BiosLoadError {
+    /// The string was not valid hex.
+    #[error("Invalid bios hex: {0}")]
+    Hex(#[from] HexParseError),
+    /// The string was valid hex but the wrong size for a bios.
+    #[error("Wrong bios size: {0}")]
+    Size(#[from] BiosSizeError),
+}
+
+/// Error from a fallible memory access.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Error)]
+pub enum MemError {
+    /// A device which expects system addresses received an address which had already been
+    /// offset.
+    #[error("Expected a system address, but got {raw:#06X} offset to {relative:#06X}")]
+    UnexpectedOffset {
+        /// The raw address.
+        raw: u16,
+        /// The relative address.
+        relative: u16,
+    },
+}
+
+/// Error when loading a bios or cartridge into a [`GbMmu`].
+#[derive(Debug, Error)]
+pub enum MmuLoadError {
+    /// The bios could not be loaded.
+    #[error("Unable to load bios: {0}")]
+    Bios(#[from] BiosSizeError),
+    /// The cartridge could not be loaded.
+    #[error("Unable to load cartridge: {0}")]
+    Cartridge(#[from] ParseCartridgeError),
+    /// The cartridge only runs on a GameBoy Color, but CGB features aren't available on the
+    /// hardware model being emulated.
+    #[error("Cartridge requires a GameBoy Color, but the hardware model is {0:?}")]
+    RequiresCgb(HardwareModel),
+}
+
 impl TryFrom<&[u8]> for BiosRom {
     type Error = BiosSizeError;
 
@@ -185,6 +470,10 @@ impl TryFrom<&[u8]> for BiosRom {
 }
 
 impl<const N: usize> MemDevice for [u8; N] {
+    fn size(&self) -> usize {
+        N
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         match self.get(addr.index()) {
             Some(val) => *val,
@@ -200,9 +489,102 @@ impl<const N: usize> MemDevice for [u8; N] {
     }
 }
 
+/// Callback invoked on an out-of-range access to a [`CheckedArray`]. Receives the address and,
+/// for writes, the value that was dropped. Reads pass `None`.
+pub type OutOfRangeHook = fn(addr: Addr, write: Option<u8>);
+
+/// Memory array which doesn't panic on out-of-range accesses. Out-of-range reads return a
+/// configurable value, and out-of-range writes are dropped. Either way, the access is passed to
+/// the [`OutOfRangeHook`] if one is set, or logged as a warning otherwise.
+///
+/// This is the non-fatal alternative to the `[u8; N]` `MemDevice` impl, which panics.
+#[derive(Clone, Debug)]
+pub struct CheckedArray<const N: usize> {
+    data: [u8; N],
+    /// Value returned from out-of-range reads.
+    out_of_range_value: u8,
+    /// Hook called for out-of-range accesses.
+    hook: Option<OutOfRangeHook>,
+}
+
+impl<const N: usize> CheckedArray<N> {
+    /// Wraps the given array. Out-of-range reads return 0xFF and out-of-range accesses are
+    /// logged.
+    pub fn new(data: [u8; N]) -> Self {
+        CheckedArray {
+            data,
+            out_of_range_value: 0xff,
+            hook: None,
+        }
+    }
+
+    /// Constructs a `CheckedArray` from a string of exactly `2 * N` hex digits. Whitespace is
+    /// ignored. Mostly useful for test fixtures.
+    pub fn from_hex(hex: &str) -> Result<Self, HexParseError> {
+        Ok(Self::new(hex::parse_hex_array(hex)?))
+    }
+
+    /// Sets the value returned from out-of-range reads.
+    pub fn with_out_of_range_value(mut self, value: u8) -> Self {
+        self.out_of_range_value = value;
+        self
+    }
+
+    /// Sets a hook to call on out-of-range accesses, instead of logging them.
+    pub fn with_hook(mut self, hook: OutOfRangeHook) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Gets the underlying array.
+    pub fn data(&self) -> &[u8; N] {
+        &self.data
+    }
+
+    /// Gets a mutable reference to the underlying array.
+    pub fn data_mut(&mut self) -> &mut [u8; N] {
+        &mut self.data
+    }
+
+    /// Reports an out-of-range access.
+    fn out_of_range(&self, addr: Addr, write: Option<u8>) {
+        match self.hook {
+            Some(hook) => hook(addr, write),
+            None => warn!("Address {} out of range for {} byte checked array", addr, N),
+        }
+    }
+}
+
+impl<const N: usize> MemDevice for CheckedArray<N> {
+    fn size(&self) -> usize {
+        N
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match self.data.get(addr.index()) {
+            Some(val) => *val,
+            None => {
+                self.out_of_range(addr, None);
+                self.out_of_range_value
+            }
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match self.data.get_mut(addr.index()) {
+            Some(val) => *val = value,
+            None => self.out_of_range(addr, Some(value)),
+        }
+    }
+}
+
 // This makes sure that Box<dyn MemDevice> implements MemDevice (as well as Box<Anything that
 // implements MemDevice>).
 impl<D: MemDevice + ?Sized> MemDevice for Box<D> {
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         (**self).read(addr)
     }
@@ -212,158 +594,4416 @@ impl<D: MemDevice + ?Sized> MemDevice for Box<D> {
     }
 }
 
-/// Memory device connecting memory mapped IO.
-#[derive(Clone, Debug)]
-pub struct MemMappedIo {
-    serial_data: u8,
-    serial_control: u8,
-    bios_enabled: bool,
-}
+/// High ram ("Page Zero"), the 127 bytes at 0xFF80..0xFFFF.
+///
+/// High ram is its own type because it is special: it is the only memory the CPU can use while
+/// OAM DMA is running, so it's where games put their DMA routine, and it's often used for time
+/// critical code and the stack.
+#[derive(Copy, Clone, Debug)]
+pub struct HighRam([u8; 127]);
 
-impl MemMappedIo {
-    /// Construct new memory-mapped IO manager.
+impl HighRam {
+    /// Construct a new `HighRam` with all memory set to 0.
     pub fn new() -> Self {
-        MemMappedIo {
-            serial_data: 0x00,
-            serial_control: 0x00,
-            bios_enabled: true,
+        HighRam([0; 127])
+    }
+
+    /// Gets the contents of high ram. Index 0 is address 0xFF80.
+    pub fn data(&self) -> &[u8; 127] {
+        &self.0
+    }
+}
+
+impl Default for HighRam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemDevice for HighRam {
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match self.0.get(addr.index()) {
+            Some(val) => *val,
+            None => panic!("Address {} out of range for high ram", addr),
         }
     }
 
-    /// Returns true if bios is enabled.
-    pub fn bios_enabled(&self) -> bool {
-        self.bios_enabled
+    fn write(&mut self, addr: Addr, value: u8) {
+        match self.0.get_mut(addr.index()) {
+            Some(val) => *val = value,
+            None => panic!("Address {} out of range for high ram", addr),
+        }
     }
 }
 
-impl Default for MemMappedIo {
+/// The bios disable register at 0xFF50. Writing a value with bit 0 set unmaps the bios, which
+/// stays unmapped until reset.
+///
+/// The register always reads 0xFF, so software can't detect whether the bios is still mapped by
+/// reading it. Use [`enabled`](BootRomControl::enabled) or
+/// [`MemMappedIo::bios_enabled`] to get the latch state.
+#[derive(Copy, Clone, Debug)]
+pub struct BootRomControl {
+    enabled: bool,
+}
+
+impl BootRomControl {
+    /// Constructs a new `BootRomControl` with the bios mapped.
+    pub fn new() -> Self {
+        BootRomControl { enabled: true }
+    }
+
+    /// Returns true if the bios is still mapped.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+impl Default for BootRomControl {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl MemDevice for MemMappedIo {
+impl MemDevice for BootRomControl {
+    fn size(&self) -> usize {
+        1
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         match addr.relative() {
-            0x00 => 0xff,
-            0x01 => self.serial_data,
-            0x02 => self.serial_control,
-            0x03..=0x4f => 0xff,
-            0x50 => self.bios_enabled as u8,
-            0x51..=0x7f => 0xff,
-            _ => panic!("Address {} out of range for Mem Mapped IO", addr),
+            0 => 0xff,
+            _ => panic!("Address {} out of range for BootRomControl", addr),
         }
     }
 
     fn write(&mut self, addr: Addr, value: u8) {
         match addr.relative() {
-            0x00..=0x4f => {}
-            0x50 => {
+            0 => {
                 if value & 1 != 0 {
-                    self.bios_enabled = false;
+                    self.enabled = false;
                 }
             }
-            0x51..=0x7f => {}
-            _ => panic!("Address {} out of range for Mem Mapped IO", addr),
+            _ => panic!("Address {} out of range for BootRomControl", addr),
         }
     }
 }
 
-/// MemoryDevice which configures the standard memory mapping of the real GameBoy.
-#[derive(Clone, Debug)]
-pub struct GbMmu {
-    /// The bios. Mapped to 0..0x100 while bios is enabled.
-    bios: BiosRom,
-    /// The inserted cartridge. Mapped to 0..0x8000 (rom) and 0xA000..0xC000 (ram).
-    cart: Cartridge,
-    /// Video Ram. Mapped to 0x8000..0xA000
-    vram: [u8; 0x2000],
-    /// Working Ram. Mapped to 0xC000..0xE000 and duplicately mapped at 0xE000..0xFE00.
-    wram: [u8; 0x2000],
-    /// Spirte info. Mapped to 0xFE00..0xFEA0.
-    oam: [u8; 160],
-    /// Memory mapped IO. Mapped to 0xff00..FF80.
-    io: MemMappedIo,
-    /// "Page Zero", memory primarily used for software-hardware interaction. Mapped to
-    /// 0xFF80..0xffff
-    zram: [u8; 127],
-    /// Interrupt enable register. Mapped to 0xffff
-    interrupt_enable: InterruptEnable,
+impl Component for BootRomControl {
+    fn box_clone(&self) -> Box<dyn Component> {
+        Box::new(*self)
+    }
 }
 
-impl GbMmu {
-    /// Construct a new MMU with the given bios and cartridge.
-    /// Panics if the given bios data is not exactly 256 bytes.
-    pub fn new(bios: BiosRom, cart: Cartridge) -> GbMmu {
-        GbMmu {
-            bios,
-            cart,
-            vram: [0; 0x2000],
-            wram: [0; 0x2000],
-            oam: [0; 160],
-            io: MemMappedIo::new(),
-            zram: [0; 127],
-            interrupt_enable: InterruptEnable(InterruptFlags::empty()),
-        }
+impl IoDevice for BootRomControl {
+    fn registers(&self) -> RangeInclusive<u8> {
+        0x50..=0x50
+    }
+
+    fn box_clone_io(&self) -> Box<dyn IoDevice> {
+        Box::new(*self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
 
-impl Default for GbMmu {
-    fn default() -> Self {
-        Self::new(Default::default(), Cartridge::None)
+/// Memory device connecting memory mapped IO.
+///
+/// Every address in the block is described by a table entry, which gives the models the
+/// register exists on and the bits which always read 1. Registers which are just storage are
+/// kept here, and the rest are handed to the device that owns them.
+///
+/// Devices can also be registered at runtime with [`register_device`](MemMappedIo::register_device)
+/// to take over a range of registers. Registered devices take precedence over the table. The bios
+/// disable register is the first such device, a [`BootRomControl`].
+#[derive(Clone, Debug)]
+pub struct MemMappedIo {
+    /// Joypad register (P1). Mapped to 0xFF00.
+    joypad: Joypad,
+    /// Serial port (SB and SC). Mapped to 0xFF01..0xFF03.
+    serial: Serial,
+    /// Timer component. Mapped to 0xFF04..0xFF08.
+    timer: Box<dyn Component>,
+    /// Audio component. Mapped to 0xFF10..0xFF40.
+    apu: Box<dyn Component>,
+    /// LCD registers. Mapped to 0xFF40..0xFF46 and 0xFF47..0xFF4C.
+    lcd: Lcd,
+    /// Last value written to the OAM DMA register. Mapped to 0xFF46.
+    oam_dma: u8,
+    /// Source page of an OAM DMA transfer which has been started but not yet run by the
+    /// [`GbMmu`].
+    pending_dma: Option<u8>,
+    /// Registers which are plain storage, such as KEY1 (0xFF4D).
+    storage: [u8; ioregs::NUM_STORED],
+    /// VRAM DMA registers (HDMA1..HDMA5). Mapped to 0xFF51..0xFF56. CGB only.
+    hdma: Hdma,
+    /// Infrared port (RP). Mapped to 0xFF56. CGB only.
+    infrared: InfraredPort,
+    /// Background palette ram (BCPS and BCPD). Mapped to 0xFF68..0xFF6A. CGB only.
+    bg_palettes: PaletteRam,
+    /// Sprite palette ram (OCPS and OCPD). Mapped to 0xFF6A..0xFF6C. CGB only.
+    obj_palettes: PaletteRam,
+    /// Registered devices, each mapped to the range of registers it reports.
+    devices: Vec<Box<dyn IoDevice>>,
+    /// The hardware model, which determines which registers are available.
+    model: HardwareModel,
+    /// Interrupt controller. IF is mapped to 0xFF0F here, and IE is mapped to 0xFFFF by the
+    /// [`GbMmu`].
+    interrupts: InterruptController,
+    /// Callback run after every write to the IO block, if set.
+    write_observer: Option<WriteObserver>,
+    /// Whether a machine cycle is left over from ticking in double speed mode, to be passed on
+    /// with the next tick to components which run at normal speed.
+    half_cycle: bool,
+    /// Whether the CPU is blocked from memory the PPU is using, such as palette ram while
+    /// drawing. Turned off by [`GbMmu::unrestricted`].
+    restrictions: bool,
+}
+
+/// Callback run after writes to [`MemMappedIo`], with the register index and the value written.
+/// Clones of the IO device share the same callback.
+#[derive(Clone)]
+struct WriteObserver(Rc<RefCell<ObserverFn>>);
+
+/// Type of the callback held by a [`WriteObserver`].
+type ObserverFn = Box<dyn FnMut(u8, u8)>;
+
+impl fmt::Debug for WriteObserver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("WriteObserver")
     }
 }
 
-impl MemDevice for GbMmu {
-    fn read(&self, addr: Addr) -> u8 {
-        assert!(
-            addr.relative() == addr.raw(),
-            "Using Root MMU with offset address {}",
-            addr
-        );
-        // Address guaranteed to be in range since we cover the whole memory space.
-        match addr.relative() {
-            0x0..=0xff if self.io.bios_enabled() => self.bios.read(addr),
-            0x0..=0x7fff => self.cart.read(addr),
-            0x8000..=0x9fff => self.vram.read(addr.offset_by(0x8000)),
-            // Cartridge ram starts right after cartridge Rom, so the offset used here is the
-            // size of vram, since we only want to shift the address by the ammount we skipped in
-            // order to splice in the vram.
-            0xa000..=0xbfff => self.cart.read(addr.offset_by(0x2000)),
-            0xc000..=0xdfff => self.wram.read(addr.offset_by(0xc000)),
-            0xe000..=0xfdff => self.wram.read(addr.offset_by(0xe000)),
-            0xfe00..=0xfe9f => self.oam.read(addr.offset_by(0xfe00)),
-            // Unmapped portion above sprite information, always returns 0.
-            0xfea0..=0xfeff => 0,
-            0xff00..=0xff7f => self.io.read(addr.offset_by(0xff00)),
-            0xff80..=0xfffe => self.zram.read(addr.offset_by(0xff80)),
-            0xffff => self.interrupt_enable.read(addr.offset_by(0xffff)),
+impl MemMappedIo {
+    /// Construct new memory-mapped IO manager.
+    pub fn new() -> Self {
+        MemMappedIo {
+            joypad: Joypad::new(),
+            serial: Serial::default(),
+            timer: Box::new(Timer::new()),
+            apu: Box::new(Apu::new()),
+            lcd: Lcd::new(),
+            oam_dma: 0x00,
+            pending_dma: None,
+            storage: [0; ioregs::NUM_STORED],
+            hdma: Hdma::new(),
+            infrared: InfraredPort::new(),
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            devices: vec![Box::new(BootRomControl::new())],
+            model: HardwareModel::Dmg,
+            interrupts: InterruptController::new(),
+            write_observer: None,
+            half_cycle: false,
+            restrictions: true,
         }
     }
 
-    fn write(&mut self, addr: Addr, value: u8) {
+    /// Returns true if bios is enabled.
+    pub fn bios_enabled(&self) -> bool {
+        self.device::<BootRomControl>()
+            .is_some_and(BootRomControl::enabled)
+    }
+
+    /// Registers a device to handle the registers it reports with [`IoDevice::registers`]. It
+    /// takes precedence over any built-in register in its range. Panics if the range is outside
+    /// of the IO block or overlaps a device which is already registered.
+    pub fn register_device(&mut self, mut device: Box<dyn IoDevice>) {
+        let registers = device.registers();
         assert!(
-            addr.relative() == addr.raw(),
-            "Using Root MMU with offset address {}",
-            addr
+            *registers.end() < 0x80,
+            "Registers {:#04X}..={:#04X} are outside of the IO block",
+            registers.start(),
+            registers.end()
         );
-        // Address guaranteed to be in range since we cover the whole memory space.
-        match addr.relative() {
-            0x0..=0xff if self.io.bios_enabled() => self.bios.write(addr, value),
-            0x0..=0x7fff => self.cart.write(addr, value),
-            0x8000..=0x9fff => self.vram.write(addr.offset_by(0x8000), value),
-            // Cartridge ram starts right after cartridge Rom, so the offset used here is the
-            // size of vram, since we only want to shift the address by the ammount we skipped in
-            // order to splice in the vram.
-            0xa000..=0xbfff => self.cart.write(addr.offset_by(0x2000), value),
-            0xc000..=0xdfff => self.wram.write(addr.offset_by(0xc000), value),
-            0xe000..=0xfdff => self.wram.write(addr.offset_by(0xe000), value),
-            0xfe00..=0xfe9f => self.oam.write(addr.offset_by(0xfe00), value),
-            // Unmapped portion above sprite information.
-            0xfea0..=0xfeff => {}
-            0xff00..=0xff7f => self.io.write(addr.offset_by(0xff00), value),
-            0xff80..=0xfffe => self.zram.write(addr.offset_by(0xff80), value),
-            0xffff => self.interrupt_enable.write(addr.offset_by(0xffff), value),
+        for other in self.devices.iter() {
+            let other = other.registers();
+            assert!(
+                registers.end() < other.start() || other.end() < registers.start(),
+                "Registers {:#04X}..={:#04X} overlap a device at {:#04X}..={:#04X}",
+                registers.start(),
+                registers.end(),
+                other.start(),
+                other.end()
+            );
+        }
+        device.set_model(self.model);
+        self.devices.push(device);
+    }
+
+    /// Gets the registered device of the given type, if there is one.
+    pub fn device<T: IoDevice + 'static>(&self) -> Option<&T> {
+        self.devices
+            .iter()
+            .find_map(|device| device.as_any().downcast_ref())
+    }
+
+    /// Gets a mutable reference to the registered device of the given type, if there is one.
+    pub fn device_mut<T: IoDevice + 'static>(&mut self) -> Option<&mut T> {
+        self.devices
+            .iter_mut()
+            .find_map(|device| device.as_any_mut().downcast_mut())
+    }
+
+    /// Gets the hardware model.
+    pub fn model(&self) -> HardwareModel {
+        self.model
+    }
+
+    /// Gets the compatibility mode selected in KEY0 (0xFF4C), which decides whether the PPU and
+    /// palettes of a CGB behave like a DMG. Always [`CompatMode::DmgOnCgb`] on models which
+    /// aren't CGB hardware.
+    pub fn compat_mode(&self) -> CompatMode {
+        if self.model.is_cgb_hardware() && self.storage[ioregs::KEY0] & 0x0c == 0 {
+            CompatMode::Cgb
+        } else {
+            CompatMode::DmgOnCgb
+        }
+    }
+
+    /// Gets the sprite priority mode selected in OPRI (0xFF6C), which the renderer uses to decide
+    /// which of several overlapping sprites is drawn on top. Always
+    /// [`ObjPriorityMode::Coordinate`] on models which aren't CGB hardware.
+    pub fn obj_priority_mode(&self) -> ObjPriorityMode {
+        if self.model.is_cgb_hardware() && self.storage[ioregs::OPRI] & 0x01 == 0 {
+            ObjPriorityMode::OamOrder
+        } else {
+            ObjPriorityMode::Coordinate
+        }
+    }
+
+    /// Gets the sprite priority mode selected in OPRI, for
+    /// [`Oam::sprites_on_line_with_priority`]. This is the same as
+    /// [`obj_priority_mode`](MemMappedIo::obj_priority_mode).
+    pub fn object_priority_mode(&self) -> ObjectPriority {
+        self.obj_priority_mode()
+    }
+
+    /// Sets the hardware model, and tells the timer, APU, and registered devices about it.
+    fn set_model(&mut self, model: HardwareModel) {
+        self.model = model;
+        self.timer.set_model(model);
+        self.apu.set_model(model);
+        for device in self.devices.iter_mut() {
+            device.set_model(model);
+        }
+    }
+
+    /// Sets KEY0 and OPRI to the values the CGB boot rom would select for the model, which
+    /// depend on more than whether the model is CGB hardware, so they aren't in the register
+    /// table.
+    fn select_compat_mode(&mut self) {
+        let compat_mode = self.model.power_on_compat_mode();
+        self.storage[ioregs::KEY0] = compat_mode.key0();
+        self.storage[ioregs::OPRI] = match compat_mode {
+            CompatMode::Cgb => 0x00,
+            CompatMode::DmgOnCgb => 0x01,
+        };
+    }
+
+    /// Takes a snapshot of every named register which exists on the current model, with the
+    /// values the CPU would read.
+    pub fn snapshot(&self) -> IoSnapshot {
+        let registers = self
+            .named_registers()
+            .map(|(addr, name, value)| IoRegisterValue {
+                addr,
+                name,
+                value,
+                decoded: iosnapshot::decode(addr, value),
+            })
+            .collect();
+        IoSnapshot::new(registers)
+    }
+
+    /// Lists the name, system address, and value of every named register which exists on the
+    /// current model, in address order. Values are what the CPU would read, so write-only bits
+    /// read as 1. This is a quick dump for debugging; [`snapshot`](MemMappedIo::snapshot) also
+    /// describes the fields of each register.
+    pub fn named_dump(&self) -> Vec<(&'static str, u16, u8)> {
+        self.named_registers()
+            .map(|(addr, name, value)| (name, IO_START + addr as u16, value))
+            .collect()
+    }
+
+    /// Iterates the address relative to 0xFF00, name, and CPU-visible value of every named
+    /// register which exists on the current model.
+    fn named_registers(&self) -> impl Iterator<Item = (u8, &'static str, u8)> + '_ {
+        ioregs::IO_REGISTERS
+            .iter()
+            .enumerate()
+            .filter(move |(_, reg)| reg.is_used() && reg.availability.includes(self.model))
+            .map(move |(addr, reg)| {
+                let addr = addr as u8;
+                (addr, reg.name, self.read(Addr::from(addr as u16)))
+            })
+    }
+
+    /// Restores the registers in a snapshot by writing them the same way the CPU would. Bits
+    /// which are read-only or which have side effects when written, such as DIV, LY, the DMA
+    /// register, and the sound channel trigger bits, are not restored, and the bios can't be
+    /// turned back on.
+    ///
+    /// NR52 and TAC are written first, since the other audio registers ignore writes while the
+    /// APU is off and changing TAC can increment TIMA. IF is written last, so that interrupts
+    /// requested by the other writes don't remain.
+    pub fn apply(&mut self, snapshot: &IoSnapshot) {
+        let order = |reg: &&IoRegisterValue| match reg.addr {
+            0x07 | 0x26 => 0,
+            0x0f => 2,
+            _ => 1,
+        };
+        let mut registers: Vec<_> = snapshot.registers().iter().collect();
+        registers.sort_by_key(order);
+        for reg in registers {
+            let restore_mask = self.register_at(Addr::from(reg.addr as u16)).restore_mask;
+            if restore_mask != 0 {
+                self.write(Addr::from(reg.addr as u16), reg.value & restore_mask);
+            }
+        }
+    }
+
+    /// Loads the values the boot rom of the given model leaves in the IO registers, and disables
+    /// the bios, as if the boot rom had just finished. Used to start a game without running the
+    /// boot rom, in which case the CPU registers must also be set to their post-boot values.
+    ///
+    /// Registers are written the same way as by [`apply`](MemMappedIo::apply), so bits which are
+    /// read-only or have side effects aren't loaded. DIV keeps counting from wherever it was, and
+    /// the channel status bits of NR52 stay clear, since the boot sound isn't played. The DMA
+    /// register is loaded without starting a transfer, and the PPU mode is set to VBlank, where
+    /// the boot rom hands over.
+    pub fn apply_post_boot(&mut self, model: HardwareModel) {
+        self.set_model(model);
+        self.load_table_values(|reg| reg.post_boot.get(model));
+        self.select_compat_mode();
+        self.oam_dma = ioregs::IO_REGISTERS[0x46].post_boot.get(model);
+        // The boot rom leaves the STAT interrupt disabled, and IF is already loaded.
+        self.lcd.set_mode(LcdMode::VBlank);
+        if let Some(bios) = self.device_mut::<BootRomControl>() {
+            bios.write(Addr::from(0), 0x01);
+        }
+    }
+
+    /// Writes every register which exists on the current model with its value from the register
+    /// table, without notifying the write observer.
+    fn load_table_values(&mut self, value: impl Fn(&IoRegister) -> u8) {
+        let registers = ioregs::IO_REGISTERS
+            .iter()
+            .enumerate()
+            .filter(|(_, reg)| reg.is_used() && reg.availability.includes(self.model))
+            .map(|(addr, reg)| IoRegisterValue {
+                addr: addr as u8,
+                name: reg.name,
+                value: value(reg),
+                decoded: None,
+            })
+            .collect();
+        let observer = self.write_observer.take();
+        self.apply(&IoSnapshot::new(registers));
+        self.write_observer = observer;
+    }
+
+    /// Returns true if the CGB is running in double-speed mode. Components which count machine
+    /// cycles use this to scale their rates, since the CPU runs twice as fast relative to them.
+    /// Always false unless CGB features are available.
+    pub fn double_speed(&self) -> bool {
+        self.storage[ioregs::KEY1] & 0x80 != 0
+    }
+
+    /// Switches between normal and double speed if a switch was requested through KEY1
+    /// (0xFF4D), and returns whether the speed changed. The CPU calls this when it executes
+    /// STOP. The request is cleared by the switch.
+    pub fn perform_speed_switch(&mut self) -> bool {
+        if self.storage[ioregs::KEY1] & 0x01 != 0 && self.model.cgb_features() {
+            // Flips the speed and clears the request.
+            self.storage[ioregs::KEY1] ^= 0x81;
+            true
+        } else {
+            false
         }
     }
+
+    /// Gets the VRAM DMA registers and the progress of the current transfer.
+    pub fn hdma(&self) -> &Hdma {
+        &self.hdma
+    }
+
+    /// Gets the infrared port.
+    pub fn infrared(&self) -> &InfraredPort {
+        &self.infrared
+    }
+
+    /// Sets whether the infrared receiver currently sees light. To link two emulators, pass the
+    /// [`ir_output`](MemMappedIo::ir_output) of each to `set_ir_input` of the other.
+    pub fn set_ir_input(&mut self, seen: bool) {
+        self.infrared.set_input(seen);
+    }
+
+    /// Returns true if the infrared LED is on. Always false unless CGB features are available.
+    pub fn ir_output(&self) -> bool {
+        self.infrared.output()
+    }
+
+    /// Gets the joypad.
+    pub fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    /// Enables or disables decoding Super Game Boy command packets sent through the joypad
+    /// register.
+    pub fn set_sgb_packets(&mut self, enabled: bool) {
+        self.joypad.set_sgb_packets(enabled);
+    }
+
+    /// Returns and clears the Super Game Boy packets the game has sent since the last call,
+    /// oldest first. Always empty unless decoding is enabled.
+    pub fn take_sgb_packets(&mut self) -> Vec<SgbPacket> {
+        self.joypad.take_sgb_packets()
+    }
+
+    /// Sets the buttons currently held down. Frontends call this whenever input changes, such as
+    /// once per frame. Pressing a button in the column the game has selected requests a joypad
+    /// interrupt.
+    pub fn set_buttons(&mut self, buttons: ButtonState) {
+        if self.joypad.set_buttons(buttons) {
+            self.request_interrupt(InterruptFlags::JOYPAD);
+        }
+    }
+
+    /// Gets the LCD registers.
+    pub fn lcd(&self) -> &Lcd {
+        &self.lcd
+    }
+
+    /// Gets the background palette ram, accessed through BCPS and BCPD (0xFF68..0xFF6A).
+    pub fn bg_palette_ram(&self) -> &PaletteRam {
+        &self.bg_palettes
+    }
+
+    /// Gets the sprite palette ram, accessed through OCPS and OCPD (0xFF6A..0xFF6C).
+    pub fn obj_palette_ram(&self) -> &PaletteRam {
+        &self.obj_palettes
+    }
+
+    /// Gets the colors of the CGB background palette with the given number, which must be 0-7.
+    pub fn bg_palette(&self, n: usize) -> [Rgb555; 4] {
+        self.bg_palettes.palette(n)
+    }
+
+    /// Gets the colors of the CGB sprite palette with the given number, which must be 0-7. Color
+    /// 0 is transparent, so it is unused.
+    pub fn obj_palette(&self, n: usize) -> [Rgb555; 4] {
+        self.obj_palettes.palette(n)
+    }
+
+    /// Returns true if the CPU can't access palette ram because the PPU is using it to draw,
+    /// based on the mode reported with [`set_lcd_mode`](MemMappedIo::set_lcd_mode). Never true
+    /// while access restrictions are turned off.
+    pub fn palette_access_blocked(&self) -> bool {
+        self.restrictions && self.lcd.mode() == LcdMode::Drawing
+    }
+
+    /// Returns true if the CPU is blocked from memory the PPU is using, as on the hardware.
+    pub fn access_restrictions(&self) -> bool {
+        self.restrictions
+    }
+
+    /// Reads a register like [`read`](MemDevice::read), but ignoring access restrictions, for
+    /// [`GbMmu::peek`].
+    pub fn peek(&self, addr: Addr) -> u8 {
+        self.read_register(addr, false)
+    }
+
+    /// Gets the current LCD line (LY).
+    pub fn ly(&self) -> u8 {
+        self.lcd.ly()
+    }
+
+    /// Sets the current LCD line (LY). This is how the PPU reports line changes, since LY is
+    /// read-only to the CPU. Updates the LY == LYC coincidence. Before there is a PPU, the
+    /// embedding loop can call this to move games past loops which wait for a particular line.
+    pub fn set_ly(&mut self, ly: u8) {
+        if self.lcd.set_ly(ly) {
+            self.request_interrupt(InterruptFlags::STAT);
+        }
+    }
+
+    /// Sets the PPU mode reported in STAT. Requests a STAT interrupt if the mode's interrupt is
+    /// enabled and no other STAT source was already active. Entering HBlank makes the next
+    /// block of an HBlank VRAM DMA transfer due, which the [`GbMmu`] copies on its next tick.
+    pub fn set_lcd_mode(&mut self, mode: LcdMode) {
+        if mode == LcdMode::HBlank && self.lcd.mode() != LcdMode::HBlank {
+            self.hdma.enter_hblank();
+        }
+        if self.lcd.set_mode(mode) {
+            self.request_interrupt(InterruptFlags::STAT);
+        }
+    }
+
+    /// Sets whether CPU writes to LY reset it to 0 rather than being ignored.
+    pub fn set_ly_write_resets(&mut self, resets: bool) {
+        self.lcd.set_ly_write_resets(resets);
+    }
+
+    /// Gets the LCD line compare value (LYC).
+    pub fn lyc(&self) -> u8 {
+        self.lcd.lyc()
+    }
+
+    /// Returns true if LY currently equals LYC, as reported in bit 2 of STAT.
+    pub fn lyc_coincidence(&self) -> bool {
+        self.lcd.lyc_coincidence()
+    }
+
+    /// Sets a callback to run after every write to the IO block (0xFF00..0xFF80), replacing any
+    /// previous callback. It receives the index of the register relative to 0xFF00 and the value
+    /// written. This lets a component kept outside of the memory map, such as a separate PPU or
+    /// APU model, react to register changes without polling.
+    pub fn set_write_observer(&mut self, f: Box<dyn FnMut(u8, u8)>) {
+        self.write_observer = Some(WriteObserver(Rc::new(RefCell::new(f))));
+    }
+
+    /// Removes the write observer, if any.
+    pub fn clear_write_observer(&mut self) {
+        self.write_observer = None;
+    }
+
+    /// Gets the interrupt controller.
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    /// Gets a mutable reference to the interrupt controller.
+    pub fn interrupts_mut(&mut self) -> &mut InterruptController {
+        &mut self.interrupts
+    }
+
+    /// Gets the interrupt flags (IF).
+    pub fn interrupt_flags(&self) -> InterruptFlags {
+        self.interrupts.if_()
+    }
+
+    /// Sets the interrupt flags (IF), for example to clear an interrupt once it is serviced.
+    pub fn set_interrupt_flags(&mut self, flags: InterruptFlags) {
+        self.interrupts.set_if(flags);
+    }
+
+    /// Requests the given interrupts by setting them in IF.
+    pub fn request_interrupt(&mut self, flags: InterruptFlags) {
+        self.interrupts.request(flags);
+    }
+
+    /// Gets the interrupts which are both requested in IF and enabled in the given value of IE.
+    pub fn active_interrupts(&self, ie: u8) -> InterruptFlags {
+        self.interrupts.if_() & InterruptFlags::from_bits_truncate(ie)
+    }
+
+    /// Returns and clears all of the interrupts currently requested in IF.
+    pub fn take_requested_interrupts(&mut self) -> InterruptFlags {
+        let flags = self.interrupts.if_();
+        self.interrupts.set_if(InterruptFlags::empty());
+        flags
+    }
+
+    /// Advances the IO devices by the given number of machine cycles. Interrupts they request
+    /// are set in IF. A large number of cycles has the same effect as ticking one at a time.
+    ///
+    /// The cycles are those of the CPU. In double speed mode, the timer, serial port, and
+    /// registered devices run along with the CPU, but the APU keeps running at normal speed, so
+    /// it is ticked for half as many cycles. The APU frame sequencer is clocked by the DIV-APU
+    /// events from the timer.
+    pub fn tick(&mut self, m_cycles: u32) {
+        self.tick_devices(m_cycles);
+    }
+
+    /// Ticks the IO devices, as for [`tick`](MemMappedIo::tick). Returns the number of cycles
+    /// the components which run at normal speed were ticked for.
+    fn tick_devices(&mut self, m_cycles: u32) -> u32 {
+        let normal_cycles = self.normal_speed_cycles(m_cycles);
+        self.timer.set_double_speed(self.double_speed());
+        self.timer.tick(m_cycles);
+        let timer_interrupts = self.timer.take_requested_interrupts();
+        self.request_interrupt(timer_interrupts);
+        // Events from DIV writes since the last tick are delivered along with those from this
+        // tick, before the APU runs.
+        let div_apu_events = self.timer.take_div_apu_events();
+        self.apu.clock_div_apu(div_apu_events);
+        if self.serial.tick(m_cycles) {
+            self.request_interrupt(InterruptFlags::SERIAL);
+        }
+        self.apu.tick(normal_cycles);
+        let apu_interrupts = self.apu.take_requested_interrupts();
+        self.request_interrupt(apu_interrupts);
+        for device in self.devices.iter_mut() {
+            device.tick(m_cycles);
+            self.interrupts.request(device.take_requested_interrupts());
+        }
+        normal_cycles
+    }
+
+    /// Converts a number of CPU machine cycles to cycles at normal speed. In double speed mode
+    /// this halves them, carrying an odd cycle over to the next call.
+    fn normal_speed_cycles(&mut self, m_cycles: u32) -> u32 {
+        if self.double_speed() {
+            let total = m_cycles + self.half_cycle as u32;
+            self.half_cycle = total & 1 != 0;
+            total / 2
+        } else {
+            m_cycles
+        }
+    }
+
+    /// Gets the serial port.
+    pub fn serial(&self) -> &Serial {
+        &self.serial
+    }
+
+    /// Shifts in one bit supplied by a link partner providing the external clock, as
+    /// [`Serial::push_external_bit`] does, and requests a serial interrupt if that completed the
+    /// transfer. The received byte is in SB by the time the interrupt is requested.
+    pub fn push_serial_bit(&mut self, bit: bool) {
+        if self.serial.push_external_bit(bit) {
+            self.request_interrupt(InterruptFlags::SERIAL);
+        }
+    }
+
+    /// Gets a mutable reference to the serial port, for example to replace its backend.
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    /// Gets the timer component.
+    pub fn timer(&self) -> &dyn Component {
+        &*self.timer
+    }
+
+    /// Gets a mutable reference to the timer component.
+    pub fn timer_mut(&mut self) -> &mut dyn Component {
+        &mut *self.timer
+    }
+
+    /// Gets the audio component.
+    pub fn apu(&self) -> &dyn Component {
+        &*self.apu
+    }
+
+    /// Gets a mutable reference to the audio component.
+    pub fn apu_mut(&mut self) -> &mut dyn Component {
+        &mut *self.apu
+    }
+}
+
+impl MemMappedIo {
+    /// Returns all registers to their power-on values for the given model, with the bios
+    /// enabled. The values come from the register table, except for KEY0 and OPRI, which are
+    /// set from the model.
+    ///
+    /// Keeps the current components, registered devices other than the bios control, serial
+    /// backend and timing, write observer, LY write behavior, whether SGB packets are decoded,
+    /// whether access restrictions are on, and the buttons being held and infrared light being
+    /// seen.
+    pub fn reset(&mut self, model: HardwareModel) {
+        let timer = std::mem::replace(&mut self.timer, Box::new(NullTimer));
+        let apu = std::mem::replace(&mut self.apu, Box::new(NullApu));
+        let mut serial = Serial::new(self.serial.set_backend(Box::new(Disconnected)));
+        serial.set_timing(self.serial.timing());
+        let buttons = self.joypad.buttons();
+        let sgb_packets = self.joypad.sgb_packets_enabled();
+        let ly_write_resets = self.lcd.ly_write_resets();
+        let ir_input = self.infrared.input();
+        let devices = std::mem::take(&mut self.devices);
+        *self = MemMappedIo {
+            serial,
+            timer,
+            apu,
+            write_observer: self.write_observer.take(),
+            restrictions: self.restrictions,
+            ..MemMappedIo::new()
+        };
+        self.joypad.set_sgb_packets(sgb_packets);
+        self.lcd.set_ly_write_resets(ly_write_resets);
+        self.infrared.set_input(ir_input);
+        self.devices.extend(
+            devices
+                .into_iter()
+                .filter(|device| !device.as_any().is::<BootRomControl>()),
+        );
+        self.set_model(model);
+        self.load_table_values(|reg| reg.power_on.get(model));
+        self.select_compat_mode();
+        // No column is selected after a reset, so this can't request an interrupt.
+        self.joypad.set_buttons(buttons);
+    }
+}
+
+impl Default for MemMappedIo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemDevice for MemMappedIo {
+    fn size(&self) -> usize {
+        0x80
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        self.read_register(addr, self.restrictions)
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        if let Some(i) = self.device_index(addr) {
+            let device = &mut self.devices[i];
+            device.write(addr.offset_by(*device.registers().start() as u16), value);
+            let flags = device.take_requested_interrupts();
+            self.request_interrupt(flags);
+        } else {
+            let reg = self.register_at(addr);
+            if reg.availability.includes(self.model) {
+                self.write_register(addr, reg, value);
+            }
+        }
+        if let Some(ref observer) = self.write_observer {
+            (observer.0.borrow_mut())(addr.relative() as u8, value);
+        }
+    }
+}
+
+impl CpuMem for MemMappedIo {
+    fn interrupt_controller(&self) -> Option<&InterruptController> {
+        Some(&self.interrupts)
+    }
+
+    fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController> {
+        Some(&mut self.interrupts)
+    }
+
+    fn fetch(&mut self, addr: Addr) -> u8 {
+        self.read(addr)
+    }
+
+    fn joypad_held(&self) -> Option<bool> {
+        Some(self.joypad.held())
+    }
+
+    fn stop(&mut self) -> bool {
+        // STOP always resets the divider, as if DIV were written.
+        self.timer.write(Addr::from(0xff04).offset_by(0xff04), 0);
+        self.perform_speed_switch()
+    }
+
+    fn yield1m(&mut self) {
+        self.tick(1);
+    }
+}
+
+impl MemMappedIo {
+    /// Gets the index of the registered device which owns the given address, if any.
+    fn device_index(&self, addr: Addr) -> Option<usize> {
+        let index = u8::try_from(addr.relative()).ok()?;
+        self.devices
+            .iter()
+            .position(|device| device.registers().contains(&index))
+    }
+
+    /// Gets the description of the register at the given address.
+    fn register_at(&self, addr: Addr) -> &'static IoRegister {
+        match io_register(addr.index()) {
+            Some(reg) => reg,
+            None => panic!("Address {} out of range for Mem Mapped IO", addr),
+        }
+    }
+
+    /// Reads a register, blocking access to memory the PPU is using if `restricted` is set.
+    fn read_register(&self, addr: Addr, restricted: bool) -> u8 {
+        if let Some(i) = self.device_index(addr) {
+            let device = &self.devices[i];
+            return device.read(addr.offset_by(*device.registers().start() as u16));
+        }
+        let reg = self.register_at(addr);
+        if !reg.availability.includes(self.model) {
+            return 0xff;
+        }
+        let value = match addr.relative() {
+            0x00 => self.joypad.register(),
+            0x01 => self.serial.data(),
+            0x02 => self.serial.control(),
+            0x04..=0x07 => self.timer.read(addr.offset_by(0x04)),
+            0x0f => self.interrupts.read_if(),
+            0x10..=0x3f => self.apu.read(addr.offset_by(0x10)),
+            0x40..=0x45 | 0x47..=0x4b => self.lcd.register(addr.relative() as u8 - 0x40),
+            0x46 => self.oam_dma,
+            // HDMA1..HDMA4 are write-only.
+            0x51..=0x54 => 0xff,
+            0x55 => self.hdma.hdma5(),
+            0x56 => self.infrared.register(),
+            0x68 => self.bg_palettes.index_register(),
+            0x69 => self
+                .bg_palettes
+                .data_register(restricted && self.palette_access_blocked()),
+            0x6a => self.obj_palettes.index_register(),
+            0x6b => self
+                .obj_palettes
+                .data_register(restricted && self.palette_access_blocked()),
+            0x76 | 0x77 => {
+                let pcm = self.apu.pcm_output();
+                let channels = 2 * (addr.index() - 0x76);
+                pcm[channels] & 0xf | (pcm[channels + 1] & 0xf) << 4
+            }
+            _ => reg.storage.map_or(0, |slot| self.storage[slot]),
+        };
+        value | reg.read_mask
+    }
+
+    /// Writes a register which exists on the current model.
+    fn write_register(&mut self, addr: Addr, reg: &IoRegister, value: u8) {
+        match addr.relative() {
+            0x00 => {
+                if self.joypad.write_register(value) {
+                    self.request_interrupt(InterruptFlags::JOYPAD);
+                }
+            }
+            0x01 => self.serial.write_data(value),
+            0x02 => {
+                if self.serial.write_control(value) {
+                    self.request_interrupt(InterruptFlags::SERIAL);
+                }
+            }
+            0x04..=0x07 => self.timer.write(addr.offset_by(0x04), value),
+            0x0f => self.interrupts.write_if(value),
+            0x10..=0x3f => self.apu.write(addr.offset_by(0x10), value),
+            0x40..=0x45 | 0x47..=0x4b => {
+                if self.lcd.write_register(addr.relative() as u8 - 0x40, value) {
+                    self.request_interrupt(InterruptFlags::STAT);
+                }
+            }
+            0x46 => {
+                self.oam_dma = value;
+                self.pending_dma = Some(value);
+            }
+            // KEY0 and OPRI are latched once the bios is disabled.
+            0x4c | 0x6c if !self.bios_enabled() => {}
+            0x51..=0x55 => self
+                .hdma
+                .write_register(addr.relative() as u8 - 0x51, value),
+            0x56 => self.infrared.write_register(value),
+            0x68 => self.bg_palettes.write_index_register(value),
+            0x69 => {
+                let blocked = self.palette_access_blocked();
+                self.bg_palettes.write_data_register(value, blocked);
+            }
+            0x6a => self.obj_palettes.write_index_register(value),
+            0x6b => {
+                let blocked = self.palette_access_blocked();
+                self.obj_palettes.write_data_register(value, blocked);
+            }
+            // PCM12 and PCM34 are read-only.
+            0x76 | 0x77 => {}
+            _ => {
+                if let Some(slot) = reg.storage {
+                    let old = self.storage[slot];
+                    self.storage[slot] = old & !reg.write_mask | value & reg.write_mask;
+                }
+            }
+        }
+    }
+}
+
+/// MemoryDevice which configures the standard memory mapping of the real GameBoy.
+///
+/// The `GbMmu` is the root of the memory map, so it decodes addresses using [`Addr::raw`]. If it
+/// is accessed with an address that has been offset, the offset is ignored. Use
+/// [`try_read`](GbMmu::try_read) and [`try_write`](GbMmu::try_write) to detect offset addresses
+/// instead. To nest a `GbMmu` under another device, use [`read_relative`](GbMmu::read_relative)
+/// and [`write_relative`](GbMmu::write_relative), which decode [`Addr::relative`] instead.
+///
+/// There are two ways to access memory. [`read`](MemDevice::read) and
+/// [`write`](MemDevice::write) are the CPU-faithful path, which is subject to any access
+/// restrictions the hardware has and is counted in the access statistics. [`peek`](GbMmu::peek)
+/// and [`poke`](GbMmu::poke) are the unrestricted debug path, for debuggers, scripting, cheats,
+/// and test setup, which must always be able to see and set memory.
+#[derive(Clone, Debug)]
+pub struct GbMmu {
+    /// The bios. Mapped to 0..0x100 while bios is enabled.
+    bios: BiosRom,
+    /// The inserted cartridge, behind any Game Genie patches. Mapped to 0..0x8000 (rom) and
+    /// 0xA000..0xC000 (ram).
+    cart: Patched<Cartridge>,
+    /// PPU component, which owns Video Ram and sprite info. Mapped to 0x8000..0xA000 (VRAM) and
+    /// 0xFE00..0xFEA0 (OAM).
+    ppu: Box<dyn Component>,
+    /// Working Ram. Mapped to 0xC000..0xE000 and duplicately mapped at 0xE000..0xFE00.
+    wram: ChunkedRam<0x2000>,
+    /// Memory mapped IO. Mapped to 0xff00..FF80.
+    io: MemMappedIo,
+    /// "Page Zero", memory primarily used for software-hardware interaction. Mapped to
+    /// 0xFF80..0xffff
+    high_ram: HighRam,
+    /// Access statistics, if enabled.
+    #[cfg(any(test, feature = "mmu-stats"))]
+    stats: Option<Box<StatsCollector>>,
+    /// Log of writes to the cartridge slot while no cartridge is inserted, if enabled.
+    empty_slot_writes: Option<WriteLog>,
+    /// Callback for writes to the rom area of a cartridge without a mapper, if enabled.
+    rom_write_warning: Option<RomWriteWarning>,
+    /// Number of writes `rom_write_warning` has been called for.
+    rom_writes_warned: u64,
+    /// Devices shadowing parts of the memory map, most recently added first.
+    overlays: Vec<Overlay>,
+    /// Handle to give to the next overlay.
+    next_overlay: u64,
+    /// Kind of access currently being made through `read` and `write`.
+    access_kind: AccessKind,
+    /// Watchpoints on accesses made through `read` and `write`.
+    watchpoints: Watchpoints,
+    /// Whether OAM DMA from VRAM asks the PPU for bus conflicts.
+    dma_bus_conflicts: bool,
+    /// What ram contains at power on and after it is cleared by a reset.
+    ram_init: RamInit,
+    /// Last value read or written through `read` and `write`.
+    last_bus_value: Cell<u8>,
+    /// Whether reads from the unusable region return `last_bus_value` rather than 0.
+    open_bus: bool,
+    /// Whether the machine starts, and restarts after a reset, as if the boot rom had just
+    /// finished.
+    skip_bios: bool,
+}
+
+/// Guard returned by [`GbMmu::unrestricted`], which keeps access restrictions turned off while
+/// it is held.
+#[derive(Debug)]
+pub struct UnrestrictedGuard<'a> {
+    /// The MMU whose restrictions are turned off.
+    mmu: &'a mut GbMmu,
+    /// Whether restrictions were on when the guard was created.
+    previous: bool,
+}
+
+impl Deref for UnrestrictedGuard<'_> {
+    type Target = GbMmu;
+
+    fn deref(&self) -> &GbMmu {
+        self.mmu
+    }
+}
+
+impl DerefMut for UnrestrictedGuard<'_> {
+    fn deref_mut(&mut self) -> &mut GbMmu {
+        self.mmu
+    }
+}
+
+impl Drop for UnrestrictedGuard<'_> {
+    fn drop(&mut self) {
+        self.mmu.io.restrictions = self.previous;
+    }
+}
+
+/// Identifies an overlay added with [`GbMmu::overlay`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct OverlayHandle(u64);
+
+/// A device shadowing part of the memory map of a [`GbMmu`].
+#[derive(Clone, Debug)]
+struct Overlay {
+    handle: OverlayHandle,
+    range: AddrRange,
+    device: Box<dyn Component>,
+}
+
+/// Callback run for writes to the rom area which no mapper register takes, with the system
+/// address and the value written. Clones of the `GbMmu` share the same callback.
+#[derive(Clone)]
+struct RomWriteWarning(Rc<RefCell<RomWriteFn>>);
+
+/// Type of the callback held by a [`RomWriteWarning`].
+type RomWriteFn = Box<dyn FnMut(u16, u8)>;
+
+impl fmt::Debug for RomWriteWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RomWriteWarning")
+    }
+}
+
+bitflags! {
+    /// Whether the CPU can read and write an address, as reported by [`GbMmu::permissions`].
+    pub struct Permissions: u8 {
+        /// Reads return the contents of the address.
+        const READ = 0b01;
+
+        /// Writes change the contents of the address, or are taken by a register.
+        const WRITE = 0b10;
+    }
+}
+
+bitflags! {
+    /// Set of ram regions, used to choose which regions survive a [`GbMmu::reset`].
+    pub struct RegionMask: u8 {
+        /// Working ram, 0xC000..0xE000.
+        const WRAM = 0b00001;
+
+        /// Video ram, 0x8000..0xA000.
+        const VRAM = 0b00010;
+
+        /// Sprite attribute memory, 0xFE00..0xFEA0.
+        const OAM = 0b00100;
+
+        /// High ram, 0xFF80..0xFFFF.
+        const HRAM = 0b01000;
+
+        /// Cartridge ram, in all banks.
+        const CART_RAM = 0b10000;
+    }
+}
+
+impl GbMmu {
+    /// Construct a new MMU with the given bios and cartridge.
+    /// Panics if the given bios data is not exactly 256 bytes.
+    pub fn new(bios: BiosRom, cart: Cartridge) -> GbMmu {
+        Self::builder().bios(bios).cartridge(cart).build()
+    }
+
+    /// Creates a builder for a `GbMmu`, which allows replacing the default components.
+    pub fn builder() -> GbMmuBuilder {
+        GbMmuBuilder::new()
+    }
+
+    /// Reads the byte at the given system address. This is the preferred way to access the
+    /// `GbMmu` directly, since the `Addr` is constructed internally and so can never have been
+    /// offset by some other device.
+    pub fn read_raw(&self, raw: u16) -> u8 {
+        self.read(raw.into())
+    }
+
+    /// Writes the byte at the given system address. This is the preferred way to access the
+    /// `GbMmu` directly, since the `Addr` is constructed internally and so can never have been
+    /// offset by some other device.
+    pub fn write_raw(&mut self, raw: u16, value: u8) {
+        self.write(raw.into(), value)
+    }
+
+    /// Reads the byte at the given system address without side effects. Unlike
+    /// [`read_raw`](GbMmu::read_raw), the access is not counted in the access statistics, which
+    /// makes this suitable for debuggers and memory dumps. Access restrictions don't apply, so
+    /// this can see memory the PPU is using.
+    pub fn peek(&self, raw: u16) -> u8 {
+        self.read_unrecorded(Addr::from(raw), false)
+    }
+
+    /// Reads the given address without counting it in the statistics or checking watchpoints.
+    /// The access restrictions of the hardware only apply if `restricted` is set.
+    fn read_unrecorded(&self, addr: Addr, restricted: bool) -> u8 {
+        let raw = addr.raw();
+        if !self.overlays.is_empty() {
+            if let Some(overlay) = self.find_overlay(raw) {
+                return overlay.device.read(addr.offset_by(*overlay.range.start()));
+            }
+        }
+        // Address guaranteed to be in range since we cover the whole memory space.
+        match addr.relative() {
+            BIOS_START..=BIOS_END if self.io.bios_enabled() => self.bios.read(addr),
+            ROM_START..=ROM_END => self.cart.read(addr),
+            // The PPU owns multiple regions, so it is addressed with the system address.
+            VRAM_START..=VRAM_END => self.ppu.read(addr),
+            // Cartridge ram starts right after cartridge Rom, so the offset used here is the
+            // size of vram, since we only want to shift the address by the ammount we skipped in
+            // order to splice in the vram.
+            CART_RAM_START..=CART_RAM_END => self.cart.read(addr.offset_by(VRAM_SIZE)),
+            WRAM_START..=WRAM_END => self.wram.read(addr.offset_by(WRAM_START)),
+            ECHO_START..=ECHO_END => self.wram.read(addr.offset_by(ECHO_START)),
+            OAM_START..=OAM_END => self.ppu.read(addr),
+            // Unmapped portion above sprite information, which returns 0 unless open bus is
+            // modeled.
+            UNUSABLE_START..=UNUSABLE_END if self.open_bus => self.last_bus_value.get(),
+            UNUSABLE_START..=UNUSABLE_END => 0,
+            IO_START..=IO_END if restricted => self.io.read(addr.offset_by(IO_START)),
+            IO_START..=IO_END => self.io.peek(addr.offset_by(IO_START)),
+            HRAM_START..=HRAM_END => self.high_ram.read(addr.offset_by(HRAM_START)),
+            IE_REGISTER => self.io.interrupts.read_ie(),
+        }
+    }
+
+    /// Gets whether the CPU can currently read and write the given system address, for example to
+    /// color a memory viewer. This depends on the machine state: the bios is read-only while it
+    /// is mapped, cartridge ram is neither readable nor writable while the mapper has it
+    /// disabled, and video ram and OAM are neither while the PPU is using them, unless access
+    /// restrictions are turned off. Rom is read-only, since writes there only go to mapper
+    /// registers. Has no side effects.
+    pub fn permissions(&self, raw: u16) -> Permissions {
+        let both = Permissions::READ | Permissions::WRITE;
+        if !self.overlays.is_empty() && self.find_overlay(raw).is_some() {
+            return both;
+        }
+        let mode = self.io.lcd.mode();
+        let restricted = |blocked: bool| {
+            if self.io.restrictions && blocked {
+                Permissions::empty()
+            } else {
+                both
+            }
+        };
+        match raw {
+            BIOS_START..=BIOS_END if self.io.bios_enabled() => Permissions::READ,
+            ROM_START..=ROM_END => match self.cart.inner() {
+                Cartridge::None => Permissions::empty(),
+                _ => Permissions::READ,
+            },
+            VRAM_START..=VRAM_END => restricted(mode == LcdMode::Drawing),
+            CART_RAM_START..=CART_RAM_END => self.cart.inner().ram_permissions(),
+            OAM_START..=OAM_END => restricted(mode == LcdMode::Drawing || mode == LcdMode::OamScan),
+            // Reads return a fixed value and writes are ignored.
+            UNUSABLE_START..=UNUSABLE_END => Permissions::READ,
+            WRAM_START..=WRAM_END | ECHO_START..=ECHO_END => both,
+            IO_START..=IO_END | HRAM_START..=HRAM_END | IE_REGISTER => both,
+        }
+    }
+
+    /// Writes the contents of the given range of memory to `writer`, one byte per address. Uses
+    /// [`peek`](GbMmu::peek), so dumping has no side effects.
+    pub fn dump_range(&self, range: AddrRange, mut writer: impl Write) -> io::Result<()> {
+        let data: Vec<u8> = range.map(|addr| self.peek(addr)).collect();
+        writer.write_all(&data)
+    }
+
+    /// Gets the contents of the whole address space, as seen through the current banking. Uses
+    /// [`peek`](GbMmu::peek), so dumping has no side effects. Pass the result to
+    /// [`scan_changed`](GbMmu::scan_changed) later to find what changed in between.
+    pub fn dump(&self) -> Box<[u8; 0x10000]> {
+        let mut data = Box::new([0; 0x10000]);
+        for (addr, byte) in data.iter_mut().enumerate() {
+            *byte = self.peek(addr as u16);
+        }
+        data
+    }
+
+    /// Finds every address which currently holds the given value, as read with
+    /// [`peek`](GbMmu::peek). Addresses are in increasing order. Banked regions are only searched
+    /// in the banks currently mapped.
+    pub fn scan(&self, value: u8) -> Vec<u16> {
+        (0..=0xffff)
+            .filter(|&addr| self.peek(addr) == value)
+            .collect()
+    }
+
+    /// Finds every address whose current value differs from its value in `previous`, a dump
+    /// taken earlier with [`dump`](GbMmu::dump). Addresses are in increasing order. Narrowing a
+    /// search down by repeatedly scanning for changes is the usual way to find where a game keeps
+    /// a value, such as a health counter.
+    pub fn scan_changed(&self, previous: &[u8; 0x10000]) -> Vec<u16> {
+        (0..=0xffff)
+            .filter(|&addr| self.peek(addr) != previous[addr as usize])
+            .collect()
+    }
+
+    /// Writes the byte at the given system address, bypassing any access restrictions. The write
+    /// is not counted in the access statistics. This is the unrestricted debug counterpart to
+    /// [`write_raw`](GbMmu::write_raw). Note that the cartridge rom area is still handled by the
+    /// cartridge, so pokes there change the mapper registers rather than the rom.
+    pub fn poke(&mut self, raw: u16, value: u8) {
+        self.unrestricted().write_unrecorded(Addr::from(raw), value);
+    }
+
+    /// Reads a little-endian 16 bit value from the given address and the one after it (wrapping
+    /// around at the end of memory), without side effects.
+    pub fn peek_u16(&self, raw: u16) -> u16 {
+        u16::from_le_bytes([self.peek(raw), self.peek(raw.wrapping_add(1))])
+    }
+
+    /// Writes a little-endian 16 bit value to the given address and the one after it (wrapping
+    /// around at the end of memory), bypassing any access restrictions.
+    pub fn poke_u16(&mut self, raw: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.poke(raw, low);
+        self.poke(raw.wrapping_add(1), high);
+    }
+
+    /// Computes a hash of the given range of memory, using [`peek`](GbMmu::peek) so that hashing
+    /// has no side effects. The hash is 64-bit FNV-1a over the bytes in address order, so it is
+    /// stable across platforms and versions and can be recorded in test fixtures.
+    pub fn hash_region(&self, range: AddrRange) -> u64 {
+        fnv1a(range.map(|addr| self.peek(addr)))
+    }
+
+    /// Computes a hash of all CPU-visible mutable state: VRAM, cartridge ram, WRAM, OAM, IO
+    /// registers, high ram, and the interrupt enable register. Like
+    /// [`hash_region`](GbMmu::hash_region), this uses 64-bit FNV-1a over the bytes in address
+    /// order, skipping echo ram (which would duplicate WRAM) and the unusable region.
+    pub fn hash_state(&self) -> u64 {
+        self.hash_state_excluding(&[])
+    }
+
+    /// Like [`hash_state`](GbMmu::hash_state), but skips addresses in any of the given ranges.
+    /// Use this to leave out state which isn't deterministic between runs.
+    pub fn hash_state_excluding(&self, exclude: &[AddrRange]) -> u64 {
+        let state = (VRAM_START..=WRAM_END)
+            .chain(OAM_START..=OAM_END)
+            .chain(IO_START..=IE_REGISTER);
+        fnv1a(
+            state
+                .filter(|addr| !exclude.iter().any(|range| range.contains(addr)))
+                .map(|addr| self.peek(addr)),
+        )
+    }
+
+    /// Reads one byte per address in the given range from `reader` and writes them to memory,
+    /// for example to restore a dump made with [`dump_range`](GbMmu::dump_range). Nothing is
+    /// written unless the whole range can be read.
+    ///
+    /// If `raw` is false, the bytes go through the normal write path, so writes to the cartridge
+    /// rom area change the MBC registers just as if the CPU made them. If `raw` is true, the
+    /// cartridge rom area (and bios) is skipped so that the MBC registers are left alone, and
+    /// the writes are not counted in the access statistics.
+    pub fn restore_range(
+        &mut self,
+        range: AddrRange,
+        mut reader: impl Read,
+        raw: bool,
+    ) -> io::Result<()> {
+        let mut data = vec![0u8; range.clone().count()];
+        reader.read_exact(&mut data)?;
+        for (addr, value) in range.zip(data) {
+            if !raw {
+                self.write_raw(addr, value);
+            } else if addr > ROM_END {
+                self.poke(addr, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the byte at the given address, or returns an error if the address has been offset.
+    /// Unlike [`read`](MemDevice::read), which ignores any offset, this allows the caller to
+    /// detect a mis-composed memory map.
+    pub fn try_read(&self, addr: Addr) -> Result<u8, MemError> {
+        check_root_addr(addr)?;
+        Ok(self.read(addr))
+    }
+
+    /// Writes the byte at the given address, or returns an error if the address has been offset.
+    /// Unlike [`write`](MemDevice::write), which ignores any offset, this allows the caller to
+    /// detect a mis-composed memory map.
+    pub fn try_write(&mut self, addr: Addr, value: u8) -> Result<(), MemError> {
+        check_root_addr(addr)?;
+        self.write(addr, value);
+        Ok(())
+    }
+
+    /// Reads the byte at the address relative to this `GbMmu`, for when it is nested as a
+    /// sub-device of another memory map which has already offset the address. The relative
+    /// address is decoded exactly as if it were a system address, including the bios check, and
+    /// is what overlays, watchpoints, and access statistics see.
+    pub fn read_relative(&self, addr: Addr) -> u8 {
+        self.read(addr.relative().into())
+    }
+
+    /// Writes the byte at the address relative to this `GbMmu`. See
+    /// [`read_relative`](GbMmu::read_relative).
+    pub fn write_relative(&mut self, addr: Addr, value: u8) {
+        self.write(addr.relative().into(), value)
+    }
+
+    /// Replaces the bios with one loaded from the given data, which must be exactly 256 bytes.
+    /// Does not change whether the bios is currently enabled.
+    pub fn load_bios(&mut self, data: &[u8]) -> Result<(), MmuLoadError> {
+        self.bios = BiosRom::try_from_slice(data)?;
+        Ok(())
+    }
+
+    /// Replaces the cartridge with one parsed from the given rom data. The new cartridge starts
+    /// with its mapper in the power-on state. Returns the parsed cartridge header. If the rom
+    /// can't be parsed, the existing cartridge is left in place. Patches stay in place either
+    /// way, like a Game Genie which stays plugged in when the game is swapped.
+    ///
+    /// CGB-only cartridges are refused unless the hardware model has CGB features, since they
+    /// won't start on other models.
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<CartridgeHeader, MmuLoadError> {
+        let header = CartridgeHeader::parse(data)?;
+        if header.cgb_support() == CgbSupport::CgbOnly && !self.model().cgb_features() {
+            return Err(MmuLoadError::RequiresCgb(self.model()));
+        }
+        *self.cart.inner_mut() = Cartridge::parse(data)?;
+        Ok(header)
+    }
+
+    /// Gets the inserted cartridge along with its patches.
+    pub fn cartridge(&self) -> &Patched<Cartridge> {
+        &self.cart
+    }
+
+    /// Gets a mutable reference to the inserted cartridge along with its patches. Use this to
+    /// add and remove Game Genie codes.
+    pub fn cartridge_mut(&mut self) -> &mut Patched<Cartridge> {
+        &mut self.cart
+    }
+
+    /// Gets a snapshot of the memory access statistics, or `None` if statistics were not enabled
+    /// when this `GbMmu` was built. Only available with the `mmu-stats` feature.
+    #[cfg(any(test, feature = "mmu-stats"))]
+    pub fn stats(&self) -> Option<AccessStats> {
+        self.stats.as_ref().map(|stats| stats.snapshot())
+    }
+
+    /// Resets all access statistics to 0. Does nothing if statistics are not enabled. Only
+    /// available with the `mmu-stats` feature.
+    #[cfg(any(test, feature = "mmu-stats"))]
+    pub fn reset_stats(&mut self) {
+        if let Some(ref stats) = self.stats {
+            stats.reset();
+        }
+    }
+
+    /// Gets the log of writes made to the cartridge slot while no cartridge was inserted, or
+    /// `None` if capturing those writes was not enabled when this `GbMmu` was built.
+    pub fn empty_slot_writes(&self) -> Option<&WriteLog> {
+        self.empty_slot_writes.as_ref()
+    }
+
+    /// Gets the number of writes to the rom area which no mapper register took, or 0 if warning
+    /// about them was not enabled when this `GbMmu` was built. See
+    /// [`GbMmuBuilder::warn_rom_writes`].
+    pub fn suspicious_rom_writes(&self) -> u64 {
+        self.rom_writes_warned
+    }
+
+    /// Counts and reports a CPU write to the rom area if warnings are enabled and the cartridge
+    /// has no mapper to take it.
+    fn warn_rom_write(&mut self, addr: Addr, value: u8) {
+        if let Some(ref warning) = self.rom_write_warning {
+            if addr.raw() <= ROM_END && !self.cart.inner().has_mapper() {
+                self.rom_writes_warned += 1;
+                (warning.0.borrow_mut())(addr.raw(), value);
+            }
+        }
+    }
+
+    /// Records a write to the cartridge address space if no cartridge is inserted and write
+    /// capture is enabled.
+    fn log_empty_slot_write(&mut self, addr: Addr, value: u8) {
+        if let (Some(log), Cartridge::None) = (&mut self.empty_slot_writes, self.cart.inner()) {
+            log.record(addr.raw(), value);
+        }
+    }
+
+    /// Copies 160 bytes into OAM from the source selected by the given page, as if the whole OAM
+    /// DMA transfer happened at once. The source is read through the normal read path, so
+    /// cartridge sources respect the current bank selection.
+    ///
+    /// Normally the DMA reads the same value the CPU would. If DMA bus conflicts are enabled,
+    /// reads from VRAM while the PPU is using it get the PPU's bus value instead, as reported by
+    /// [`Component::vram_bus_conflict`].
+    fn run_oam_dma(&mut self, page: u8) {
+        let source = oam_dma_source(page);
+        self.with_access_kind(AccessKind::Dma, |mmu| {
+            for i in 0..0xa0 {
+                let addr = Addr::from(source + i);
+                let mut value = mmu.read(addr);
+                if mmu.dma_bus_conflicts && (VRAM_START..=VRAM_END).contains(&addr.raw()) {
+                    value = mmu.ppu.vram_bus_conflict(addr).unwrap_or(value);
+                }
+                mmu.ppu.write(Addr::from(OAM_START + i), value);
+            }
+        });
+    }
+
+    /// Copies the blocks of a VRAM DMA transfer which are due: all of a general purpose
+    /// transfer, or the block for the current HBlank of an HBlank transfer. Like OAM DMA, the
+    /// copy happens at once, and the source is read through the normal read path.
+    fn run_hdma(&mut self) {
+        while let Some((source, dest)) = self.io.hdma.take_block() {
+            self.with_access_kind(AccessKind::Dma, |mmu| {
+                for i in 0..0x10 {
+                    let value = mmu.read(Addr::from(source.wrapping_add(i)));
+                    mmu.ppu.write(Addr::from(VRAM_START + dest + i), value);
+                }
+            });
+        }
+    }
+
+    /// Writes the given byte without counting it in the access statistics. The address must
+    /// not be offset.
+    fn write_unrecorded(&mut self, addr: Addr, value: u8) {
+        if !self.overlays.is_empty() {
+            let raw = addr.raw();
+            if let Some(overlay) = self.overlays.iter_mut().find(|o| o.range.contains(&raw)) {
+                let start = *overlay.range.start();
+                overlay.device.write(addr.offset_by(start), value);
+                return;
+            }
+        }
+        // Address guaranteed to be in range since we cover the whole memory space.
+        match addr.relative() {
+            BIOS_START..=BIOS_END if self.io.bios_enabled() => self.bios.write(addr, value),
+            ROM_START..=ROM_END => {
+                self.log_empty_slot_write(addr, value);
+                self.cart.write(addr, value)
+            }
+            // The PPU owns multiple regions, so it is addressed with the system address.
+            VRAM_START..=VRAM_END => self.ppu.write(addr, value),
+            // Cartridge ram starts right after cartridge Rom, so the offset used here is the
+            // size of vram, since we only want to shift the address by the ammount we skipped in
+            // order to splice in the vram.
+            CART_RAM_START..=CART_RAM_END => {
+                self.log_empty_slot_write(addr, value);
+                self.cart.write(addr.offset_by(VRAM_SIZE), value)
+            }
+            WRAM_START..=WRAM_END => self.wram.write(addr.offset_by(WRAM_START), value),
+            ECHO_START..=ECHO_END => self.wram.write(addr.offset_by(ECHO_START), value),
+            OAM_START..=OAM_END => self.ppu.write(addr, value),
+            // Unmapped portion above sprite information.
+            UNUSABLE_START..=UNUSABLE_END => {}
+            IO_START..=IO_END => {
+                self.io.write(addr.offset_by(IO_START), value);
+                if let Some(page) = self.io.pending_dma.take() {
+                    self.run_oam_dma(page);
+                }
+                self.run_hdma();
+            }
+            HRAM_START..=HRAM_END => self.high_ram.write(addr.offset_by(HRAM_START), value),
+            IE_REGISTER => self.io.interrupts.write_ie(value),
+        }
+    }
+
+    /// Shadows the given range of memory with a device, so that all accesses in the range go to
+    /// the device instead of wherever they would normally go, until the overlay is removed with
+    /// [`remove_overlay`](GbMmu::remove_overlay). The device is addressed relative to the start
+    /// of the range. If overlays overlap, the most recently added one wins.
+    ///
+    /// This is meant for test fixtures, such as recording IO accesses or simulating a
+    /// misbehaving cartridge. When there are no overlays, checking for them costs a single
+    /// branch per access.
+    pub fn overlay(&mut self, range: AddrRange, device: Box<dyn Component>) -> OverlayHandle {
+        let handle = OverlayHandle(self.next_overlay);
+        self.next_overlay += 1;
+        self.overlays.insert(
+            0,
+            Overlay {
+                handle,
+                range,
+                device,
+            },
+        );
+        handle
+    }
+
+    /// Removes an overlay, restoring normal routing for its range (unless another overlay
+    /// covers it). Returns the overlay's device, or `None` if the overlay was already removed.
+    pub fn remove_overlay(&mut self, handle: OverlayHandle) -> Option<Box<dyn Component>> {
+        let index = self.overlays.iter().position(|o| o.handle == handle)?;
+        Some(self.overlays.remove(index).device)
+    }
+
+    /// Finds the overlay covering the given address, if any.
+    fn find_overlay(&self, raw: u16) -> Option<&Overlay> {
+        self.overlays.iter().find(|o| o.range.contains(&raw))
+    }
+
+    /// Advances the hardware by the given number of CPU machine cycles. The CPU ticks the
+    /// hardware 1 M cycle at a time between its memory accesses, through [`CpuMem::yield1m`], so
+    /// this only needs to be called directly to run the hardware without the CPU. The IO devices
+    /// are ticked by [`MemMappedIo::tick`], and the PPU is ticked at normal speed, like the APU.
+    /// Interrupts they request are set in IF.
+    ///
+    /// OAM DMA has no state to advance, since a transfer runs to completion as soon as the DMA
+    /// register is written. Blocks of an HBlank VRAM DMA transfer which became due when the PPU
+    /// entered HBlank are copied before ticking.
+    pub fn tick(&mut self, m_cycles: u32) {
+        self.run_hdma();
+        let normal_cycles = self.io.tick_devices(m_cycles);
+        self.ppu.tick(normal_cycles);
+        let flags = self.ppu.take_requested_interrupts();
+        self.io.request_interrupt(flags);
+    }
+
+    /// Turns off the access restrictions of the hardware until the returned guard is dropped, so
+    /// that [`read`](MemDevice::read) and [`write`](MemDevice::write) reach memory the PPU is
+    /// using. [`peek`](GbMmu::peek) and [`poke`](GbMmu::poke) always ignore the restrictions,
+    /// but unlike them, accesses made through the guard are still counted in the statistics and
+    /// seen by watchpoints. The guard derefs to the `GbMmu`, and puts the restrictions back the
+    /// way they were when it is dropped, so guards can be nested.
+    ///
+    /// Currently the only restriction is on the CGB palette data registers while the PPU is
+    /// drawing.
+    pub fn unrestricted(&mut self) -> UnrestrictedGuard<'_> {
+        let previous = std::mem::replace(&mut self.io.restrictions, false);
+        UnrestrictedGuard {
+            mmu: self,
+            previous,
+        }
+    }
+
+    /// Gets the kind of access currently being made through [`read`](MemDevice::read) and
+    /// [`write`](MemDevice::write).
+    pub fn access_kind(&self) -> AccessKind {
+        self.access_kind
+    }
+
+    /// Sets the kind of access being made through [`read`](MemDevice::read) and
+    /// [`write`](MemDevice::write), returning the previous kind. The CPU fetches opcodes and their
+    /// operands through [`CpuMem::fetch`], which makes them with the kind set to
+    /// [`AccessKind::Execute`].
+    pub fn set_access_kind(&mut self, kind: AccessKind) -> AccessKind {
+        std::mem::replace(&mut self.access_kind, kind)
+    }
+
+    /// Runs `f` with the access kind set to `kind`, then restores the previous kind.
+    pub fn with_access_kind<R>(&mut self, kind: AccessKind, f: impl FnOnce(&mut Self) -> R) -> R {
+        let previous = self.set_access_kind(kind);
+        let result = f(self);
+        self.access_kind = previous;
+        result
+    }
+
+    /// Adds a watchpoint. Watchpoints only see accesses made through [`read`](MemDevice::read)
+    /// and [`write`](MemDevice::write), not the debug path.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchHandle {
+        self.watchpoints.add(watchpoint)
+    }
+
+    /// Removes a watchpoint, returning it if it existed. Hits it already recorded are kept.
+    pub fn remove_watchpoint(&mut self, handle: WatchHandle) -> Option<Watchpoint> {
+        self.watchpoints.remove(handle)
+    }
+
+    /// Removes and returns the watchpoint hits recorded so far, oldest first.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.watchpoints.take_hits()
+    }
+
+    /// Decodes a tile from VRAM into an 8x8 grid of 2 bit color indexes, indexed by row then
+    /// column.
+    ///
+    /// Tiles are numbered from 0 at 0x8000 to 383 at 0x97F0. Each row of a tile is stored as two
+    /// bytes, the first holding the low bit of each pixel and the second the high bit, with the
+    /// leftmost pixel in bit 7. The tile is read through the debug path, so it is read from
+    /// whatever is currently mapped in VRAM. Only bank 0 exists until CGB VRAM banking is
+    /// supported. Panics if the bank or index is out of range.
+    pub fn vram_tile(&self, bank: u8, index: usize) -> [[u8; 8]; 8] {
+        assert!(bank == 0, "VRAM bank {} does not exist", bank);
+        assert!(index < VRAM_TILES, "Tile index {} is out of range", index);
+        let base = VRAM_START + index as u16 * 16;
+        let mut tile = [[0; 8]; 8];
+        for (y, row) in tile.iter_mut().enumerate() {
+            let low = self.peek(base + y as u16 * 2);
+            let high = self.peek(base + y as u16 * 2 + 1);
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let bit = 7 - x;
+                *pixel = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+            }
+        }
+        tile
+    }
+
+    /// Iterates over all 384 tiles in the given VRAM bank, decoded as by
+    /// [`vram_tile`](GbMmu::vram_tile).
+    pub fn vram_tiles(&self, bank: u8) -> impl Iterator<Item = [[u8; 8]; 8]> + '_ {
+        (0..VRAM_TILES).map(move |index| self.vram_tile(bank, index))
+    }
+
+    /// Gets the PPU component.
+    pub fn ppu(&self) -> &dyn Component {
+        &*self.ppu
+    }
+
+    /// Gets a mutable reference to the PPU component.
+    pub fn ppu_mut(&mut self) -> &mut dyn Component {
+        &mut *self.ppu
+    }
+
+    /// Gets the hardware model being emulated.
+    pub fn model(&self) -> HardwareModel {
+        self.io.model()
+    }
+
+    /// Gets the last value read or written through [`read`](MemDevice::read) and
+    /// [`write`](MemDevice::write), which is the value left on the data bus. Debug accesses
+    /// through [`peek`](GbMmu::peek) and [`poke`](GbMmu::poke) don't change it.
+    pub fn last_bus_value(&self) -> u8 {
+        self.last_bus_value.get()
+    }
+
+    /// Gets the high ram, for example to inspect the stack or routines copied there.
+    pub fn high_ram(&self) -> &HighRam {
+        &self.high_ram
+    }
+
+    /// Gets the memory mapped IO.
+    pub fn io(&self) -> &MemMappedIo {
+        &self.io
+    }
+
+    /// Gets a mutable reference to the memory mapped IO.
+    pub fn io_mut(&mut self) -> &mut MemMappedIo {
+        &mut self.io
+    }
+
+    /// Sets whether infrared light is reaching the machine. This feeds both the CGB infrared port
+    /// (RP, 0xFF56) and the port on cartridges which have one, such as the HuC1, since both would
+    /// see the same light. Ports which aren't present ignore it.
+    pub fn set_ir_input(&mut self, seen: bool) {
+        self.io.set_ir_input(seen);
+        self.cart.inner_mut().set_ir_input(seen);
+    }
+
+    /// Returns true if any infrared LED on the machine is on, either the CGB infrared port or the
+    /// one on the cartridge. To link two emulators, pass the `ir_output` of each to
+    /// [`set_ir_input`](GbMmu::set_ir_input) of the other.
+    pub fn ir_output(&self) -> bool {
+        self.io.ir_output() || self.cart.inner().ir_output()
+    }
+
+    /// Gets the interrupt controller, which holds both IE and IF.
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.io.interrupts
+    }
+
+    /// Gets a mutable reference to the interrupt controller.
+    pub fn interrupts_mut(&mut self) -> &mut InterruptController {
+        &mut self.io.interrupts
+    }
+
+    /// Resets the machine's memory to the power-on state, except for the ram regions in
+    /// `preserve`, which keep their contents. The bios is re-enabled, IO registers and the
+    /// interrupt enable register return to their initial values, and the cartridge's mapper
+    /// registers are reset. If the builder's [`skip_bios`](GbMmuBuilder::skip_bios) is set, the
+    /// IO registers are then loaded with their post-boot values and the bios is disabled again.
+    /// The bios, cartridge rom, components, and debugging state (such as access statistics and
+    /// cheats) are kept.
+    pub fn reset(&mut self, preserve: RegionMask) {
+        self.init_ram(!preserve);
+        if !preserve.contains(RegionMask::CART_RAM) {
+            self.cart.inner_mut().clear_ram();
+        }
+        self.cart.inner_mut().reset_registers();
+        self.io.reset(self.io.model());
+        if self.skip_bios {
+            self.io.apply_post_boot(self.io.model());
+        }
+    }
+
+    /// Sets the given regions to their power-on contents, as chosen by the builder's
+    /// [`ram_init`](GbMmuBuilder::ram_init). Cartridge ram is not affected.
+    fn init_ram(&mut self, regions: RegionMask) {
+        let init = self.ram_init;
+        if regions.contains(RegionMask::WRAM) {
+            self.wram = ChunkedRam::new();
+            if init != RamInit::Zeros {
+                let wram = &mut self.wram;
+                init.fill(WRAM_START..=WRAM_END, |addr, value| {
+                    wram.write(addr.offset_by(WRAM_START), value)
+                });
+            }
+        }
+        if regions.contains(RegionMask::VRAM) {
+            let ppu = &mut self.ppu;
+            init.fill(VRAM_START..=VRAM_END, |addr, value| ppu.write(addr, value));
+        }
+        if regions.contains(RegionMask::OAM) {
+            let ppu = &mut self.ppu;
+            init.fill(OAM_START..=OAM_END, |addr, value| ppu.write(addr, value));
+        }
+        if regions.contains(RegionMask::HRAM) {
+            self.high_ram = HighRam::new();
+            let high_ram = &mut self.high_ram;
+            init.fill(HRAM_START..=HRAM_END, |addr, value| {
+                high_ram.write(addr.offset_by(HRAM_START), value)
+            });
+        }
+    }
+
+    /// Resets the machine, clearing all ram, as happens when it is turned off and back on
+    /// after a while.
+    pub fn hard_reset(&mut self) {
+        self.reset(RegionMask::empty());
+    }
+
+    /// Resets the machine, keeping the contents of all ram, as happens on a quick power
+    /// cycle on real hardware, where ram mostly keeps its contents.
+    pub fn soft_reset(&mut self) {
+        self.reset(RegionMask::all());
+    }
+
+    /// Takes a snapshot of the machine's memory state, which can be passed to
+    /// [`restore`](GbMmu::restore) to rewind to this point.
+    ///
+    /// Snapshots are cheap: ram is stored in copy-on-write chunks, and cartridge rom is shared,
+    /// so a snapshot only costs a copy of the chunk handles and registers. Memory is only copied
+    /// when a chunk is next written, so keeping a snapshot every frame costs roughly the memory
+    /// the game touched that frame.
+    pub fn snapshot(&self) -> MmuSnapshot {
+        MmuSnapshot {
+            cart: self.cart.inner().clone(),
+            ppu: self.ppu.clone(),
+            wram: self.wram.clone(),
+            io: self.io.clone(),
+            high_ram: self.high_ram,
+        }
+    }
+
+    /// Restores the memory state from a snapshot taken with [`snapshot`](GbMmu::snapshot). The
+    /// bios, the IO write observer, and debugging state (access statistics, the empty slot log,
+    /// overlays, and Game Genie patches) are not part of the snapshot and are kept.
+    pub fn restore(&mut self, snapshot: &MmuSnapshot) {
+        *self.cart.inner_mut() = snapshot.cart.clone();
+        self.ppu = snapshot.ppu.clone();
+        self.wram = snapshot.wram.clone();
+        let observer = self.io.write_observer.take();
+        self.io = snapshot.io.clone();
+        self.io.write_observer = observer;
+        self.high_ram = snapshot.high_ram;
+    }
+}
+
+/// Memory state of a [`GbMmu`] at some point in time, taken with [`GbMmu::snapshot`].
+#[derive(Clone, Debug)]
+pub struct MmuSnapshot {
+    cart: Cartridge,
+    ppu: Box<dyn Component>,
+    wram: ChunkedRam<0x2000>,
+    io: MemMappedIo,
+    high_ram: HighRam,
+}
+
+impl Default for GbMmu {
+    fn default() -> Self {
+        Self::new(Default::default(), Cartridge::None)
+    }
+}
+
+/// Returns an error if the address is not a system address.
+fn check_root_addr(addr: Addr) -> Result<(), MemError> {
+    if addr.relative() == addr.raw() {
+        Ok(())
+    } else {
+        Err(MemError::UnexpectedOffset {
+            raw: addr.raw(),
+            relative: addr.relative(),
+        })
+    }
+}
+
+/// Number of tiles in each bank of VRAM.
+const VRAM_TILES: usize = 384;
+
+/// Size of video ram, which is the gap between cartridge rom and cartridge ram in the memory map.
+const VRAM_SIZE: u16 = VRAM_END - VRAM_START + 1;
+
+/// Gets the first address an OAM DMA transfer copies from when the given page is written to the
+/// DMA register. The DMA unit can't reach anything above working ram: pages 0xE0 and up read
+/// working ram the same way the echo region does, which on DMG also applies to pages 0xFE and
+/// 0xFF (reading 0xDE00 and 0xDF00), rather than OAM and IO.
+fn oam_dma_source(page: u8) -> u16 {
+    let base = (page as u16) << 8;
+    if base >= ECHO_START {
+        base - (ECHO_START - WRAM_START)
+    } else {
+        base
+    }
+}
+
+/// Computes the 64-bit FNV-1a hash of the given bytes.
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.into_iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// The `GbMmu` covers the whole address space, so it always decodes using the raw address. If it
+/// is given an address that some other device has already offset, the offset is discarded.
+fn root_addr(addr: Addr) -> Addr {
+    if addr.relative() != addr.raw() {
+        debug!(
+            "Using Root MMU with offset address {}, ignoring offset",
+            addr
+        );
+    }
+    Addr::from(addr.raw())
+}
+
+impl MemDevice for GbMmu {
+    fn size(&self) -> usize {
+        0x10000
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        let addr = root_addr(addr);
+        #[cfg(any(test, feature = "mmu-stats"))]
+        if let Some(ref stats) = self.stats {
+            stats.record_read(Region::of(addr.raw(), self.io.bios_enabled()), addr.raw());
+        }
+        let value = self.read_unrecorded(addr, true);
+        self.last_bus_value.set(value);
+        if !self.watchpoints.is_empty() {
+            self.watchpoints
+                .check(addr.raw(), value, false, self.access_kind);
+        }
+        value
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        let addr = root_addr(addr);
+        #[cfg(any(test, feature = "mmu-stats"))]
+        if let Some(ref stats) = self.stats {
+            stats.record_write(Region::of(addr.raw(), self.io.bios_enabled()), addr.raw());
+        }
+        if !self.watchpoints.is_empty() {
+            self.watchpoints
+                .check(addr.raw(), value, true, self.access_kind);
+        }
+        self.last_bus_value.set(value);
+        self.warn_rom_write(addr, value);
+        self.write_unrecorded(addr, value);
+    }
+}
+
+impl CpuMem for GbMmu {
+    fn interrupt_controller(&self) -> Option<&InterruptController> {
+        Some(&self.io.interrupts)
+    }
+
+    fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController> {
+        Some(&mut self.io.interrupts)
+    }
+
+    /// Fetches are made with the access kind set to [`AccessKind::Execute`], so execute
+    /// watchpoints see them.
+    fn fetch(&mut self, addr: Addr) -> u8 {
+        self.with_access_kind(AccessKind::Execute, |mmu| mmu.read(addr))
+    }
+
+    fn joypad_held(&self) -> Option<bool> {
+        self.io.joypad_held()
+    }
+
+    fn stop(&mut self) -> bool {
+        self.io.stop()
+    }
+
+    fn yield1m(&mut self) {
+        self.tick(1);
+    }
+}
+
+/// Fixed-capacity log of memory writes, which keeps only the most recent writes.
+#[derive(Clone, Debug)]
+pub struct WriteLog {
+    /// Logged writes as (raw address, value), oldest first.
+    writes: VecDeque<(u16, u8)>,
+    /// Maximum number of writes to keep.
+    capacity: usize,
+}
+
+impl WriteLog {
+    /// Creates an empty log which keeps up to `capacity` writes.
+    pub fn new(capacity: usize) -> Self {
+        WriteLog {
+            writes: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records a write, discarding the oldest write if the log is full.
+    pub fn record(&mut self, addr: u16, value: u8) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.writes.len() == self.capacity {
+            self.writes.pop_front();
+        }
+        self.writes.push_back((addr, value));
+    }
+
+    /// Iterates over the logged writes as (raw address, value), oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.writes.iter().copied()
+    }
+
+    /// Number of writes currently in the log.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Returns true if the log is empty.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Removes all writes from the log.
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+}
+
+/// Builder for a [`GbMmu`]. Every component starts out as the default used by [`GbMmu::new`],
+/// and any of them can be replaced before building.
+#[derive(Debug)]
+pub struct GbMmuBuilder {
+    bios: BiosRom,
+    cart: Cartridge,
+    ppu: Box<dyn Component>,
+    timer: Box<dyn Component>,
+    apu: Box<dyn Component>,
+    serial: Box<dyn SerialBackend>,
+    model: HardwareModel,
+    #[cfg(any(test, feature = "mmu-stats"))]
+    access_stats: bool,
+    #[cfg(any(test, feature = "mmu-stats"))]
+    page_histogram: bool,
+    empty_slot_writes: Option<usize>,
+    rom_write_warning: Option<RomWriteWarning>,
+    dma_bus_conflicts: bool,
+    sgb_packets: bool,
+    ram_init: RamInit,
+    open_bus: bool,
+    skip_bios: bool,
+}
+
+impl GbMmuBuilder {
+    /// Creates a builder with an empty bios, no cartridge, a [`Timer`], an [`Apu`], the
+    /// [`NullPpu`], a disconnected link cable, and the DMG hardware model.
+    pub fn new() -> Self {
+        GbMmuBuilder {
+            bios: Default::default(),
+            cart: Cartridge::None,
+            ppu: Box::new(NullPpu::new()),
+            timer: Box::new(Timer::new()),
+            apu: Box::new(Apu::new()),
+            serial: Box::new(Disconnected),
+            model: HardwareModel::Dmg,
+            #[cfg(any(test, feature = "mmu-stats"))]
+            access_stats: false,
+            #[cfg(any(test, feature = "mmu-stats"))]
+            page_histogram: false,
+            empty_slot_writes: None,
+            rom_write_warning: None,
+            dma_bus_conflicts: false,
+            sgb_packets: false,
+            ram_init: RamInit::Zeros,
+            open_bus: false,
+            skip_bios: false,
+        }
+    }
+
+    /// Sets the bios to use.
+    pub fn bios(mut self, bios: BiosRom) -> Self {
+        self.bios = bios;
+        self
+    }
+
+    /// Sets the inserted cartridge.
+    pub fn cartridge(mut self, cart: Cartridge) -> Self {
+        self.cart = cart;
+        self
+    }
+
+    /// Sets the PPU component. The PPU is addressed using system addresses, and must accept
+    /// addresses in 0x8000..0xA000 (VRAM) and 0xFE00..0xFEA0 (OAM).
+    pub fn ppu(mut self, ppu: Box<dyn Component>) -> Self {
+        self.ppu = ppu;
+        self
+    }
+
+    /// Sets the timer component. The timer must accept addresses 0..4, which are mapped to
+    /// 0xFF04..0xFF08.
+    pub fn timer(mut self, timer: Box<dyn Component>) -> Self {
+        self.timer = timer;
+        self
+    }
+
+    /// Sets the audio component. The APU must accept addresses 0..0x30, which are mapped to
+    /// 0xFF10..0xFF40.
+    pub fn apu(mut self, apu: Box<dyn Component>) -> Self {
+        self.apu = apu;
+        self
+    }
+
+    /// Sets what is on the other end of the link cable.
+    pub fn serial(mut self, backend: Box<dyn SerialBackend>) -> Self {
+        self.serial = backend;
+        self
+    }
+
+    /// Sets the hardware model to emulate. See [`HardwareModel::preferred_for`] to choose one
+    /// based on the cartridge.
+    pub fn model(mut self, model: HardwareModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Enables or disables collection of per-region access statistics. Statistics are disabled by
+    /// default, and cost only a single branch per access when disabled. Only available with the
+    /// `mmu-stats` feature; without it, there is no cost at all.
+    #[cfg(any(test, feature = "mmu-stats"))]
+    pub fn access_stats(mut self, enabled: bool) -> Self {
+        self.access_stats = enabled;
+        self
+    }
+
+    /// Enables or disables the 256 bucket per-page access histogram. Enabling the histogram also
+    /// enables access statistics. Only available with the `mmu-stats` feature.
+    #[cfg(any(test, feature = "mmu-stats"))]
+    pub fn page_histogram(mut self, enabled: bool) -> Self {
+        self.page_histogram = enabled;
+        self
+    }
+
+    /// Enables capturing writes to the cartridge slot while no cartridge is inserted, keeping the
+    /// given number of most recent writes. This allows checking what software tried to configure
+    /// when run without a cartridge.
+    pub fn capture_empty_slot_writes(mut self, capacity: usize) -> Self {
+        self.empty_slot_writes = Some(capacity);
+        self
+    }
+
+    /// Enables warning about writes to the rom area (0x0000..0x8000) when the cartridge has no
+    /// mapper, so nothing takes them. Such writes are almost always a bug in the game. The
+    /// callback is called with the system address and value of each one, and
+    /// [`GbMmu::suspicious_rom_writes`] counts them. Only CPU writes through
+    /// [`write`](MemDevice::write) are checked, not [`poke`](GbMmu::poke).
+    pub fn warn_rom_writes(mut self, f: Box<dyn FnMut(u16, u8)>) -> Self {
+        self.rom_write_warning = Some(RomWriteWarning(Rc::new(RefCell::new(f))));
+        self
+    }
+
+    /// Enables modeling bus conflicts between OAM DMA and the PPU. When enabled, a DMA transfer
+    /// reading from VRAM while the PPU is using it copies the PPU's bus value, as reported by the
+    /// PPU component's [`Component::vram_bus_conflict`]. Disabled by default, in which case DMA
+    /// always reads the normal value.
+    pub fn dma_bus_conflicts(mut self, enabled: bool) -> Self {
+        self.dma_bus_conflicts = enabled;
+        self
+    }
+
+    /// Enables decoding Super Game Boy command packets, which are then available from
+    /// [`MemMappedIo::take_sgb_packets`]. Disabled by default.
+    pub fn sgb_packets(mut self, enabled: bool) -> Self {
+        self.sgb_packets = enabled;
+        self
+    }
+
+    /// Sets what ram contains at power on. [`RamInit::Seeded`] fills working ram, video ram,
+    /// OAM, and high ram with a pattern which is the same every time for the same seed, and is
+    /// reapplied when a reset clears them. Cartridge ram is always left alone. Defaults to
+    /// [`RamInit::Zeros`].
+    pub fn ram_init(mut self, init: RamInit) -> Self {
+        self.ram_init = init;
+        self
+    }
+
+    /// Enables open bus reads from the unusable region (0xFEA0..0xFF00), which then return the
+    /// last value read or written, as reported by [`GbMmu::last_bus_value`]. A few test roms probe
+    /// this. Disabled by default, in which case the region reads 0.
+    pub fn open_bus(mut self, enabled: bool) -> Self {
+        self.open_bus = enabled;
+        self
+    }
+
+    /// Starts the machine as if the boot rom had already run, with the bios disabled and the IO
+    /// registers holding the values it leaves, as loaded by [`MemMappedIo::apply_post_boot`].
+    /// [`GbMmu::reset`] does the same. The CPU registers must also be set to their post-boot
+    /// values. Disabled by default.
+    pub fn skip_bios(mut self, skip: bool) -> Self {
+        self.skip_bios = skip;
+        self
+    }
+
+    /// Builds the `GbMmu`.
+    pub fn build(self) -> GbMmu {
+        let mut io = MemMappedIo::new();
+        io.timer = self.timer;
+        io.apu = self.apu;
+        io.serial = Serial::new(self.serial);
+        io.joypad.set_sgb_packets(self.sgb_packets);
+        io.reset(self.model);
+        if self.skip_bios {
+            io.apply_post_boot(self.model);
+        }
+        let mut mmu = GbMmu {
+            bios: self.bios,
+            cart: Patched::new(self.cart),
+            ppu: self.ppu,
+            wram: ChunkedRam::new(),
+            io,
+            high_ram: HighRam::new(),
+            #[cfg(any(test, feature = "mmu-stats"))]
+            stats: if self.access_stats || self.page_histogram {
+                Some(Box::new(StatsCollector::new(self.page_histogram)))
+            } else {
+                None
+            },
+            empty_slot_writes: self.empty_slot_writes.map(WriteLog::new),
+            rom_write_warning: self.rom_write_warning,
+            rom_writes_warned: 0,
+            overlays: Vec::new(),
+            next_overlay: 0,
+            access_kind: AccessKind::Data,
+            watchpoints: Default::default(),
+            dma_bus_conflicts: self.dma_bus_conflicts,
+            ram_init: self.ram_init,
+            last_bus_value: Cell::new(0),
+            open_bus: self.open_bus,
+            skip_bios: self.skip_bios,
+        };
+        if self.ram_init != RamInit::Zeros {
+            mmu.init_ram(RegionMask::all());
+        }
+        mmu
+    }
+}
+
+impl Default for GbMmuBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lcd::{STAT_COINCIDENCE, STAT_COINCIDENCE_INTERRUPT};
+
+    #[test]
+    fn default_components_behave_like_plain_memory() {
+        let mut mmu = GbMmu::default();
+        mmu.write(0x8123.into(), 0x12);
+        mmu.write(0xfe45.into(), 0x34);
+        assert_eq!(mmu.read(0x8123.into()), 0x12);
+        assert_eq!(mmu.read(0xfe45.into()), 0x34);
+        // Audio registers read back through their masks.
+        mmu.write(0xff26.into(), 0x80);
+        mmu.write(0xff10.into(), 0x00);
+        assert_eq!(mmu.read(0xff10.into()), 0x80);
+        assert_eq!(mmu.read(0xff26.into()), 0xf0);
+        assert_eq!(mmu.read(0xff27.into()), 0xff);
+    }
+
+    #[test]
+    fn timer_ticks_through_io() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff06, 0xf0);
+        mmu.write_raw(0xff05, 0xfe);
+        mmu.write_raw(0xff07, 0x05);
+        assert_eq!(mmu.read_raw(0xff07), 0xfd);
+        mmu.io_mut().tick(4);
+        assert_eq!(mmu.read_raw(0xff05), 0xff);
+        // Overflow, then the reload cycle.
+        mmu.io_mut().tick(4);
+        assert_eq!(mmu.read_raw(0xff05), 0x00);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::empty()
+        );
+        mmu.io_mut().tick(1);
+        assert_eq!(mmu.read_raw(0xff05), 0xf0);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::TIMER
+        );
+        assert_eq!(mmu.read_raw(0xff04), 0);
+        mmu.io_mut().tick(64);
+        assert_eq!(mmu.read_raw(0xff04), 1);
+        mmu.write_raw(0xff04, 0x80);
+        assert_eq!(mmu.read_raw(0xff04), 0);
+    }
+
+    #[test]
+    fn raw_access_matches_addr_access() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xc123, 0x42);
+        assert_eq!(mmu.read_raw(0xc123), 0x42);
+        assert_eq!(mmu.read(0xc123.into()), 0x42);
+        // Echo ram sees the same write.
+        assert_eq!(mmu.read_raw(0xe123), 0x42);
+    }
+
+    #[test]
+    fn oam_dma_from_echo_region() {
+        let mut mmu = GbMmu::default();
+        for i in 0..0xa0 {
+            mmu.write(Addr::from(0xc100 + i), i as u8 ^ 0x5a);
+            mmu.write(Addr::from(0xdf00 + i), i as u8);
+        }
+
+        mmu.write(Addr::from(0xff46), 0xe1);
+        assert_eq!(mmu.read(Addr::from(0xff46)), 0xe1);
+        for i in 0..0xa0 {
+            assert_eq!(
+                mmu.read(Addr::from(0xfe00 + i)),
+                mmu.read(Addr::from(0xc100 + i))
+            );
+        }
+
+        // Pages above the echo region still read working ram rather than OAM or IO.
+        mmu.write(Addr::from(0xff46), 0xff);
+        for i in 0..0xa0 {
+            assert_eq!(mmu.read(Addr::from(0xfe00 + i)), i as u8);
+        }
+    }
+
+    #[test]
+    fn oam_dma_from_vram() {
+        /// PPU which is always using VRAM, with 0x77 on the bus.
+        #[derive(Clone, Debug, Default)]
+        struct BusyPpu(NullPpu);
+
+        impl MemDevice for BusyPpu {
+            fn read(&self, addr: Addr) -> u8 {
+                self.0.read(addr)
+            }
+
+            fn write(&mut self, addr: Addr, value: u8) {
+                self.0.write(addr, value)
+            }
+        }
+
+        impl Component for BusyPpu {
+            fn box_clone(&self) -> Box<dyn Component> {
+                Box::new(self.clone())
+            }
+
+            fn vram_bus_conflict(&self, _addr: Addr) -> Option<u8> {
+                Some(0x77)
+            }
+        }
+
+        let fill = |mmu: &mut GbMmu| {
+            for i in 0..0xa0 {
+                mmu.write(Addr::from(0x8100 + i), i as u8);
+            }
+            mmu.write(Addr::from(0xff46), 0x81);
+        };
+
+        // Without conflicts modeled, DMA reads the normal value even if the PPU is busy. With
+        // conflicts modeled, the default PPU never reports one.
+        for mut mmu in [
+            GbMmu::builder().ppu(Box::new(BusyPpu::default())).build(),
+            GbMmu::builder().dma_bus_conflicts(true).build(),
+        ] {
+            fill(&mut mmu);
+            for i in 0..0xa0 {
+                assert_eq!(mmu.read(Addr::from(0xfe00 + i)), i as u8);
+            }
+        }
+
+        let mut mmu = GbMmu::builder()
+            .ppu(Box::new(BusyPpu::default()))
+            .dma_bus_conflicts(true)
+            .build();
+        fill(&mut mmu);
+        for i in 0..0xa0 {
+            assert_eq!(mmu.read(Addr::from(0xfe00 + i)), 0x77);
+        }
+    }
+
+    #[test]
+    fn checked_array_out_of_range() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static OUT_OF_RANGE: AtomicUsize = AtomicUsize::new(0);
+        fn count(_addr: Addr, _write: Option<u8>) {
+            OUT_OF_RANGE.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut mem = CheckedArray::new([0u8; 4])
+            .with_out_of_range_value(0xab)
+            .with_hook(count);
+        mem.write(3.into(), 0x12);
+        mem.write(4.into(), 0x34);
+        assert_eq!(mem.read(3.into()), 0x12);
+        assert_eq!(mem.read(4.into()), 0xab);
+        assert_eq!(mem.data(), &[0, 0, 0, 0x12]);
+        assert_eq!(OUT_OF_RANGE.load(Ordering::Relaxed), 2);
+
+        // Without a hook, accesses are only logged.
+        let mut mem = CheckedArray::new([0u8; 4]);
+        mem.write(0x100.into(), 1);
+        assert_eq!(mem.read(0x100.into()), 0xff);
+    }
+
+    #[test]
+    fn interrupt_controller_registers() {
+        use crate::interrupts::Interrupt;
+
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xffff, 0x05);
+        mmu.io_mut()
+            .request_interrupt(InterruptFlags::TIMER | InterruptFlags::SERIAL);
+        assert_eq!(mmu.read_raw(0xff0f), 0xec);
+        assert_eq!(mmu.interrupts().pending(), Some(Interrupt::Timer));
+        mmu.interrupts_mut().acknowledge(Interrupt::Timer);
+        assert_eq!(mmu.read_raw(0xff0f), 0xe8);
+        assert_eq!(mmu.interrupts().pending(), None);
+        mmu.write_raw(0xffff, 0x08);
+        assert_eq!(mmu.interrupts().pending(), Some(Interrupt::Serial));
+        assert_eq!(
+            InterruptFlags::get_interrupt_enable(&mmu),
+            InterruptFlags::SERIAL
+        );
+
+        // Both registers are part of snapshots and cleared by a reset.
+        let snapshot = mmu.snapshot();
+        mmu.hard_reset();
+        assert_eq!(mmu.read_raw(0xffff), 0x00);
+        assert_eq!(mmu.read_raw(0xff0f), 0xe0);
+        mmu.restore(&snapshot);
+        assert_eq!(mmu.interrupts().pending(), Some(Interrupt::Serial));
+    }
+
+    #[test]
+    fn nested_relative_access() {
+        let mut mmu = GbMmu::default();
+        // An outer map which put this mmu at 0x3f80 would hand it 0xff85 offset to 0xc005.
+        let addr = Addr::from(0xff85).offset_by(0x3f80);
+        mmu.write_relative(addr, 0x42);
+        assert_eq!(mmu.read_raw(0xc005), 0x42);
+        assert_eq!(mmu.read_raw(0xff85), 0x00);
+        assert_eq!(mmu.read_relative(addr), 0x42);
+        // The plain path decodes the raw address instead.
+        assert_eq!(mmu.read(addr), 0x00);
+
+        // The bios check uses the relative address too.
+        let mut bios = [0u8; 0x100];
+        bios[0x10] = 0x31;
+        mmu.load_bios(&bios).unwrap();
+        assert_eq!(
+            mmu.read_relative(Addr::from(0x4010).offset_by(0x4000)),
+            0x31
+        );
+    }
+
+    #[test]
+    fn load_from_hex() {
+        let mem = CheckedArray::<4>::from_hex("de ad\nbe ef").unwrap();
+        assert_eq!(mem.data(), &[0xde, 0xad, 0xbe, 0xef]);
+        assert!(matches!(
+            CheckedArray::<4>::from_hex("dead"),
+            Err(HexParseError::WrongLength {
+                expected: 4,
+                actual: 2
+            })
+        ));
+
+        let hex = "31 fe ff af".repeat(64);
+        let bios = BiosRom::from_hex(&hex).unwrap();
+        assert_eq!(bios.read(0x00.into()), 0x31);
+        assert_eq!(bios.read(0xff.into()), 0xaf);
+        assert!(matches!(
+            BiosRom::from_hex(&hex[..hex.len() - 1]),
+            Err(BiosLoadError::Hex(HexParseError::OddLength(511)))
+        ));
+        assert!(matches!(
+            BiosRom::from_hex("00"),
+            Err(BiosLoadError::Size(BiosSizeError(1)))
+        ));
+    }
+
+    #[test]
+    fn empty_slot_reads_open_bus() {
+        let mmu = GbMmu::default();
+        // Bios covers the first 256 bytes until disabled.
+        assert_eq!(mmu.read_raw(0x0100), 0xff);
+        assert_eq!(mmu.read_raw(0x0134), 0xff);
+        assert_eq!(mmu.read_raw(0x7fff), 0xff);
+        assert_eq!(mmu.read_raw(0xa000), 0xff);
+        assert_eq!(mmu.read_raw(0xbfff), 0xff);
+        assert!(mmu.empty_slot_writes().is_none());
+    }
+
+    #[test]
+    fn empty_slot_write_capture() {
+        let mut mmu = GbMmu::builder().capture_empty_slot_writes(2).build();
+        mmu.write_raw(0x0000, 0x0a);
+        mmu.write_raw(0xc000, 0x01);
+        mmu.write_raw(0x2000, 0x01);
+        mmu.write_raw(0xa123, 0x55);
+        assert_eq!(mmu.read_raw(0xa123), 0xff);
+
+        let log = mmu.empty_slot_writes().unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(
+            log.iter().collect::<Vec<_>>(),
+            vec![(0x2000, 0x01), (0xa123, 0x55)]
+        );
+    }
+
+    #[test]
+    fn permissions() {
+        let both = Permissions::READ | Permissions::WRITE;
+        let mut mmu = GbMmu::default();
+        // The bios is mapped over an empty slot.
+        assert_eq!(mmu.permissions(0x0000), Permissions::READ);
+        assert_eq!(mmu.permissions(0x0100), Permissions::empty());
+        assert_eq!(mmu.permissions(0xa000), Permissions::empty());
+        assert_eq!(mmu.permissions(0xc000), both);
+        assert_eq!(mmu.permissions(0xe000), both);
+        assert_eq!(mmu.permissions(0xfea0), Permissions::READ);
+        assert_eq!(mmu.permissions(0xff40), both);
+        assert_eq!(mmu.permissions(0xffff), both);
+
+        // The PPU locks out video ram while drawing, and OAM during the OAM scan as well.
+        assert_eq!(mmu.permissions(0x8000), both);
+        assert_eq!(mmu.permissions(0xfe00), both);
+        mmu.io_mut().set_lcd_mode(LcdMode::OamScan);
+        assert_eq!(mmu.permissions(0x9fff), both);
+        assert_eq!(mmu.permissions(0xfe9f), Permissions::empty());
+        mmu.io_mut().set_lcd_mode(LcdMode::Drawing);
+        assert_eq!(mmu.permissions(0x8000), Permissions::empty());
+        assert_eq!(mmu.permissions(0xfe00), Permissions::empty());
+        assert_eq!(mmu.unrestricted().permissions(0x8000), both);
+
+        // Cartridge ram follows the mapper's ram enable.
+        let banks = vec![ReadOnly::new([0u8; 0x4000]); 2];
+        let mut mmu = GbMmu::new(
+            BiosRom::default(),
+            Cartridge::Mbc1(Mbc1Rom::new(banks, 1, false)),
+        );
+        assert_eq!(mmu.permissions(0x0100), Permissions::READ);
+        assert_eq!(mmu.permissions(0xa000), Permissions::empty());
+        // Writes go to the bios while it is mapped, so it must be turned off first.
+        mmu.write_raw(0xff50, 0x01);
+        assert_eq!(mmu.permissions(0x0000), Permissions::READ);
+        mmu.write_raw(0x0000, 0x0a);
+        assert_eq!(mmu.permissions(0xbfff), both);
+    }
+
+    #[test]
+    fn rom_write_warnings() {
+        let warned = Rc::new(RefCell::new(Vec::new()));
+        let log = warned.clone();
+        let mut mmu = GbMmu::builder()
+            .warn_rom_writes(Box::new(move |addr, value| {
+                log.borrow_mut().push((addr, value))
+            }))
+            .build();
+        // With no cartridge, every write to the rom area is suspicious, but only CPU writes are
+        // checked.
+        mmu.write_raw(0x2000, 0x01);
+        mmu.write_raw(0x7fff, 0x02);
+        mmu.write_raw(0x8000, 0x03);
+        mmu.write_raw(0xa000, 0x04);
+        mmu.poke(0x1000, 0x05);
+        assert_eq!(*warned.borrow(), [(0x2000, 0x01), (0x7fff, 0x02)]);
+        assert_eq!(mmu.suspicious_rom_writes(), 2);
+
+        // Mapper register writes are expected.
+        let mut mmu = GbMmu::builder()
+            .cartridge(Cartridge::Mbc1(Mbc1Rom::new(
+                vec![ReadOnly::new([0u8; 0x4000]); 2],
+                0,
+                false,
+            )))
+            .warn_rom_writes(Box::new(|addr, value| {
+                panic!("Unexpected warning for {:#06X} <- {:#04X}", addr, value)
+            }))
+            .build();
+        mmu.write_raw(0x2000, 0x01);
+        mmu.write_raw(0x0000, 0x0a);
+        assert_eq!(mmu.suspicious_rom_writes(), 0);
+
+        // Without the warning, nothing is counted.
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0x2000, 0x01);
+        assert_eq!(mmu.suspicious_rom_writes(), 0);
+    }
+
+    #[test]
+    fn concat_splits_addresses() {
+        let mut rom = [0u8; 0x10];
+        rom[0x0f] = 0x42;
+        let mut mem = Concat::new(ReadOnly::new(rom), [0u8; 0x20], 0x10);
+        assert_eq!(mem.size(), 0x30);
+        assert_eq!(mem.read(0x0f.into()), 0x42);
+        mem.write(0x0f.into(), 0x00);
+        assert_eq!(mem.read(0x0f.into()), 0x42);
+
+        mem.write(0x10.into(), 0x11);
+        mem.write(0x2f.into(), 0x22);
+        assert_eq!(mem.1[0x00], 0x11);
+        assert_eq!(mem.1[0x1f], 0x22);
+        assert_eq!(mem.read(0x2f.into()), 0x22);
+
+        // Concat nests under an offset like any other device.
+        let addr = Addr::from(0x1010).offset_by(0x1000);
+        assert_eq!(mem.read(addr), 0x11);
+    }
+
+    #[test]
+    fn partial_read_only() {
+        let mut mem = PartialReadOnly::new([0u8; 0x20], (0x04, 0x07));
+        assert_eq!(mem.size(), 0x20);
+        for addr in 0..0x20u16 {
+            mem.write(addr.into(), 0xaa);
+        }
+        assert_eq!(mem.read(0x03.into()), 0xaa);
+        assert_eq!(mem.read(0x04.into()), 0x00);
+        assert_eq!(mem.read(0x07.into()), 0x00);
+        assert_eq!(mem.read(0x08.into()), 0xaa);
+
+        // The range is relative to the device, so it moves with an offset.
+        let addr = Addr::from(0xc005).offset_by(0xc000);
+        mem.write(addr, 0x55);
+        assert_eq!(mem.read(addr), 0x00);
+        assert_eq!(mem.into_inner()[0x1f], 0xaa);
+    }
+
+    #[test]
+    #[should_panic(expected = "read-only address")]
+    fn partial_read_only_panics() {
+        let mut mem = PartialReadOnly::new([0u8; 0x20], (0x04, 0x07)).panic_on_write(true);
+        mem.write(0x08.into(), 0x01);
+        mem.write(0x04.into(), 0x01);
+    }
+
+    #[test]
+    fn checked_offset() {
+        let addr = Addr::from(0xff42).offset_by(0xff00);
+        let offset = addr.checked_offset_by(0x40).unwrap();
+        assert_eq!(offset.raw(), 0xff42);
+        assert_eq!(offset.relative(), 0x02);
+        assert_eq!(addr.checked_offset_by(0x42).unwrap().relative(), 0);
+        assert!(addr.checked_offset_by(0x43).is_none());
+    }
+
+    #[test]
+    fn with_offset() {
+        let addr = Addr::with_offset(0xff42, 0xff00);
+        assert_eq!(
+            (addr.raw(), addr.relative(), addr.offset()),
+            (0xff42, 0x42, 0xff00)
+        );
+        let plain = Addr::with_offset(0x1234, 0);
+        assert_eq!((plain.raw(), plain.relative()), (0x1234, 0x1234));
+
+        assert_eq!(
+            Addr::checked_with_offset(0xff42, 0xff42)
+                .unwrap()
+                .relative(),
+            0
+        );
+        assert!(Addr::checked_with_offset(0xff42, 0xff43).is_none());
+
+        // Sub-devices see only the relative address.
+        let mut mem = [0u8; 0x10];
+        mem.write(Addr::with_offset(0xc005, 0xc000), 0x42);
+        assert_eq!(mem[5], 0x42);
+    }
+
+    #[test]
+    fn offset_address_uses_raw() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xc010, 0x42);
+        let offset = Addr::from(0xc010).offset_by(0x10);
+        assert_eq!(mmu.read(offset), 0x42);
+        mmu.write(offset, 0x43);
+        assert_eq!(mmu.read_raw(0xc010), 0x43);
+        assert_eq!(mmu.read_raw(0xc000), 0x00);
+    }
+
+    #[test]
+    fn try_access_reports_offset() {
+        let mut mmu = GbMmu::default();
+        let offset = Addr::from(0xc010).offset_by(0x10);
+        let err = MemError::UnexpectedOffset {
+            raw: 0xc010,
+            relative: 0xc000,
+        };
+        assert_eq!(mmu.try_read(offset), Err(err));
+        assert_eq!(mmu.try_write(offset, 0x42), Err(err));
+        assert_eq!(mmu.read_raw(0xc010), 0x00);
+
+        assert_eq!(mmu.try_write(0xc010.into(), 0x42), Ok(()));
+        assert_eq!(mmu.try_read(0xc010.into()), Ok(0x42));
+    }
+
+    /// Builds a minimal 2-bank RomOnly cartridge image with the given title and a valid header
+    /// checksum.
+    fn rom_only_image(title: &str) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+        rom[0x14d] = rom[0x134..=0x14c]
+            .iter()
+            .fold(0u8, |x, &h| x.wrapping_sub(h).wrapping_sub(1));
+        rom[0x4000] = 0x42;
+        rom
+    }
+
+    #[test]
+    fn dump_restore_round_trip() {
+        let mut mmu = GbMmu::default();
+        for (i, addr) in (0xc000..=0xc0ff).enumerate() {
+            mmu.write_raw(addr, i as u8);
+        }
+        let mut dump = Vec::new();
+        mmu.dump_range(0xc000..=0xc0ff, &mut dump).unwrap();
+        assert_eq!(dump, (0..=0xff).collect::<Vec<u8>>());
+
+        let mut restored = GbMmu::default();
+        restored
+            .restore_range(0xd000..=0xd0ff, &dump[..], false)
+            .unwrap();
+        crate::testutils::assert_mem_eq(&restored, 0xd000, &dump);
+
+        // Short input fails without writing anything.
+        let err = restored
+            .restore_range(0xc000..=0xc0ff, &dump[..0x80], false)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(restored.read_raw(0xc001), 0);
+    }
+
+    #[test]
+    fn open_bus_unusable_region() {
+        let mut mmu = GbMmu::builder().open_bus(true).build();
+        mmu.write_raw(0xc000, 0x42);
+        assert_eq!(mmu.last_bus_value(), 0x42);
+        assert_eq!(mmu.read_raw(0xfea0), 0x42);
+        mmu.write_raw(0xc001, 0x17);
+        assert_eq!(mmu.read_raw(0xfeff), 0x17);
+        // Reads drive the bus too.
+        assert_eq!(mmu.read_raw(0xc000), 0x42);
+        assert_eq!(mmu.read_raw(0xfef0), 0x42);
+        // Debug accesses leave the bus alone.
+        mmu.poke(0xc002, 0x99);
+        assert_eq!(mmu.peek(0xc002), 0x99);
+        assert_eq!(mmu.read_raw(0xfea0), 0x42);
+        // IE is a real register, not open bus.
+        assert_eq!(mmu.read_raw(0xffff), 0x00);
+        assert_eq!(mmu.read_raw(0xfea0), 0x00);
+
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xc000, 0x42);
+        assert_eq!(mmu.read_raw(0xfea0), 0x00);
+    }
+
+    #[test]
+    fn scan_for_values() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff80, 0xfa);
+        mmu.poke(0xc123, 0xfa);
+        mmu.poke(0x8001, 0xfa);
+        // Everything else is zero, or reads 0xFF, which isn't being searched for.
+        assert_eq!(mmu.scan(0xfa), [0x8001, 0xc123, 0xe123, 0xff80]);
+
+        let before = mmu.dump();
+        assert_eq!(before[0xc123], 0xfa);
+        assert!(mmu.scan_changed(&before).is_empty());
+        mmu.poke(0xc123, 0xf9);
+        mmu.poke(0xd000, 0x01);
+        assert_eq!(mmu.scan_changed(&before), [0xc123, 0xd000, 0xe123, 0xf000]);
+    }
+
+    #[test]
+    fn dump_restore_across_vram_and_cart_ram() {
+        // RomOnly cartridge with a ram bank.
+        let mut rom = rom_only_image("RAM");
+        rom[0x147] = 0x08;
+        rom[0x149] = 0x03;
+        let mut mmu = GbMmu::default();
+        mmu.load_rom(&rom).unwrap();
+        mmu.write_raw(0x9fff, 0x12);
+        mmu.write_raw(0xa000, 0x34);
+
+        let mut dump = Vec::new();
+        mmu.dump_range(0x9ffe..=0xa001, &mut dump).unwrap();
+        assert_eq!(dump, [0, 0x12, 0x34, 0]);
+
+        let mut restored = GbMmu::default();
+        restored.load_rom(&rom).unwrap();
+        restored
+            .restore_range(0x9ffe..=0xa001, &dump[..], true)
+            .unwrap();
+        let mut redump = Vec::new();
+        restored.dump_range(0x9ffe..=0xa001, &mut redump).unwrap();
+        assert_eq!(redump, dump);
+    }
+
+    #[test]
+    fn raw_restore_skips_mbc_registers() {
+        let mut mmu = GbMmu::builder().access_stats(true).build();
+        mmu.restore_range(0x3ffe..=0x8001, &[0xffu8; 0x4004][..], true)
+            .unwrap();
+        assert_eq!(mmu.read_raw(0x8001), 0xff);
+        // Only the read above was counted.
+        assert_eq!(mmu.stats().unwrap().total(), 1);
+
+        mmu.restore_range(0x3ffe..=0x8001, &[0xffu8; 0x4004][..], false)
+            .unwrap();
+        assert_eq!(mmu.stats().unwrap().writes(Region::Rom), 0x4002);
+
+        // Peeking doesn't count.
+        mmu.peek(0x8001);
+        mmu.dump_range(0x0..=0xffff, io::sink()).unwrap();
+        assert_eq!(mmu.stats().unwrap().reads(Region::Vram), 1);
+    }
+
+    #[test]
+    fn game_genie_across_bank_switch() {
+        // Rom where 0x4a17 holds 0xc8 in bank 1 and 0xc9 in bank 2.
+        let banks = (0..4)
+            .map(|i| {
+                let mut bank = [0u8; 0x4000];
+                bank[0x0a17] = 0xc7 + i as u8;
+                ReadOnly::new(bank)
+            })
+            .collect();
+        let cart = Cartridge::Mbc1(Mbc1Rom::new(banks, 0, false));
+        let mut mmu = GbMmu::new(BiosRom::default(), cart);
+        let handle = mmu.cartridge_mut().add_game_genie("00A-17B-C49").unwrap();
+
+        // Bank 1 is selected, and its byte matches the compare value.
+        assert_eq!(mmu.read_raw(0x4a17), 0x00);
+        mmu.write_raw(0x2000, 2);
+        assert_eq!(mmu.read_raw(0x4a17), 0xc9);
+        mmu.write_raw(0x2000, 1);
+        assert_eq!(mmu.read_raw(0x4a17), 0x00);
+
+        assert!(mmu.cartridge_mut().remove_patch(handle).is_some());
+        assert_eq!(mmu.read_raw(0x4a17), 0xc8);
+    }
+
+    #[test]
+    fn reset_preserves_selected_regions() {
+        // One marker address in each ram region.
+        let regions = [
+            (RegionMask::WRAM, 0xc123),
+            (RegionMask::VRAM, 0x8123),
+            (RegionMask::OAM, 0xfe12),
+            (RegionMask::HRAM, 0xff90),
+            (RegionMask::CART_RAM, 0xa123),
+        ];
+        let enable_cart_ram = |mmu: &mut GbMmu| mmu.write_raw(0x1000, 0x0a);
+        for &(preserved, _) in regions.iter() {
+            let banks = vec![ReadOnly::new([0u8; 0x4000]); 2];
+            let cart = Cartridge::Mbc1(Mbc1Rom::new(banks, 1, true));
+            let mut mmu = GbMmu::new(BiosRom::default(), cart);
+            enable_cart_ram(&mut mmu);
+            for &(_, addr) in regions.iter() {
+                mmu.write_raw(addr, 0x5a);
+            }
+            mmu.write_raw(0xff50, 1);
+            mmu.write_raw(0xffff, 0x1f);
+
+            mmu.reset(preserved);
+            assert!(mmu.io().bios_enabled());
+            assert_eq!(mmu.read_raw(0xffff), 0);
+            // Cartridge ram is disabled again by the reset.
+            assert_eq!(mmu.read_raw(0xa123), 0xff);
+            enable_cart_ram(&mut mmu);
+            for &(region, addr) in regions.iter() {
+                let expected = if region == preserved { 0x5a } else { 0 };
+                assert_eq!(mmu.read_raw(addr), expected, "{:?}", region);
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_ram_init_is_reproducible() {
+        let build = |seed| GbMmu::builder().ram_init(RamInit::Seeded(seed)).build();
+        let dump = |mmu: &GbMmu| {
+            let mut dump = Vec::new();
+            mmu.dump_range(0x8000..=0x9fff, &mut dump).unwrap();
+            mmu.dump_range(0xc000..=0xdfff, &mut dump).unwrap();
+            mmu.dump_range(0xff80..=0xfffe, &mut dump).unwrap();
+            dump
+        };
+        let mut mmu = build(42);
+        let first = dump(&mmu);
+        assert_eq!(first, dump(&build(42)));
+        assert_ne!(first, dump(&build(43)));
+        // The pattern isn't trivially repetitive.
+        assert!(first.windows(2).any(|pair| pair[0] != pair[1]));
+
+        // A reset which clears ram restores the same pattern.
+        mmu.write_raw(0xc000, !first[0x2000]);
+        mmu.hard_reset();
+        assert_eq!(dump(&mmu), first);
+        assert_eq!(dump(&GbMmu::default()), vec![0; first.len()]);
+    }
+
+    #[test]
+    fn high_ram() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff80, 0x12);
+        mmu.write_raw(0xfffe, 0x34);
+        assert_eq!(mmu.high_ram().data()[0], 0x12);
+        assert_eq!(mmu.high_ram().data()[126], 0x34);
+        assert_eq!(mmu.high_ram().read(0x7e.into()), 0x34);
+    }
+
+    #[test]
+    fn hard_and_soft_reset() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xc000, 1);
+        mmu.soft_reset();
+        assert_eq!(mmu.read_raw(0xc000), 1);
+        mmu.hard_reset();
+        assert_eq!(mmu.read_raw(0xc000), 0);
+    }
+
+    #[test]
+    fn peek_and_poke() {
+        let mut mmu = GbMmu::builder().access_stats(true).build();
+        mmu.poke(0xc000, 0x12);
+        mmu.poke_u16(0xc001, 0x5678);
+        assert_eq!(mmu.peek(0xc000), 0x12);
+        assert_eq!(mmu.peek(0xc001), 0x78);
+        assert_eq!(mmu.peek_u16(0xc001), 0x5678);
+        assert_eq!(mmu.peek_u16(0xbfff), 0x12ff);
+        assert_eq!(mmu.stats().unwrap().total(), 0);
+
+        // 16 bit accesses wrap around the end of memory, where the high byte comes from the
+        // read-only bios.
+        mmu.poke_u16(0xffff, 0xab1f);
+        assert_eq!(mmu.peek_u16(0xffff), 0x001f);
+    }
+
+    #[test]
+    fn hash_region_is_fnv1a() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xc000, b'a');
+        assert_eq!(mmu.hash_region(0xc000..=0xc000), 0xaf63dc4c8601ec8c);
+        mmu.write_raw(0xc001, b'b');
+        assert_eq!(mmu.hash_region(0xc000..=0xc001), 0x089c4407b545986a);
+    }
+
+    #[test]
+    fn hash_state() {
+        let mut mmu = GbMmu::builder().access_stats(true).build();
+        let initial = mmu.hash_state();
+        assert_eq!(mmu.hash_state(), initial);
+        // Hashing doesn't count as access.
+        assert_eq!(mmu.stats().unwrap().total(), 0);
+
+        mmu.write_raw(0xff80, 1);
+        let changed = mmu.hash_state();
+        assert_ne!(changed, initial);
+        // Excluded ranges are ignored.
+        let excluded = mmu.hash_state_excluding(&[0xff80..=0xff80, 0xc000..=0xc0ff]);
+        mmu.write_raw(0xc010, 1);
+        assert_ne!(mmu.hash_state(), changed);
+        assert_eq!(
+            mmu.hash_state_excluding(&[0xff80..=0xff80, 0xc000..=0xc0ff]),
+            excluded
+        );
+    }
+
+    #[test]
+    fn hardware_model() {
+        assert_eq!(GbMmu::default().model(), HardwareModel::Dmg);
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        mmu.hard_reset();
+        assert_eq!(mmu.model(), HardwareModel::Cgb);
+        assert!(mmu.model().cgb_features());
+        assert!(HardwareModel::CgbDmgMode.is_cgb_hardware());
+        assert!(!HardwareModel::CgbDmgMode.cgb_features());
+
+        let mut rom = rom_only_image("COLOR");
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(HardwareModel::preferred_for(&header), HardwareModel::Dmg);
+        rom[0x143] = 0x80;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(HardwareModel::preferred_for(&header), HardwareModel::Cgb);
+    }
+
+    #[test]
+    fn overlay_shadows_range() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xc010, 0x12);
+        let handle = mmu.overlay(0xc000..=0xc0ff, Box::new([0xabu8; 0x100]));
+        assert_eq!(mmu.read_raw(0xc010), 0xab);
+        mmu.write_raw(0xc011, 0x34);
+        // Addresses outside of the overlay still go to the normal target.
+        assert_eq!(mmu.read_raw(0xc100), 0);
+
+        // A newer overlay takes priority where they overlap.
+        let inner = mmu.overlay(0xc010..=0xc010, Box::new([0xcdu8; 1]));
+        assert_eq!(mmu.read_raw(0xc010), 0xcd);
+        assert_eq!(mmu.read_raw(0xc011), 0x34);
+        mmu.remove_overlay(inner).unwrap();
+
+        let device = mmu.remove_overlay(handle).unwrap();
+        assert!(mmu.remove_overlay(handle).is_none());
+        assert_eq!(device.read(0x11.into()), 0x34);
+        assert_eq!(mmu.read_raw(0xc010), 0x12);
+        assert_eq!(mmu.read_raw(0xc011), 0);
+    }
+
+    #[test]
+    fn device_sizes() {
+        assert_eq!([0u8; 12].size(), 12);
+        assert_eq!(NullRom::<0x100>.size(), 0x100);
+        assert_eq!(BiosRom::default().size(), 0x100);
+        assert_eq!(GbMmu::default().size(), 0x10000);
+        let boxed: Box<dyn MemDevice> = Box::new(ReadOnly::new([0u8; 3]));
+        assert_eq!(boxed.size(), 3);
+        assert_eq!(NullPpu::new().size(), usize::MAX);
+
+        assert_eq!([1u8, 2, 3].read_all(), [1, 2, 3]);
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xc000, 0x12);
+        let all = mmu.read_all();
+        assert_eq!(all.len(), 0x10000);
+        assert_eq!(all[0xc000], 0x12);
+    }
+
+    #[test]
+    fn watchpoints_distinguish_execute_from_data() {
+        let mut mmu = GbMmu::default();
+        // A tiny program at 0xc000: LD A,(0xc000), which reads its own opcode as data.
+        for (i, &byte) in [0xfa, 0x00, 0xc0].iter().enumerate() {
+            mmu.poke(0xc000 + i as u16, byte);
+        }
+        let exec = mmu.add_watchpoint(Watchpoint::execute(0xc000..=0xc000));
+        let data = mmu.add_watchpoint(Watchpoint::read(0xc000..=0xc000).kind(AccessKind::Data));
+        let any = mmu.add_watchpoint(Watchpoint::read(0xc000..=0xc0ff));
+
+        // Fake CPU loop: fetch the opcode and operands as Execute, then do the load as Data.
+        let mut pc = 0xc000;
+        let opcode = mmu.with_access_kind(AccessKind::Execute, |m| m.read(pc.into()));
+        assert_eq!(opcode, 0xfa);
+        pc += 1;
+        let operand = mmu.with_access_kind(AccessKind::Execute, |m| {
+            u16::from_le_bytes([m.read(pc.into()), m.read((pc + 1).into())])
+        });
+        assert_eq!(mmu.access_kind(), AccessKind::Data);
+        assert_eq!(mmu.read(operand.into()), 0xfa);
+
+        let hits = mmu.take_watch_hits();
+        let handles: Vec<_> = hits.iter().map(|hit| (hit.handle, hit.kind)).collect();
+        assert_eq!(
+            handles,
+            [
+                (exec, AccessKind::Execute),
+                (any, AccessKind::Execute),
+                (any, AccessKind::Execute),
+                (any, AccessKind::Execute),
+                (data, AccessKind::Data),
+                (any, AccessKind::Data),
+            ]
+        );
+        assert!(hits.iter().all(|hit| !hit.write));
+        assert!(mmu.take_watch_hits().is_empty());
+
+        // OAM DMA reads are tagged as such, and the debug path isn't watched.
+        mmu.remove_watchpoint(exec).unwrap();
+        mmu.remove_watchpoint(data).unwrap();
+        mmu.add_watchpoint(Watchpoint::write(0xc000..=0xc000));
+        mmu.peek(0xc010);
+        mmu.poke(0xc000, 0);
+        assert!(mmu.take_watch_hits().is_empty());
+        mmu.write_raw(0xff46, 0xc0);
+        let hits = mmu.take_watch_hits();
+        assert_eq!(hits.len(), 0xa0);
+        assert!(hits.iter().all(|hit| hit.kind == AccessKind::Dma));
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let rom_banks = vec![ReadOnly::new([0u8; 0x4000]); 4];
+        let mut mmu = GbMmu::new(
+            BiosRom::default(),
+            Cartridge::Mbc1(Mbc1Rom::new(rom_banks, 1, false)),
+        );
+        mmu.write_raw(0xc000, 1);
+        mmu.write_raw(0x1000, 0x0a);
+        mmu.write_raw(0xa000, 2);
+        mmu.write_raw(0xff80, 3);
+        let snapshot = mmu.snapshot();
+        // Nothing has been copied yet.
+        assert_eq!(mmu.wram.shared_chunks(), 0x20);
+
+        mmu.write_raw(0xc000, 0x11);
+        mmu.write_raw(0xa000, 0x22);
+        mmu.write_raw(0xff80, 0x33);
+        mmu.write_raw(0x2000, 0x02);
+        assert_eq!(mmu.wram.shared_chunks(), 0x1f);
+
+        mmu.restore(&snapshot);
+        assert_eq!(mmu.read_raw(0xc000), 1);
+        assert_eq!(mmu.read_raw(0xa000), 2);
+        assert_eq!(mmu.read_raw(0xff80), 3);
+        // The mapper registers are restored too, so bank 1 is mapped again.
+        assert_eq!(
+            mmu.cartridge().inner().physical_offset(0x4000.into()),
+            Some(0x4000)
+        );
+    }
+
+    #[test]
+    fn load_bios() {
+        let mut mmu = GbMmu::default();
+        let mut bios = [0u8; 0x100];
+        bios[0x10] = 0x31;
+        mmu.load_bios(&bios).unwrap();
+        assert_eq!(mmu.read_raw(0x10), 0x31);
+
+        let err = mmu.load_bios(&bios[..0x80]).unwrap_err();
+        assert!(matches!(err, MmuLoadError::Bios(BiosSizeError(0x80))));
+        // Old bios is still installed.
+        assert_eq!(mmu.read_raw(0x10), 0x31);
+    }
+
+    #[test]
+    fn load_rom() {
+        let mut mmu = GbMmu::default();
+        let header = mmu.load_rom(&rom_only_image("FEO3TEST")).unwrap();
+        assert_eq!(header.title, "FEO3TEST");
+        assert_eq!(header.cartridge_type, 0);
+        assert!(header.header_checksum_valid);
+        assert_eq!(mmu.read_raw(0x4000), 0x42);
+
+        let err = mmu.load_rom(&[0u8; 0x100]).unwrap_err();
+        assert!(matches!(
+            err,
+            MmuLoadError::Cartridge(ParseCartridgeError::InsufficientData(_))
+        ));
+        // Old cartridge is still installed.
+        assert_eq!(mmu.read_raw(0x4000), 0x42);
+    }
+
+    #[test]
+    fn cgb_only_rom_requires_cgb() {
+        let mut rom = rom_only_image("CGBONLY");
+        rom[0x143] = 0xc0;
+        let mut mmu = GbMmu::default();
+        assert!(matches!(
+            mmu.load_rom(&rom),
+            Err(MmuLoadError::RequiresCgb(HardwareModel::Dmg))
+        ));
+        assert_eq!(mmu.read_raw(0x4000), 0xff);
+
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        let header = mmu.load_rom(&rom).unwrap();
+        assert_eq!(header.cgb_support(), CgbSupport::CgbOnly);
+        assert_eq!(mmu.read_raw(0x4000), 0x42);
+
+        // CGB-enhanced games still run on a DMG.
+        rom[0x143] = 0x80;
+        let mut mmu = GbMmu::default();
+        let header = mmu.load_rom(&rom).unwrap();
+        assert_eq!(header.cgb_support(), CgbSupport::CgbEnhanced);
+    }
+
+    #[test]
+    fn stats_disabled_by_default() {
+        let mmu = GbMmu::default();
+        mmu.read_raw(0xc000);
+        assert_eq!(mmu.stats(), None);
+    }
+
+    #[test]
+    fn stats_count_by_region() {
+        let mut mmu = GbMmu::builder().access_stats(true).build();
+        mmu.read_raw(0x0010);
+        mmu.write_raw(0xff50, 1);
+        mmu.read_raw(0x0010);
+        mmu.write_raw(0xc000, 1);
+        mmu.write_raw(0xdfff, 1);
+        mmu.read_raw(0xe000);
+        for _ in 0..3 {
+            mmu.read_raw(0xff44);
+        }
+
+        let stats = mmu.stats().unwrap();
+        assert_eq!(stats.reads(Region::Bios), 1);
+        assert_eq!(stats.reads(Region::Rom), 1);
+        assert_eq!(stats.writes(Region::Wram), 2);
+        assert_eq!(stats.reads(Region::EchoRam), 1);
+        assert_eq!(stats.reads(Region::Io), 3);
+        assert_eq!(stats.writes(Region::Io), 1);
+        assert_eq!(stats.total(), 9);
+        assert_eq!(stats.page_reads(0xff), None);
+
+        mmu.reset_stats();
+        assert_eq!(mmu.stats().unwrap().total(), 0);
+    }
+
+    #[test]
+    fn stats_page_histogram() {
+        let mmu = GbMmu::builder().page_histogram(true).build();
+        for _ in 0..3 {
+            mmu.read_raw(0xff44);
+        }
+        mmu.read_raw(0xff80);
+        mmu.read_raw(0x1234);
+
+        let stats = mmu.stats().unwrap();
+        assert_eq!(stats.page_reads(0xff), Some(4));
+        assert_eq!(stats.page_reads(0x12), Some(1));
+        assert_eq!(stats.page_writes(0xff), Some(0));
+        // The most accessed region is printed first.
+        let table = stats.to_string();
+        let io = table.find("IO").unwrap();
+        let hram = table.find("HRAM").unwrap();
+        assert!(io < hram, "{}", table);
+        assert!(table.contains("FFxx"), "{}", table);
+    }
+
+    #[test]
+    fn lcd_registers_through_io() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff40, 0x91);
+        mmu.write_raw(0xff42, 0x10);
+        mmu.write_raw(0xff43, 0x20);
+        mmu.write_raw(0xff4a, 0x30);
+        mmu.write_raw(0xff4b, 0x47);
+        let lcd = mmu.io().lcd();
+        assert!(lcd
+            .lcdc()
+            .contains(LcdcFlags::LCD_ENABLE | LcdcFlags::BG_ENABLE));
+        assert_eq!(lcd.scroll(), (0x20, 0x10));
+        assert_eq!(lcd.window(), (0x47, 0x30));
+        assert_eq!(mmu.read_raw(0xff4b), 0x47);
+
+        mmu.io_mut().set_lcd_mode(LcdMode::Drawing);
+        mmu.io_mut().set_ly(0x90);
+        assert_eq!(mmu.read_raw(0xff41) & 0x03, 0x03);
+        assert_eq!(mmu.read_raw(0xff44), 0x90);
+        mmu.write_raw(0xff47, 0xe4);
+        assert_eq!(mmu.read_raw(0xff47), 0xe4);
+        assert_eq!(mmu.io().lcd().bg_palette().shade(3), Shade::Black);
+    }
+
+    #[test]
+    fn lyc_coincidence_tracks_ly_and_lyc() {
+        let mut io = MemMappedIo::new();
+        // LY and LYC both start at 0.
+        assert!(io.lyc_coincidence());
+        io.write(0x45.into(), 10);
+        assert!(!io.lyc_coincidence());
+        assert_eq!(io.read(0x41.into()) & STAT_COINCIDENCE, 0);
+
+        io.set_ly(10);
+        assert!(io.lyc_coincidence());
+        assert_eq!(io.read(0x41.into()) & STAT_COINCIDENCE, STAT_COINCIDENCE);
+        // Interrupt wasn't enabled.
+        assert_eq!(io.take_requested_interrupts(), InterruptFlags::empty());
+
+        // The coincidence bit is read-only.
+        io.write(0x41.into(), 0x00);
+        assert!(io.lyc_coincidence());
+        // So is LY.
+        io.write(0x44.into(), 0x00);
+        assert_eq!(io.ly(), 10);
+    }
+
+    #[test]
+    fn lyc_coincidence_interrupt_on_rising_edge() {
+        let mut io = MemMappedIo::new();
+        io.write(0x41.into(), STAT_COINCIDENCE_INTERRUPT);
+        io.write(0x45.into(), 3);
+        io.take_requested_interrupts();
+
+        let mut count = 0;
+        for ly in 0..=153 {
+            io.set_ly(ly);
+            if io
+                .take_requested_interrupts()
+                .contains(InterruptFlags::STAT)
+            {
+                count += 1;
+                assert_eq!(ly, 3);
+            }
+        }
+        assert_eq!(count, 1);
+
+        // Staying on the same line doesn't re-trigger.
+        io.set_ly(3);
+        assert!(io
+            .take_requested_interrupts()
+            .contains(InterruptFlags::STAT));
+        io.set_ly(3);
+        assert_eq!(io.take_requested_interrupts(), InterruptFlags::empty());
+
+        // Writing LYC to match the current line does trigger.
+        io.write(0x45.into(), 4);
+        io.write(0x45.into(), 3);
+        assert!(io
+            .take_requested_interrupts()
+            .contains(InterruptFlags::STAT));
+    }
+
+    #[test]
+    fn interrupt_flags_register() {
+        let mut io = MemMappedIo::new();
+        assert_eq!(io.read(0x0f.into()), 0xe0);
+        // Only the low 5 bits are stored, and the rest read 1.
+        io.write(0x0f.into(), 0x1f);
+        assert_eq!(io.read(0x0f.into()), 0xff);
+        io.write(0x0f.into(), 0x25);
+        assert_eq!(io.read(0x0f.into()), 0xe5);
+        assert_eq!(
+            io.interrupt_flags(),
+            InterruptFlags::VBLANK | InterruptFlags::TIMER
+        );
+
+        // Components request interrupts, and the CPU clears them by writing IF.
+        io.write(0x0f.into(), 0x00);
+        io.request_interrupt(InterruptFlags::SERIAL);
+        io.request_interrupt(InterruptFlags::VBLANK);
+        assert_eq!(io.read(0x0f.into()), 0xe9);
+        assert_eq!(io.active_interrupts(0x08), InterruptFlags::SERIAL);
+        assert_eq!(io.active_interrupts(0xe6), InterruptFlags::empty());
+        let serviced = io.active_interrupts(0xff).highest_priority().unwrap();
+        assert_eq!(serviced, InterruptFlags::VBLANK);
+        io.write(0x0f.into(), io.read(0x0f.into()) & !serviced.bits());
+        assert_eq!(io.read(0x0f.into()), 0xe8);
+        assert_eq!(io.take_requested_interrupts(), InterruptFlags::SERIAL);
+        assert_eq!(io.read(0x0f.into()), 0xe0);
+    }
+
+    /// Value of every DMG IO register after writing 0x00 to all of them, in order.
+    #[rustfmt::skip]
+    const DMG_IO_AFTER_ZEROS: [u8; 0x80] = [
+        0xcf, 0x00, 0x7e, 0xff, 0x00, 0x00, 0x00, 0xf8,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xe0,
+        0x80, 0x3f, 0x00, 0xff, 0xbf, 0xff, 0x3f, 0x00,
+        0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff,
+        0xff, 0x00, 0x00, 0xbf, 0x00, 0x00, 0x70, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    ];
+
+    /// Value of every DMG IO register after writing 0xFF to all of them, in order.
+    #[rustfmt::skip]
+    const DMG_IO_AFTER_ONES: [u8; 0x80] = [
+        0xff, 0xff, 0x7f, 0xff, 0x00, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0x80, 0x3f, 0x00, 0xff, 0xbf, 0xff, 0x3f, 0x00,
+        0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff,
+        0xff, 0x00, 0x00, 0xbf, 0x00, 0x00, 0xf0, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xf8, 0xff, 0xff, 0x00, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    ];
+
+    #[test]
+    fn dmg_io_sweep() {
+        for &(value, expected) in [(0x00, &DMG_IO_AFTER_ZEROS), (0xff, &DMG_IO_AFTER_ONES)].iter() {
+            let mut io = MemMappedIo::new();
+            for index in 0..0x80u16 {
+                io.write(index.into(), value);
+            }
+            for index in 0..0x80u16 {
+                assert_eq!(
+                    io.read(index.into()),
+                    expected[index as usize],
+                    "{:#06X} after writing {:#04X}",
+                    0xff00 + index,
+                    value
+                );
+            }
+        }
+    }
+
+    /// IO device for testing, with two registers: a countdown, which requests a timer interrupt
+    /// each time it reaches 0 while ticking, and a plain register which requests a serial
+    /// interrupt whenever it is written.
+    #[derive(Clone, Debug, Default)]
+    struct CountdownDevice {
+        countdown: u8,
+        scratch: u8,
+        requested: InterruptFlags,
+    }
+
+    impl MemDevice for CountdownDevice {
+        fn read(&self, addr: Addr) -> u8 {
+            match addr.relative() {
+                0 => self.countdown,
+                1 => self.scratch,
+                _ => panic!("Address {} out of range for CountdownDevice", addr),
+            }
+        }
+
+        fn write(&mut self, addr: Addr, value: u8) {
+            match addr.relative() {
+                0 => self.countdown = value,
+                1 => {
+                    self.scratch = value;
+                    self.requested |= InterruptFlags::SERIAL;
+                }
+                _ => panic!("Address {} out of range for CountdownDevice", addr),
+            }
+        }
+    }
+
+    impl Component for CountdownDevice {
+        fn box_clone(&self) -> Box<dyn Component> {
+            Box::new(self.clone())
+        }
+
+        fn tick(&mut self, m_cycles: u32) {
+            for _ in 0..m_cycles {
+                if self.countdown > 0 {
+                    self.countdown -= 1;
+                    if self.countdown == 0 {
+                        self.requested |= InterruptFlags::TIMER;
+                    }
+                }
+            }
+        }
+
+        fn take_requested_interrupts(&mut self) -> InterruptFlags {
+            std::mem::replace(&mut self.requested, InterruptFlags::empty())
+        }
+    }
+
+    impl IoDevice for CountdownDevice {
+        fn registers(&self) -> RangeInclusive<u8> {
+            0x58..=0x59
+        }
+
+        fn box_clone_io(&self) -> Box<dyn IoDevice> {
+            Box::new(self.clone())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn bios_disable_register() {
+        for &model in [HardwareModel::Dmg, HardwareModel::Cgb].iter() {
+            let mut mmu = GbMmu::builder().model(model).build();
+            // Reading the register can't reveal whether the bios is mapped.
+            assert_eq!(mmu.read_raw(0xff50), 0xff);
+            assert!(mmu.io().bios_enabled());
+            // Only bit 0 disables the bios.
+            mmu.write_raw(0xff50, 0xfe);
+            assert!(mmu.io().bios_enabled());
+            mmu.write_raw(0xff50, 0x01);
+            assert!(!mmu.io().bios_enabled());
+            assert_eq!(mmu.read_raw(0xff50), 0xff);
+            // The latch is one-way.
+            mmu.write_raw(0xff50, 0x00);
+            assert!(!mmu.io().bios_enabled());
+        }
+    }
+
+    #[test]
+    fn io_snapshot_round_trip() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        mmu.write_raw(0xff26, 0x80);
+        mmu.write_raw(0xff24, 0x77);
+        mmu.write_raw(0xff14, 0x86);
+        mmu.write_raw(0xff40, 0x91);
+        mmu.write_raw(0xff43, 0x12);
+        mmu.write_raw(0xff06, 0xab);
+        mmu.write_raw(0xff07, 0x05);
+        mmu.write_raw(0xff4d, 0x01);
+        mmu.write_raw(0xff56, 0xc1);
+        mmu.write_raw(0xff0f, 0x04);
+        let snapshot = mmu.io().snapshot();
+
+        assert_eq!(snapshot.value("SCX"), Some(0x12));
+        assert_eq!(snapshot.get(0x4d).unwrap().name, "KEY1");
+        assert_eq!(snapshot.get(0x27), None);
+        let panel = snapshot.to_string();
+        assert!(panel.contains("FF40 LCDC  91  LCD on, window off map 9800"));
+        assert!(panel.contains("FF43 SCX   12\n"));
+
+        let mut restored = GbMmu::builder().model(HardwareModel::Cgb).build();
+        restored.io_mut().apply(&snapshot);
+        assert_eq!(restored.io().snapshot(), snapshot);
+        // DMG has no KEY1 or RP, so they aren't in its snapshot.
+        let dmg = GbMmu::default();
+        assert_eq!(dmg.io().snapshot().get(0x4d), None);
+        assert_eq!(dmg.io().snapshot().get(0x56), None);
+    }
+
+    #[test]
+    fn io_named_dump() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff40, 0x91);
+        mmu.write_raw(0xff0f, 0x01);
+        let dump = mmu.io().named_dump();
+        assert!(dump.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert!(dump.contains(&("LCDC", 0xff40, 0x91)));
+        assert!(dump.contains(&("IF", 0xff0f, 0xe1)));
+        // Unused bits read as 1.
+        let stat = dump.iter().find(|&&(name, _, _)| name == "STAT").unwrap();
+        assert_eq!((stat.1, stat.2 & 0x80), (0xff41, 0x80));
+        // DMG has no KEY1.
+        assert!(dump.iter().all(|&(name, _, _)| name != "KEY1"));
+
+        let cgb = GbMmu::builder().model(HardwareModel::Cgb).build();
+        let dump = cgb.io().named_dump();
+        assert!(dump
+            .iter()
+            .any(|&(name, addr, _)| (name, addr) == ("KEY1", 0xff4d)));
+        assert_eq!(dump.len(), cgb.io().snapshot().registers().len());
+    }
+
+    #[test]
+    fn registered_io_device() {
+        let mut mmu = GbMmu::default();
+        assert_eq!(mmu.read_raw(0xff59), 0xff);
+        mmu.io_mut()
+            .register_device(Box::new(CountdownDevice::default()));
+
+        // Accesses are relative to the start of the device's range.
+        mmu.write_raw(0xff58, 3);
+        assert_eq!(mmu.io().device::<CountdownDevice>().unwrap().countdown, 3);
+        assert_eq!(mmu.read_raw(0xff58), 3);
+
+        // Interrupts requested by writes and ticks are collected into IF.
+        mmu.write_raw(0xff59, 0x42);
+        assert_eq!(mmu.read_raw(0xff59), 0x42);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::SERIAL
+        );
+        mmu.io_mut().tick(2);
+        assert_eq!(mmu.read_raw(0xff58), 1);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::empty()
+        );
+        mmu.io_mut().tick(2);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::TIMER
+        );
+
+        mmu.io_mut()
+            .device_mut::<CountdownDevice>()
+            .unwrap()
+            .countdown = 9;
+        assert_eq!(mmu.read_raw(0xff58), 9);
+
+        // Registered devices survive a reset, and the bios control is replaced.
+        mmu.write_raw(0xff50, 1);
+        assert!(!mmu.io().bios_enabled());
+        mmu.reset(RegionMask::all());
+        assert!(mmu.io().bios_enabled());
+        assert_eq!(mmu.read_raw(0xff58), 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlap")]
+    fn overlapping_io_devices() {
+        let mut io = MemMappedIo::new();
+        io.register_device(Box::new(CountdownDevice::default()));
+        io.register_device(Box::new(CountdownDevice::default()));
+    }
+
+    #[test]
+    fn key1_speed_switch() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        assert_eq!(mmu.read_raw(0xff4d), 0x7e);
+        assert!(!mmu.io_mut().perform_speed_switch());
+
+        // Only bit 0 is writable.
+        mmu.write_raw(0xff4d, 0xff);
+        assert_eq!(mmu.read_raw(0xff4d), 0x7f);
+        assert!(!mmu.io().double_speed());
+        assert!(mmu.io_mut().perform_speed_switch());
+        assert!(mmu.io().double_speed());
+        assert_eq!(mmu.read_raw(0xff4d), 0xfe);
+        // The switch disarms itself.
+        assert!(!mmu.io_mut().perform_speed_switch());
+        assert!(mmu.io().double_speed());
+
+        // Switching back.
+        mmu.write_raw(0xff4d, 0x01);
+        assert_eq!(mmu.read_raw(0xff4d), 0xff);
+        assert!(mmu.io_mut().perform_speed_switch());
+        assert!(!mmu.io().double_speed());
+        assert_eq!(mmu.read_raw(0xff4d), 0x7e);
+
+        // Disarming without switching.
+        mmu.write_raw(0xff4d, 0x01);
+        mmu.write_raw(0xff4d, 0x00);
+        assert!(!mmu.io_mut().perform_speed_switch());
+    }
+
+    #[test]
+    fn key1_dmg() {
+        for &model in [HardwareModel::Dmg, HardwareModel::CgbDmgMode].iter() {
+            let mut mmu = GbMmu::builder().model(model).build();
+            assert_eq!(mmu.read_raw(0xff4d), 0xff);
+            mmu.write_raw(0xff4d, 0x01);
+            assert_eq!(mmu.read_raw(0xff4d), 0xff);
+            assert!(!mmu.io_mut().perform_speed_switch());
+            assert!(!mmu.io().double_speed());
+        }
+    }
+
+    #[test]
+    fn key0_written_before_lock() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        assert_eq!(mmu.io().compat_mode(), CompatMode::Cgb);
+        assert_eq!(mmu.read_raw(0xff4c), 0x80);
+        // The boot rom selects compatibility mode for a DMG game, then locks it in.
+        mmu.write_raw(0xff4c, 0x04);
+        assert_eq!(mmu.io().compat_mode(), CompatMode::DmgOnCgb);
+        mmu.write_raw(0xff50, 0x01);
+        assert_eq!(mmu.read_raw(0xff4c), 0x04);
+        assert_eq!(mmu.io().compat_mode(), CompatMode::DmgOnCgb);
+
+        // A reset re-enables the bios and returns to the model's mode.
+        mmu.soft_reset();
+        assert_eq!(mmu.io().compat_mode(), CompatMode::Cgb);
+    }
+
+    #[test]
+    fn key0_written_after_lock() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::CgbDmgMode).build();
+        assert_eq!(mmu.io().compat_mode(), CompatMode::DmgOnCgb);
+        mmu.write_raw(0xff50, 0x01);
+        mmu.write_raw(0xff4c, 0x80);
+        assert_eq!(mmu.read_raw(0xff4c), 0x04);
+        assert_eq!(mmu.io().compat_mode(), CompatMode::DmgOnCgb);
+    }
+
+    #[test]
+    fn key0_dmg() {
+        for &model in [HardwareModel::Dmg, HardwareModel::Mgb].iter() {
+            let mut mmu = GbMmu::builder().model(model).build();
+            assert_eq!(mmu.read_raw(0xff4c), 0xff);
+            mmu.write_raw(0xff4c, 0x80);
+            assert_eq!(mmu.read_raw(0xff4c), 0xff);
+            assert_eq!(mmu.io().compat_mode(), CompatMode::DmgOnCgb);
+        }
+    }
+
+    #[test]
+    fn opri_written_during_boot() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        assert_eq!(mmu.io().obj_priority_mode(), ObjPriorityMode::OamOrder);
+        // Only bit 0 is stored, and the rest read 1.
+        assert_eq!(mmu.read_raw(0xff6c), 0xfe);
+        mmu.write_raw(0xff6c, 0x01);
+        assert_eq!(mmu.read_raw(0xff6c), 0xff);
+        assert_eq!(mmu.io().obj_priority_mode(), ObjPriorityMode::Coordinate);
+        mmu.write_raw(0xff6c, 0xfe);
+        assert_eq!(mmu.read_raw(0xff6c), 0xfe);
+        assert_eq!(mmu.io().obj_priority_mode(), ObjPriorityMode::OamOrder);
+        assert_eq!(mmu.io().object_priority_mode(), ObjectPriority::OamOrder);
+    }
+
+    #[test]
+    fn opri_written_after_boot() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::CgbDmgMode).build();
+        assert_eq!(mmu.read_raw(0xff6c), 0xff);
+        mmu.write_raw(0xff50, 0x01);
+        mmu.write_raw(0xff6c, 0x00);
+        assert_eq!(mmu.read_raw(0xff6c), 0xff);
+        assert_eq!(mmu.io().obj_priority_mode(), ObjPriorityMode::Coordinate);
+    }
+
+    #[test]
+    fn opri_dmg() {
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff6c, 0x00);
+        assert_eq!(mmu.read_raw(0xff6c), 0xff);
+        assert_eq!(mmu.io().obj_priority_mode(), ObjPriorityMode::Coordinate);
+    }
+
+    /// Checks the IO registers in the given snapshot against a reference table of names and
+    /// values.
+    fn assert_registers(snapshot: &IoSnapshot, expected: &[(&str, u8)]) {
+        for &(name, value) in expected {
+            assert_eq!(
+                snapshot.value(name),
+                Some(value),
+                "{} differs in\n{}",
+                name,
+                snapshot
+            );
+        }
+    }
+
+    #[test]
+    fn power_on_registers() {
+        for &model in [HardwareModel::Dmg, HardwareModel::Cgb].iter() {
+            let mut mmu = GbMmu::builder().model(model).build();
+            mmu.write_raw(0xff40, 0x91);
+            mmu.write_raw(0xff00, 0x10);
+            mmu.write_raw(0xff50, 0x01);
+            mmu.reset(RegionMask::empty());
+            assert!(mmu.io().bios_enabled());
+            #[rustfmt::skip]
+            assert_registers(&mmu.io().snapshot(), &[
+                ("P1", 0xff), ("SC", 0x7e), ("TAC", 0xf8),
+                ("IF", 0xe0), ("NR52", 0x70), ("LCDC", 0x00), ("STAT", 0x84), ("LY", 0x00),
+                ("DMA", 0x00), ("BGP", 0x00),
+            ]);
+        }
+    }
+
+    #[test]
+    fn post_boot_registers_dmg() {
+        let mmu = GbMmu::builder().skip_bios(true).build();
+        assert!(!mmu.io().bios_enabled());
+        // The DMG column of the Pan Docs power up sequence table, except for DIV, which depends
+        // on how long the boot rom took, OBP0 and OBP1, which are uninitialized, and the channel
+        // 1 status bit of NR52, which is set by the boot sound.
+        #[rustfmt::skip]
+        assert_registers(&mmu.io().snapshot(), &[
+            ("P1", 0xcf), ("SB", 0x00), ("SC", 0x7e), ("TIMA", 0x00), ("TMA", 0x00),
+            ("TAC", 0xf8), ("IF", 0xe1), ("NR10", 0x80), ("NR11", 0xbf), ("NR12", 0xf3),
+            ("NR13", 0xff), ("NR14", 0xbf), ("NR21", 0x3f), ("NR22", 0x00), ("NR23", 0xff),
+            ("NR24", 0xbf), ("NR30", 0x7f), ("NR31", 0xff), ("NR32", 0x9f), ("NR33", 0xff),
+            ("NR34", 0xbf), ("NR41", 0xff), ("NR42", 0x00), ("NR43", 0x00), ("NR44", 0xbf),
+            ("NR50", 0x77), ("NR51", 0xf3), ("NR52", 0xf0), ("LCDC", 0x91), ("STAT", 0x85),
+            ("SCY", 0x00), ("SCX", 0x00), ("LY", 0x00), ("LYC", 0x00), ("DMA", 0xff),
+            ("BGP", 0xfc), ("WY", 0x00), ("WX", 0x00),
+        ]);
+        // CGB registers don't exist.
+        assert_eq!(mmu.io().snapshot().value("KEY1"), None);
+        assert_eq!(mmu.read_raw(0xff4d), 0xff);
+        assert_eq!(mmu.read_raw(0xffff), 0x00);
+    }
+
+    #[test]
+    fn post_boot_registers_cgb() {
+        let mut mmu = GbMmu::builder()
+            .model(HardwareModel::Cgb)
+            .skip_bios(true)
+            .build();
+        // The CGB column of the Pan Docs power up sequence table, with the same exceptions as on
+        // the DMG, and also STAT and the color palette registers, which are uninitialized.
+        #[rustfmt::skip]
+        let expected = [
+            ("P1", 0xcf), ("SB", 0x00), ("SC", 0x7f), ("TIMA", 0x00), ("TMA", 0x00),
+            ("TAC", 0xf8), ("IF", 0xe1), ("NR10", 0x80), ("NR11", 0xbf), ("NR12", 0xf3),
+            ("NR13", 0xff), ("NR14", 0xbf), ("NR21", 0x3f), ("NR22", 0x00), ("NR23", 0xff),
+            ("NR24", 0xbf), ("NR30", 0x7f), ("NR31", 0xff), ("NR32", 0x9f), ("NR33", 0xff),
+            ("NR34", 0xbf), ("NR41", 0xff), ("NR42", 0x00), ("NR43", 0x00), ("NR44", 0xbf),
+            ("NR50", 0x77), ("NR51", 0xf3), ("NR52", 0xf0), ("LCDC", 0x91), ("SCY", 0x00),
+            ("SCX", 0x00), ("LY", 0x00), ("LYC", 0x00), ("DMA", 0x00), ("BGP", 0xfc),
+            ("WY", 0x00), ("WX", 0x00), ("KEY1", 0x7e), ("RP", 0x3e), ("FF72", 0x00),
+            ("FF73", 0x00), ("FF74", 0x00), ("FF75", 0x8f),
+        ];
+        assert_registers(&mmu.io().snapshot(), &expected);
+        assert_eq!(mmu.io().compat_mode(), CompatMode::Cgb);
+
+        // A reset starts over from the post-boot values.
+        mmu.write_raw(0xff47, 0x00);
+        mmu.write_raw(0xff26, 0x00);
+        mmu.soft_reset();
+        assert!(!mmu.io().bios_enabled());
+        assert_registers(&mmu.io().snapshot(), &expected);
+    }
+
+    #[test]
+    fn hdma_registers() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        // Before any transfer, HDMA1..HDMA4 are write-only and HDMA5 reads 0xFF.
+        for addr in 0xff51..=0xff55 {
+            assert_eq!(mmu.read_raw(addr), 0xff, "{:#06X}", addr);
+        }
+        for i in 0..0x40 {
+            mmu.write_raw(0xc000 + i, i as u8 + 1);
+        }
+        mmu.write_raw(0xff51, 0xc0);
+        mmu.write_raw(0xff52, 0x00);
+        mmu.write_raw(0xff53, 0x01);
+        mmu.write_raw(0xff54, 0x00);
+        assert_eq!(mmu.read_raw(0xff51), 0xff);
+
+        // An HBlank transfer copies a block on the tick after each HBlank starts.
+        mmu.write_raw(0xff55, 0x82);
+        assert_eq!(mmu.read_raw(0xff55), 0x02);
+        mmu.io_mut().set_lcd_mode(LcdMode::Drawing);
+        mmu.io_mut().set_lcd_mode(LcdMode::HBlank);
+        mmu.tick(1);
+        assert_eq!(mmu.read_raw(0xff55), 0x01);
+        assert_eq!(mmu.read_raw(0x8100), 0x01);
+        assert_eq!(mmu.read_raw(0x810f), 0x10);
+        assert_eq!(mmu.read_raw(0x8110), 0x00);
+        // Staying in HBlank doesn't copy another block.
+        mmu.io_mut().set_lcd_mode(LcdMode::HBlank);
+        mmu.tick(1);
+        assert_eq!(mmu.read_raw(0xff55), 0x01);
+
+        // Cancelling leaves the count of blocks left, with bit 7 set.
+        mmu.write_raw(0xff55, 0x00);
+        assert_eq!(mmu.read_raw(0xff55), 0x81);
+        mmu.io_mut().set_lcd_mode(LcdMode::Drawing);
+        mmu.io_mut().set_lcd_mode(LcdMode::HBlank);
+        mmu.tick(1);
+        assert_eq!(mmu.read_raw(0x8110), 0x00);
+
+        // A general transfer started right away copies everything at once, continuing from
+        // where the HBlank transfer stopped.
+        mmu.write_raw(0xff55, 0x01);
+        assert_eq!(mmu.read_raw(0xff55), 0xff);
+        assert_eq!(mmu.read_raw(0x8110), 0x11);
+        assert_eq!(mmu.read_raw(0x812f), 0x30);
+
+        // On the DMG the registers don't exist.
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff55, 0x00);
+        assert_eq!(mmu.read_raw(0xff55), 0xff);
+        assert_eq!(mmu.read_raw(0x8000), 0x00);
+    }
+
+    #[test]
+    fn cgb_palette_registers() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        mmu.write_raw(0xff68, 0x80 | 0x3e);
+        mmu.write_raw(0xff69, 0x1f);
+        mmu.write_raw(0xff69, 0x00);
+        // Wrapped around to the first color of palette 0.
+        mmu.write_raw(0xff69, 0xe0);
+        mmu.write_raw(0xff69, 0x03);
+        assert_eq!(mmu.read_raw(0xff68), 0xc2);
+        assert_eq!(mmu.io().bg_palette(7)[3], Rgb555(0x001f));
+        assert_eq!(mmu.io().bg_palette(0)[0], Rgb555(0x03e0));
+
+        mmu.write_raw(0xff6a, 0x02);
+        mmu.write_raw(0xff6b, 0x7c);
+        assert_eq!(mmu.read_raw(0xff6b), 0x7c);
+        assert_eq!(mmu.read_raw(0xff6a), 0x42);
+        assert_eq!(mmu.io().obj_palette(0)[1], Rgb555(0x007c));
+
+        // The data registers are blocked while the PPU draws.
+        mmu.io_mut().set_lcd_mode(LcdMode::Drawing);
+        assert_eq!(mmu.read_raw(0xff6b), 0xff);
+        mmu.write_raw(0xff6b, 0x00);
+        mmu.io_mut().set_lcd_mode(LcdMode::HBlank);
+        assert_eq!(mmu.read_raw(0xff6b), 0x7c);
+
+        // Peek and poke reach them anyway.
+        mmu.io_mut().set_lcd_mode(LcdMode::Drawing);
+        mmu.write_raw(0xff68, 0x00);
+        assert_eq!(mmu.peek(0xff69), 0xe0);
+        mmu.poke(0xff69, 0x42);
+        assert_eq!(mmu.read_raw(0xff69), 0xff);
+        assert_eq!(mmu.peek(0xff69), 0x42);
+        mmu.poke(0xff6b, 0x24);
+        assert_eq!(mmu.peek(0xff6b), 0x24);
+        assert!(mmu.io().access_restrictions());
+        mmu.io_mut().set_lcd_mode(LcdMode::HBlank);
+        assert_eq!(mmu.io().bg_palette(0)[0], Rgb555(0x0342));
+        assert_eq!(mmu.io().obj_palette(0)[1], Rgb555(0x0024));
+
+        let mut mmu = GbMmu::default();
+        mmu.write_raw(0xff68, 0x80);
+        mmu.write_raw(0xff69, 0x12);
+        assert_eq!(mmu.read_raw(0xff68), 0xff);
+        assert_eq!(mmu.read_raw(0xff69), 0xff);
+        assert_eq!(mmu.io().bg_palette(0)[0], Rgb555(0));
+    }
+
+    #[test]
+    fn unrestricted_guard() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        mmu.write_raw(0xff68, 0x00);
+        mmu.write_raw(0xff69, 0x42);
+        mmu.io_mut().set_lcd_mode(LcdMode::Drawing);
+        assert_eq!(mmu.read_raw(0xff69), 0xff);
+        {
+            let mut guard = mmu.unrestricted();
+            assert!(!guard.io().access_restrictions());
+            assert_eq!(guard.read_raw(0xff69), 0x42);
+            guard.write_raw(0xff69, 0x17);
+            {
+                let inner = guard.unrestricted();
+                assert_eq!(inner.read_raw(0xff69), 0x17);
+            }
+            // Dropping the inner guard leaves the outer one in effect.
+            assert_eq!(guard.read_raw(0xff69), 0x17);
+        }
+        assert!(mmu.io().access_restrictions());
+        assert_eq!(mmu.read_raw(0xff69), 0xff);
+        assert_eq!(mmu.io().bg_palette_ram().bytes()[0], 0x17);
+    }
+
+    #[test]
+    fn infrared_loopback() {
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        mmu.write_raw(0xff56, 0xc0);
+        assert_eq!(mmu.read_raw(0xff56), 0xfe);
+
+        // With the LED wired back to the receiver, turning it on is seen by the same port.
+        mmu.write_raw(0xff56, 0xc1);
+        assert!(mmu.io().ir_output());
+        let output = mmu.io().ir_output();
+        mmu.io_mut().set_ir_input(output);
+        assert_eq!(mmu.read_raw(0xff56), 0xfd);
+
+        mmu.write_raw(0xff56, 0xc0);
+        let output = mmu.io().ir_output();
+        mmu.io_mut().set_ir_input(output);
+        assert_eq!(mmu.read_raw(0xff56), 0xfe);
+    }
+
+    #[test]
+    fn infrared_reaches_cartridge() {
+        // HuC1 cartridge.
+        let mut rom = rom_only_image("IR");
+        rom[0x147] = 0xff;
+        let mut mmu = GbMmu::builder().model(HardwareModel::Cgb).build();
+        mmu.load_rom(&rom).unwrap();
+        mmu.write_raw(0x1000, 0x0e);
+        mmu.write_raw(0xff56, 0xc0);
+        assert_eq!(mmu.read_raw(0xa000), 0xc0);
+        assert_eq!(mmu.read_raw(0xff56), 0xfe);
+
+        // Light reaches both ports.
+        mmu.set_ir_input(true);
+        assert_eq!(mmu.read_raw(0xa000), 0xc1);
+        assert_eq!(mmu.read_raw(0xff56), 0xfc);
+
+        // Either LED counts as output.
+        assert!(!mmu.ir_output());
+        mmu.write_raw(0xa000, 0x01);
+        assert!(mmu.ir_output());
+    }
+
+    #[test]
+    fn undocumented_cgb_registers() {
+        use HardwareModel::*;
+        // Register, model, value written, value read back.
+        let cases = [
+            (0xff72, Dmg, 0x5a, 0xff),
+            (0xff72, Cgb, 0x5a, 0x5a),
+            (0xff72, CgbDmgMode, 0xa5, 0xa5),
+            (0xff73, Dmg, 0x5a, 0xff),
+            (0xff73, Cgb, 0xff, 0xff),
+            (0xff73, CgbDmgMode, 0x00, 0x00),
+            (0xff74, Dmg, 0x5a, 0xff),
+            (0xff74, Cgb, 0x5a, 0x5a),
+            (0xff74, CgbDmgMode, 0x5a, 0xff),
+            (0xff75, Dmg, 0xff, 0xff),
+            (0xff75, Cgb, 0x00, 0x8f),
+            (0xff75, Cgb, 0xff, 0xff),
+            (0xff75, CgbDmgMode, 0x50, 0xdf),
+            (0xff76, Dmg, 0x12, 0xff),
+            (0xff76, Cgb, 0x12, 0x00),
+            (0xff76, CgbDmgMode, 0x12, 0x00),
+            (0xff77, Dmg, 0x12, 0xff),
+            (0xff77, Cgb, 0x12, 0x00),
+            (0xff77, CgbDmgMode, 0x12, 0x00),
+        ];
+        for &(addr, model, value, expected) in cases.iter() {
+            let mut mmu = GbMmu::builder().model(model).build();
+            mmu.write_raw(addr, value);
+            assert_eq!(
+                mmu.read_raw(addr),
+                expected,
+                "{:#06X} on {:?} after writing {:#04X}",
+                addr,
+                model,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn tick_all_at_once() {
+        let setup = || {
+            let mut mmu = GbMmu::default();
+            // TIMA counts every 4 cycles, and overflows several times.
+            mmu.write_raw(0xff07, 0x05);
+            mmu.write_raw(0xff06, 0xf0);
+            mmu.write_raw(0xff05, 0xfe);
+            mmu
+        };
+        let mut once = setup();
+        once.tick(1000);
+        let mut stepped = setup();
+        for _ in 0..1000 {
+            stepped.tick(1);
+        }
+        for &addr in [0xff04, 0xff05, 0xff06, 0xff07, 0xff0f].iter() {
+            assert_eq!(once.read_raw(addr), stepped.read_raw(addr), "{:#06X}", addr);
+        }
+        assert_eq!(once.read_raw(0xff04), (1000 * 4 / 256) as u8);
+        assert_eq!(once.io().interrupt_flags(), InterruptFlags::TIMER);
+    }
+
+    /// Component which counts the cycles it is ticked for.
+    #[derive(Clone, Debug)]
+    struct CycleCounter(Rc<Cell<u32>>);
+
+    impl MemDevice for CycleCounter {
+        fn size(&self) -> usize {
+            0x10000
+        }
+
+        fn read(&self, _addr: Addr) -> u8 {
+            0xff
+        }
+
+        fn write(&mut self, _addr: Addr, _value: u8) {}
+    }
+
+    impl Component for CycleCounter {
+        fn box_clone(&self) -> Box<dyn Component> {
+            Box::new(self.clone())
+        }
+
+        fn tick(&mut self, m_cycles: u32) {
+            self.0.set(self.0.get() + m_cycles);
+        }
+    }
+
+    /// APU which counts the DIV-APU events it is clocked by.
+    #[derive(Clone, Debug)]
+    struct DivApuCounter(Rc<Cell<u32>>);
+
+    impl MemDevice for DivApuCounter {
+        fn size(&self) -> usize {
+            0x30
+        }
+
+        fn read(&self, _addr: Addr) -> u8 {
+            0xff
+        }
+
+        fn write(&mut self, _addr: Addr, _value: u8) {}
+    }
+
+    impl Component for DivApuCounter {
+        fn box_clone(&self) -> Box<dyn Component> {
+            Box::new(self.clone())
+        }
+
+        fn clock_div_apu(&mut self, events: u32) {
+            self.0.set(self.0.get() + events);
+        }
+    }
+
+    #[test]
+    fn div_apu_events_clock_apu() {
+        let events = Rc::new(Cell::new(0));
+        let mut mmu = GbMmu::builder()
+            .model(HardwareModel::Cgb)
+            .apu(Box::new(DivApuCounter(events.clone())))
+            .build();
+        mmu.write_raw(0xff04, 0);
+        mmu.tick(2048 * 2);
+        assert_eq!(events.get(), 2);
+        // Writing DIV halfway through a period clocks the frame sequencer early. The event is
+        // delivered on the next tick.
+        mmu.tick(1024);
+        mmu.write_raw(0xff04, 0);
+        assert_eq!(events.get(), 2);
+        mmu.tick(1);
+        assert_eq!(events.get(), 3);
+
+        // In double speed mode, events keep the same rate relative to the APU.
+        mmu.write_raw(0xff4d, 0x01);
+        assert!(mmu.io_mut().perform_speed_switch());
+        mmu.write_raw(0xff04, 0);
+        mmu.tick(2048);
+        assert_eq!(events.get(), 3);
+        mmu.tick(2048);
+        assert_eq!(events.get(), 4);
+    }
+
+    #[test]
+    fn tick_double_speed() {
+        let ppu_cycles = Rc::new(Cell::new(0));
+        let apu_cycles = Rc::new(Cell::new(0));
+        let mut mmu = GbMmu::builder()
+            .model(HardwareModel::Cgb)
+            .ppu(Box::new(CycleCounter(ppu_cycles.clone())))
+            .apu(Box::new(CycleCounter(apu_cycles.clone())))
+            .build();
+        mmu.tick(10);
+        assert_eq!((ppu_cycles.get(), apu_cycles.get()), (10, 10));
+        let div = mmu.read_raw(0xff04);
+
+        mmu.write_raw(0xff4d, 0x01);
+        assert!(mmu.io_mut().perform_speed_switch());
+        // Odd cycles carry over to the next tick.
+        mmu.tick(3);
+        assert_eq!((ppu_cycles.get(), apu_cycles.get()), (11, 11));
+        mmu.tick(3);
+        assert_eq!((ppu_cycles.get(), apu_cycles.get()), (13, 13));
+        // The timer runs with the CPU.
+        mmu.tick(58);
+        assert_eq!(mmu.read_raw(0xff04), div.wrapping_add(1));
+        assert_eq!(ppu_cycles.get(), 42);
+    }
+
+    /// APU which reports fixed channel outputs.
+    #[derive(Clone, Debug)]
+    struct PcmApu([u8; 4]);
+
+    impl MemDevice for PcmApu {
+        fn size(&self) -> usize {
+            0x30
+        }
+
+        fn read(&self, _addr: Addr) -> u8 {
+            0xff
+        }
+
+        fn write(&mut self, _addr: Addr, _value: u8) {}
+    }
+
+    impl Component for PcmApu {
+        fn box_clone(&self) -> Box<dyn Component> {
+            Box::new(self.clone())
+        }
+
+        fn pcm_output(&self) -> [u8; 4] {
+            self.0
+        }
+    }
+
+    #[test]
+    fn pcm_registers_read_apu_output() {
+        let mut mmu = GbMmu::builder()
+            .model(HardwareModel::Cgb)
+            .apu(Box::new(PcmApu([0x1, 0xf, 0x7, 0x0])))
+            .build();
+        assert_eq!(mmu.read_raw(0xff76), 0xf1);
+        assert_eq!(mmu.read_raw(0xff77), 0x07);
+        mmu.write_raw(0xff76, 0x00);
+        assert_eq!(mmu.read_raw(0xff76), 0xf1);
+    }
+
+    #[test]
+    fn infrared_dmg() {
+        let mut mmu = GbMmu::default();
+        mmu.io_mut().set_ir_input(true);
+        assert_eq!(mmu.read_raw(0xff56), 0xff);
+        mmu.write_raw(0xff56, 0xc1);
+        assert_eq!(mmu.read_raw(0xff56), 0xff);
+        assert!(!mmu.io().ir_output());
+    }
+
+    #[test]
+    fn joypad_register() {
+        let mut mmu = GbMmu::default();
+        mmu.io_mut().set_buttons(ButtonState::START);
+        assert_eq!(mmu.read_raw(0xff00), 0xff);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::empty()
+        );
+
+        // Selecting the action buttons shows Start pressed, and requests an interrupt because
+        // it was already held.
+        mmu.write_raw(0xff00, 0x10);
+        assert_eq!(mmu.read_raw(0xff00), 0xd7);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::JOYPAD
+        );
+        mmu.io_mut()
+            .set_buttons(ButtonState::START | ButtonState::A);
+        assert_eq!(mmu.read_raw(0xff00), 0xd6);
+        assert_eq!(
+            mmu.io_mut().take_requested_interrupts(),
+            InterruptFlags::JOYPAD
+        );
+
+        // Reset deselects both columns, but the buttons are still held.
+        mmu.soft_reset();
+        assert_eq!(mmu.read_raw(0xff00), 0xff);
+        assert_eq!(
+            mmu.io().joypad().buttons(),
+            ButtonState::START | ButtonState::A
+        );
+    }
+
+    #[test]
+    fn joypad_interrupt_edges() {
+        let mut io = MemMappedIo::new();
+        let fired = |io: &mut MemMappedIo| io.take_requested_interrupts() == InterruptFlags::JOYPAD;
+
+        // Nothing selected: presses don't pull any line low.
+        io.set_buttons(ButtonState::A);
+        assert!(!fired(&mut io));
+        // Selecting the actions pulls line 0 low.
+        io.write(0x00.into(), 0x10);
+        assert!(fired(&mut io));
+        // Line 0 is already low, so pressing Right in the unselected column doesn't fire, and
+        // neither does switching to the directions, since Right keeps line 0 low.
+        io.set_buttons(ButtonState::A | ButtonState::RIGHT);
+        assert!(!fired(&mut io));
+        io.write(0x00.into(), 0x20);
+        assert!(!fired(&mut io));
+        // Releasing Right raises line 0, then pressing Left pulls line 1 low.
+        io.set_buttons(ButtonState::A);
+        assert!(!fired(&mut io));
+        io.set_buttons(ButtonState::A | ButtonState::LEFT);
+        assert!(fired(&mut io));
+        // Deselecting everything raises all lines without firing, and selecting both columns
+        // pulls lines 0 and 1 low again.
+        io.write(0x00.into(), 0x30);
+        assert!(!fired(&mut io));
+        io.write(0x00.into(), 0x00);
+        assert!(fired(&mut io));
+        // Rewriting the same selection is not a transition.
+        io.write(0x00.into(), 0x00);
+        assert!(!fired(&mut io));
+    }
+
+    #[test]
+    fn io_write_observer() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut mmu = GbMmu::default();
+        let log = seen.clone();
+        mmu.io_mut().set_write_observer(Box::new(move |reg, value| {
+            log.borrow_mut().push((reg, value))
+        }));
+
+        mmu.write_raw(0xff40, 0x91);
+        mmu.write_raw(0xff26, 0x80);
+        // Read-only registers are still reported, with the value that was written.
+        mmu.write_raw(0xff44, 0x12);
+        // Writes outside of the IO block aren't.
+        mmu.write_raw(0xff80, 0x34);
+        mmu.write_raw(0xc000, 0x56);
+        // The observer survives resets and snapshot restores.
+        let snapshot = mmu.snapshot();
+        mmu.soft_reset();
+        mmu.restore(&snapshot);
+        mmu.write_raw(0xff47, 0xe4);
+        assert_eq!(
+            *seen.borrow(),
+            [(0x40, 0x91), (0x26, 0x80), (0x44, 0x12), (0x47, 0xe4)]
+        );
+
+        mmu.io_mut().clear_write_observer();
+        mmu.write_raw(0xff40, 0);
+        assert_eq!(seen.borrow().len(), 4);
+    }
+
+    #[test]
+    fn serial_output() {
+        let output = BufferedSerial::new();
+        let mut mmu = GbMmu::builder().serial(Box::new(output.clone())).build();
+        for &byte in b"ok\n" {
+            mmu.write_raw(0xff01, byte);
+            mmu.write_raw(0xff02, 0x81);
+            assert_eq!(mmu.read_raw(0xff02), 0x7f);
+            assert_eq!(
+                mmu.io_mut().take_requested_interrupts(),
+                InterruptFlags::SERIAL
+            );
+        }
+        assert_eq!(mmu.read_raw(0xff01), 0xff);
+        // The backend stays connected through a reset.
+        mmu.hard_reset();
+        mmu.write_raw(0xff02, 0x81);
+        assert_eq!(output.output(), b"ok\n\0");
+    }
+
+    #[test]
+    fn timed_serial_through_io() {
+        let output = BufferedSerial::new();
+        let mut io = MemMappedIo::new();
+        io.serial_mut().set_backend(Box::new(output.clone()));
+        io.serial_mut().set_timing(SerialTiming::Timed);
+        io.reset(HardwareModel::Dmg);
+        assert_eq!(io.serial().timing(), SerialTiming::Timed);
+
+        io.write(0x01.into(), b'!');
+        io.write(0x02.into(), 0x81);
+        io.tick(128 * 8 - 1);
+        assert_eq!(io.read(0x02.into()), 0xff);
+        assert_eq!(io.take_requested_interrupts(), InterruptFlags::empty());
+        io.tick(1);
+        assert_eq!(io.read(0x02.into()), 0x7f);
+        assert_eq!(io.take_requested_interrupts(), InterruptFlags::SERIAL);
+        assert_eq!(output.output(), b"!");
+    }
+
+    /// State shared by the two ends of a [`CableEnd`] link.
+    #[derive(Debug, Default)]
+    struct Cable {
+        /// Byte the side on the external clock is waiting to send.
+        armed: Option<u8>,
+        /// Byte the side on the internal clock sent.
+        from_master: Option<u8>,
+        /// Clock pulses the side on the internal clock has sent which the other side hasn't
+        /// received yet.
+        pulses: u32,
+    }
+
+    /// Serial backend for one end of a link cable between two `MemMappedIo`s. The master
+    /// provides the clock.
+    #[derive(Clone, Debug)]
+    struct CableEnd {
+        cable: Rc<RefCell<Cable>>,
+        master: bool,
+    }
+
+    impl SerialBackend for CableEnd {
+        fn exchange(&mut self, out: u8) -> u8 {
+            let mut cable = self.cable.borrow_mut();
+            if self.master {
+                cable.from_master = Some(out);
+                cable.pulses += 8;
+                // With nothing armed on the other end, the line floats high.
+                cable.armed.take().unwrap_or(0xff)
+            } else {
+                cable.from_master.take().unwrap_or(0xff)
+            }
+        }
+
+        fn clock_pulses(&mut self, m_cycles: u32) -> u32 {
+            let mut cable = self.cable.borrow_mut();
+            let pulses = cable.pulses.min(m_cycles);
+            cable.pulses -= pulses;
+            pulses
+        }
+
+        fn armed(&mut self, out: u8) {
+            self.cable.borrow_mut().armed = Some(out);
+        }
+
+        fn box_clone(&self) -> Box<dyn SerialBackend> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn serial_link_loopback() {
+        let cable = Rc::new(RefCell::new(Cable::default()));
+        let mut master = MemMappedIo::new();
+        master.serial_mut().set_backend(Box::new(CableEnd {
+            cable: cable.clone(),
+            master: true,
+        }));
+        master.serial_mut().set_timing(SerialTiming::Timed);
+        let mut slave = MemMappedIo::new();
+        slave.serial_mut().set_backend(Box::new(CableEnd {
+            cable: cable.clone(),
+            master: false,
+        }));
+
+        let to_slave = b"ping from master";
+        let to_master = b"pong from slave!";
+        let mut received = (vec![], vec![]);
+        let mut interrupts = (0, 0);
+        for i in 0..16 {
+            slave.write(0x01.into(), to_master[i]);
+            slave.write(0x02.into(), 0x80);
+            master.write(0x01.into(), to_slave[i]);
+            master.write(0x02.into(), 0x81);
+            let mut done = (false, false);
+            for _ in 0..128 * 8 + 1 {
+                master.tick(1);
+                slave.tick(1);
+                // SB holds the received byte by the time the interrupt is visible, and SC bit 7
+                // has been cleared.
+                if master.take_requested_interrupts() == InterruptFlags::SERIAL {
+                    assert_eq!(master.read(0x02.into()) & 0x80, 0);
+                    received.0.push(master.read(0x01.into()));
+                    interrupts.0 += 1;
+                    done.0 = true;
+                }
+                if slave.take_requested_interrupts() == InterruptFlags::SERIAL {
+                    assert_eq!(slave.read(0x02.into()) & 0x80, 0);
+                    received.1.push(slave.read(0x01.into()));
+                    interrupts.1 += 1;
+                    done.1 = true;
+                }
+                if done == (true, true) {
+                    break;
+                }
+            }
+            assert_eq!(done, (true, true), "byte {} didn't complete", i);
+        }
+        assert_eq!(interrupts, (16, 16));
+        assert_eq!(received.0, to_master);
+        assert_eq!(received.1, to_slave);
+
+        // Bits pushed by a partner also complete the transfer through IF.
+        slave.write(0x02.into(), 0x80);
+        for _ in 0..7 {
+            slave.push_serial_bit(true);
+        }
+        assert_eq!(slave.take_requested_interrupts(), InterruptFlags::empty());
+        slave.push_serial_bit(false);
+        assert_eq!(slave.read(0x01.into()), 0xfe);
+        assert_eq!(slave.take_requested_interrupts(), InterruptFlags::SERIAL);
+    }
+
+    #[test]
+    fn vram_tile_decoding() {
+        let mut mmu = GbMmu::default();
+        // The first two rows of the example tile from the Pan Docs.
+        let rows = [0x3c, 0x7e, 0x42, 0x42];
+        for (i, &byte) in rows.iter().enumerate() {
+            mmu.poke(0x8010 + i as u16, byte);
+        }
+        mmu.poke(0x97fe, 0xff);
+        let tile = mmu.vram_tile(0, 1);
+        assert_eq!(tile[0], [0, 2, 3, 3, 3, 3, 2, 0]);
+        assert_eq!(tile[1], [0, 3, 0, 0, 0, 0, 3, 0]);
+        assert_eq!(tile[2], [0; 8]);
+
+        let tiles: Vec<_> = mmu.vram_tiles(0).collect();
+        assert_eq!(tiles.len(), 384);
+        assert_eq!(tiles[1], tile);
+        assert_eq!(tiles[383][7], [1; 8]);
+    }
+
+    #[test]
+    fn custom_components_receive_their_ranges() {
+        /// Component which returns the low byte of the relative address it was accessed with.
+        #[derive(Copy, Clone, Debug, Default)]
+        struct Echo;
+
+        impl MemDevice for Echo {
+            fn read(&self, addr: Addr) -> u8 {
+                addr.relative() as u8
+            }
+
+            fn write(&mut self, _addr: Addr, _value: u8) {}
+        }
+
+        impl Component for Echo {
+            fn box_clone(&self) -> Box<dyn Component> {
+                Box::new(*self)
+            }
+        }
+
+        let mmu = GbMmu::builder()
+            .ppu(Box::new(Echo))
+            .timer(Box::new(Echo))
+            .apu(Box::new(Echo))
+            .build();
+        // The PPU sees system addresses.
+        assert_eq!(mmu.read(0x8042.into()), 0x42);
+        assert_eq!(mmu.read(0xfe9f.into()), 0x9f);
+        // The timer and APU see addresses relative to the start of their ranges.
+        assert_eq!(mmu.read(0xff07.into()), 0x03);
+        assert_eq!(mmu.read(0xff3f.into()), 0x2f);
+    }
 }
@@ -0,0 +1,268 @@
+//! The audio registers NR10..NR52 at 0xFF10..0xFF27 and wave ram at 0xFF30..0xFF40.
+
+use super::{Addr, Component, HardwareModel, MemDevice};
+
+/// Number of audio registers, NR10 (0xFF10) through NR52 (0xFF26).
+const NUM_REGISTERS: usize = 0x17;
+
+/// Offset of NR52, the sound on/off register, relative to 0xFF10.
+const NR52: usize = 0x16;
+
+/// NR52 bit which turns the APU on.
+const NR52_POWER: u8 = 0x80;
+
+/// Bits which always read as 1 for each register from 0xFF10 to 0xFF2F, indexed relative to
+/// 0xFF10. Write-only bits and unused bits read 1, so a register reads back as the value written
+/// ORed with its mask. Unused addresses have a mask of 0xFF.
+#[rustfmt::skip]
+const READ_MASKS: [u8; 0x20] = [
+    // NR10  NR11  NR12  NR13  NR14
+    0x80, 0x3f, 0x00, 0xff, 0xbf,
+    // Unused, NR21  NR22  NR23  NR24
+    0xff, 0x3f, 0x00, 0xff, 0xbf,
+    // NR30  NR31  NR32  NR33  NR34
+    0x7f, 0xff, 0x9f, 0xff, 0xbf,
+    // Unused, NR41  NR42  NR43  NR44
+    0xff, 0xff, 0x00, 0x00, 0xbf,
+    // NR50  NR51  NR52
+    0x00, 0x00, 0x70,
+    // 0xFF27..0xFF30 are unused.
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+/// APU component with the audio registers but no sound generation. Covers the audio registers and
+/// wave ram at 0xFF10..0xFF40, relative to 0xFF10.
+///
+/// Games read the audio registers back and can depend on which bits are unused or write-only,
+/// so each register stores what was written and reads back with the bits in its entry of
+/// [`READ_MASKS`] set. While the APU is turned off with NR52, writes to the other registers are
+/// ignored, and turning it off clears them.
+///
+/// Wave ram holds the 32 4-bit samples played by channel 3. While the channel is playing, the CPU
+/// can't access wave ram normally. On a CGB, accesses go to whichever byte the channel is
+/// currently reading, regardless of the address. On a DMG, reads return 0xFF and writes are
+/// ignored. Since there is no channel 3 yet, whatever models it reports the byte it is reading
+/// with [`set_wave_position`](Apu::set_wave_position).
+///
+/// The frame sequencer, which will clock the length counters, envelopes, and sweep, steps
+/// through its 8 steps once for each DIV-APU event from the timer while the APU is on. Turning
+/// the APU on restarts it from step 0.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Apu {
+    /// Values written to NR10..NR51, plus the power bit of NR52.
+    registers: [u8; NUM_REGISTERS],
+    /// Wave pattern ram.
+    wave: [u8; 16],
+    /// Index of the wave ram byte channel 3 is reading, if it is playing.
+    wave_position: Option<usize>,
+    /// The hardware model, which determines how wave ram behaves while channel 3 is playing.
+    model: HardwareModel,
+    /// The frame sequencer step which the next DIV-APU event runs, from 0 to 7.
+    frame_sequencer_step: u8,
+}
+
+impl Apu {
+    /// Constructs a new `Apu`, which starts out turned off.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns true if the APU is turned on, via bit 7 of NR52.
+    pub fn powered(&self) -> bool {
+        self.registers[NR52] & NR52_POWER != 0
+    }
+
+    /// Gets the frame sequencer step which the next DIV-APU event runs, from 0 to 7. Length
+    /// counters are clocked on even steps, the sweep on steps 2 and 6, and envelopes on step 7.
+    pub fn frame_sequencer_step(&self) -> u8 {
+        self.frame_sequencer_step
+    }
+
+    /// Clocks the frame sequencer once for each of the given number of DIV-APU events. Does
+    /// nothing while the APU is off.
+    pub fn clock_div_apu(&mut self, events: u32) {
+        if self.powered() {
+            self.frame_sequencer_step = ((self.frame_sequencer_step as u32 + events) % 8) as u8;
+        }
+    }
+
+    /// Gets the contents of wave ram.
+    pub fn wave_ram(&self) -> &[u8; 16] {
+        &self.wave
+    }
+
+    /// Sets the index of the wave ram byte channel 3 is currently reading, or `None` if the
+    /// channel is not playing.
+    pub fn set_wave_position(&mut self, position: Option<usize>) {
+        if let Some(position) = position {
+            assert!(position < 16, "Wave ram position {} out of range", position);
+        }
+        self.wave_position = position;
+    }
+
+    /// Gets the index in wave ram the CPU actually accesses at the given index, or `None` if wave
+    /// ram is inaccessible.
+    fn wave_index(&self, index: usize) -> Option<usize> {
+        match self.wave_position {
+            None => Some(index),
+            Some(position) if self.model.is_cgb_hardware() => Some(position),
+            Some(_) => None,
+        }
+    }
+}
+
+impl MemDevice for Apu {
+    fn size(&self) -> usize {
+        0x30
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match addr.index() {
+            // There are no channels, so the channel status bits of NR52 always read 0.
+            index @ 0..=0x1f => self.registers.get(index).copied().unwrap_or(0) | READ_MASKS[index],
+            index @ 0x20..=0x2f => match self.wave_index(index - 0x20) {
+                Some(index) => self.wave[index],
+                None => 0xff,
+            },
+            _ => panic!("Address {} out of range for Apu", addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match addr.index() {
+            NR52 => {
+                if value & NR52_POWER == 0 {
+                    self.registers = [0; NUM_REGISTERS];
+                } else {
+                    if !self.powered() {
+                        self.frame_sequencer_step = 0;
+                    }
+                    self.registers[NR52] = NR52_POWER;
+                }
+            }
+            index @ 0..=0x15 => {
+                if self.powered() {
+                    self.registers[index] = value;
+                }
+            }
+            0x17..=0x1f => {}
+            index @ 0x20..=0x2f => {
+                if let Some(index) = self.wave_index(index - 0x20) {
+                    self.wave[index] = value;
+                }
+            }
+            _ => panic!("Address {} out of range for Apu", addr),
+        }
+    }
+}
+
+impl Component for Apu {
+    fn box_clone(&self) -> Box<dyn Component> {
+        Box::new(*self)
+    }
+
+    fn set_model(&mut self, model: HardwareModel) {
+        self.model = model;
+    }
+
+    fn clock_div_apu(&mut self, events: u32) {
+        Apu::clock_div_apu(self, events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_masks() {
+        let mut apu = Apu::new();
+        apu.write(0x16.into(), 0x80);
+        for index in 0..0x20u16 {
+            if index as usize == NR52 {
+                continue;
+            }
+            let mask = READ_MASKS[index as usize];
+            apu.write(index.into(), 0x00);
+            assert_eq!(apu.read(index.into()), mask, "{:#06X}", 0xff10 + index);
+            apu.write(index.into(), 0xff);
+            assert_eq!(apu.read(index.into()), 0xff, "{:#06X}", 0xff10 + index);
+        }
+        // NR52 only stores the power bit, and the channel status bits read 0.
+        assert_eq!(apu.read(0x16.into()), 0xf0);
+    }
+
+    #[test]
+    fn power_gates_registers() {
+        let mut apu = Apu::new();
+        // Off at power on, so writes are ignored.
+        assert!(!apu.powered());
+        assert_eq!(apu.read(0x16.into()), 0x70);
+        apu.write(0x14.into(), 0x77);
+        assert_eq!(apu.read(0x14.into()), 0x00);
+
+        apu.write(0x16.into(), 0xff);
+        assert!(apu.powered());
+        apu.write(0x14.into(), 0x77);
+        apu.write(0x02.into(), 0xf3);
+        assert_eq!(apu.read(0x14.into()), 0x77);
+        assert_eq!(apu.read(0x02.into()), 0xf3);
+
+        // Turning it off clears everything.
+        apu.write(0x16.into(), 0x00);
+        apu.write(0x16.into(), 0x80);
+        assert_eq!(apu.read(0x14.into()), 0x00);
+        assert_eq!(apu.read(0x02.into()), 0x00);
+    }
+
+    #[test]
+    fn frame_sequencer_steps() {
+        let mut apu = Apu::new();
+        // Events are ignored while the APU is off.
+        apu.clock_div_apu(3);
+        assert_eq!(apu.frame_sequencer_step(), 0);
+
+        apu.write(0x16.into(), 0x80);
+        apu.clock_div_apu(3);
+        assert_eq!(apu.frame_sequencer_step(), 3);
+        apu.clock_div_apu(6);
+        assert_eq!(apu.frame_sequencer_step(), 1);
+        // Writing NR52 while already on doesn't restart it.
+        apu.write(0x16.into(), 0x80);
+        assert_eq!(apu.frame_sequencer_step(), 1);
+
+        // Turning the APU back on restarts from step 0.
+        apu.write(0x16.into(), 0x00);
+        apu.write(0x16.into(), 0x80);
+        assert_eq!(apu.frame_sequencer_step(), 0);
+    }
+
+    #[test]
+    fn wave_ram() {
+        let mut apu = Apu::new();
+        // Wave ram is accessible even while the APU is off.
+        for i in 0..16u16 {
+            apu.write((0x20 + i).into(), i as u8 * 0x11);
+        }
+        assert_eq!(apu.read(0x25.into()), 0x55);
+        assert_eq!(apu.wave_ram()[15], 0xff);
+        apu.write(0x16.into(), 0x00);
+        assert_eq!(apu.read(0x2a.into()), 0xaa);
+
+        // On a DMG, wave ram is blocked while channel 3 is playing.
+        apu.set_wave_position(Some(3));
+        assert_eq!(apu.read(0x21.into()), 0xff);
+        apu.write(0x21.into(), 0x00);
+        apu.set_wave_position(None);
+        assert_eq!(apu.read(0x21.into()), 0x11);
+
+        // On a CGB, accesses go to the byte being played instead.
+        apu.set_model(HardwareModel::Cgb);
+        apu.set_wave_position(Some(3));
+        assert_eq!(apu.read(0x21.into()), 0x33);
+        apu.write(0x2f.into(), 0x42);
+        apu.set_wave_position(None);
+        assert_eq!(apu.read(0x23.into()), 0x42);
+        assert_eq!(apu.read(0x2f.into()), 0xff);
+    }
+}
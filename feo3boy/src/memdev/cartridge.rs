@@ -1,12 +1,23 @@
 //! Implementation of different cartridge types.
 
+use std::borrow::Cow;
 use std::io::{self, ErrorKind, Read};
+use std::ops::Range;
 use std::slice;
+use std::sync::Arc;
 
 use log::warn;
 use thiserror::Error;
 
-use super::{Addr, MemDevice, NullRom, ReadOnly};
+use super::{Addr, ChunkedRam, Component, MemDevice, Permissions, ReadOnly};
+
+pub use camera::PocketCamera;
+pub use huc1::Huc1Rom;
+pub use mmm01::Mmm01Rom;
+
+mod camera;
+mod huc1;
+mod mmm01;
 
 /// Errors that can result from attempting to parse a cartridge dump.
 #[derive(Debug, Error)]
@@ -52,6 +63,65 @@ pub enum ParseCartridgeError {
     IoError(#[source] io::Error),
 }
 
+/// Problems found by [`Mbc1Rom::verify_integrity`] which suggest a bad rom dump. These don't
+/// prevent the rom from loading, since a legitimate rom might still trigger them.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub enum IntegrityWarning {
+    /// The rom size code in the header isn't one with a known number of banks.
+    #[error("Unrecognized rom-size code in header: {0:#04X}")]
+    UnknownRomSize(u8),
+    /// The number of rom banks doesn't match the size declared in the header.
+    #[error("Header declares {declared} rom banks, but {actual} are loaded")]
+    SizeMismatch {
+        /// Number of banks declared in the header.
+        declared: usize,
+        /// Number of banks actually loaded.
+        actual: usize,
+    },
+    /// Some banks after bank 0 are entirely zero, which usually means the dump was truncated
+    /// and padded out to the declared size.
+    #[error("Rom banks {0:?} are all zero, the rom dump may be truncated")]
+    ZeroBanks(Vec<usize>),
+}
+
+/// Errors that can result from loading a [`VolatileState`] or restoring it into a cartridge.
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub enum VolatileStateError {
+    /// The state was saved in a format version this build doesn't know how to load.
+    #[error("Unsupported volatile state version: {0}")]
+    UnsupportedVersion(u32),
+    /// The state names a type of mapper this build doesn't know about.
+    #[error("Unknown mapper in volatile state: {0}")]
+    UnknownMapper(u8),
+    /// Ran out of data before the end of the state.
+    #[error("Unexpected end of volatile state")]
+    Truncated,
+    /// The state was saved from a cartridge with a different type of mapper.
+    #[error("Volatile state is for a {saved} cartridge, but the cartridge is {actual}")]
+    MapperMismatch {
+        /// Name of the mapper the state was saved from.
+        saved: &'static str,
+        /// Name of the mapper of the cartridge.
+        actual: &'static str,
+    },
+    /// The state has a different number of registers than the cartridge's mapper.
+    #[error("Volatile state has {saved} registers, but the cartridge has {actual}")]
+    RegisterCountMismatch {
+        /// Number of registers in the state.
+        saved: usize,
+        /// Number of registers in the cartridge.
+        actual: usize,
+    },
+    /// The state has a different amount of ram than the cartridge.
+    #[error("Volatile state has {saved} bytes of ram, but the cartridge has {actual}")]
+    RamSizeMismatch {
+        /// Size of the ram in the state.
+        saved: usize,
+        /// Size of the cartridge ram.
+        actual: usize,
+    },
+}
+
 impl From<io::Error> for ParseCartridgeError {
     fn from(err: io::Error) -> Self {
         match err.kind() {
@@ -69,32 +139,27 @@ impl From<io::Error> for ParseCartridgeError {
 /// the caller to remap the memory spaces as needed to insert the GPU ram.
 #[derive(Clone, Debug)]
 pub enum Cartridge {
-    /// No cartridge. All reads return 0 and all writes are ignored.
+    /// No cartridge. All reads return 0xFF (open bus) and all writes are ignored.
     None,
     /// A basic [`RomOnly`] cartridge.
     RomOnly(RomOnly),
     /// An [`Mbc1Rom`] cartridge.
     Mbc1(Mbc1Rom),
+    /// An [`Mmm01Rom`] multi-game cartridge.
+    Mmm01(Mmm01Rom),
+    /// A [`PocketCamera`] cartridge.
+    PocketCamera(PocketCamera),
+    /// A [`Huc1Rom`] cartridge.
+    Huc1(Huc1Rom),
 }
 
 impl Cartridge {
     /// Parse a cartridge rom.
     ///
     /// Note: expects the cartridge header to contain accurate data about the number of ram and rom
-    /// banks.
+    /// banks. A rom with fewer banks than the header declares, including one whose last bank is
+    /// cut short, fails with [`ParseCartridgeError::InsufficientData`].
     pub fn parse(mut reader: impl Read) -> Result<Cartridge, ParseCartridgeError> {
-        /// Location of the cartridge type in the header.
-        const CART_TYPE: usize = 0x147;
-        /// Location of the cartridge rom size in the header.
-        const ROM_SIZE: usize = 0x148;
-        /// Location of the cartridge ram size in the header.
-        const RAM_SIZE: usize = 0x149;
-        /// Location of the cartridge header checksum in the header.
-        const HEADER_CHECKSUM: usize = 0x14d;
-        /// Header length (including the first 0x100 bytes which aren't really part of the header).
-        /// This is the number of bytes you need to read to get the whole header.
-        const HEADER_LEN: usize = 0x150;
-
         /// Finish loading bank 0, which is partially read in order to get the cartridge header.
         fn finish_bank0(
             header: &[u8; HEADER_LEN],
@@ -106,6 +171,22 @@ impl Cartridge {
             Ok(())
         }
 
+        /// Loads the given number of rom banks, starting with the partially read bank 0.
+        fn read_rom_banks(
+            header: &[u8; HEADER_LEN],
+            mut reader: impl Read,
+            count: usize,
+        ) -> Result<Vec<RomBank>, ParseCartridgeError> {
+            let mut rom_banks = Vec::with_capacity(count);
+            rom_banks.push(ReadOnly([0u8; ROM_BANK_SIZE]));
+            finish_bank0(header, &mut reader, &mut rom_banks[0].0)?;
+            for bank in 1..count {
+                rom_banks.push(ReadOnly([0u8; ROM_BANK_SIZE]));
+                reader.read_exact(&mut rom_banks[bank].0[..])?;
+            }
+            Ok(rom_banks)
+        }
+
         /// Tries to read one more byte to make sure the reader is actually at EOF.
         fn ensure_eof(mut reader: impl Read) -> Result<(), ParseCartridgeError> {
             let mut buf = 0u8;
@@ -138,11 +219,8 @@ impl Cartridge {
             let code = header[RAM_SIZE];
             match code {
                 0 => Ok(0),
-                1 => {
-                    warn!("2 KiB ram size is unsupported, using 1 8 KiB ram bank instead.");
-                    Ok(1)
-                }
-                2 => Ok(1),
+                // A single 2 KiB bank, mirrored through the 8 KiB ram window.
+                1 | 2 => Ok(1),
                 3 => Ok(4),
                 4 => Ok(16),
                 5 => Ok(8),
@@ -155,9 +233,7 @@ impl Cartridge {
         // Load the first bank in order to read the cartridge header.
         reader.read_exact(&mut header[..])?;
 
-        let computed_checksum = header[0x134..=0x14c]
-            .iter()
-            .fold(0u8, |x, &h| x.wrapping_sub(h).wrapping_sub(1));
+        let computed_checksum = compute_header_checksum(&header);
         let header_checksum = header[HEADER_CHECKSUM];
         if computed_checksum != header_checksum {
             warn!(
@@ -166,29 +242,39 @@ impl Cartridge {
             );
         }
 
+        // Carts with 2 KiB of ram only decode the low 11 bits of the ram address.
+        let ram_mask = if header[RAM_SIZE] == 1 {
+            0x7ff
+        } else {
+            RAM_BANK_SIZE as u16 - 1
+        };
+
         match header[CART_TYPE] {
             code @ (0 | 8 | 9) => {
                 match rom_size(&header) {
-                    Ok(size) if size == 2 => {},
+                    Ok(2) => {},
                     Ok(size) => warn!("RomOnly cartridge had rom size {}, but RomOnly always has exactly 2 banks.", size),
                     Err(ParseCartridgeError::UnrecognizedRomSizeCode(code)) => warn!("RomOnly cartridge had an invalid rom size code {}", code),
                     Err(_) => unreachable!(),
                 }
                 match (code, ram_size(&header)) {
-                    (0, Ok(0)) | (8 | 9, Ok(2)) => {}
+                    (0, Ok(0)) | (8 | 9, Ok(1)) => {}
                     (0, Ok(size)) => warn!("RomOnly cartridge with no ram specified {} ram banks. It will be run without ram.", size),
                     (8 | 9, Ok(size)) => warn!("RomOnly cartridge with ram specified {} ram banks. It will be run with 1 ram bank.", size),
                     (_, Err(ParseCartridgeError::UnrecognizedRamSizeCode(code))) => warn!("RomOnly cartrige had an unrecognized ram size code {}.", code),
                     _ => unreachable!(),
                 }
 
-                let mut rom = RomOnly::empty();
-                finish_bank0(&header, &mut reader, &mut rom.rom_banks[0].0)?;
-                reader.read_exact(&mut rom.rom_banks[1].0[..])?;
+                let mut rom_banks = Box::new([ReadOnly([0u8; ROM_BANK_SIZE]); 2]);
+                finish_bank0(&header, &mut reader, &mut rom_banks[0].0)?;
+                reader.read_exact(&mut rom_banks[1].0[..])?;
                 ensure_eof(reader)?;
 
+                let mut rom = RomOnly::empty();
+                rom.rom_banks = Arc::from(rom_banks);
                 if matches!(code, 8 | 9) {
-                    rom.ram_bank = Some(Box::new([0u8; RAM_BANK_SIZE]));
+                    rom.ram_bank = Some(ChunkedRam::new());
+                    rom.ram_mask = ram_mask;
                     rom.save_ram = code == 9;
                 }
                 Ok(Cartridge::RomOnly(rom))
@@ -199,72 +285,717 @@ impl Cartridge {
                     return Err(ParseCartridgeError::UnsupportedRomSize { rom_type, rom_size });
                 }
                 let ram_size = match (rom_type, ram_size(&header)) {
-                    (0, Err(e)) => {
+                    (1, Err(e)) => {
                         warn!("Error parsing ram type for ramless MBC1: {}", e);
                         0
                     }
-                    (0, Ok(0)) => 0,
-                    (0, Ok(size)) => {
+                    (1, Ok(0)) => 0,
+                    (1, Ok(size)) => {
                         warn!("Got {} ram banks on a ramless MBC1, expected 0.", size);
                         0
                     }
                     (2 | 3, Err(e)) => return Err(e),
-                    (2 | 3, Ok(size @ (8 | 32))) => size,
+                    (2 | 3, Ok(size @ (1 | 4))) => size,
                     (2 | 3, Ok(ram_size)) => {
                         return Err(ParseCartridgeError::UnsupportedRamSize { rom_type, ram_size })
                     }
                     _ => unreachable!(),
                 };
 
-                let mut rom_banks = Vec::with_capacity(rom_size);
-                rom_banks.push(ReadOnly([0u8; ROM_BANK_SIZE]));
-                finish_bank0(&header, &mut reader, &mut rom_banks[0].0)?;
-                for bank in 1..rom_size {
-                    rom_banks.push(ReadOnly([0u8; ROM_BANK_SIZE]));
-                    reader.read(&mut rom_banks[bank].0[..])?;
-                }
+                let rom_banks = read_rom_banks(&header, &mut reader, rom_size)?;
                 ensure_eof(reader)?;
 
-                Ok(Cartridge::Mbc1(Mbc1Rom::new(
+                let cart = Mbc1Builder::new()
+                    .rom_banks(rom_banks)
+                    .ram_size(if ram_size == 0 { 0 } else { header[RAM_SIZE] })
+                    .battery(rom_type == 3)
+                    .build()?;
+                Ok(Cartridge::Mbc1(cart))
+            }
+            rom_type @ 0xb..=0xd => {
+                let rom_size = rom_size(&header)?;
+                if rom_size > 512 {
+                    return Err(ParseCartridgeError::UnsupportedRomSize { rom_type, rom_size });
+                }
+                let ram_size = match (rom_type, ram_size(&header)) {
+                    (0xb, Ok(0)) => 0,
+                    (0xb, Ok(size)) => {
+                        warn!("Got {} ram banks on a ramless MMM01, expected 0.", size);
+                        0
+                    }
+                    (0xb, Err(e)) => {
+                        warn!("Error parsing ram type for ramless MMM01: {}", e);
+                        0
+                    }
+                    (_, Err(e)) => return Err(e),
+                    (_, Ok(size @ (1 | 4 | 16))) => size,
+                    (_, Ok(ram_size)) => {
+                        return Err(ParseCartridgeError::UnsupportedRamSize { rom_type, ram_size })
+                    }
+                };
+
+                let rom_banks = read_rom_banks(&header, &mut reader, rom_size)?;
+                ensure_eof(reader)?;
+                Ok(Cartridge::Mmm01(Mmm01Rom::new(
                     rom_banks,
                     ram_size,
-                    rom_type == 3,
+                    rom_type == 0xd,
                 )))
             }
-            code @ (5..=6 | 0xb..=0xd | 0xf..=0x13 | 0x19..=0x1e | 0x20 | 0x22 | 0xfc..=0xff) => {
+            rom_type @ 0xfc => {
+                let rom_size = rom_size(&header)?;
+                if rom_size > 64 {
+                    return Err(ParseCartridgeError::UnsupportedRomSize { rom_type, rom_size });
+                }
+                match ram_size(&header) {
+                    Ok(camera::NUM_RAM_BANKS) => {}
+                    Ok(size) => warn!("Pocket Camera specified {} ram banks, but it always has 16. It will be run with 16 ram banks.", size),
+                    Err(e) => warn!("Error parsing ram type for Pocket Camera: {}", e),
+                }
+
+                let rom_banks = read_rom_banks(&header, &mut reader, rom_size)?;
+                ensure_eof(reader)?;
+                Ok(Cartridge::PocketCamera(PocketCamera::new(rom_banks)))
+            }
+            rom_type @ 0xff => {
+                let rom_size = rom_size(&header)?;
+                if rom_size > 64 {
+                    return Err(ParseCartridgeError::UnsupportedRomSize { rom_type, rom_size });
+                }
+                let ram_size = match ram_size(&header)? {
+                    size @ (0 | 1 | 4) => size,
+                    ram_size => {
+                        return Err(ParseCartridgeError::UnsupportedRamSize { rom_type, ram_size })
+                    }
+                };
+
+                let rom_banks = read_rom_banks(&header, &mut reader, rom_size)?;
+                ensure_eof(reader)?;
+                Ok(Cartridge::Huc1(Huc1Rom::new(rom_banks, ram_size)))
+            }
+            code @ (5..=6 | 0xf..=0x13 | 0x19..=0x1e | 0x20 | 0x22 | 0xfd..=0xfe) => {
                 Err(ParseCartridgeError::UnsupportedMbcType(code))
             }
             code => Err(ParseCartridgeError::UnknownMbcType(code)),
         }
     }
+
+    /// Computes the offset into the rom file of the byte which is currently mapped at the given
+    /// address, taking into account the current bank selection of the mapper. Returns `None` if
+    /// the address is not in the rom portion of the cartridge (0..0x8000) or there is no
+    /// cartridge.
+    pub fn physical_offset(&self, addr: Addr) -> Option<usize> {
+        match self {
+            Cartridge::None => None,
+            Cartridge::RomOnly(_) => match addr.relative() {
+                0..=0x7fff => Some(addr.index()),
+                _ => None,
+            },
+            Cartridge::Mbc1(ref cart) => cart.physical_offset(addr),
+            Cartridge::Mmm01(ref cart) => cart.physical_offset(addr),
+            Cartridge::PocketCamera(ref cart) => cart.physical_offset(addr),
+            Cartridge::Huc1(ref cart) => cart.physical_offset(addr),
+        }
+    }
+
+    /// Gets the number of the rom bank currently mapped in the switchable window at
+    /// 0x4000..0x8000, including any high bits selected by the mapper. Cartridges without a
+    /// mapper always have bank 1 there. Returns 0 if there is no cartridge.
+    pub fn current_rom_bank(&self) -> u16 {
+        match self {
+            Cartridge::None => 0,
+            Cartridge::RomOnly(_) => 1,
+            Cartridge::Mbc1(ref cart) => cart.current_rom_bank(),
+            Cartridge::Mmm01(ref cart) => cart.current_rom_bank(),
+            Cartridge::PocketCamera(ref cart) => cart.current_rom_bank(),
+            Cartridge::Huc1(ref cart) => cart.current_rom_bank(),
+        }
+    }
+
+    /// Returns true if the cartridge has a mapper, whose registers take writes to the rom area.
+    /// Without one, writes to the rom area do nothing.
+    pub fn has_mapper(&self) -> bool {
+        match self {
+            Cartridge::None | Cartridge::RomOnly(_) => false,
+            Cartridge::Mbc1(_)
+            | Cartridge::Mmm01(_)
+            | Cartridge::PocketCamera(_)
+            | Cartridge::Huc1(_) => true,
+        }
+    }
+
+    /// Gets whether the CPU can currently read and write the cartridge ram area
+    /// (0xA000..0xC000). Neither is possible with no cartridge, no ram, or ram which the mapper
+    /// has disabled.
+    pub fn ram_permissions(&self) -> Permissions {
+        let both = Permissions::READ | Permissions::WRITE;
+        match self {
+            Cartridge::None => Permissions::empty(),
+            Cartridge::RomOnly(ref cart) if cart.ram_bank.is_some() => both,
+            Cartridge::RomOnly(_) => Permissions::empty(),
+            Cartridge::Mbc1(ref cart) if cart.ram_bank_index().is_some() => both,
+            Cartridge::Mbc1(_) => Permissions::empty(),
+            Cartridge::Mmm01(ref cart) => cart.ram_permissions(),
+            Cartridge::PocketCamera(ref cart) => cart.ram_permissions(),
+            Cartridge::Huc1(ref cart) => cart.ram_permissions(),
+        }
+    }
+
+    /// Sets whether the infrared receiver on the cartridge sees light. Does nothing unless the
+    /// cartridge has an infrared port, as the HuC1 does.
+    pub fn set_ir_input(&mut self, seen: bool) {
+        if let Cartridge::Huc1(ref mut cart) = self {
+            cart.set_ir_input(seen);
+        }
+    }
+
+    /// Returns true if the cartridge has an infrared LED and it is on.
+    pub fn ir_output(&self) -> bool {
+        match self {
+            Cartridge::Huc1(ref cart) => cart.ir_output(),
+            _ => false,
+        }
+    }
+
+    /// Resets the mapper registers to their power-on state, as happens when the cartridge is
+    /// removed and reinserted, without touching the cartridge ram. Cartridges without a mapper
+    /// are unaffected.
+    pub fn reset_registers(&mut self) {
+        match self {
+            Cartridge::Mbc1(ref mut cart) => cart.reset_registers(),
+            Cartridge::Mmm01(ref mut cart) => cart.reset_registers(),
+            Cartridge::PocketCamera(ref mut cart) => cart.reset_registers(),
+            Cartridge::Huc1(ref mut cart) => cart.reset_registers(),
+            Cartridge::None | Cartridge::RomOnly(_) => {}
+        }
+    }
+
+    /// Gets the header of the inserted cartridge, or `None` if there is no cartridge.
+    pub fn header(&self) -> Option<CartridgeHeader> {
+        let bank0 = match self {
+            Cartridge::None => return None,
+            Cartridge::RomOnly(ref cart) => &cart.rom_banks[0],
+            Cartridge::Mbc1(ref cart) => &cart.rom_banks[0],
+            Cartridge::Mmm01(ref cart) => cart.bank0(),
+            Cartridge::PocketCamera(ref cart) => cart.bank0(),
+            Cartridge::Huc1(ref cart) => cart.bank0(),
+        };
+        // Bank 0 always contains the whole header, so this can't fail.
+        CartridgeHeader::parse(&bank0.0[..]).ok()
+    }
+
+    /// Gets the signature used to check whether a save file belongs to this cartridge. It
+    /// should be stored alongside the save.
+    pub fn save_signature(&self) -> SaveSignature {
+        let (title, global_checksum) = match self.header() {
+            Some(header) => (header.title, header.global_checksum),
+            None => (String::new(), 0),
+        };
+        SaveSignature {
+            title,
+            global_checksum,
+            ram_size: self.ram_size(),
+        }
+    }
+
+    /// Returns true if a save with the given signature can be loaded into this cartridge: the
+    /// cartridge has ram, and the title, global checksum, and ram size all match.
+    pub fn is_save_compatible(&self, other: &SaveSignature) -> bool {
+        self.ram_size() > 0 && self.save_signature() == *other
+    }
+
+    /// Number of ram banks and the size of each bank in bytes. Carts with 2 KiB of ram have a
+    /// single bank which is smaller than the 8 KiB window it is mapped to.
+    fn ram_banks(&self) -> (usize, usize) {
+        match self {
+            Cartridge::None => (0, 0),
+            Cartridge::RomOnly(ref cart) => {
+                (cart.ram_bank.is_some() as usize, cart.ram_mask as usize + 1)
+            }
+            Cartridge::Mbc1(ref cart) => (cart.ram.num_banks(), cart.ram_mask as usize + 1),
+            Cartridge::Mmm01(ref cart) => (cart.num_ram_banks(), RAM_BANK_SIZE),
+            Cartridge::PocketCamera(_) => (camera::NUM_RAM_BANKS, RAM_BANK_SIZE),
+            Cartridge::Huc1(ref cart) => (cart.num_ram_banks(), RAM_BANK_SIZE),
+        }
+    }
+
+    /// Total size of the cartridge ram in bytes.
+    fn ram_size(&self) -> usize {
+        let (banks, bank_size) = self.ram_banks();
+        banks * bank_size
+    }
+
+    /// Sets all of the cartridge ram to 0, as if the battery had run out.
+    pub fn clear_ram(&mut self) {
+        let (banks, bank_size) = self.ram_banks();
+        for bank in 0..banks {
+            for offset in 0..bank_size {
+                self.write_ram_bank(bank, Addr::from(offset as u16), 0);
+            }
+        }
+    }
+
+    /// Writes directly to the given ram bank, regardless of which bank is currently mapped or
+    /// whether ram is enabled. The address is relative to the start of the bank. Writes to banks
+    /// which don't exist are ignored.
+    pub fn write_ram_bank(&mut self, bank: usize, addr: Addr, value: u8) {
+        match self {
+            Cartridge::None => {}
+            Cartridge::RomOnly(ref mut cart) => {
+                if let (0, Some(ram)) = (bank, &mut cart.ram_bank) {
+                    ram.write(mirror_ram(addr, cart.ram_mask), value);
+                }
+            }
+            Cartridge::Mbc1(ref mut cart) => {
+                if bank < cart.ram.num_banks() {
+                    cart.ram.write(bank, mirror_ram(addr, cart.ram_mask), value);
+                }
+            }
+            Cartridge::Mmm01(ref mut cart) => cart.write_ram_bank(bank, addr, value),
+            Cartridge::PocketCamera(ref mut cart) => cart.write_ram_bank(bank, addr, value),
+            Cartridge::Huc1(ref mut cart) => cart.write_ram_bank(bank, addr, value),
+        }
+    }
+
+    /// Gets a copy of the given ram bank, or `None` if there is no such bank. Ram smaller than a
+    /// bank fills only the start of the copy.
+    fn read_ram_bank(&self, bank: usize) -> Option<RamBank> {
+        match self {
+            Cartridge::None => None,
+            Cartridge::RomOnly(ref cart) => match (bank, &cart.ram_bank) {
+                (0, Some(ram)) => {
+                    let mut data = [0; RAM_BANK_SIZE];
+                    for (byte, value) in data.iter_mut().zip(ram.iter()) {
+                        *byte = value;
+                    }
+                    Some(data)
+                }
+                _ => None,
+            },
+            Cartridge::Mbc1(ref cart) => cart.ram_bank(bank),
+            Cartridge::Mmm01(ref cart) => cart.ram_bank(bank),
+            Cartridge::PocketCamera(ref cart) => cart.ram_bank(bank),
+            Cartridge::Huc1(ref cart) => cart.ram_bank(bank),
+        }
+    }
+
+    /// Index of the type of mapper in [`MAPPER_NAMES`], which identifies it in a
+    /// [`VolatileState`].
+    fn mapper_id(&self) -> u8 {
+        match self {
+            Cartridge::None => 0,
+            Cartridge::RomOnly(_) => 1,
+            Cartridge::Mbc1(_) => 2,
+            Cartridge::Mmm01(_) => 3,
+            Cartridge::PocketCamera(_) => 4,
+            Cartridge::Huc1(_) => 5,
+        }
+    }
+
+    /// Gets the values of the mapper registers.
+    fn mapper_registers(&self) -> Vec<u8> {
+        match self {
+            Cartridge::None | Cartridge::RomOnly(_) => vec![],
+            Cartridge::Mbc1(ref cart) => cart.registers(),
+            Cartridge::Mmm01(ref cart) => cart.registers(),
+            Cartridge::PocketCamera(ref cart) => cart.registers(),
+            Cartridge::Huc1(ref cart) => cart.registers(),
+        }
+    }
+
+    /// Saves the parts of the cartridge which change while a game runs: the mapper registers
+    /// and the contents of ram. The rom isn't included, so this is cheap enough to do on every
+    /// quick save, and the state can be restored into any cartridge loaded from the same rom.
+    pub fn save_volatile(&self) -> VolatileState {
+        let (banks, bank_size) = self.ram_banks();
+        let mut ram = Vec::with_capacity(banks * bank_size);
+        for bank in 0..banks {
+            if let Some(data) = self.read_ram_bank(bank) {
+                ram.extend_from_slice(&data[..bank_size]);
+            }
+        }
+        VolatileState {
+            mapper: self.mapper_id(),
+            registers: self.mapper_registers(),
+            ram,
+        }
+    }
+
+    /// Restores the mapper registers and ram from a state saved by
+    /// [`save_volatile`](Self::save_volatile). The state must come from a cartridge with the
+    /// same type of mapper and the same amount of ram, otherwise an error is returned and the
+    /// cartridge is left unchanged.
+    pub fn restore_volatile(&mut self, state: &VolatileState) -> Result<(), VolatileStateError> {
+        if state.mapper != self.mapper_id() {
+            return Err(VolatileStateError::MapperMismatch {
+                saved: MAPPER_NAMES[state.mapper as usize],
+                actual: MAPPER_NAMES[self.mapper_id() as usize],
+            });
+        }
+        let num_registers = self.mapper_registers().len();
+        if state.registers.len() != num_registers {
+            return Err(VolatileStateError::RegisterCountMismatch {
+                saved: state.registers.len(),
+                actual: num_registers,
+            });
+        }
+        let (banks, bank_size) = self.ram_banks();
+        if state.ram.len() != banks * bank_size {
+            return Err(VolatileStateError::RamSizeMismatch {
+                saved: state.ram.len(),
+                actual: banks * bank_size,
+            });
+        }
+
+        let registers = &state.registers[..];
+        match self {
+            Cartridge::None | Cartridge::RomOnly(_) => {}
+            Cartridge::Mbc1(ref mut cart) => cart.restore_registers(registers),
+            Cartridge::Mmm01(ref mut cart) => cart.restore_registers(registers),
+            Cartridge::PocketCamera(ref mut cart) => cart.restore_registers(registers),
+            Cartridge::Huc1(ref mut cart) => cart.restore_registers(registers),
+        }
+        if bank_size > 0 {
+            for (bank, data) in state.ram.chunks(bank_size).enumerate() {
+                for (offset, &value) in data.iter().enumerate() {
+                    self.write_ram_bank(bank, Addr::from(offset as u16), value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Names of the types of mapper, indexed by the id used in a [`VolatileState`]. Ids are never
+/// reused, so new mappers must be added at the end.
+const MAPPER_NAMES: [&str; 6] = ["none", "rom only", "MBC1", "MMM01", "Pocket Camera", "HuC1"];
+
+/// The parts of a cartridge which change while a game runs: the mapper registers and the
+/// contents of ram, without the rom. Saved with [`Cartridge::save_volatile`] and restored with
+/// [`Cartridge::restore_volatile`].
+///
+/// [`to_bytes`](Self::to_bytes) writes the state in a versioned format, and
+/// [`from_bytes`](Self::from_bytes) migrates states written by older versions, so a front-end
+/// can keep them on disk across upgrades.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct VolatileState {
+    /// Which type of mapper the state was saved from, as an index into [`MAPPER_NAMES`].
+    mapper: u8,
+    /// Mapper registers, in an order specific to the type of mapper.
+    registers: Vec<u8>,
+    /// Contents of ram, with every bank in order. Ram smaller than a bank is saved at its real
+    /// size.
+    ram: Vec<u8>,
+}
+
+impl VolatileState {
+    /// Version of the format written by [`to_bytes`](Self::to_bytes). This must be increased
+    /// whenever the format or the registers saved for an existing mapper change, and
+    /// `from_bytes` taught to migrate the previous version.
+    pub const VERSION: u32 = 1;
+
+    /// Gets the contents of the cartridge ram in the state.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Writes the state as bytes: the format version (4 bytes, little endian), the mapper id,
+    /// the number of registers (2 bytes, little endian), the registers, and then the ram.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(7 + self.registers.len() + self.ram.len());
+        bytes.extend_from_slice(&Self::VERSION.to_le_bytes());
+        bytes.push(self.mapper);
+        bytes.extend_from_slice(&(self.registers.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.registers);
+        bytes.extend_from_slice(&self.ram);
+        bytes
+    }
+
+    /// Reads a state written by [`to_bytes`](Self::to_bytes), by this or any older version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<VolatileState, VolatileStateError> {
+        if bytes.len() < 4 {
+            return Err(VolatileStateError::Truncated);
+        }
+        let (version, rest) = bytes.split_at(4);
+        match u32::from_le_bytes([version[0], version[1], version[2], version[3]]) {
+            1 => Self::from_v1_bytes(rest),
+            version => Err(VolatileStateError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// Reads the body of a version 1 state, after the version.
+    fn from_v1_bytes(bytes: &[u8]) -> Result<VolatileState, VolatileStateError> {
+        if bytes.len() < 3 {
+            return Err(VolatileStateError::Truncated);
+        }
+        let mapper = bytes[0];
+        if mapper as usize >= MAPPER_NAMES.len() {
+            return Err(VolatileStateError::UnknownMapper(mapper));
+        }
+        let num_registers = u16::from_le_bytes([bytes[1], bytes[2]]) as usize;
+        let rest = &bytes[3..];
+        if rest.len() < num_registers {
+            return Err(VolatileStateError::Truncated);
+        }
+        let (registers, ram) = rest.split_at(num_registers);
+        Ok(VolatileState {
+            mapper,
+            registers: registers.to_vec(),
+            ram: ram.to_vec(),
+        })
+    }
+}
+
+/// Identifies which cartridge a save file belongs to. See [`Cartridge::save_signature`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SaveSignature {
+    /// Title of the game.
+    pub title: String,
+    /// Global checksum from the cartridge header.
+    pub global_checksum: u16,
+    /// Size of the cartridge ram in bytes.
+    pub ram_size: usize,
+}
+
+/// Location of the cartridge title in the header.
+const TITLE: Range<usize> = 0x134..0x144;
+/// Location of the CGB support flag in the header. Overlaps the last byte of the title.
+const CGB_FLAG: usize = 0x143;
+/// Location of the SGB support flag in the header.
+const SGB_FLAG: usize = 0x146;
+/// Location of the cartridge type in the header.
+const CART_TYPE: usize = 0x147;
+/// Location of the cartridge rom size in the header.
+const ROM_SIZE: usize = 0x148;
+/// Location of the cartridge ram size in the header.
+const RAM_SIZE: usize = 0x149;
+/// Location of the cartridge header checksum in the header.
+const HEADER_CHECKSUM: usize = 0x14d;
+/// Location of the (big-endian) global checksum in the header.
+const GLOBAL_CHECKSUM: usize = 0x14e;
+/// Header length (including the first 0x100 bytes which aren't really part of the header).
+/// This is the number of bytes you need to read to get the whole header.
+const HEADER_LEN: usize = 0x150;
+
+/// Computes the checksum of the header, which should match the value stored at
+/// `HEADER_CHECKSUM`.
+fn compute_header_checksum(header: &[u8]) -> u8 {
+    header[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |x, &h| x.wrapping_sub(h).wrapping_sub(1))
+}
+
+/// Gets a human readable name for a cartridge type code from the header, such as
+/// "MBC3+RAM+BATTERY". Unrecognized codes are named "Unknown (0xNN)".
+pub fn cartridge_type_name(code: u8) -> Cow<'static, str> {
+    let name = match code {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0b => "MMM01",
+        0x0c => "MMM01+RAM",
+        0x0d => "MMM01+RAM+BATTERY",
+        0x0f => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1a => "MBC5+RAM",
+        0x1b => "MBC5+RAM+BATTERY",
+        0x1c => "MBC5+RUMBLE",
+        0x1d => "MBC5+RUMBLE+RAM",
+        0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xfc => "POCKET CAMERA",
+        0xfd => "BANDAI TAMA5",
+        0xfe => "HuC3",
+        0xff => "HuC1+RAM+BATTERY",
+        _ => return unknown_code(code),
+    };
+    Cow::Borrowed(name)
+}
+
+/// Gets a human readable name for a rom size code from the header, such as "64 KiB (4 banks)".
+/// Unrecognized codes are named "Unknown (0xNN)".
+pub fn rom_size_name(code: u8) -> Cow<'static, str> {
+    let name = match code {
+        0x00 => "32 KiB (2 banks)",
+        0x01 => "64 KiB (4 banks)",
+        0x02 => "128 KiB (8 banks)",
+        0x03 => "256 KiB (16 banks)",
+        0x04 => "512 KiB (32 banks)",
+        0x05 => "1 MiB (64 banks)",
+        0x06 => "2 MiB (128 banks)",
+        0x07 => "4 MiB (256 banks)",
+        0x08 => "8 MiB (512 banks)",
+        0x52 => "1.1 MiB (72 banks)",
+        0x53 => "1.2 MiB (80 banks)",
+        0x54 => "1.5 MiB (96 banks)",
+        _ => return unknown_code(code),
+    };
+    Cow::Borrowed(name)
+}
+
+/// Gets a human readable name for a ram size code from the header, such as "32 KiB (4 banks)".
+/// Unrecognized codes are named "Unknown (0xNN)".
+pub fn ram_size_name(code: u8) -> Cow<'static, str> {
+    let name = match code {
+        0x00 => "None",
+        0x01 => "2 KiB",
+        0x02 => "8 KiB (1 bank)",
+        0x03 => "32 KiB (4 banks)",
+        0x04 => "128 KiB (16 banks)",
+        0x05 => "64 KiB (8 banks)",
+        _ => return unknown_code(code),
+    };
+    Cow::Borrowed(name)
+}
+
+/// Name used for unrecognized header codes.
+fn unknown_code(code: u8) -> Cow<'static, str> {
+    Cow::Owned(format!("Unknown ({:#04X})", code))
+}
+
+/// How a cartridge supports the GameBoy Color, from the CGB flag in its header.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CgbSupport {
+    /// A DMG game, with no CGB features.
+    DmgOnly,
+    /// A game which uses CGB features when available but also runs on a DMG (flag 0x80).
+    CgbEnhanced,
+    /// A game which only works on a CGB (flag 0xC0). These typically hang or show an error
+    /// screen when run on a DMG.
+    CgbOnly,
+}
+
+/// Information from the header of a cartridge rom.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CartridgeHeader {
+    /// Title of the game, with any trailing padding removed.
+    pub title: String,
+    /// Raw CGB support flag.
+    pub cgb_flag: u8,
+    /// Raw SGB support flag.
+    pub sgb_flag: u8,
+    /// Raw cartridge type code, which identifies the MBC and extra hardware.
+    pub cartridge_type: u8,
+    /// Raw rom size code.
+    pub rom_size_code: u8,
+    /// Raw ram size code.
+    pub ram_size_code: u8,
+    /// Header checksum stored in the header.
+    pub header_checksum: u8,
+    /// Whether the stored header checksum matches the computed header checksum.
+    pub header_checksum_valid: bool,
+    /// Global checksum stored in the header. This is not verified by the hardware.
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /// Parses the header from the start of a cartridge rom. The data must contain at least the
+    /// whole header (0x150 bytes), but may be longer. Only the header itself is validated, so
+    /// this will succeed even if the rom has a type that isn't supported.
+    pub fn parse(data: &[u8]) -> Result<CartridgeHeader, ParseCartridgeError> {
+        if data.len() < HEADER_LEN {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+
+        // On CGB-aware carts, the last byte of the title is the CGB flag.
+        let title_end = if data[CGB_FLAG] & 0x80 != 0 {
+            CGB_FLAG
+        } else {
+            TITLE.end
+        };
+        let title = data[TITLE.start..title_end]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char)
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        Ok(CartridgeHeader {
+            title,
+            cgb_flag: data[CGB_FLAG],
+            sgb_flag: data[SGB_FLAG],
+            cartridge_type: data[CART_TYPE],
+            rom_size_code: data[ROM_SIZE],
+            ram_size_code: data[RAM_SIZE],
+            header_checksum: data[HEADER_CHECKSUM],
+            header_checksum_valid: data[HEADER_CHECKSUM] == compute_header_checksum(data),
+            global_checksum: u16::from_be_bytes([data[GLOBAL_CHECKSUM], data[GLOBAL_CHECKSUM + 1]]),
+        })
+    }
+
+    /// Classifies the cartridge's CGB support from its CGB flag.
+    pub fn cgb_support(&self) -> CgbSupport {
+        match self.cgb_flag {
+            0xc0 => CgbSupport::CgbOnly,
+            flag if flag & 0x80 != 0 => CgbSupport::CgbEnhanced,
+            _ => CgbSupport::DmgOnly,
+        }
+    }
 }
 
 impl MemDevice for Cartridge {
+    fn size(&self) -> usize {
+        0xa000
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         match self {
-            Cartridge::None => NullRom::<0xA000>.read(addr),
+            // With no cartridge, nothing drives the bus, so reads float high.
+            Cartridge::None => {
+                assert!(
+                    addr.index() < 0xA000,
+                    "Address {} out of range for empty cartridge slot",
+                    addr
+                );
+                OPEN_BUS
+            }
             Cartridge::RomOnly(ref cart) => cart.read(addr),
             Cartridge::Mbc1(ref cart) => cart.read(addr),
+            Cartridge::Mmm01(ref cart) => cart.read(addr),
+            Cartridge::PocketCamera(ref cart) => cart.read(addr),
+            Cartridge::Huc1(ref cart) => cart.read(addr),
         }
     }
 
     fn write(&mut self, addr: Addr, value: u8) {
         match self {
-            Cartridge::None => NullRom::<0xA000>.write(addr, value),
+            Cartridge::None => assert!(
+                addr.index() < 0xA000,
+                "Address {} out of range for empty cartridge slot",
+                addr
+            ),
             Cartridge::RomOnly(ref mut cart) => cart.write(addr, value),
             Cartridge::Mbc1(ref mut cart) => cart.write(addr, value),
+            Cartridge::Mmm01(ref mut cart) => cart.write(addr, value),
+            Cartridge::PocketCamera(ref mut cart) => cart.write(addr, value),
+            Cartridge::Huc1(ref mut cart) => cart.write(addr, value),
         }
     }
 }
 
+/// Value read from parts of the cartridge which don't drive the bus, such as absent or
+/// disabled ram. The bus floats high.
+const OPEN_BUS: u8 = 0xff;
+
 /// Rom banks are 0x4000 = 16 KiB.
 const ROM_BANK_SIZE: usize = 0x4000;
 
 /// A single 16 KiB rom bank within a cartridge.
 pub type RomBank = ReadOnly<[u8; ROM_BANK_SIZE]>;
 
-/// Ram banks are 0x2000 = 8 KiB.
-const RAM_BANK_SIZE: usize = 0x4000;
+/// Ram banks are 0x2000 = 8 KiB, the size of the cartridge ram window at 0xA000..0xC000.
+const RAM_BANK_SIZE: usize = 0x2000;
 
 /// A single 8 KiB ram bank within a cartridge.
 pub type RamBank = [u8; RAM_BANK_SIZE];
@@ -272,10 +1003,12 @@ pub type RamBank = [u8; RAM_BANK_SIZE];
 /// Cartridge which has only 2 rom banks and optionally up to 1 ram bank.
 #[derive(Clone, Debug)]
 pub struct RomOnly {
-    /// Rom banks. Both are always accessible.
-    rom_banks: Box<[RomBank; 2]>,
+    /// Rom banks. Both are always accessible. Shared between clones, since rom never changes.
+    rom_banks: Arc<[RomBank; 2]>,
     /// Ram bank, may or may not be included.
-    ram_bank: Option<Box<RamBank>>,
+    ram_bank: Option<ChunkedRam<RAM_BANK_SIZE>>,
+    /// Mask applied to addresses within the ram bank, which mirrors 2 KiB ram.
+    ram_mask: u16,
     /// Whether ram is saved when the device is powered off. (Does the ram have a battery?)
     save_ram: bool,
 }
@@ -284,21 +1017,26 @@ impl RomOnly {
     /// Constructs a new `RomOnly` with empty (all 0) rom banks and no ram bank.
     fn empty() -> Self {
         Self {
-            rom_banks: Box::new([ReadOnly([0u8; ROM_BANK_SIZE]); 2]),
+            rom_banks: Arc::new([ReadOnly([0u8; ROM_BANK_SIZE]); 2]),
             ram_bank: None,
+            ram_mask: RAM_BANK_SIZE as u16 - 1,
             save_ram: false,
         }
     }
 }
 
 impl MemDevice for RomOnly {
+    fn size(&self) -> usize {
+        0xa000
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         match addr.relative() {
             0..=0x3fff => self.rom_banks[0].read(addr),
             0x4000..=0x7fff => self.rom_banks[1].read(addr.offset_by(0x4000)),
             0x8000..=0x9fff => match self.ram_bank {
-                Some(ref ram) => ram.read(addr.offset_by(0x8000)),
-                None => 0,
+                Some(ref ram) => ram.read(mirror_ram(addr.offset_by(0x8000), self.ram_mask)),
+                None => OPEN_BUS,
             },
             _ => panic!("Address {} out of range for Mbc1Rom", addr),
         }
@@ -306,12 +1044,13 @@ impl MemDevice for RomOnly {
 
     fn write(&mut self, addr: Addr, value: u8) {
         match addr.relative() {
-            0..=0x3fff => self.rom_banks[0].write(addr, value),
-            0x4000..=0x7fff => self.rom_banks[1].write(addr.offset_by(0x4000), value),
-            0x8000..=0x9fff => match self.ram_bank {
-                Some(ref mut ram) => ram.write(addr.offset_by(0x8000), value),
-                None => {}
-            },
+            // Rom is read-only, and there are no registers.
+            0..=0x7fff => {}
+            0x8000..=0x9fff => {
+                if let Some(ref mut ram) = self.ram_bank {
+                    ram.write(mirror_ram(addr.offset_by(0x8000), self.ram_mask), value);
+                }
+            }
             _ => panic!("Address {} out of range for Mbc1Rom", addr),
         }
     }
@@ -321,10 +1060,13 @@ impl MemDevice for RomOnly {
 
 #[derive(Clone, Debug)]
 pub struct Mbc1Rom {
-    /// Set of rom banks loaded from the cartridge.
-    rom_banks: Vec<RomBank>,
-    /// Set of ram banks on this Mbc1Rom, if any. If none, this will be an empty vector.
-    ram_banks: Vec<RamBank>,
+    /// Set of rom banks loaded from the cartridge. Shared between clones, since rom never
+    /// changes.
+    rom_banks: Arc<[RomBank]>,
+    /// Set of ram banks on this Mbc1Rom, if any.
+    ram: Mbc1Ram,
+    /// Mask applied to addresses within a ram bank, which mirrors 2 KiB ram.
+    ram_mask: u16,
     /// Whether ram is saved when the device is powered off. (Does the ram have a battery?)
     save_ram: bool,
 
@@ -354,7 +1096,33 @@ pub struct Mbc1Rom {
 
 impl Mbc1Rom {
     /// Construct a new Mbc1Rom with the given rom banks and number of ram banks.
-    fn new(rom_banks: Vec<RomBank>, num_ram_banks: usize, save_ram: bool) -> Self {
+    pub(super) fn new(rom_banks: Vec<RomBank>, num_ram_banks: usize, save_ram: bool) -> Self {
+        let ram = Mbc1Ram::Inline(vec![ChunkedRam::new(); num_ram_banks]);
+        Self::with_storage(rom_banks, ram, save_ram)
+    }
+
+    /// Construct a new Mbc1Rom with the given rom banks, whose ram is stored in an external
+    /// device, such as a memory mapped save file.
+    ///
+    /// The external device is addressed as if all `num_ram_banks` 8 KiB banks were laid out
+    /// contiguously from address 0, so the selected bank determines the offset, and the device
+    /// must accept addresses in `0..num_ram_banks * 0x2000`.
+    pub fn with_ram(
+        rom_banks: Vec<RomBank>,
+        num_ram_banks: usize,
+        ram: Box<dyn Component>,
+        save_ram: bool,
+    ) -> Self {
+        let ram = Mbc1Ram::External {
+            device: ram,
+            num_banks: num_ram_banks,
+        };
+        Self::with_storage(rom_banks, ram, save_ram)
+    }
+
+    /// Construct a new Mbc1Rom with the given rom banks and ram storage.
+    fn with_storage(rom_banks: Vec<RomBank>, ram: Mbc1Ram, save_ram: bool) -> Self {
+        let num_ram_banks = ram.num_banks();
         assert!(rom_banks.len() >= 2, "Must have at least 2 rom banks.");
         assert!(
             rom_banks.len() <= 128,
@@ -383,68 +1151,355 @@ impl Mbc1Rom {
             warn!("MBC1 Rom is both Large Ram and Large Rom. Banking behavior may be wrong.");
         }
         Mbc1Rom {
-            rom_banks,
-            ram_banks: vec![[0u8; RAM_BANK_SIZE]; num_ram_banks],
+            rom_banks: rom_banks.into(),
+            ram,
+            ram_mask: RAM_BANK_SIZE as u16 - 1,
             save_ram,
             ram_enable: false,
-            rom_bank: 0,
+            rom_bank: 1,
             bank_set: 0,
             advanced_banking_mode: false,
         }
     }
 
+    /// Returns true if the cartridge ram is battery-backed, meaning it should be saved when the
+    /// device is powered off.
+    pub fn has_battery(&self) -> bool {
+        self.save_ram
+    }
+
+    /// Gets the contents of the ram to write to a save file, with every bank in order. Ram smaller
+    /// than a bank is saved at its real size. Returns `None` if the ram doesn't have a battery,
+    /// since its contents are lost when the device is powered off, or if there is no ram.
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        if !self.save_ram || self.ram.num_banks() == 0 {
+            return None;
+        }
+        let len = self.ram_mask as usize + 1;
+        Some(
+            self.ram_banks()
+                .iter()
+                .flat_map(|bank| bank[..len].iter().copied())
+                .collect(),
+        )
+    }
+
+    /// Resets the banking registers to their power-on state: ram disabled, rom bank 1 selected,
+    /// and simple banking mode. The contents of the ram are left untouched.
+    pub fn reset_registers(&mut self) {
+        self.ram_enable = false;
+        self.rom_bank = 1;
+        self.bank_set = 0;
+        self.advanced_banking_mode = false;
+    }
+
+    /// Gets the values of the banking registers, to save in a [`VolatileState`].
+    fn registers(&self) -> Vec<u8> {
+        vec![
+            self.ram_enable as u8,
+            self.rom_bank,
+            self.bank_set,
+            self.advanced_banking_mode as u8,
+        ]
+    }
+
+    /// Restores the banking registers from values returned by [`registers`](Self::registers).
+    fn restore_registers(&mut self, registers: &[u8]) {
+        self.ram_enable = registers[0] != 0;
+        self.rom_bank = (registers[1] & 0x1f).max(1);
+        self.bank_set = registers[2] & 0x3;
+        self.advanced_banking_mode = registers[3] != 0;
+    }
+
     /// Convenient access to the "fixed" lower rom bank. This bank only changes in Advanced rom
     /// mode.
     fn lower_bank(&self) -> &RomBank {
+        &self.rom_banks[self.lower_bank_index()]
+    }
+
+    /// Get the index of the "fixed" lower rom bank.
+    fn lower_bank_index(&self) -> usize {
         if self.advanced_banking_mode {
-            let bank = (self.bank_set as usize * 32) % self.rom_banks.len();
-            &self.rom_banks[bank]
+            (self.bank_set as usize * 32) % self.rom_banks.len()
         } else {
-            &self.rom_banks[0]
+            0
         }
     }
 
     /// Get the currently selected rom bank. This will never be bank 0, 32, 64, or 96.
     fn upper_bank(&self) -> &RomBank {
+        &self.rom_banks[self.upper_bank_index()]
+    }
+
+    /// Get the index of the currently selected rom bank.
+    fn upper_bank_index(&self) -> usize {
         let low_order = self.rom_bank;
         let high_order = self.bank_set << 5;
-        let rom = (low_order | high_order) as usize % self.rom_banks.len();
-        &self.rom_banks[rom]
+        (low_order | high_order) as usize % self.rom_banks.len()
+    }
+
+    /// Gets the number of the rom bank currently mapped at 0x4000..0x8000, including the high
+    /// bits from the bank set register.
+    pub fn current_rom_bank(&self) -> u16 {
+        self.upper_bank_index() as u16
+    }
+
+    /// Computes the offset into the rom file of the byte which is currently mapped at the given
+    /// address, taking into account the current bank selection. Returns `None` if the address is
+    /// not in the rom portion of the cartridge (0..0x8000).
+    pub fn physical_offset(&self, addr: Addr) -> Option<usize> {
+        match addr.relative() {
+            0..=0x3fff => Some(self.lower_bank_index() * ROM_BANK_SIZE + addr.index()),
+            0x4000..=0x7fff => {
+                Some(self.upper_bank_index() * ROM_BANK_SIZE + addr.offset_by(0x4000).index())
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets a copy of the contents of every ram bank, in bank order, for inspecting save ram. The
+    /// ram is stored in copy-on-write chunks or an external device rather than as plain arrays,
+    /// so the banks are copied out. Ram smaller than a bank is mirrored through it, just as the
+    /// CPU would see it.
+    pub fn ram_banks(&self) -> Vec<RamBank> {
+        (0..self.ram.num_banks())
+            .map(|bank| self.copy_ram_bank(bank))
+            .collect()
     }
 
-    /// Gets the currently selected ram bank, if the rom has ram and ram is enabled.
-    fn ram_bank(&self) -> Option<&RamBank> {
-        if self.ram_banks.is_empty() || !self.ram_enable {
+    /// Gets a copy of the contents of the ram bank with the given index, or `None` if there is
+    /// no such bank. Unlike the bank the CPU sees, this ignores the ram enable and bank select
+    /// registers.
+    pub fn ram_bank(&self, index: usize) -> Option<RamBank> {
+        if index < self.ram.num_banks() {
+            Some(self.copy_ram_bank(index))
+        } else {
             None
-        } else if self.advanced_banking_mode {
-            let bank = self.bank_set as usize % self.ram_banks.len();
-            Some(&self.ram_banks[bank])
+        }
+    }
+
+    /// Copies out the contents of the given ram bank, which must exist.
+    fn copy_ram_bank(&self, bank: usize) -> RamBank {
+        let mut data = [0; RAM_BANK_SIZE];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self
+                .ram
+                .read(bank, mirror_ram(Addr::from(i as u16), self.ram_mask));
+        }
+        data
+    }
+
+    /// Checks the rom for signs of a bad dump: a bank count which doesn't match the size declared
+    /// in the header, or banks after bank 0 which are entirely zero. This is never done while
+    /// loading, since a legitimately padded rom can have zero banks. Reports the size mismatch
+    /// first if there is one, since it makes the zero banks check meaningless.
+    pub fn verify_integrity(&self) -> Result<(), IntegrityWarning> {
+        let code = self.rom_banks[0].0[ROM_SIZE];
+        let declared = match code {
+            0..=8 => 2 << code,
+            _ => return Err(IntegrityWarning::UnknownRomSize(code)),
+        };
+        let actual = self.rom_banks.len();
+        if declared != actual {
+            return Err(IntegrityWarning::SizeMismatch { declared, actual });
+        }
+        let zero_banks: Vec<usize> = self
+            .rom_banks
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, bank)| bank.0.iter().all(|&b| b == 0))
+            .map(|(i, _)| i)
+            .collect();
+        if zero_banks.is_empty() {
+            Ok(())
         } else {
-            Some(&self.ram_banks[0])
+            Err(IntegrityWarning::ZeroBanks(zero_banks))
         }
     }
 
-    /// Gets the currently selected ram bank, if the rom has ram and ram is enabled.
-    fn ram_bank_mut(&mut self) -> Option<&mut RamBank> {
-        if self.ram_banks.is_empty() || !self.ram_enable {
+    /// Gets the index of the currently selected ram bank, if the rom has ram and ram is enabled.
+    fn ram_bank_index(&self) -> Option<usize> {
+        let num_banks = self.ram.num_banks();
+        if num_banks == 0 || !self.ram_enable {
             None
         } else if self.advanced_banking_mode {
-            let bank = self.bank_set as usize % self.ram_banks.len();
-            Some(&mut self.ram_banks[bank])
+            Some(self.bank_set as usize % num_banks)
         } else {
-            Some(&mut self.ram_banks[0])
+            Some(0)
+        }
+    }
+}
+
+/// Builder for an [`Mbc1Rom`], to construct one from its parts rather than parsing a rom file
+/// with [`Cartridge::parse`]. The cartridge header in the rom is not consulted.
+#[derive(Clone, Debug, Default)]
+pub struct Mbc1Builder {
+    /// Rom banks, with the last one padded if the rom wasn't a whole number of banks.
+    rom_banks: Vec<RomBank>,
+    /// Whether the rom ended partway through a bank.
+    truncated: bool,
+    /// Ram size code, as used in the cartridge header.
+    ram_size: u8,
+    /// Whether the ram has a battery.
+    battery: bool,
+}
+
+impl Mbc1Builder {
+    /// Constructs a builder with no rom, no ram, and no battery.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the contents of the rom, which must be a power of 2 number of 16 KiB banks, from 2
+    /// to 128.
+    pub fn rom(mut self, bytes: &[u8]) -> Self {
+        self.rom_banks = bytes
+            .chunks(ROM_BANK_SIZE)
+            .map(|chunk| {
+                let mut bank = [0u8; ROM_BANK_SIZE];
+                bank[..chunk.len()].copy_from_slice(chunk);
+                ReadOnly(bank)
+            })
+            .collect();
+        self.truncated = !bytes.len().is_multiple_of(ROM_BANK_SIZE);
+        self
+    }
+
+    /// Sets the rom banks directly, for the parser, which has already split the rom into banks.
+    fn rom_banks(mut self, rom_banks: Vec<RomBank>) -> Self {
+        self.rom_banks = rom_banks;
+        self.truncated = false;
+        self
+    }
+
+    /// Sets the size of the ram, using the same codes as the cartridge header: 0 for no ram, 1
+    /// for 2 KiB, 2 for one 8 KiB bank, and 3 for four banks. Larger sizes are not supported by
+    /// the MBC1. Defaults to 0.
+    pub fn ram_size(mut self, code: u8) -> Self {
+        self.ram_size = code;
+        self
+    }
+
+    /// Sets whether the ram has a battery, so that [`Mbc1Rom::save_ram`] returns its contents.
+    /// Only allowed if there is ram. Defaults to false.
+    pub fn battery(mut self, battery: bool) -> Self {
+        self.battery = battery;
+        self
+    }
+
+    /// Builds the `Mbc1Rom`. Errors are reported as they would be when parsing a rom whose header
+    /// had the cartridge type matching the ram and battery: 0x01 without ram, 0x02 with ram, and
+    /// 0x03 with ram and battery.
+    pub fn build(self) -> Result<Mbc1Rom, ParseCartridgeError> {
+        let rom_type = match (self.ram_size, self.battery) {
+            (_, true) => 3,
+            (0, false) => 1,
+            (_, false) => 2,
+        };
+        if self.truncated {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof).into());
+        }
+        let rom_size = self.rom_banks.len();
+        if !(2..=128).contains(&rom_size) || !rom_size.is_power_of_two() {
+            return Err(ParseCartridgeError::UnsupportedRomSize { rom_type, rom_size });
+        }
+        let (ram_size, ram_mask) = match self.ram_size {
+            0 => (0, RAM_BANK_SIZE as u16 - 1),
+            1 => (1, 0x7ff),
+            2 => (1, RAM_BANK_SIZE as u16 - 1),
+            3 => (4, RAM_BANK_SIZE as u16 - 1),
+            4 => {
+                return Err(ParseCartridgeError::UnsupportedRamSize {
+                    rom_type,
+                    ram_size: 16,
+                })
+            }
+            5 => {
+                return Err(ParseCartridgeError::UnsupportedRamSize {
+                    rom_type,
+                    ram_size: 8,
+                })
+            }
+            code => return Err(ParseCartridgeError::UnrecognizedRamSizeCode(code)),
+        };
+        // A battery without ram has nothing to keep.
+        if ram_size == 0 && self.battery {
+            return Err(ParseCartridgeError::UnsupportedRamSize { rom_type, ram_size });
+        }
+        let mut cart = Mbc1Rom::new(self.rom_banks, ram_size, self.battery);
+        cart.ram_mask = ram_mask;
+        Ok(cart)
+    }
+}
+
+/// Storage for the ram of an [`Mbc1Rom`].
+#[derive(Clone, Debug)]
+enum Mbc1Ram {
+    /// Ram banks stored directly in the cartridge. If there is no ram, this will be an empty
+    /// vector.
+    Inline(Vec<ChunkedRam<RAM_BANK_SIZE>>),
+    /// Ram stored in an external device, with all banks laid out contiguously starting from 0.
+    External {
+        device: Box<dyn Component>,
+        num_banks: usize,
+    },
+}
+
+/// Applies a ram mask to an address relative to the start of a ram bank, so that ram smaller than
+/// a bank is mirrored through it.
+fn mirror_ram(addr: Addr, mask: u16) -> Addr {
+    addr.offset_by(addr.relative() & !mask)
+}
+
+impl Mbc1Ram {
+    /// Number of ram banks available.
+    fn num_banks(&self) -> usize {
+        match self {
+            Mbc1Ram::Inline(banks) => banks.len(),
+            Mbc1Ram::External { num_banks, .. } => *num_banks,
+        }
+    }
+
+    /// Address within an external device of the given address in the given bank.
+    fn external_addr(bank: usize, addr: Addr) -> Addr {
+        Addr::from((bank * RAM_BANK_SIZE + addr.index()) as u16)
+    }
+
+    /// Read from the given address within the given bank.
+    fn read(&self, bank: usize, addr: Addr) -> u8 {
+        match self {
+            Mbc1Ram::Inline(banks) => banks[bank].read(addr),
+            Mbc1Ram::External { device, .. } => device.read(Self::external_addr(bank, addr)),
+        }
+    }
+
+    /// Write to the given address within the given bank.
+    fn write(&mut self, bank: usize, addr: Addr, value: u8) {
+        match self {
+            Mbc1Ram::Inline(banks) => banks[bank].write(addr, value),
+            Mbc1Ram::External { device, .. } => {
+                device.write(Self::external_addr(bank, addr), value)
+            }
         }
     }
 }
 
 impl MemDevice for Mbc1Rom {
+    fn size(&self) -> usize {
+        0xa000
+    }
+
     fn read(&self, addr: Addr) -> u8 {
         match addr.relative() {
             0..=0x3fff => self.lower_bank().read(addr),
             0x4000..=0x7fff => self.upper_bank().read(addr.offset_by(0x4000)),
-            0x8000..=0x9fff => match self.ram_bank() {
-                Some(bank) => bank.read(addr.offset_by(0x8000)),
-                None => 0,
+            0x8000..=0x9fff => match self.ram_bank_index() {
+                Some(bank) => self
+                    .ram
+                    .read(bank, mirror_ram(addr.offset_by(0x8000), self.ram_mask)),
+                // Absent or disabled ram doesn't drive the bus.
+                None => OPEN_BUS,
             },
             _ => panic!("Address {} out of range for Mbc1Rom", addr),
         }
@@ -461,11 +1516,501 @@ impl MemDevice for Mbc1Rom {
             0x4000..=0x5fff => self.bank_set = value & 0x3,
             // Change between basic and advanced banking mode.
             0x6000..=0x7fff => self.advanced_banking_mode = (value & 1) != 0,
-            0x8000..=0x9fff => match self.ram_bank_mut() {
-                Some(bank) => bank.write(addr.offset_by(0x8000), value),
-                None => {}
-            },
+            0x8000..=0x9fff => {
+                if let Some(bank) = self.ram_bank_index() {
+                    let addr = mirror_ram(addr.offset_by(0x8000), self.ram_mask);
+                    self.ram.write(bank, addr, value);
+                }
+            }
             _ => panic!("Address {} out of range for Mbc1Rom", addr),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::testutils::assert_mem_eq;
+
+    /// Builds an Mbc1Rom with the given number of rom banks, where the first byte of each bank is
+    /// the bank index.
+    fn numbered_mbc1(rom_banks: usize, ram_banks: usize) -> Mbc1Rom {
+        let banks = (0..rom_banks)
+            .map(|i| {
+                let mut bank = [0u8; ROM_BANK_SIZE];
+                bank[0] = i as u8;
+                ReadOnly(bank)
+            })
+            .collect();
+        Mbc1Rom::new(banks, ram_banks, false)
+    }
+
+    #[test]
+    fn ram_banks_fill_the_ram_window() {
+        assert_eq!(std::mem::size_of::<RamBank>(), 0xc000 - 0xa000);
+    }
+
+    #[test]
+    fn mbc1_physical_offset() {
+        let mut cart = numbered_mbc1(64, 0);
+        assert_eq!(cart.physical_offset(0x0123.into()), Some(0x0123));
+        assert_eq!(cart.physical_offset(0x8000.into()), None);
+
+        // Select bank 5.
+        cart.write(0x2000.into(), 5);
+        assert_eq!(
+            cart.physical_offset(0x4123.into()),
+            Some(5 * 0x4000 + 0x123)
+        );
+        assert_eq!(cart.physical_offset(0x7fff.into()), Some(6 * 0x4000 - 1));
+
+        // Select bank 0x25 using the bank set bits, then check that advanced mode moves the lower
+        // bank too.
+        cart.write(0x4000.into(), 1);
+        assert_eq!(cart.physical_offset(0x4000.into()), Some(0x25 * 0x4000));
+        assert_eq!(cart.physical_offset(0x0000.into()), Some(0));
+        cart.write(0x6000.into(), 1);
+        assert_eq!(
+            cart.physical_offset(0x0010.into()),
+            Some(0x20 * 0x4000 + 0x10)
+        );
+
+        // Offsets agree with the data actually mapped.
+        let offset = cart.physical_offset(0x4000.into()).unwrap();
+        assert_eq!(cart.read(0x4000.into()) as usize, offset / ROM_BANK_SIZE);
+    }
+
+    #[test]
+    fn mbc1_verify_integrity() {
+        let mut rom = vec![0u8; 8 * ROM_BANK_SIZE];
+        for (i, bank) in rom.chunks_mut(ROM_BANK_SIZE).enumerate().skip(1) {
+            bank[0] = i as u8;
+        }
+        rom[CART_TYPE] = 0x01;
+        rom[ROM_SIZE] = 0x02;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let parse = |rom: &[u8]| match Cartridge::parse(rom).unwrap() {
+            Cartridge::Mbc1(cart) => cart,
+            _ => panic!("expected MBC1"),
+        };
+        assert_eq!(parse(&rom).verify_integrity(), Ok(()));
+
+        // A dump padded out with zeros.
+        for bank in rom.chunks_mut(ROM_BANK_SIZE).skip(5) {
+            bank[0] = 0;
+        }
+        assert_eq!(
+            parse(&rom).verify_integrity(),
+            Err(IntegrityWarning::ZeroBanks(vec![5, 6, 7]))
+        );
+
+        // Banks which don't match the header. Banks after 0 are numbered, so only the size is
+        // wrong.
+        let cart = numbered_mbc1(4, 0);
+        assert_eq!(
+            cart.verify_integrity(),
+            Err(IntegrityWarning::SizeMismatch {
+                declared: 2,
+                actual: 4
+            })
+        );
+    }
+
+    #[test]
+    fn mbc1_ram_bank_contents() {
+        let mut cart = numbered_mbc1(4, 4);
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x6000.into(), 0x01);
+        for bank in 0..4 {
+            cart.write(0x4000.into(), bank);
+            cart.write(0x8000.into(), 0x10 + bank);
+            cart.write(0x9fff.into(), 0x20 + bank);
+        }
+        // Banks are visible regardless of the ram enable and bank select registers.
+        cart.write(0x0000.into(), 0x00);
+        let banks = cart.ram_banks();
+        assert_eq!(banks.len(), 4);
+        for (i, bank) in banks.iter().enumerate() {
+            assert_eq!(bank[0], 0x10 + i as u8);
+            assert_eq!(bank[RAM_BANK_SIZE - 1], 0x20 + i as u8);
+            assert_eq!(cart.ram_bank(i).as_ref(), Some(bank));
+        }
+        assert_eq!(cart.ram_bank(4), None);
+        assert!(numbered_mbc1(2, 0).ram_banks().is_empty());
+    }
+
+    #[test]
+    fn mbc1_bank_set_selects_large_rom_banks() {
+        // 1 MiB, so the bank set bits are needed to reach the upper half of the rom.
+        let mut cart = numbered_mbc1(64, 0);
+        cart.write(0x2000.into(), 0x05);
+        cart.write(0x4000.into(), 0x01);
+        assert_eq!(cart.read(0x4000.into()), 0x25);
+        // Only the low 5 bits of the rom bank register are used.
+        cart.write(0x2000.into(), 0xe5);
+        assert_eq!(cart.read(0x4000.into()), 0x25);
+        // Selecting bank 0 in the low bits still maps bank 1 of the set.
+        cart.write(0x2000.into(), 0x00);
+        assert_eq!(cart.read(0x4000.into()), 0x21);
+        // In simple banking mode, the bank set doesn't affect the lower bank.
+        assert_eq!(cart.read(0x0000.into()), 0x00);
+        assert_eq!(cart.current_rom_bank(), 0x21);
+
+        // The same cartridge, parsed from a rom image.
+        let mut rom = vec![0u8; 64 * ROM_BANK_SIZE];
+        for (i, bank) in rom.chunks_mut(ROM_BANK_SIZE).enumerate().skip(1) {
+            bank[0] = i as u8;
+        }
+        rom[CART_TYPE] = 0x01;
+        rom[ROM_SIZE] = 0x05;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let mut cart = Cartridge::parse(&rom[..]).unwrap();
+        cart.write(0x2000.into(), 0x05);
+        cart.write(0x4000.into(), 0x01);
+        assert_eq!(cart.read(0x4000.into()), 0x25);
+        assert_eq!(cart.current_rom_bank(), 0x25);
+        cart.reset_registers();
+        assert_eq!(cart.current_rom_bank(), 1);
+
+        // Battery-backed MBC1 with 4 ram banks.
+        rom[CART_TYPE] = 0x03;
+        rom[RAM_SIZE] = 0x03;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let cart = Cartridge::parse(&rom[..]).unwrap();
+        assert_eq!(cart.ram_size(), 4 * RAM_BANK_SIZE);
+
+        // A truncated rom is rejected rather than loaded with the rest of the last bank zeroed.
+        assert!(matches!(
+            Cartridge::parse(&rom[..rom.len() - 1]),
+            Err(ParseCartridgeError::InsufficientData(_))
+        ));
+    }
+
+    /// Ram device whose storage is shared between clones, so tests can inspect it after handing
+    /// it to a cartridge.
+    #[derive(Clone, Debug)]
+    struct SharedRam(Rc<RefCell<Vec<u8>>>);
+
+    impl MemDevice for SharedRam {
+        fn read(&self, addr: Addr) -> u8 {
+            self.0.borrow()[addr.index()]
+        }
+
+        fn write(&mut self, addr: Addr, value: u8) {
+            self.0.borrow_mut()[addr.index()] = value;
+        }
+    }
+
+    impl Component for SharedRam {
+        fn box_clone(&self) -> Box<dyn Component> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn mbc1_external_ram() {
+        let storage = Rc::new(RefCell::new(vec![0u8; 4 * RAM_BANK_SIZE]));
+        let banks = vec![ReadOnly([0u8; ROM_BANK_SIZE]); 4];
+        let mut cart = Mbc1Rom::with_ram(banks, 4, Box::new(SharedRam(storage.clone())), true);
+
+        // Ram is disabled until enabled through the register.
+        cart.write(0x8010.into(), 1);
+        assert_eq!(storage.borrow()[0x10], 0);
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x8010.into(), 1);
+        assert_eq!(storage.borrow()[0x10], 1);
+
+        // Bank selection offsets into the external device.
+        cart.write(0x6000.into(), 1);
+        cart.write(0x4000.into(), 2);
+        cart.write(0x9fff.into(), 2);
+        assert_eq!(storage.borrow()[3 * RAM_BANK_SIZE - 1], 2);
+        storage.borrow_mut()[2 * RAM_BANK_SIZE + 0x10] = 3;
+        assert_eq!(cart.read(0x8010.into()), 3);
+    }
+
+    #[test]
+    fn mbc1_disabled_ram_reads_open_bus() {
+        let mut cart = numbered_mbc1(2, 1);
+        assert_eq!(cart.read(0x8000.into()), 0xff);
+        cart.write(0x0000.into(), 0x0a);
+        assert_eq!(cart.read(0x8000.into()), 0x00);
+        cart.write(0x0000.into(), 0x00);
+        assert_eq!(cart.read(0x8000.into()), 0xff);
+
+        // Without ram, the ram area reads open bus even when enabled.
+        let mut cart = numbered_mbc1(2, 0);
+        cart.write(0x0000.into(), 0x0a);
+        assert_eq!(cart.read(0x9fff.into()), 0xff);
+    }
+
+    #[test]
+    fn mbc1_builder() {
+        let mut rom = vec![0u8; 4 * ROM_BANK_SIZE];
+        rom[2 * ROM_BANK_SIZE] = 0x42;
+        let mut cart = Mbc1Builder::new()
+            .rom(&rom)
+            .ram_size(1)
+            .battery(true)
+            .build()
+            .unwrap();
+        assert!(cart.has_battery());
+        cart.write(0x2000.into(), 2);
+        assert_eq!(cart.read(0x4000.into()), 0x42);
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x8001.into(), 0x17);
+        // 2 KiB of ram is saved at its real size.
+        let save = cart.save_ram().unwrap();
+        assert_eq!(save.len(), 0x800);
+        assert_eq!(save[1], 0x17);
+
+        // Without a battery there is nothing to save.
+        let cart = Mbc1Builder::new().rom(&rom).ram_size(3).build().unwrap();
+        assert!(!cart.has_battery());
+        assert_eq!(cart.save_ram(), None);
+        assert_eq!(cart.ram_banks().len(), 4);
+
+        assert!(matches!(
+            Mbc1Builder::new().rom(&rom).battery(true).build(),
+            Err(ParseCartridgeError::UnsupportedRamSize {
+                rom_type: 3,
+                ram_size: 0
+            })
+        ));
+        assert!(matches!(
+            Mbc1Builder::new().rom(&rom).ram_size(4).build(),
+            Err(ParseCartridgeError::UnsupportedRamSize {
+                rom_type: 2,
+                ram_size: 16
+            })
+        ));
+        assert!(matches!(
+            Mbc1Builder::new().rom(&rom).ram_size(7).build(),
+            Err(ParseCartridgeError::UnrecognizedRamSizeCode(7))
+        ));
+        assert!(matches!(
+            Mbc1Builder::new().rom(&rom[..3 * ROM_BANK_SIZE]).build(),
+            Err(ParseCartridgeError::UnsupportedRomSize {
+                rom_type: 1,
+                rom_size: 3
+            })
+        ));
+        assert!(matches!(
+            Mbc1Builder::new().rom(&rom[..ROM_BANK_SIZE + 1]).build(),
+            Err(ParseCartridgeError::InsufficientData(_))
+        ));
+    }
+
+    #[test]
+    fn mbc1_ram_survives_mode_switches() {
+        let mut cart = numbered_mbc1(4, 4);
+        // Give each bank a distinct first byte to tell which one is mapped.
+        for bank in 0..4 {
+            cart.ram.write(bank, Addr::from(0), 0x10 + bank as u8);
+        }
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x6000.into(), 1);
+        cart.write(0x4000.into(), 2);
+        cart.write(0x8000.into(), 0x42);
+        assert_eq!(cart.read(0x8000.into()), 0x42);
+
+        // In simple banking mode, only bank 0 is mapped, even though bank_set is still 2.
+        cart.write(0x6000.into(), 0);
+        assert_eq!(cart.read(0x8000.into()), 0x10);
+
+        // Back in advanced mode, bank 2 is mapped again with the written data.
+        cart.write(0x6000.into(), 1);
+        assert_eq!(cart.read(0x8000.into()), 0x42);
+        assert_eq!(cart.ram_bank(2).unwrap()[0], 0x42);
+        assert_eq!(cart.ram_bank(0).unwrap()[0], 0x10);
+    }
+
+    #[test]
+    fn mbc1_reset_registers() {
+        let mut cart = numbered_mbc1(64, 4);
+        assert_eq!(cart.read(0x4000.into()), 1);
+
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x6000.into(), 1);
+        cart.write(0x4000.into(), 1);
+        cart.write(0x2000.into(), 3);
+        cart.write(0x8000.into(), 0x55);
+        assert_eq!(cart.read(0x4000.into()), 0x23);
+
+        cart.reset_registers();
+        assert_eq!(cart.read(0x0000.into()), 0);
+        assert_eq!(cart.read(0x4000.into()), 1);
+        // Ram is disabled again, but its contents survive.
+        assert_eq!(cart.read(0x8000.into()), 0xff);
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x6000.into(), 1);
+        cart.write(0x4000.into(), 1);
+        assert_eq!(cart.read(0x8000.into()), 0x55);
+    }
+
+    #[test]
+    fn volatile_state_round_trip() {
+        let mut cart = Cartridge::Mbc1(numbered_mbc1(16, 4));
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x6000.into(), 1);
+        cart.write(0x4000.into(), 2);
+        cart.write(0x8000.into(), 0x11);
+        cart.write(0x2000.into(), 5);
+        let state = cart.save_volatile();
+        assert_eq!(state.ram().len(), 4 * RAM_BANK_SIZE);
+        assert_eq!(state.ram()[2 * RAM_BANK_SIZE], 0x11);
+
+        // Another game is played on the same rom, then the state is swapped back in.
+        let mut other = cart.clone();
+        other.reset_registers();
+        other.clear_ram();
+        assert_eq!(other.current_rom_bank(), 1);
+        let loaded = VolatileState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(loaded, state);
+        other.restore_volatile(&loaded).unwrap();
+        assert_eq!(other.current_rom_bank(), 5);
+        assert_eq!(other.read(0x8000.into()), 0x11);
+        assert_eq!(other.save_volatile(), state);
+
+        // A state from a different cartridge is rejected without changing anything.
+        let mut small = Cartridge::Mbc1(numbered_mbc1(16, 1));
+        assert_eq!(
+            small.restore_volatile(&state),
+            Err(VolatileStateError::RamSizeMismatch {
+                saved: 4 * RAM_BANK_SIZE,
+                actual: RAM_BANK_SIZE
+            })
+        );
+        assert_eq!(small.current_rom_bank(), 1);
+        assert_eq!(
+            Cartridge::None.restore_volatile(&state),
+            Err(VolatileStateError::MapperMismatch {
+                saved: "MBC1",
+                actual: "none"
+            })
+        );
+
+        let mut bytes = state.to_bytes();
+        assert_eq!(
+            VolatileState::from_bytes(&bytes[..8]),
+            Err(VolatileStateError::Truncated)
+        );
+        bytes[4] = 0x7f;
+        assert_eq!(
+            VolatileState::from_bytes(&bytes),
+            Err(VolatileStateError::UnknownMapper(0x7f))
+        );
+        bytes[0] = 2;
+        assert_eq!(
+            VolatileState::from_bytes(&bytes),
+            Err(VolatileStateError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn header_code_names() {
+        assert_eq!(cartridge_type_name(0x00), "ROM ONLY");
+        assert_eq!(cartridge_type_name(0x13), "MBC3+RAM+BATTERY");
+        assert_eq!(cartridge_type_name(0xff), "HuC1+RAM+BATTERY");
+        assert_eq!(cartridge_type_name(0x04), "Unknown (0x04)");
+        assert_eq!(rom_size_name(0x05), "1 MiB (64 banks)");
+        assert_eq!(rom_size_name(0x09), "Unknown (0x09)");
+        assert_eq!(ram_size_name(0x03), "32 KiB (4 banks)");
+        assert_eq!(ram_size_name(0xab), "Unknown (0xAB)");
+    }
+
+    /// Builds a RomOnly cartridge image, optionally with ram.
+    fn rom_only_image(title: &str, global_checksum: u16, ram: bool) -> Vec<u8> {
+        let mut rom = vec![0u8; 2 * ROM_BANK_SIZE];
+        rom[TITLE][..title.len()].copy_from_slice(title.as_bytes());
+        if ram {
+            rom[CART_TYPE] = 0x09;
+            rom[RAM_SIZE] = 0x02;
+        }
+        rom[GLOBAL_CHECKSUM..GLOBAL_CHECKSUM + 2].copy_from_slice(&global_checksum.to_be_bytes());
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        rom
+    }
+
+    #[test]
+    fn save_signature() {
+        let parse = |rom: Vec<u8>| Cartridge::parse(&rom[..]).unwrap();
+        let pokemon = parse(rom_only_image("POKEMON RED", 0x91e6, true));
+        let signature = pokemon.save_signature();
+        assert_eq!(
+            signature,
+            SaveSignature {
+                title: "POKEMON RED".to_string(),
+                global_checksum: 0x91e6,
+                ram_size: 0x2000,
+            }
+        );
+        assert!(pokemon.is_save_compatible(&signature));
+
+        let zelda = parse(rom_only_image("ZELDA", 0x1234, true));
+        assert!(!zelda.is_save_compatible(&signature));
+        let other_revision = parse(rom_only_image("POKEMON RED", 0x9999, true));
+        assert!(!other_revision.is_save_compatible(&signature));
+
+        // Without ram, nothing is compatible, even a signature from the same cartridge.
+        let no_ram = parse(rom_only_image("TETRIS", 0x1234, false));
+        assert!(!no_ram.is_save_compatible(&no_ram.save_signature()));
+        assert!(!Cartridge::None.is_save_compatible(&Cartridge::None.save_signature()));
+    }
+
+    #[test]
+    fn two_kib_ram_mirrors() {
+        let mut rom = rom_only_image("SMALL RAM", 0, true);
+        rom[RAM_SIZE] = 0x01;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let mut cart = Cartridge::parse(&rom[..]).unwrap();
+        assert_eq!(cart.ram_size(), 0x800);
+        // Cartridge ram starts at 0x8000 relative to the cartridge (0xA000 in the system).
+        cart.write(0x8000.into(), 0x42);
+        cart.write(0x9fff.into(), 0x24);
+        for &mirror in [0x8000, 0x8800, 0x9000, 0x9800].iter() {
+            assert_mem_eq(&cart, mirror, &[0x42, 0x00]);
+            assert_mem_eq(&cart, mirror + 0x7fe, &[0x00, 0x24]);
+        }
+        cart.clear_ram();
+        assert_eq!(cart.read(0x8800.into()), 0);
+
+        // MBC1 with 2 KiB of ram mirrors the same way.
+        let mut rom = vec![0u8; 4 * ROM_BANK_SIZE];
+        rom[CART_TYPE] = 0x02;
+        rom[ROM_SIZE] = 0x01;
+        rom[RAM_SIZE] = 0x01;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let mut cart = Cartridge::parse(&rom[..]).unwrap();
+        assert_eq!(cart.ram_size(), 0x800);
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x8000.into(), 0x42);
+        assert_eq!(cart.read(0x8800.into()), 0x42);
+        cart.write_ram_bank(0, 0x1001.into(), 0x33);
+        assert_eq!(cart.read(0x8001.into()), 0x33);
+    }
+
+    #[test]
+    fn cartridge_physical_offset() {
+        assert_eq!(Cartridge::None.physical_offset(0x0100.into()), None);
+        let rom_only = Cartridge::RomOnly(RomOnly::empty());
+        assert_eq!(rom_only.physical_offset(0x4100.into()), Some(0x4100));
+        assert_eq!(rom_only.physical_offset(0x9000.into()), None);
+    }
+
+    #[test]
+    fn current_rom_bank() {
+        assert_eq!(Cartridge::None.current_rom_bank(), 0);
+        assert_eq!(Cartridge::RomOnly(RomOnly::empty()).current_rom_bank(), 1);
+        // Bank numbers wrap around the size of the rom.
+        let mut cart = numbered_mbc1(8, 0);
+        assert_eq!(cart.current_rom_bank(), 1);
+        cart.write(0x2000.into(), 0x0a);
+        assert_eq!(cart.current_rom_bank(), 2);
+        cart.write(0x2000.into(), 0x07);
+        assert_eq!(cart.current_rom_bank(), 7);
+    }
+}
@@ -0,0 +1,325 @@
+//! The Pocket Camera (Game Boy Camera) mapper.
+
+use std::sync::Arc;
+
+use super::{RamBank, RomBank, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::memdev::{Addr, ChunkedRam, MemDevice, Permissions};
+
+/// Number of 8 KiB ram banks on the Pocket Camera (128 KiB).
+pub(super) const NUM_RAM_BANKS: usize = 16;
+
+/// Bit of the ram bank register which maps the camera registers instead of ram.
+const CAMERA_SELECT: u8 = 0x10;
+
+/// Number of camera registers. They are mirrored every 0x80 bytes through the ram window.
+const NUM_CAMERA_REGISTERS: usize = 0x36;
+
+/// Bit of the capture trigger register which starts a capture, and reads 1 while the capture is
+/// in progress.
+const CAPTURE_BUSY: u8 = 0x01;
+
+/// Bits of the capture trigger register which are stored.
+const TRIGGER_MASK: u8 = 0x07;
+
+/// Location of the captured image in ram bank 0: 16x14 tiles of 2 bits per pixel.
+const IMAGE: std::ops::Range<usize> = 0x100..0xf00;
+
+/// Cartridge with the Pocket Camera mapper, which has up to 64 rom banks, 16 ram banks, and a set
+/// of registers which control the image sensor.
+///
+/// Setting bit 4 of the ram bank register maps the camera registers over the ram window at
+/// 0xA000..0xC000. Only the capture trigger register at 0xA000 can be read, and the rest read 0.
+/// Ram can always be read, but writing requires enabling it, the same as other mappers.
+///
+/// There is no real sensor. Starting a capture writes a fixed checkerboard pattern to the image
+/// area of ram bank 0 and finishes immediately, so the busy bit of the trigger register is
+/// already clear when the game next polls it. That is enough for the camera software to boot
+/// and manage its photo album.
+#[derive(Clone, Debug)]
+pub struct PocketCamera {
+    /// Rom banks. Shared between clones, since rom never changes.
+    rom_banks: Arc<[RomBank]>,
+    /// Ram banks.
+    ram: Vec<ChunkedRam<RAM_BANK_SIZE>>,
+
+    // Registers:
+    /// Whether ram can be written.
+    ram_write_enable: bool,
+    /// Rom bank mapped at 0x4000..0x8000. Unlike MBC1, bank 0 can be selected.
+    rom_bank: u8,
+    /// Ram bank select in the low 4 bits, and whether the camera registers are mapped.
+    ram_select: u8,
+    /// Camera registers, mapped at 0xA000..0xA036 when selected.
+    camera_registers: [u8; NUM_CAMERA_REGISTERS],
+}
+
+impl PocketCamera {
+    /// Constructs a new `PocketCamera` with the given rom banks and empty ram.
+    pub(super) fn new(rom_banks: Vec<RomBank>) -> Self {
+        assert!(
+            (2..=64).contains(&rom_banks.len()),
+            "Pocket Camera must have between 2 and 64 rom banks."
+        );
+        PocketCamera {
+            rom_banks: rom_banks.into(),
+            ram: vec![ChunkedRam::new(); NUM_RAM_BANKS],
+            ram_write_enable: false,
+            rom_bank: 1,
+            ram_select: 0,
+            camera_registers: [0; NUM_CAMERA_REGISTERS],
+        }
+    }
+
+    /// Gets rom bank 0, which contains the header.
+    pub(super) fn bank0(&self) -> &RomBank {
+        &self.rom_banks[0]
+    }
+
+    /// Resets the mapper and camera registers to their power-on state, leaving ram untouched.
+    pub fn reset_registers(&mut self) {
+        self.ram_write_enable = false;
+        self.rom_bank = 1;
+        self.ram_select = 0;
+        self.camera_registers = [0; NUM_CAMERA_REGISTERS];
+    }
+
+    /// Gets the values of the mapper registers followed by the camera registers, to save in a
+    /// [`VolatileState`](super::VolatileState).
+    pub(super) fn registers(&self) -> Vec<u8> {
+        let mut registers = vec![self.ram_write_enable as u8, self.rom_bank, self.ram_select];
+        registers.extend_from_slice(&self.camera_registers);
+        registers
+    }
+
+    /// Restores the registers from values returned by [`registers`](Self::registers).
+    pub(super) fn restore_registers(&mut self, registers: &[u8]) {
+        self.ram_write_enable = registers[0] != 0;
+        self.rom_bank = registers[1] & 0x3f;
+        self.ram_select = registers[2] & 0x1f;
+        self.camera_registers.copy_from_slice(&registers[3..]);
+    }
+
+    /// Gets the number of the rom bank currently mapped at 0x4000..0x8000.
+    pub fn current_rom_bank(&self) -> u16 {
+        self.upper_bank_index() as u16
+    }
+
+    /// Computes the offset into the rom file of the byte which is currently mapped at the given
+    /// address. Returns `None` if the address is not in the rom portion of the cartridge
+    /// (0..0x8000).
+    pub fn physical_offset(&self, addr: Addr) -> Option<usize> {
+        match addr.relative() {
+            0..=0x3fff => Some(addr.index()),
+            0x4000..=0x7fff => {
+                Some(self.upper_bank_index() * ROM_BANK_SIZE + addr.offset_by(0x4000).index())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if the camera registers are mapped instead of ram.
+    pub fn camera_selected(&self) -> bool {
+        self.ram_select & CAMERA_SELECT != 0
+    }
+
+    /// Gets whether the CPU can read and write the cartridge ram area. The camera registers can
+    /// always be accessed, and ram can always be read but only written once enabled.
+    pub(super) fn ram_permissions(&self) -> Permissions {
+        if self.camera_selected() || self.ram_write_enable {
+            Permissions::READ | Permissions::WRITE
+        } else {
+            Permissions::READ
+        }
+    }
+
+    /// Gets a copy of the contents of the ram bank with the given index, or `None` if there is
+    /// no such bank.
+    pub fn ram_bank(&self, index: usize) -> Option<RamBank> {
+        let ram = self.ram.get(index)?;
+        let mut data = [0; RAM_BANK_SIZE];
+        for (byte, value) in data.iter_mut().zip(ram.iter()) {
+            *byte = value;
+        }
+        Some(data)
+    }
+
+    /// Writes directly to the given ram bank, regardless of the mapper registers. Writes to banks
+    /// which don't exist are ignored.
+    pub(super) fn write_ram_bank(&mut self, bank: usize, addr: Addr, value: u8) {
+        if let Some(ram) = self.ram.get_mut(bank) {
+            ram.write(addr, value);
+        }
+    }
+
+    /// Get the index of the currently selected rom bank.
+    fn upper_bank_index(&self) -> usize {
+        self.rom_bank as usize % self.rom_banks.len()
+    }
+
+    /// Get the index of the currently selected ram bank.
+    fn ram_bank_index(&self) -> usize {
+        (self.ram_select & 0xf) as usize
+    }
+
+    /// Runs a capture with the stub sensor, filling the image area of ram bank 0 with a
+    /// checkerboard of black and white tiles.
+    fn capture(&mut self) {
+        for offset in IMAGE {
+            let tile = (offset - IMAGE.start) / 16;
+            let (x, y) = (tile % 16, tile / 16);
+            let value = if (x + y) % 2 == 0 { 0xff } else { 0x00 };
+            self.ram[0].write(Addr::from(offset as u16), value);
+        }
+    }
+
+    /// Writes one of the camera registers, given its address relative to the ram window.
+    fn write_camera_register(&mut self, addr: Addr, value: u8) {
+        match addr.index() & 0x7f {
+            0 => {
+                self.camera_registers[0] = value & TRIGGER_MASK;
+                if value & CAPTURE_BUSY != 0 {
+                    self.capture();
+                    // The stub sensor finishes instantly.
+                    self.camera_registers[0] &= !CAPTURE_BUSY;
+                }
+            }
+            reg if reg < NUM_CAMERA_REGISTERS => self.camera_registers[reg] = value,
+            _ => {}
+        }
+    }
+}
+
+impl MemDevice for PocketCamera {
+    fn size(&self) -> usize {
+        0xa000
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match addr.relative() {
+            0..=0x3fff => self.rom_banks[0].read(addr),
+            0x4000..=0x7fff => self.rom_banks[self.upper_bank_index()].read(addr.offset_by(0x4000)),
+            0x8000..=0x9fff if self.camera_selected() => {
+                // Only the capture trigger register can be read.
+                if addr.index() & 0x7f == 0 {
+                    self.camera_registers[0]
+                } else {
+                    0x00
+                }
+            }
+            0x8000..=0x9fff => self.ram[self.ram_bank_index()].read(addr.offset_by(0x8000)),
+            _ => panic!("Address {} out of range for PocketCamera", addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match addr.relative() {
+            0x0000..=0x1fff => self.ram_write_enable = (value & 0xf) == 0xa,
+            0x2000..=0x3fff => self.rom_bank = value & 0x3f,
+            0x4000..=0x5fff => self.ram_select = value & 0x1f,
+            0x6000..=0x7fff => {}
+            0x8000..=0x9fff if self.camera_selected() => {
+                self.write_camera_register(addr.offset_by(0x8000), value)
+            }
+            0x8000..=0x9fff => {
+                if self.ram_write_enable {
+                    let bank = self.ram_bank_index();
+                    self.ram[bank].write(addr.offset_by(0x8000), value);
+                }
+            }
+            _ => panic!("Address {} out of range for PocketCamera", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memdev::cartridge::{
+        compute_header_checksum, Cartridge, CART_TYPE, HEADER_CHECKSUM, RAM_SIZE, ROM_SIZE,
+    };
+    use crate::memdev::ReadOnly;
+
+    /// Builds a Pocket Camera with 16 rom banks, where the first byte of each bank is its index.
+    fn camera() -> PocketCamera {
+        let banks = (0..16)
+            .map(|i| {
+                let mut bank = [0u8; ROM_BANK_SIZE];
+                bank[0] = i as u8;
+                ReadOnly(bank)
+            })
+            .collect();
+        PocketCamera::new(banks)
+    }
+
+    #[test]
+    fn rom_and_ram_banking() {
+        let mut cart = camera();
+        assert_eq!(cart.read(0x4000.into()), 1);
+        cart.write(0x2000.into(), 0x0a);
+        assert_eq!(cart.read(0x4000.into()), 0x0a);
+        assert_eq!(cart.current_rom_bank(), 0x0a);
+        // Bank 0 can be mapped in the switchable window.
+        cart.write(0x2000.into(), 0x00);
+        assert_eq!(cart.read(0x4000.into()), 0x00);
+
+        // Ram is readable while disabled, but writes are ignored.
+        cart.write(0x4000.into(), 0x03);
+        cart.write(0x8123.into(), 0x42);
+        assert_eq!(cart.read(0x8123.into()), 0x00);
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x8123.into(), 0x42);
+        assert_eq!(cart.read(0x8123.into()), 0x42);
+        assert_eq!(cart.ram_bank(3).unwrap()[0x123], 0x42);
+        cart.write(0x4000.into(), 0x0f);
+        assert_eq!(cart.read(0x8123.into()), 0x00);
+        assert_eq!(cart.ram_bank(16), None);
+    }
+
+    #[test]
+    fn camera_registers() {
+        let mut cart = camera();
+        cart.write(0x0000.into(), 0x0a);
+        cart.write(0x8000.into(), 0x55);
+        cart.write(0x4000.into(), 0x10);
+        assert!(cart.camera_selected());
+
+        // Only the trigger register can be read back, and it is mirrored every 0x80 bytes.
+        cart.write(0x8001.into(), 0x12);
+        assert_eq!(cart.read(0x8001.into()), 0x00);
+        cart.write(0x8080.into(), 0x06);
+        assert_eq!(cart.read(0x8000.into()), 0x06);
+        assert_eq!(cart.read(0x9f00.into()), 0x06);
+        // Register writes don't reach ram.
+        cart.write(0x4000.into(), 0x00);
+        assert_eq!(cart.read(0x8000.into()), 0x55);
+        assert_eq!(cart.read(0x8080.into()), 0x00);
+
+        // A capture fills the image area of bank 0 and is no longer busy when polled.
+        cart.write(0x4000.into(), 0x10);
+        cart.write(0x8000.into(), 0x07);
+        assert_eq!(cart.read(0x8000.into()) & CAPTURE_BUSY, 0);
+        let bank0 = cart.ram_bank(0).unwrap();
+        assert_eq!(bank0[0x0ff], 0x00);
+        assert_eq!(bank0[0x100], 0xff);
+        assert_eq!(bank0[0x110], 0x00);
+        assert_eq!(bank0[0x200], 0x00);
+        assert_eq!(bank0[0xeff], 0xff);
+        assert_eq!(bank0[0xf00], 0x00);
+    }
+
+    #[test]
+    fn parse_pocket_camera() {
+        let mut rom = vec![0u8; 64 * ROM_BANK_SIZE];
+        rom[CART_TYPE] = 0xfc;
+        rom[ROM_SIZE] = 0x05;
+        rom[RAM_SIZE] = 0x04;
+        rom[0x4000 * 5] = 0x99;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let mut cart = match Cartridge::parse(&rom[..]).unwrap() {
+            Cartridge::PocketCamera(cart) => cart,
+            other => panic!("Parsed as {:?}", other),
+        };
+        cart.write(0x2000.into(), 5);
+        assert_eq!(cart.read(0x4000.into()), 0x99);
+    }
+}
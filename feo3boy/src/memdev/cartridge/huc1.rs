@@ -0,0 +1,253 @@
+//! The HuC1 mapper, used by some Hudson games, which has an infrared port.
+
+use std::sync::Arc;
+
+use super::{RamBank, RomBank, OPEN_BUS, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::memdev::{Addr, ChunkedRam, MemDevice, Permissions};
+
+/// Value written to the mode register to map the infrared port instead of ram.
+const IR_MODE: u8 = 0xe;
+
+/// Value the infrared port reads when no light is seen. Bit 0 is set when light is seen.
+const IR_IDLE: u8 = 0xc0;
+
+/// Cartridge with the HuC1 mapper, which has up to 64 rom banks and 4 ram banks, banked the same
+/// way as a simple MBC1.
+///
+/// In place of a ram enable register, writing 0x0E to 0x0000..0x2000 maps the infrared port over
+/// the ram window, and any other value maps ram. Ram is always accessible while it is mapped.
+/// While the port is mapped, reads return 0xC0, with bit 0 set if the receiver sees light, and
+/// bit 0 of writes turns the LED on or off. Nothing is connected to the port unless
+/// [`set_ir_input`](Huc1Rom::set_ir_input) is used, so games see no signal.
+#[derive(Clone, Debug)]
+pub struct Huc1Rom {
+    /// Rom banks. Shared between clones, since rom never changes.
+    rom_banks: Arc<[RomBank]>,
+    /// Ram banks. Empty if there is no ram.
+    ram: Vec<ChunkedRam<RAM_BANK_SIZE>>,
+
+    // Registers:
+    /// Whether the infrared port is mapped instead of ram.
+    ir_mode: bool,
+    /// Rom bank mapped at 0x4000..0x8000.
+    rom_bank: u8,
+    /// Ram bank mapped at 0xA000..0xC000.
+    ram_bank: u8,
+    /// Whether the LED is on.
+    ir_output: bool,
+    /// Whether the receiver currently sees light.
+    ir_input: bool,
+}
+
+impl Huc1Rom {
+    /// Constructs a new `Huc1Rom` with the given rom banks and number of ram banks.
+    pub(super) fn new(rom_banks: Vec<RomBank>, num_ram_banks: usize) -> Self {
+        assert!(
+            (2..=64).contains(&rom_banks.len()),
+            "HuC1 Rom must have between 2 and 64 rom banks."
+        );
+        assert!(num_ram_banks <= 4, "HuC1 Rom can have at most 4 ram banks.");
+        Huc1Rom {
+            rom_banks: rom_banks.into(),
+            ram: vec![ChunkedRam::new(); num_ram_banks],
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_output: false,
+            ir_input: false,
+        }
+    }
+
+    /// Gets rom bank 0, which contains the header.
+    pub(super) fn bank0(&self) -> &RomBank {
+        &self.rom_banks[0]
+    }
+
+    /// Sets whether the infrared receiver currently sees light.
+    pub fn set_ir_input(&mut self, seen: bool) {
+        self.ir_input = seen;
+    }
+
+    /// Returns true if the infrared LED is on.
+    pub fn ir_output(&self) -> bool {
+        self.ir_output
+    }
+
+    /// Resets the registers to their power-on state, leaving ram untouched.
+    pub fn reset_registers(&mut self) {
+        self.ir_mode = false;
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ir_output = false;
+    }
+
+    /// Gets the values of the mapper registers, to save in a
+    /// [`VolatileState`](super::VolatileState). The infrared receiver is an input, so it isn't
+    /// included.
+    pub(super) fn registers(&self) -> Vec<u8> {
+        vec![
+            self.ir_mode as u8,
+            self.rom_bank,
+            self.ram_bank,
+            self.ir_output as u8,
+        ]
+    }
+
+    /// Restores the mapper registers from values returned by [`registers`](Self::registers).
+    pub(super) fn restore_registers(&mut self, registers: &[u8]) {
+        self.ir_mode = registers[0] != 0;
+        self.rom_bank = (registers[1] & 0x3f).max(1);
+        self.ram_bank = registers[2] & 0x3;
+        self.ir_output = registers[3] != 0;
+    }
+
+    /// Gets the number of the rom bank currently mapped at 0x4000..0x8000.
+    pub fn current_rom_bank(&self) -> u16 {
+        self.upper_bank_index() as u16
+    }
+
+    /// Computes the offset into the rom file of the byte which is currently mapped at the given
+    /// address. Returns `None` if the address is not in the rom portion of the cartridge
+    /// (0..0x8000).
+    pub fn physical_offset(&self, addr: Addr) -> Option<usize> {
+        match addr.relative() {
+            0..=0x3fff => Some(addr.index()),
+            0x4000..=0x7fff => {
+                Some(self.upper_bank_index() * ROM_BANK_SIZE + addr.offset_by(0x4000).index())
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of ram banks.
+    pub(super) fn num_ram_banks(&self) -> usize {
+        self.ram.len()
+    }
+
+    /// Gets a copy of the contents of the ram bank with the given index, or `None` if there is
+    /// no such bank.
+    pub fn ram_bank(&self, index: usize) -> Option<RamBank> {
+        let ram = self.ram.get(index)?;
+        let mut data = [0; RAM_BANK_SIZE];
+        for (byte, value) in data.iter_mut().zip(ram.iter()) {
+            *byte = value;
+        }
+        Some(data)
+    }
+
+    /// Writes directly to the given ram bank, regardless of the mapper registers. Writes to banks
+    /// which don't exist are ignored.
+    pub(super) fn write_ram_bank(&mut self, bank: usize, addr: Addr, value: u8) {
+        if let Some(ram) = self.ram.get_mut(bank) {
+            ram.write(addr, value);
+        }
+    }
+
+    /// Gets whether the CPU can read and write the cartridge ram area, which is the infrared
+    /// port in IR mode. HuC1 has no ram enable, so ram is accessible whenever it exists.
+    pub(super) fn ram_permissions(&self) -> Permissions {
+        if self.ir_mode || self.ram_bank_index().is_some() {
+            Permissions::READ | Permissions::WRITE
+        } else {
+            Permissions::empty()
+        }
+    }
+
+    /// Get the index of the currently selected rom bank.
+    fn upper_bank_index(&self) -> usize {
+        self.rom_bank as usize % self.rom_banks.len()
+    }
+
+    /// Gets the index of the currently selected ram bank, if the rom has ram.
+    fn ram_bank_index(&self) -> Option<usize> {
+        if self.ram.is_empty() {
+            None
+        } else {
+            Some(self.ram_bank as usize % self.ram.len())
+        }
+    }
+}
+
+impl MemDevice for Huc1Rom {
+    fn size(&self) -> usize {
+        0xa000
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match addr.relative() {
+            0..=0x3fff => self.rom_banks[0].read(addr),
+            0x4000..=0x7fff => self.rom_banks[self.upper_bank_index()].read(addr.offset_by(0x4000)),
+            0x8000..=0x9fff if self.ir_mode => IR_IDLE | self.ir_input as u8,
+            0x8000..=0x9fff => match self.ram_bank_index() {
+                Some(bank) => self.ram[bank].read(addr.offset_by(0x8000)),
+                None => OPEN_BUS,
+            },
+            _ => panic!("Address {} out of range for Huc1Rom", addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match addr.relative() {
+            0x0000..=0x1fff => self.ir_mode = (value & 0xf) == IR_MODE,
+            // As on the MBC1, selecting bank 0 maps bank 1.
+            0x2000..=0x3fff => self.rom_bank = (value & 0x3f).max(1),
+            0x4000..=0x5fff => self.ram_bank = value & 0x3,
+            // There is no banking mode register.
+            0x6000..=0x7fff => {}
+            0x8000..=0x9fff if self.ir_mode => self.ir_output = value & 1 != 0,
+            0x8000..=0x9fff => {
+                if let Some(bank) = self.ram_bank_index() {
+                    self.ram[bank].write(addr.offset_by(0x8000), value);
+                }
+            }
+            _ => panic!("Address {} out of range for Huc1Rom", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memdev::cartridge::{
+        compute_header_checksum, Cartridge, CART_TYPE, HEADER_CHECKSUM, RAM_SIZE, ROM_SIZE,
+    };
+    use crate::testutils::assert_mem_eq;
+
+    #[test]
+    fn banking_and_ir_port() {
+        let mut rom = vec![0u8; 16 * ROM_BANK_SIZE];
+        for (i, bank) in rom.chunks_mut(ROM_BANK_SIZE).enumerate().skip(1) {
+            bank[0] = i as u8;
+        }
+        rom[CART_TYPE] = 0xff;
+        rom[ROM_SIZE] = 0x03;
+        rom[RAM_SIZE] = 0x03;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let mut cart = match Cartridge::parse(&rom[..]).unwrap() {
+            Cartridge::Huc1(cart) => cart,
+            other => panic!("Parsed as {:?}", other),
+        };
+
+        cart.write(0x2000.into(), 0x00);
+        assert_eq!(cart.read(0x4000.into()), 1);
+        cart.write(0x2000.into(), 0x0b);
+        assert_eq!(cart.read(0x4000.into()), 0x0b);
+        assert_eq!(cart.current_rom_bank(), 0x0b);
+
+        // Ram is mapped without being enabled.
+        cart.write(0x4000.into(), 0x02);
+        cart.write(0x8000.into(), 0x42);
+        assert_eq!(cart.read(0x8000.into()), 0x42);
+        assert_eq!(cart.ram_bank(2).unwrap()[0], 0x42);
+
+        // The infrared port sees no signal, and writes to it don't reach ram.
+        cart.write(0x0000.into(), 0x0e);
+        assert_mem_eq(&cart, 0x8000, &[0xc0; RAM_BANK_SIZE]);
+        cart.write(0x8000.into(), 0x01);
+        assert!(cart.ir_output());
+        cart.set_ir_input(true);
+        assert_mem_eq(&cart, 0x8000, &[0xc1; RAM_BANK_SIZE]);
+        cart.write(0x0000.into(), 0x00);
+        assert_eq!(cart.read(0x8000.into()), 0x42);
+    }
+}
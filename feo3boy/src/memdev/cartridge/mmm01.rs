@@ -0,0 +1,377 @@
+//! The MMM01 multi-game mapper.
+
+use std::sync::Arc;
+
+use super::{RamBank, RomBank, OPEN_BUS, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::memdev::{Addr, ChunkedRam, MemDevice, Permissions};
+
+/// Cartridge with the MMM01 mapper, used by multi-game compilations.
+///
+/// The mapper starts out unmapped, with every rom bank bit forced high, so the last 32 KiB of rom
+/// (where the menu lives) is mapped at 0x0000..0x8000. While unmapped, the menu writes the
+/// registers to describe the game it picked: the outer rom and ram bank bits, which select where
+/// the game starts, and masks of which low bank bits the game may still change. Setting bit 6 of
+/// the ram enable register then maps the game. From then on, the outer bits, the masks, and the
+/// masked low bits are locked, and the game sees what looks like an MBC1 of its own size, with
+/// its first bank at 0x0000.
+///
+/// The MBC1 advanced banking mode and the multiplexing of the bank bits are not modeled.
+#[derive(Clone, Debug)]
+pub struct Mmm01Rom {
+    /// Rom banks. Shared between clones, since rom never changes.
+    rom_banks: Arc<[RomBank]>,
+    /// Ram banks. Empty if there is no ram.
+    ram: Vec<ChunkedRam<RAM_BANK_SIZE>>,
+    /// Whether ram is saved when the device is powered off. (Does the ram have a battery?)
+    save_ram: bool,
+
+    // Registers:
+    /// Whether the selected game has been mapped, which locks the outer bank bits and masks.
+    mapped: bool,
+    /// Whether ram is enabled for reading/writing.
+    ram_enable: bool,
+    /// Bits 0-4 of the rom bank.
+    rom_bank_low: u8,
+    /// Bits 5-8 of the rom bank, which select the game. Only writable while unmapped.
+    rom_bank_high: u8,
+    /// Bits of `rom_bank_low` which are locked once mapped. Only writable while unmapped.
+    rom_bank_mask: u8,
+    /// Bits 0-1 of the ram bank.
+    ram_bank_low: u8,
+    /// Bits 2-3 of the ram bank. Only writable while unmapped.
+    ram_bank_high: u8,
+    /// Bits of `ram_bank_low` which are locked once mapped. Only writable while unmapped.
+    ram_bank_mask: u8,
+}
+
+impl Mmm01Rom {
+    /// Constructs a new `Mmm01Rom` with the given rom banks and number of ram banks.
+    pub(super) fn new(rom_banks: Vec<RomBank>, num_ram_banks: usize, save_ram: bool) -> Self {
+        assert!(rom_banks.len() >= 2, "Must have at least 2 rom banks.");
+        assert!(
+            rom_banks.len() <= 512,
+            "MMM01 Rom can have at most 512 rom banks."
+        );
+        assert!(
+            rom_banks.len().count_ones() == 1,
+            "Number of rom banks must be a power of 2."
+        );
+        assert!(
+            num_ram_banks <= 16,
+            "MMM01 Rom can have at most 16 ram banks."
+        );
+        Mmm01Rom {
+            rom_banks: rom_banks.into(),
+            ram: vec![ChunkedRam::new(); num_ram_banks],
+            save_ram,
+            mapped: false,
+            ram_enable: false,
+            rom_bank_low: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_bank_mask: 0,
+        }
+    }
+
+    /// Gets rom bank 0, which contains the header.
+    pub(super) fn bank0(&self) -> &RomBank {
+        &self.rom_banks[0]
+    }
+
+    /// Returns true if the cartridge ram is battery-backed, meaning it should be saved when the
+    /// device is powered off.
+    pub fn has_battery(&self) -> bool {
+        self.save_ram
+    }
+
+    /// Returns true if the menu has mapped the selected game.
+    pub fn mapped(&self) -> bool {
+        self.mapped
+    }
+
+    /// Resets the registers to their power-on state, which returns to the menu, leaving ram
+    /// untouched.
+    pub fn reset_registers(&mut self) {
+        self.mapped = false;
+        self.ram_enable = false;
+        self.rom_bank_low = 0;
+        self.rom_bank_high = 0;
+        self.rom_bank_mask = 0;
+        self.ram_bank_low = 0;
+        self.ram_bank_high = 0;
+        self.ram_bank_mask = 0;
+    }
+
+    /// Gets the values of the mapper registers, to save in a
+    /// [`VolatileState`](super::VolatileState).
+    pub(super) fn registers(&self) -> Vec<u8> {
+        vec![
+            self.mapped as u8,
+            self.ram_enable as u8,
+            self.rom_bank_low,
+            self.rom_bank_high,
+            self.rom_bank_mask,
+            self.ram_bank_low,
+            self.ram_bank_high,
+            self.ram_bank_mask,
+        ]
+    }
+
+    /// Restores the mapper registers from values returned by [`registers`](Self::registers).
+    pub(super) fn restore_registers(&mut self, registers: &[u8]) {
+        self.mapped = registers[0] != 0;
+        self.ram_enable = registers[1] != 0;
+        self.rom_bank_low = registers[2] & 0x1f;
+        self.rom_bank_high = registers[3] & 0xf;
+        self.rom_bank_mask = registers[4] & 0x1e;
+        self.ram_bank_low = registers[5] & 0x3;
+        self.ram_bank_high = registers[6] & 0x3;
+        self.ram_bank_mask = registers[7] & 0x3;
+    }
+
+    /// Gets the number of the rom bank currently mapped at 0x4000..0x8000.
+    pub fn current_rom_bank(&self) -> u16 {
+        self.upper_bank_index() as u16
+    }
+
+    /// Computes the offset into the rom file of the byte which is currently mapped at the given
+    /// address. Returns `None` if the address is not in the rom portion of the cartridge
+    /// (0..0x8000).
+    pub fn physical_offset(&self, addr: Addr) -> Option<usize> {
+        match addr.relative() {
+            0..=0x3fff => Some(self.lower_bank_index() * ROM_BANK_SIZE + addr.index()),
+            0x4000..=0x7fff => {
+                Some(self.upper_bank_index() * ROM_BANK_SIZE + addr.offset_by(0x4000).index())
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of ram banks.
+    pub(super) fn num_ram_banks(&self) -> usize {
+        self.ram.len()
+    }
+
+    /// Gets a copy of the contents of the ram bank with the given index, or `None` if there is
+    /// no such bank.
+    pub fn ram_bank(&self, index: usize) -> Option<RamBank> {
+        let ram = self.ram.get(index)?;
+        let mut data = [0; RAM_BANK_SIZE];
+        for (byte, value) in data.iter_mut().zip(ram.iter()) {
+            *byte = value;
+        }
+        Some(data)
+    }
+
+    /// Writes directly to the given ram bank, regardless of the mapper registers. Writes to banks
+    /// which don't exist are ignored.
+    pub(super) fn write_ram_bank(&mut self, bank: usize, addr: Addr, value: u8) {
+        if let Some(ram) = self.ram.get_mut(bank) {
+            ram.write(addr, value);
+        }
+    }
+
+    /// Get the index of the rom bank mapped at 0x0000..0x4000: the first bank of the selected
+    /// game, or the second to last bank while unmapped.
+    fn lower_bank_index(&self) -> usize {
+        let bank = if self.mapped {
+            (self.rom_bank_high as usize) << 5 | (self.rom_bank_low & self.rom_bank_mask) as usize
+        } else {
+            0x1fe
+        };
+        bank % self.rom_banks.len()
+    }
+
+    /// Get the index of the rom bank mapped at 0x4000..0x8000. As on the MBC1, if the bits the
+    /// game can change are all 0, they act as if bank 1 was selected.
+    fn upper_bank_index(&self) -> usize {
+        let bank = if self.mapped {
+            let mut low = self.rom_bank_low;
+            if low & !self.rom_bank_mask == 0 {
+                low |= 1;
+            }
+            (self.rom_bank_high as usize) << 5 | low as usize
+        } else {
+            0x1ff
+        };
+        bank % self.rom_banks.len()
+    }
+
+    /// Gets whether the CPU can read and write the cartridge ram area: both if there is ram and
+    /// it is enabled, otherwise neither.
+    pub(super) fn ram_permissions(&self) -> Permissions {
+        if self.ram_bank_index().is_some() {
+            Permissions::READ | Permissions::WRITE
+        } else {
+            Permissions::empty()
+        }
+    }
+
+    /// Gets the index of the currently selected ram bank, if the rom has ram and ram is enabled.
+    fn ram_bank_index(&self) -> Option<usize> {
+        if self.ram.is_empty() || !self.ram_enable {
+            None
+        } else {
+            let bank = self.ram_bank_high << 2 | self.ram_bank_low;
+            Some(bank as usize % self.ram.len())
+        }
+    }
+
+    /// Writes the given bits of a register, leaving the bits which are locked alone. Bits in
+    /// `locked` can't be changed once the game is mapped.
+    fn write_masked(&self, register: u8, value: u8, locked: u8) -> u8 {
+        if self.mapped {
+            register & locked | value & !locked
+        } else {
+            value
+        }
+    }
+}
+
+impl MemDevice for Mmm01Rom {
+    fn size(&self) -> usize {
+        0xa000
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match addr.relative() {
+            0..=0x3fff => self.rom_banks[self.lower_bank_index()].read(addr),
+            0x4000..=0x7fff => self.rom_banks[self.upper_bank_index()].read(addr.offset_by(0x4000)),
+            0x8000..=0x9fff => match self.ram_bank_index() {
+                Some(bank) => self.ram[bank].read(addr.offset_by(0x8000)),
+                None => OPEN_BUS,
+            },
+            _ => panic!("Address {} out of range for Mmm01Rom", addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match addr.relative() {
+            0x0000..=0x1fff => {
+                self.ram_enable = (value & 0xf) == 0xa;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0x3;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3fff => {
+                self.rom_bank_low =
+                    self.write_masked(self.rom_bank_low, value & 0x1f, self.rom_bank_mask);
+                if !self.mapped {
+                    self.rom_bank_high = (self.rom_bank_high & 0xc) | (value >> 5) & 0x3;
+                }
+            }
+            0x4000..=0x5fff => {
+                self.ram_bank_low =
+                    self.write_masked(self.ram_bank_low, value & 0x3, self.ram_bank_mask);
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x3;
+                    self.rom_bank_high = (self.rom_bank_high & 0x3) | (value >> 2) & 0xc;
+                }
+            }
+            0x6000..=0x7fff => {
+                if !self.mapped {
+                    // Bits 2-5 mask rom bank bits 1-4.
+                    self.rom_bank_mask = (value >> 1) & 0x1e;
+                }
+            }
+            0x8000..=0x9fff => {
+                if let Some(bank) = self.ram_bank_index() {
+                    self.ram[bank].write(addr.offset_by(0x8000), value);
+                }
+            }
+            _ => panic!("Address {} out of range for Mmm01Rom", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memdev::cartridge::{
+        compute_header_checksum, Cartridge, CART_TYPE, HEADER_CHECKSUM, RAM_SIZE, ROM_SIZE,
+    };
+    use crate::memdev::ReadOnly;
+
+    /// Builds an MMM01 cartridge with 64 rom banks, where the first byte of each bank is its
+    /// index.
+    fn mmm01(ram_banks: usize) -> Mmm01Rom {
+        let banks = (0..64)
+            .map(|i| {
+                let mut bank = [0u8; ROM_BANK_SIZE];
+                bank[0] = i as u8;
+                ReadOnly(bank)
+            })
+            .collect();
+        Mmm01Rom::new(banks, ram_banks, false)
+    }
+
+    #[test]
+    fn menu_to_game() {
+        let mut cart = mmm01(4);
+        // The menu in the last 32 KiB is mapped at boot.
+        assert!(!cart.mapped());
+        assert_eq!(cart.read(0x0000.into()), 62);
+        assert_eq!(cart.read(0x4000.into()), 63);
+        cart.write(0x2000.into(), 0x05);
+        assert_eq!(cart.read(0x4000.into()), 63);
+
+        // The menu picks the 16 bank game starting at bank 16, which may only change rom bank
+        // bits 0-3, and the second pair of ram banks.
+        cart.write(0x6000.into(), 0x20);
+        cart.write(0x2000.into(), 0x10);
+        cart.write(0x4000.into(), 0x02);
+        cart.write(0x0000.into(), 0x6a);
+        assert!(cart.mapped());
+        assert_eq!(cart.read(0x0000.into()), 16);
+        assert_eq!(cart.read(0x4000.into()), 17);
+
+        // The game banks within its own 16 banks.
+        cart.write(0x2000.into(), 0x05);
+        assert_eq!(cart.read(0x4000.into()), 21);
+        assert_eq!(cart.current_rom_bank(), 21);
+        cart.write(0x2000.into(), 0x0f);
+        assert_eq!(cart.read(0x4000.into()), 31);
+        cart.write(0x2000.into(), 0x00);
+        assert_eq!(cart.read(0x4000.into()), 17);
+        // The outer bits and masks are locked.
+        cart.write(0x6000.into(), 0x00);
+        cart.write(0x2000.into(), 0x60);
+        assert_eq!(cart.read(0x4000.into()), 17);
+        assert_eq!(cart.read(0x0000.into()), 16);
+
+        // Ram bank 2 is the game's first bank, and bank 3 its second.
+        cart.write(0x8000.into(), 0x22);
+        cart.write(0x4000.into(), 0x01);
+        cart.write(0x8000.into(), 0x33);
+        assert_eq!(cart.ram_bank(2).unwrap()[0], 0x22);
+        assert_eq!(cart.ram_bank(3).unwrap()[0], 0x33);
+        // Ram is still enabled and disabled normally.
+        cart.write(0x0000.into(), 0x00);
+        assert_eq!(cart.read(0x8000.into()), OPEN_BUS);
+        assert!(cart.mapped());
+
+        // A reset returns to the menu.
+        cart.reset_registers();
+        assert_eq!(cart.read(0x0000.into()), 62);
+    }
+
+    #[test]
+    fn parse_mmm01() {
+        let mut rom = vec![0u8; 8 * ROM_BANK_SIZE];
+        rom[CART_TYPE] = 0x0d;
+        rom[ROM_SIZE] = 0x02;
+        rom[RAM_SIZE] = 0x03;
+        rom[7 * ROM_BANK_SIZE] = 0x77;
+        rom[HEADER_CHECKSUM] = compute_header_checksum(&rom);
+        let cart = match Cartridge::parse(&rom[..]).unwrap() {
+            Cartridge::Mmm01(cart) => cart,
+            other => panic!("Parsed as {:?}", other),
+        };
+        assert!(cart.has_battery());
+        assert_eq!(cart.num_ram_banks(), 4);
+        assert_eq!(cart.read(0x4000.into()), 0x77);
+    }
+}
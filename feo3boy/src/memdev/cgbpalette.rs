@@ -0,0 +1,207 @@
+//! The CGB color palette ram, accessed through BCPS/BCPD (0xFF68/0xFF69) and OCPS/OCPD
+//! (0xFF6A/0xFF6B).
+
+use std::fmt;
+
+/// Number of bytes of palette ram for each of the background and sprite palettes: 8 palettes of
+/// 4 colors of 2 bytes.
+const PALETTE_RAM_SIZE: usize = 64;
+
+/// Bits of the index register which select a byte of palette ram.
+const INDEX_MASK: u8 = 0x3f;
+/// Bit of the index register which enables auto-increment.
+const AUTO_INCREMENT: u8 = 0x80;
+
+/// A color as stored in CGB palette ram: 5 bits each of red (bits 0-4), green (bits 5-9), and
+/// blue (bits 10-14). Bit 15 is unused.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Rgb555(pub u16);
+
+impl Rgb555 {
+    /// Gets the red component, from 0 to 31.
+    pub fn red(self) -> u8 {
+        (self.0 & 0x1f) as u8
+    }
+
+    /// Gets the green component, from 0 to 31.
+    pub fn green(self) -> u8 {
+        (self.0 >> 5 & 0x1f) as u8
+    }
+
+    /// Gets the blue component, from 0 to 31.
+    pub fn blue(self) -> u8 {
+        (self.0 >> 10 & 0x1f) as u8
+    }
+}
+
+impl fmt::Display for Rgb555 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {}, {})", self.red(), self.green(), self.blue())
+    }
+}
+
+/// One of the two CGB palette rams, with its index register.
+///
+/// The index register (BCPS or OCPS) selects a byte in bits 0-5, and bit 7 enables
+/// auto-increment. The data register (BCPD or OCPD) reads and writes the selected byte. Writes
+/// to the data register increment the index if auto-increment is enabled, wrapping from 0x3F to
+/// 0, and reads never do.
+///
+/// The PPU reads palette ram while it is drawing, so the CPU can't access it then: reads of the
+/// data register return 0xFF and writes are ignored. Revisions differ on whether a blocked write
+/// still increments the index. This follows the common behavior, where it does, so a game
+/// streaming colors across mode 3 stays in step with the index it expects.
+#[derive(Copy, Clone, Debug)]
+pub struct PaletteRam {
+    /// Contents of palette ram.
+    data: [u8; PALETTE_RAM_SIZE],
+    /// The index register, except for the unused bit 6.
+    index: u8,
+}
+
+impl PaletteRam {
+    /// Constructs a new `PaletteRam` with all colors and the index cleared.
+    pub fn new() -> Self {
+        PaletteRam {
+            data: [0; PALETTE_RAM_SIZE],
+            index: 0,
+        }
+    }
+
+    /// Gets the value the CPU reads from the index register. Bit 6 is unused and reads 1.
+    pub fn index_register(&self) -> u8 {
+        self.index | 0x40
+    }
+
+    /// Handles a CPU write to the index register.
+    pub fn write_index_register(&mut self, value: u8) {
+        self.index = value & (AUTO_INCREMENT | INDEX_MASK);
+    }
+
+    /// Gets the value the CPU reads from the data register. Reads 0xFF if access is `blocked`
+    /// because the PPU is drawing.
+    pub fn data_register(&self, blocked: bool) -> u8 {
+        if blocked {
+            0xff
+        } else {
+            self.data[(self.index & INDEX_MASK) as usize]
+        }
+    }
+
+    /// Handles a CPU write to the data register. The write is ignored if access is `blocked`
+    /// because the PPU is drawing, but the index is still incremented.
+    pub fn write_data_register(&mut self, value: u8, blocked: bool) {
+        if !blocked {
+            self.data[(self.index & INDEX_MASK) as usize] = value;
+        }
+        if self.index & AUTO_INCREMENT != 0 {
+            self.index = AUTO_INCREMENT | (self.index + 1) & INDEX_MASK;
+        }
+    }
+
+    /// Gets the raw contents of palette ram.
+    pub fn bytes(&self) -> &[u8; PALETTE_RAM_SIZE] {
+        &self.data
+    }
+
+    /// Gets the 4 colors of the palette with the given number, which must be 0-7.
+    pub fn palette(&self, n: usize) -> [Rgb555; 4] {
+        assert!(n < 8, "Palette {} out of range", n);
+        let color = |i: usize| {
+            let offset = n * 8 + i * 2;
+            Rgb555(u16::from_le_bytes([
+                self.data[offset],
+                self.data[offset + 1],
+            ]))
+        };
+        [color(0), color(1), color(2), color(3)]
+    }
+}
+
+impl Default for PaletteRam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_then_read_back_all_bytes() {
+        let mut ram = PaletteRam::new();
+        ram.write_index_register(0x80);
+        for i in 0..64 {
+            ram.write_data_register(i * 3 + 1, false);
+        }
+        // The index wrapped back to 0 and kept auto-increment.
+        assert_eq!(ram.index_register(), 0xc0);
+        for i in 0..64 {
+            ram.write_index_register(i);
+            assert_eq!(ram.data_register(false), i * 3 + 1);
+            // Reads don't increment.
+            assert_eq!(ram.data_register(false), i * 3 + 1);
+            assert_eq!(ram.index_register(), i | 0x40);
+        }
+    }
+
+    #[test]
+    fn index_wraps_and_increments() {
+        let mut ram = PaletteRam::new();
+        ram.write_index_register(0xbf);
+        ram.write_data_register(0x12, false);
+        assert_eq!(ram.index_register(), 0xc0);
+        ram.write_data_register(0x34, false);
+        assert_eq!(ram.index_register(), 0xc1);
+        assert_eq!(ram.bytes()[0x3f], 0x12);
+        assert_eq!(ram.bytes()[0x00], 0x34);
+
+        // Without auto-increment, writes stay on the same byte.
+        ram.write_index_register(0x3f);
+        ram.write_data_register(0x56, false);
+        ram.write_data_register(0x78, false);
+        assert_eq!(ram.index_register(), 0x7f);
+        assert_eq!(ram.data_register(false), 0x78);
+    }
+
+    #[test]
+    fn blocked_access() {
+        let mut ram = PaletteRam::new();
+        ram.write_index_register(0x80);
+        ram.write_data_register(0xaa, false);
+        ram.write_index_register(0x80);
+        assert_eq!(ram.data_register(true), 0xff);
+        // The write is dropped, but the index still moves.
+        ram.write_data_register(0x55, true);
+        assert_eq!(ram.index_register(), 0xc1);
+        ram.write_index_register(0x00);
+        assert_eq!(ram.data_register(false), 0xaa);
+    }
+
+    #[test]
+    fn decode_palettes() {
+        let mut ram = PaletteRam::new();
+        ram.write_index_register(0x80 | 8);
+        // Palette 1: white, red, green, blue.
+        for &byte in [0xff, 0x7f, 0x1f, 0x00, 0xe0, 0x03, 0x00, 0x7c].iter() {
+            ram.write_data_register(byte, false);
+        }
+        let palette = ram.palette(1);
+        assert_eq!(
+            palette,
+            [
+                Rgb555(0x7fff),
+                Rgb555(0x001f),
+                Rgb555(0x03e0),
+                Rgb555(0x7c00)
+            ]
+        );
+        assert_eq!(palette[0].to_string(), "(31, 31, 31)");
+        assert_eq!(
+            (palette[2].red(), palette[2].green(), palette[2].blue()),
+            (0, 31, 0)
+        );
+        assert_eq!(ram.palette(0), [Rgb555(0); 4]);
+    }
+}
@@ -0,0 +1,292 @@
+//! GameShark-style cheats, which patch ram once per frame.
+
+use std::fmt;
+
+use thiserror::Error;
+
+use super::regions::{CART_RAM_END, CART_RAM_START, ROM_END};
+use super::{Addr, GbMmu};
+
+/// Errors that can result from parsing a cheat code.
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum CheatParseError {
+    /// GameShark codes are exactly 8 hex digits.
+    #[error("GameShark codes must be 8 hex digits, got {0} characters")]
+    InvalidLength(usize),
+    /// The code contained something other than a hex digit.
+    #[error("Invalid hex digit {digit:?} at position {position}")]
+    InvalidDigit {
+        /// Index of the character within the code.
+        position: usize,
+        /// The invalid character.
+        digit: char,
+    },
+    /// The code type (first two digits) is not one we know how to apply.
+    #[error("Unsupported GameShark code type {0:#04X}")]
+    UnsupportedType(u8),
+    /// The code targets an address which is not ram.
+    #[error("Address {0:#06X} is not in ram")]
+    NotRam(u16),
+    /// The code selects a cartridge ram bank but targets an address outside of cartridge ram.
+    #[error("Address {0:#06X} is not in cartridge ram, but the code selects a cartridge ram bank")]
+    NotCartRam(u16),
+}
+
+/// A single parsed GameShark code.
+///
+/// GameShark codes have the form `TTVVLLHH`: a code type `TT`, a value `VV`, and an address
+/// with the low byte `LL` first and high byte `HH` second. Code type 0x01 writes to the address
+/// as the CPU would see it. Code types 0x80-0x8F write to cartridge ram bank 0-15 regardless of
+/// which bank the game has mapped. Code types 0x90-0x97 select a CGB working ram bank, which
+/// has no effect on DMG.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CheatCode {
+    /// Code type, which selects the bank the code applies to.
+    pub code_type: u8,
+    /// Value to write.
+    pub value: u8,
+    /// System address to write to.
+    pub addr: u16,
+}
+
+impl CheatCode {
+    /// Parses a code in the standard 8 digit GameShark format. Leading and trailing whitespace is
+    /// ignored.
+    pub fn parse(code: &str) -> Result<Self, CheatParseError> {
+        let code = code.trim();
+        let len = code.chars().count();
+        if len != 8 {
+            return Err(CheatParseError::InvalidLength(len));
+        }
+        let mut bytes = [0u8; 4];
+        for (position, digit) in code.chars().enumerate() {
+            let nibble = digit
+                .to_digit(16)
+                .ok_or(CheatParseError::InvalidDigit { position, digit })?;
+            bytes[position / 2] = (bytes[position / 2] << 4) | nibble as u8;
+        }
+        let [code_type, value, low, high] = bytes;
+        let addr = u16::from_le_bytes([low, high]);
+
+        match code_type {
+            0x00 | 0x01 | 0x90..=0x97 if addr <= ROM_END => Err(CheatParseError::NotRam(addr)),
+            0x00 | 0x01 | 0x90..=0x97 => Ok(CheatCode {
+                code_type,
+                value,
+                addr,
+            }),
+            0x80..=0x8f if !(CART_RAM_START..=CART_RAM_END).contains(&addr) => {
+                Err(CheatParseError::NotCartRam(addr))
+            }
+            0x80..=0x8f => Ok(CheatCode {
+                code_type,
+                value,
+                addr,
+            }),
+            _ => Err(CheatParseError::UnsupportedType(code_type)),
+        }
+    }
+
+    /// The cartridge ram bank this code writes to, if it targets a specific bank.
+    pub fn cart_ram_bank(&self) -> Option<usize> {
+        match self.code_type {
+            0x80..=0x8f => Some((self.code_type & 0xf) as usize),
+            _ => None,
+        }
+    }
+
+    /// Applies this code to the given memory once.
+    pub fn apply(&self, mmu: &mut GbMmu) {
+        match self.cart_ram_bank() {
+            Some(bank) => {
+                let addr = Addr::from(self.addr).offset_by(CART_RAM_START);
+                mmu.cart.inner_mut().write_ram_bank(bank, addr, self.value);
+            }
+            None => mmu.write_raw(self.addr, self.value),
+        }
+    }
+}
+
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [low, high] = self.addr.to_le_bytes();
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}",
+            self.code_type, self.value, low, high
+        )
+    }
+}
+
+/// Identifies a code added to a [`CheatEngine`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CheatHandle(u64);
+
+/// A code held by the [`CheatEngine`].
+#[derive(Clone, Debug)]
+struct Cheat {
+    handle: CheatHandle,
+    code: CheatCode,
+    enabled: bool,
+}
+
+/// Holds a set of GameShark codes and applies them to memory.
+///
+/// Like a real GameShark, the codes have to be reapplied regularly, since the game is free to
+/// overwrite the patched memory. The embedder should call [`apply`](CheatEngine::apply) once per
+/// frame.
+#[derive(Clone, Debug, Default)]
+pub struct CheatEngine {
+    cheats: Vec<Cheat>,
+    next_handle: u64,
+}
+
+impl CheatEngine {
+    /// Creates a new `CheatEngine` with no codes.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Parses and adds a code. New codes start out enabled.
+    pub fn add_code(&mut self, code: &str) -> Result<CheatHandle, CheatParseError> {
+        let code = CheatCode::parse(code)?;
+        let handle = CheatHandle(self.next_handle);
+        self.next_handle += 1;
+        self.cheats.push(Cheat {
+            handle,
+            code,
+            enabled: true,
+        });
+        Ok(handle)
+    }
+
+    /// Gets the code with the given handle, if it hasn't been removed.
+    pub fn code(&self, handle: CheatHandle) -> Option<CheatCode> {
+        self.find(handle).map(|cheat| cheat.code)
+    }
+
+    /// Returns true if the code with the given handle exists and is enabled.
+    pub fn is_enabled(&self, handle: CheatHandle) -> bool {
+        self.find(handle).is_some_and(|cheat| cheat.enabled)
+    }
+
+    /// Enables or disables the code with the given handle. Returns false if there is no such
+    /// code.
+    pub fn set_enabled(&mut self, handle: CheatHandle, enabled: bool) -> bool {
+        match self.cheats.iter_mut().find(|cheat| cheat.handle == handle) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the code with the given handle, returning it if it existed.
+    pub fn remove(&mut self, handle: CheatHandle) -> Option<CheatCode> {
+        let index = self
+            .cheats
+            .iter()
+            .position(|cheat| cheat.handle == handle)?;
+        Some(self.cheats.remove(index).code)
+    }
+
+    /// Applies all enabled codes to memory, in the order they were added.
+    pub fn apply(&self, mmu: &mut GbMmu) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cheat.code.apply(mmu);
+        }
+    }
+
+    /// Finds the code with the given handle.
+    fn find(&self, handle: CheatHandle) -> Option<&Cheat> {
+        self.cheats.iter().find(|cheat| cheat.handle == handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memdev::{BiosRom, Cartridge, Mbc1Rom, MemDevice, ReadOnly};
+
+    #[test]
+    fn parse_codes() {
+        // Pokemon Red/Blue: 999999 money.
+        let code = CheatCode::parse("019947D3").unwrap();
+        assert_eq!(
+            code,
+            CheatCode {
+                code_type: 0x01,
+                value: 0x99,
+                addr: 0xd347
+            }
+        );
+        assert_eq!(code.to_string(), "019947D3");
+        assert_eq!(
+            CheatCode::parse(" 8201ffbf\n").unwrap().cart_ram_bank(),
+            Some(2)
+        );
+
+        assert_eq!(
+            CheatCode::parse("019947D"),
+            Err(CheatParseError::InvalidLength(7))
+        );
+        assert_eq!(
+            CheatCode::parse("0199G7D3"),
+            Err(CheatParseError::InvalidDigit {
+                position: 4,
+                digit: 'G'
+            })
+        );
+        assert_eq!(
+            CheatCode::parse("21994703"),
+            Err(CheatParseError::UnsupportedType(0x21))
+        );
+        assert_eq!(
+            CheatCode::parse("01994703"),
+            Err(CheatParseError::NotRam(0x0347))
+        );
+        assert_eq!(
+            CheatCode::parse("819947D3"),
+            Err(CheatParseError::NotCartRam(0xd347))
+        );
+    }
+
+    #[test]
+    fn apply_wram_code() {
+        let mut mmu = GbMmu::default();
+        let mut cheats = CheatEngine::new();
+        let money = cheats.add_code("019947D3").unwrap();
+        cheats.apply(&mut mmu);
+        assert_eq!(mmu.read_raw(0xd347), 0x99);
+
+        // Disabled and removed codes aren't applied.
+        mmu.write_raw(0xd347, 0);
+        cheats.set_enabled(money, false);
+        cheats.apply(&mut mmu);
+        assert_eq!(mmu.read_raw(0xd347), 0);
+        assert!(!cheats.is_enabled(money));
+        cheats.set_enabled(money, true);
+        assert_eq!(cheats.remove(money).unwrap().value, 0x99);
+        assert_eq!(cheats.remove(money), None);
+        cheats.apply(&mut mmu);
+        assert_eq!(mmu.read_raw(0xd347), 0);
+    }
+
+    #[test]
+    fn apply_cart_ram_code() {
+        let rom_banks = vec![ReadOnly::new([0u8; 0x4000]); 4];
+        let cart = Mbc1Rom::with_ram(rom_banks, 4, Box::new([0u8; 4 * 0x2000]), true);
+        let mut mmu = GbMmu::new(BiosRom::default(), Cartridge::Mbc1(cart));
+        let mut cheats = CheatEngine::new();
+        cheats.add_code("825510A0").unwrap();
+        // Ram is disabled and bank 0 is mapped, but the code still reaches bank 2.
+        cheats.apply(&mut mmu);
+
+        mmu.write(0x1000.into(), 0x0a);
+        mmu.write(0x6000.into(), 0x01);
+        assert_eq!(mmu.read(0xa010.into()), 0);
+        mmu.write(0x4000.into(), 0x02);
+        assert_eq!(mmu.read(0xa010.into()), 0x55);
+    }
+}
@@ -0,0 +1,104 @@
+//! Ram stored in shared chunks, which makes clones cheap.
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::{Addr, MemDevice};
+
+/// Size of each shared chunk.
+const CHUNK_SIZE: usize = 0x100;
+
+/// Ram of `N` bytes stored as copy-on-write chunks of 256 bytes.
+///
+/// Cloning a `ChunkedRam` only clones the handles to the chunks, and a chunk is only copied when
+/// it is first written after a clone. This makes it cheap to keep many snapshots of ram which
+/// mostly doesn't change, such as for a rewind buffer. `N` must be a multiple of 256.
+#[derive(Clone)]
+pub struct ChunkedRam<const N: usize> {
+    chunks: Vec<Arc<[u8; CHUNK_SIZE]>>,
+}
+
+impl<const N: usize> ChunkedRam<N> {
+    /// Constructs a new `ChunkedRam` with all memory set to 0.
+    pub fn new() -> Self {
+        assert!(
+            N.is_multiple_of(CHUNK_SIZE),
+            "ChunkedRam size must be a multiple of {}",
+            CHUNK_SIZE
+        );
+        // All chunks start out sharing the same zeroed memory.
+        let zero = Arc::new([0; CHUNK_SIZE]);
+        ChunkedRam {
+            chunks: vec![zero; N / CHUNK_SIZE],
+        }
+    }
+
+    /// Number of chunks which share their memory with some other `ChunkedRam`.
+    pub fn shared_chunks(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| Arc::strong_count(chunk) > 1)
+            .count()
+    }
+
+    /// Iterates over the bytes of the ram.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        self.chunks.iter().flat_map(|chunk| chunk.iter().copied())
+    }
+}
+
+impl<const N: usize> Default for ChunkedRam<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Debug for ChunkedRam<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChunkedRam")
+            .field("size", &N)
+            .field("shared_chunks", &self.shared_chunks())
+            .finish()
+    }
+}
+
+impl<const N: usize> MemDevice for ChunkedRam<N> {
+    fn read(&self, addr: Addr) -> u8 {
+        match self.chunks.get(addr.index() / CHUNK_SIZE) {
+            Some(chunk) => chunk[addr.index() % CHUNK_SIZE],
+            None => panic!("Address {} out of range for {} byte chunked ram", addr, N),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match self.chunks.get_mut(addr.index() / CHUNK_SIZE) {
+            Some(chunk) => Arc::make_mut(chunk)[addr.index() % CHUNK_SIZE] = value,
+            None => panic!("Address {} out of range for {} byte chunked ram", addr, N),
+        }
+    }
+
+    fn size(&self) -> usize {
+        N
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_on_write() {
+        let mut ram = ChunkedRam::<0x400>::new();
+        ram.write(0x123.into(), 1);
+        let snapshot = ram.clone();
+        assert_eq!(ram.shared_chunks(), 4);
+
+        ram.write(0x124.into(), 2);
+        // Only the written chunk was copied.
+        assert_eq!(ram.shared_chunks(), 3);
+        assert_eq!(ram.read(0x123.into()), 1);
+        assert_eq!(ram.read(0x124.into()), 2);
+        assert_eq!(snapshot.read(0x124.into()), 0);
+        assert_eq!(ram.iter().count(), 0x400);
+    }
+}
@@ -0,0 +1,245 @@
+//! Pluggable hardware components which own parts of the memory map.
+//!
+//! The [`GbMmu`](super::GbMmu) and [`MemMappedIo`](super::MemMappedIo) delegate the memory owned
+//! by the PPU, APU, and timer to component slots, so that real implementations of those devices
+//! can be developed independently and swapped in. The `Null*` components in this module
+//! reproduce the behavior of the plain memory and stub registers used before any real component
+//! existed. They are the defaults, except for the timer and APU, which default to the real
+//! [`Timer`](super::Timer) and [`Apu`](super::Apu).
+//!
+//! Components are also used wherever else storage can be supplied from outside, such as the
+//! external ram of an [`Mbc1Rom`](super::Mbc1Rom). Plain byte arrays are components for that
+//! purpose.
+
+use std::any::Any;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::regions::{OAM_END, OAM_START, VRAM_END, VRAM_START};
+use super::{Addr, ChunkedRam, HardwareModel, MemDevice, Oam};
+use crate::interrupts::InterruptFlags;
+
+/// A hardware component which can be plugged into one of the component slots of the
+/// [`GbMmu`](super::GbMmu) or [`MemMappedIo`](super::MemMappedIo).
+pub trait Component: MemDevice + fmt::Debug {
+    /// Clones this component into a new box. This allows types holding boxed components to
+    /// still be `Clone`.
+    fn box_clone(&self) -> Box<dyn Component>;
+
+    /// Only used for the PPU slot, when DMA bus conflicts are enabled with
+    /// [`GbMmuBuilder::dma_bus_conflicts`](super::GbMmuBuilder::dma_bus_conflicts). If the PPU
+    /// is currently using the video ram bus, returns the value on that bus, which an OAM DMA
+    /// transfer reading from the given VRAM address sees instead of the addressed byte.
+    ///
+    /// The default never reports a conflict, so DMA reads the normal value.
+    fn vram_bus_conflict(&self, _addr: Addr) -> Option<u8> {
+        None
+    }
+
+    /// Only used for the APU slot. Gets the current digital output of each of the four sound
+    /// channels, from 0 to 15, which the CGB reports in PCM12 (0xFF76) and PCM34 (0xFF77).
+    ///
+    /// The default reports silence on every channel.
+    fn pcm_output(&self) -> [u8; 4] {
+        [0; 4]
+    }
+
+    /// Advances the component by the given number of machine cycles. The default does nothing,
+    /// for components which don't depend on time.
+    fn tick(&mut self, _m_cycles: u32) {}
+
+    /// Returns and clears the interrupts this component has requested since the last call. The
+    /// default never requests any.
+    fn take_requested_interrupts(&mut self) -> InterruptFlags {
+        InterruptFlags::empty()
+    }
+
+    /// Tells the component which hardware model is being emulated, for components whose
+    /// behavior differs between models. Called when the component is installed. The default
+    /// ignores the model.
+    fn set_model(&mut self, _model: HardwareModel) {}
+
+    /// Only used for the timer slot. Tells the timer whether the CGB is running in double speed
+    /// mode, which changes the divider bit that produces DIV-APU events. Called before every
+    /// tick. The default ignores it.
+    fn set_double_speed(&mut self, _double_speed: bool) {}
+
+    /// Only used for the timer slot. Returns and clears the number of DIV-APU events since the
+    /// last call. A DIV-APU event is a falling edge of the divider bit which clocks the APU frame
+    /// sequencer, so writing DIV can cause one early.
+    ///
+    /// The default never reports any.
+    fn take_div_apu_events(&mut self) -> u32 {
+        0
+    }
+
+    /// Only used for the APU slot. Clocks the frame sequencer once for each of the given number
+    /// of DIV-APU events reported by the timer. The default ignores them.
+    fn clock_div_apu(&mut self, _events: u32) {}
+}
+
+impl Clone for Box<dyn Component> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// A component which owns a range of registers in the IO block (0xFF00..0xFF80), registered with
+/// [`MemMappedIo::register_device`](super::MemMappedIo::register_device).
+///
+/// The device is addressed relative to the start of its range. It is ticked along with the rest
+/// of the IO block, and the interrupts it requests are collected into IF after every tick and
+/// every write to it.
+pub trait IoDevice: Component {
+    /// Registers owned by this device, as indexes relative to 0xFF00.
+    fn registers(&self) -> RangeInclusive<u8>;
+
+    /// Clones this device into a new box.
+    fn box_clone_io(&self) -> Box<dyn IoDevice>;
+
+    /// Gets the device as `Any`, so it can be looked up by type with
+    /// [`MemMappedIo::device`](super::MemMappedIo::device).
+    fn as_any(&self) -> &dyn Any;
+
+    /// Gets the device as a mutable `Any`, so it can be looked up by type with
+    /// [`MemMappedIo::device_mut`](super::MemMappedIo::device_mut).
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl Clone for Box<dyn IoDevice> {
+    fn clone(&self) -> Self {
+        self.box_clone_io()
+    }
+}
+
+impl<const N: usize> Component for [u8; N] {
+    fn box_clone(&self) -> Box<dyn Component> {
+        Box::new(*self)
+    }
+}
+
+/// PPU component with no video logic. Provides plain Video Ram and sprite memory (OAM).
+///
+/// Because the PPU owns several disjoint parts of the memory map, PPU components are addressed
+/// using the full system address rather than an address relative to the start of one region.
+/// This component accepts addresses in 0x8000..0xA000 (VRAM) and 0xFE00..0xFEA0 (OAM).
+#[derive(Clone, Debug)]
+pub struct NullPpu {
+    /// Video Ram. Mapped to 0x8000..0xA000
+    vram: ChunkedRam<0x2000>,
+    /// Spirte info. Mapped to 0xFE00..0xFEA0.
+    oam: Oam,
+}
+
+impl NullPpu {
+    /// Construct a new `NullPpu` with all memory set to 0.
+    pub fn new() -> Self {
+        NullPpu {
+            vram: ChunkedRam::new(),
+            oam: Oam::new(),
+        }
+    }
+
+    /// Gets the sprite attribute memory.
+    pub fn oam(&self) -> &Oam {
+        &self.oam
+    }
+}
+
+impl Default for NullPpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemDevice for NullPpu {
+    fn read(&self, addr: Addr) -> u8 {
+        match addr.relative() {
+            VRAM_START..=VRAM_END => self.vram.read(addr.offset_by(VRAM_START)),
+            OAM_START..=OAM_END => self.oam.read(addr.offset_by(OAM_START)),
+            _ => panic!("Address {} out of range for NullPpu", addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match addr.relative() {
+            VRAM_START..=VRAM_END => self.vram.write(addr.offset_by(VRAM_START), value),
+            OAM_START..=OAM_END => self.oam.write(addr.offset_by(OAM_START), value),
+            _ => panic!("Address {} out of range for NullPpu", addr),
+        }
+    }
+}
+
+impl Component for NullPpu {
+    fn box_clone(&self) -> Box<dyn Component> {
+        Box::new(self.clone())
+    }
+}
+
+/// APU component with no sound logic. Covers the audio registers and wave ram at
+/// 0xFF10..0xFF40, relative to 0xFF10. All reads return 0xFF and writes are ignored.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NullApu;
+
+impl MemDevice for NullApu {
+    fn size(&self) -> usize {
+        0x30
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        assert!(
+            addr.index() < 0x30,
+            "Address {} out of range for NullApu",
+            addr
+        );
+        0xff
+    }
+
+    fn write(&mut self, addr: Addr, _value: u8) {
+        assert!(
+            addr.index() < 0x30,
+            "Address {} out of range for NullApu",
+            addr
+        );
+    }
+}
+
+impl Component for NullApu {
+    fn box_clone(&self) -> Box<dyn Component> {
+        Box::new(*self)
+    }
+}
+
+/// Timer component which never ticks. Covers the timer registers at 0xFF04..0xFF08, relative to
+/// 0xFF04. All reads return 0xFF and writes are ignored.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NullTimer;
+
+impl MemDevice for NullTimer {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        assert!(
+            addr.index() < 4,
+            "Address {} out of range for NullTimer",
+            addr
+        );
+        0xff
+    }
+
+    fn write(&mut self, addr: Addr, _value: u8) {
+        assert!(
+            addr.index() < 4,
+            "Address {} out of range for NullTimer",
+            addr
+        );
+    }
+}
+
+impl Component for NullTimer {
+    fn box_clone(&self) -> Box<dyn Component> {
+        Box::new(*self)
+    }
+}
@@ -0,0 +1,222 @@
+//! VRAM DMA, which copies blocks of 16 bytes into video ram, controlled by HDMA1..HDMA5 at
+//! 0xFF51..0xFF56. CGB only.
+
+/// Number of bytes copied in each block.
+const HDMA_BLOCK_SIZE: u16 = 0x10;
+
+/// HDMA5 bit which selects an HBlank transfer when starting, and reads 1 when no transfer is
+/// active.
+const HDMA5_HBLANK: u8 = 0x80;
+/// HDMA5 bits holding the number of blocks left, minus one.
+const HDMA5_LENGTH: u8 = 0x7f;
+
+/// The VRAM DMA registers and the progress of the current transfer.
+///
+/// HDMA1..HDMA4 set the source and destination, and are write-only. Writing HDMA5 starts a
+/// transfer of `(value & 0x7F) + 1` blocks. With bit 7 clear it is a general purpose transfer,
+/// which copies everything at once. With bit 7 set it is an HBlank transfer, which copies one
+/// block each time the PPU enters HBlank. Reading HDMA5 gives the number of blocks left minus
+/// one, with bit 7 clear while an HBlank transfer is in progress and set otherwise, so it reads
+/// 0xFF once a transfer finishes. Writing HDMA5 with bit 7 clear during an HBlank transfer
+/// cancels it instead of starting a new one, leaving the count of blocks left readable.
+///
+/// `Hdma` only tracks which blocks are due. The [`GbMmu`](super::GbMmu) copies them, since it
+/// can reach both the source and video ram.
+#[derive(Copy, Clone, Debug)]
+pub struct Hdma {
+    /// Address the next block is copied from.
+    source: u16,
+    /// Offset into video ram the next block is copied to.
+    dest: u16,
+    /// Number of blocks left, minus one, as read from HDMA5.
+    remaining: u8,
+    /// Whether an HBlank transfer is in progress.
+    hblank_active: bool,
+    /// Number of blocks which are due to be copied.
+    pending_blocks: u8,
+}
+
+impl Hdma {
+    /// Constructs a new `Hdma` with no transfer in progress.
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            dest: 0,
+            remaining: HDMA5_LENGTH,
+            hblank_active: false,
+            pending_blocks: 0,
+        }
+    }
+
+    /// Returns true if an HBlank transfer is in progress.
+    pub fn hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    /// Gets the address the next block will be copied from.
+    pub fn source(&self) -> u16 {
+        self.source
+    }
+
+    /// Gets the offset into video ram the next block will be copied to.
+    pub fn dest(&self) -> u16 {
+        self.dest
+    }
+
+    /// Gets the value the CPU reads from HDMA5.
+    pub fn hdma5(&self) -> u8 {
+        if self.hblank_active {
+            self.remaining
+        } else {
+            self.remaining | HDMA5_HBLANK
+        }
+    }
+
+    /// Handles a CPU write to the register at the given index, where 0 is HDMA1 and 4 is HDMA5.
+    pub(super) fn write_register(&mut self, index: u8, value: u8) {
+        match index {
+            0 => self.source = (value as u16) << 8 | self.source & 0x00f0,
+            1 => self.source = self.source & 0xff00 | (value & 0xf0) as u16,
+            // The destination is always in video ram, so only bits 4-12 are used.
+            2 => self.dest = ((value & 0x1f) as u16) << 8 | self.dest & 0x00f0,
+            3 => self.dest = self.dest & 0x1f00 | (value & 0xf0) as u16,
+            4 => self.write_hdma5(value),
+            _ => panic!("HDMA register {} out of range", index),
+        }
+    }
+
+    /// Handles a CPU write to HDMA5, which starts or cancels a transfer.
+    fn write_hdma5(&mut self, value: u8) {
+        if self.hblank_active && value & HDMA5_HBLANK == 0 {
+            self.hblank_active = false;
+            self.pending_blocks = 0;
+            return;
+        }
+        self.remaining = value & HDMA5_LENGTH;
+        if value & HDMA5_HBLANK != 0 {
+            self.hblank_active = true;
+            self.pending_blocks = 0;
+        } else {
+            self.pending_blocks = self.remaining + 1;
+        }
+    }
+
+    /// Tells the transfer the PPU has entered HBlank, which makes the next block of an HBlank
+    /// transfer due.
+    pub(super) fn enter_hblank(&mut self) {
+        if self.hblank_active {
+            self.pending_blocks = self.pending_blocks.saturating_add(1);
+        }
+    }
+
+    /// If a block is due, returns its source address and video ram offset, and advances the
+    /// transfer past it.
+    pub(super) fn take_block(&mut self) -> Option<(u16, u16)> {
+        if self.pending_blocks == 0 {
+            return None;
+        }
+        let block = (self.source, self.dest);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_SIZE);
+        self.dest = (self.dest + HDMA_BLOCK_SIZE) & 0x1ff0;
+        self.pending_blocks -= 1;
+        if self.remaining == 0 {
+            // The count wraps to 0x7F, so HDMA5 reads 0xFF when the transfer is done.
+            self.remaining = HDMA5_LENGTH;
+            self.hblank_active = false;
+            self.pending_blocks = 0;
+        } else {
+            self.remaining -= 1;
+        }
+        Some(block)
+    }
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects the blocks which are due.
+    fn blocks(hdma: &mut Hdma) -> Vec<(u16, u16)> {
+        std::iter::from_fn(|| hdma.take_block()).collect()
+    }
+
+    /// Sets the source and destination.
+    fn set_addresses(hdma: &mut Hdma, source: u16, dest: u16) {
+        hdma.write_register(0, (source >> 8) as u8);
+        hdma.write_register(1, source as u8);
+        hdma.write_register(2, (dest >> 8) as u8);
+        hdma.write_register(3, dest as u8);
+    }
+
+    #[test]
+    fn readback_before_transfer() {
+        let mut hdma = Hdma::new();
+        assert_eq!(hdma.hdma5(), 0xff);
+        assert!(blocks(&mut hdma).is_empty());
+        // The low bits of the addresses are ignored, and the destination is in video ram.
+        set_addresses(&mut hdma, 0xc12f, 0xff1f);
+        assert_eq!((hdma.source(), hdma.dest()), (0xc120, 0x1f10));
+        assert_eq!(hdma.hdma5(), 0xff);
+    }
+
+    #[test]
+    fn general_transfer() {
+        let mut hdma = Hdma::new();
+        set_addresses(&mut hdma, 0x4000, 0x8800);
+        hdma.write_register(4, 0x02);
+        assert_eq!(
+            blocks(&mut hdma),
+            [(0x4000, 0x0800), (0x4010, 0x0810), (0x4020, 0x0820)]
+        );
+        assert_eq!(hdma.hdma5(), 0xff);
+    }
+
+    #[test]
+    fn hblank_transfer_readback() {
+        let mut hdma = Hdma::new();
+        set_addresses(&mut hdma, 0xd000, 0x9000);
+        hdma.write_register(4, 0x82);
+        // Nothing is copied until HBlank, and bit 7 reads 0 while active.
+        assert!(blocks(&mut hdma).is_empty());
+        assert_eq!(hdma.hdma5(), 0x02);
+        hdma.enter_hblank();
+        assert_eq!(blocks(&mut hdma), [(0xd000, 0x1000)]);
+        assert_eq!(hdma.hdma5(), 0x01);
+        hdma.enter_hblank();
+        assert_eq!(blocks(&mut hdma), [(0xd010, 0x1010)]);
+        assert_eq!(hdma.hdma5(), 0x00);
+        hdma.enter_hblank();
+        assert_eq!(blocks(&mut hdma), [(0xd020, 0x1020)]);
+        assert_eq!(hdma.hdma5(), 0xff);
+        assert!(!hdma.hblank_active());
+        hdma.enter_hblank();
+        assert!(blocks(&mut hdma).is_empty());
+    }
+
+    #[test]
+    fn cancel_then_general_transfer() {
+        let mut hdma = Hdma::new();
+        set_addresses(&mut hdma, 0xd000, 0x9000);
+        hdma.write_register(4, 0x85);
+        hdma.enter_hblank();
+        assert_eq!(blocks(&mut hdma).len(), 1);
+
+        // Cancelling sets bit 7 and leaves the count of blocks left.
+        hdma.write_register(4, 0x00);
+        assert!(!hdma.hblank_active());
+        assert_eq!(hdma.hdma5(), 0x84);
+        hdma.enter_hblank();
+        assert!(blocks(&mut hdma).is_empty());
+
+        // A general transfer started right away continues from where the last one stopped.
+        hdma.write_register(4, 0x01);
+        assert_eq!(blocks(&mut hdma), [(0xd010, 0x1010), (0xd020, 0x1020)]);
+        assert_eq!(hdma.hdma5(), 0xff);
+    }
+}
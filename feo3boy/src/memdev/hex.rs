@@ -0,0 +1,102 @@
+//! Parsing of hex strings into memory contents, for test fixtures and tools.
+
+use thiserror::Error;
+
+/// Errors that can result from parsing a hex string.
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum HexParseError {
+    /// The string contained something other than a hex digit or whitespace.
+    #[error("Invalid hex digit {digit:?} at position {position}")]
+    InvalidDigit {
+        /// Index of the character within the string.
+        position: usize,
+        /// The invalid character.
+        digit: char,
+    },
+    /// The string had an odd number of hex digits, so the last byte is incomplete.
+    #[error("Expected an even number of hex digits, got {0}")]
+    OddLength(usize),
+    /// The string had the wrong number of bytes for the memory being loaded.
+    #[error("Expected exactly {expected} bytes, got {actual}")]
+    WrongLength {
+        /// Number of bytes required.
+        expected: usize,
+        /// Number of bytes in the string.
+        actual: usize,
+    },
+}
+
+/// Parses a string of hex digits into bytes, two digits per byte with the high digit first.
+/// Whitespace anywhere in the string is ignored, so bytes may be grouped and split across lines.
+pub(super) fn parse_hex(hex: &str) -> Result<Vec<u8>, HexParseError> {
+    let mut bytes = Vec::with_capacity(hex.len() / 2);
+    let mut high = None;
+    let mut digits = 0;
+    for (position, digit) in hex.chars().enumerate() {
+        if digit.is_whitespace() {
+            continue;
+        }
+        let value = digit
+            .to_digit(16)
+            .ok_or(HexParseError::InvalidDigit { position, digit })? as u8;
+        digits += 1;
+        match high.take() {
+            Some(high) => bytes.push(high << 4 | value),
+            None => high = Some(value),
+        }
+    }
+    if high.is_some() {
+        return Err(HexParseError::OddLength(digits));
+    }
+    Ok(bytes)
+}
+
+/// Parses a string of hex digits into an array of exactly `N` bytes. See [`parse_hex`].
+pub(super) fn parse_hex_array<const N: usize>(hex: &str) -> Result<[u8; N], HexParseError> {
+    let bytes = parse_hex(hex)?;
+    if bytes.len() != N {
+        return Err(HexParseError::WrongLength {
+            expected: N,
+            actual: bytes.len(),
+        });
+    }
+    let mut arr = [0; N];
+    arr.copy_from_slice(&bytes);
+    Ok(arr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_hex(""), Ok(vec![]));
+        assert_eq!(parse_hex("00fF3c"), Ok(vec![0x00, 0xff, 0x3c]));
+        assert_eq!(
+            parse_hex("  31 FE\n\tFF af\r\n 2 1 "),
+            Ok(vec![0x31, 0xfe, 0xff, 0xaf, 0x21])
+        );
+        assert_eq!(parse_hex("abc"), Err(HexParseError::OddLength(3)));
+        assert_eq!(parse_hex("a b c"), Err(HexParseError::OddLength(3)));
+        assert_eq!(
+            parse_hex("00 0x"),
+            Err(HexParseError::InvalidDigit {
+                position: 4,
+                digit: 'x'
+            })
+        );
+    }
+
+    #[test]
+    fn parse_array() {
+        assert_eq!(parse_hex_array::<2>("1234"), Ok([0x12, 0x34]));
+        assert_eq!(
+            parse_hex_array::<2>("123456"),
+            Err(HexParseError::WrongLength {
+                expected: 2,
+                actual: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! The CGB infrared communications port register (RP) at 0xFF56.
+
+/// Bit of RP which turns the LED on.
+const LED: u8 = 0x01;
+/// Bit of RP which reads 0 while the receiver sees light, if reading is enabled.
+const RECEIVING: u8 = 0x02;
+/// Bits of RP which must both be set to read the receiver.
+const READ_ENABLE: u8 = 0xc0;
+/// Bits of RP which are writable by the CPU.
+const WRITABLE: u8 = LED | READ_ENABLE;
+
+/// The infrared port. The CPU turns the LED on by setting bit 0, and reads whether the receiver
+/// sees light from bit 1, which is active low. Bit 1 only reflects the receiver while bits 6-7 are
+/// both set, and otherwise reads 1. Unused bits read 1.
+///
+/// Nothing here models the light itself: whatever connects two ports, such as a frontend linking
+/// two emulators, passes [`output`](InfraredPort::output) of one to
+/// [`set_input`](InfraredPort::set_input) of the other.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InfraredPort {
+    /// Writable bits, as last written.
+    control: u8,
+    /// Whether the receiver currently sees light.
+    input: bool,
+}
+
+impl InfraredPort {
+    /// Constructs a new `InfraredPort` with the LED off and no light seen.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns true if the LED is on.
+    pub fn output(&self) -> bool {
+        self.control & LED != 0
+    }
+
+    /// Returns true if the receiver currently sees light, whether or not reading is enabled.
+    pub fn input(&self) -> bool {
+        self.input
+    }
+
+    /// Sets whether the receiver currently sees light.
+    pub fn set_input(&mut self, seen: bool) {
+        self.input = seen;
+    }
+
+    /// Gets the value of the RP register.
+    pub fn register(&self) -> u8 {
+        let receiving = self.control & READ_ENABLE == READ_ENABLE && self.input;
+        let unused = !(WRITABLE | RECEIVING);
+        unused | self.control | if receiving { 0 } else { RECEIVING }
+    }
+
+    /// Writes the RP register. Only the LED and read enable bits are writable.
+    pub fn write_register(&mut self, value: u8) {
+        self.control = value & WRITABLE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_masking() {
+        let mut port = InfraredPort::new();
+        assert_eq!(port.register(), 0x3e);
+        port.write_register(0xff);
+        assert!(port.output());
+        assert_eq!(port.register(), 0xff);
+
+        // Light is only reported while reading is enabled.
+        port.set_input(true);
+        assert_eq!(port.register(), 0xfd);
+        port.write_register(0x41);
+        assert_eq!(port.register(), 0x7f);
+        port.write_register(0xc0);
+        assert!(!port.output());
+        assert_eq!(port.register(), 0xfc);
+        port.set_input(false);
+        assert_eq!(port.register(), 0xfe);
+    }
+}
@@ -0,0 +1,316 @@
+//! Descriptions of every address in the IO block (0xFF00..0xFF80), which determine the name of
+//! each register, which models it exists on, how its unused bits read, where plain registers
+//! are stored, and what they hold at power on and after the boot rom.
+
+use super::HardwareModel;
+
+/// Slot in [`MemMappedIo`](super::MemMappedIo) storage for KEY1 (0xFF4D).
+pub(super) const KEY1: usize = 0;
+/// Slots in [`MemMappedIo`](super::MemMappedIo) storage for the undocumented CGB registers
+/// 0xFF72..0xFF76.
+const UNDOCUMENTED: usize = 1;
+
+/// Slot in [`MemMappedIo`](super::MemMappedIo) storage for KEY0 (0xFF4C).
+pub(super) const KEY0: usize = 5;
+
+/// Slot in [`MemMappedIo`](super::MemMappedIo) storage for OPRI (0xFF6C).
+pub(super) const OPRI: usize = 6;
+
+/// Number of registers held in [`MemMappedIo`](super::MemMappedIo) storage.
+pub(super) const NUM_STORED: usize = 7;
+
+/// Models on which an IO register exists. On other models, it reads 0xFF and ignores writes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum Availability {
+    /// Every model.
+    All,
+    /// GameBoy Color hardware, whether or not CGB features are enabled.
+    CgbHardware,
+    /// GameBoy Color hardware with CGB features enabled.
+    CgbFeatures,
+}
+
+impl Availability {
+    /// Returns true if the register exists on the given model.
+    pub(super) fn includes(self, model: HardwareModel) -> bool {
+        match self {
+            Availability::All => true,
+            Availability::CgbHardware => model.is_cgb_hardware(),
+            Availability::CgbFeatures => model.cgb_features(),
+        }
+    }
+}
+
+/// A register value which differs between DMG and CGB hardware.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct ByModel {
+    /// Value on the DMG and MGB.
+    pub dmg: u8,
+    /// Value on CGB hardware, whether or not CGB features are enabled.
+    pub cgb: u8,
+}
+
+impl ByModel {
+    /// The same value on every model.
+    const fn all(value: u8) -> Self {
+        ByModel {
+            dmg: value,
+            cgb: value,
+        }
+    }
+
+    /// Gets the value for the given model.
+    pub(super) fn get(self, model: HardwareModel) -> u8 {
+        if model.is_cgb_hardware() {
+            self.cgb
+        } else {
+            self.dmg
+        }
+    }
+}
+
+/// Describes one address in the IO block.
+///
+/// Registers are either plain storage kept by [`MemMappedIo`](super::MemMappedIo), which reads
+/// back what was written to the writable bits, or are owned by a device, which handles reads and
+/// writes itself. Addresses with neither are unused.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct IoRegister {
+    /// Name of the register, as given in the Pan Docs. Empty for unused addresses.
+    pub name: &'static str,
+    /// Storage slot of the register, if it is plain storage.
+    pub storage: Option<usize>,
+    /// Bits of a stored register which the CPU can write. The rest keep their value.
+    pub write_mask: u8,
+    /// Bits which always read 1.
+    pub read_mask: u8,
+    /// Models the register exists on.
+    pub availability: Availability,
+    /// Bits which [`MemMappedIo::apply`](super::MemMappedIo::apply) restores by writing them.
+    /// The rest are either read-only or have side effects when written, such as resetting DIV or
+    /// triggering a sound channel, and are written as 0.
+    pub restore_mask: u8,
+    /// Value the register is loaded with at power on, before the boot rom runs.
+    pub power_on: ByModel,
+    /// Value the boot rom leaves in the register, which is loaded when the boot rom is skipped.
+    /// Given as the value the CPU reads, as in the Pan Docs power up sequence.
+    pub post_boot: ByModel,
+}
+
+impl IoRegister {
+    /// An unused address, which reads 0xFF and ignores writes.
+    const UNUSED: IoRegister = IoRegister {
+        name: "",
+        storage: None,
+        write_mask: 0x00,
+        read_mask: 0xff,
+        availability: Availability::All,
+        restore_mask: 0x00,
+        power_on: ByModel::all(0x00),
+        post_boot: ByModel::all(0x00),
+    };
+
+    /// A register owned by a device on every model. The device applies its own masks.
+    const fn device(name: &'static str) -> Self {
+        IoRegister::device_on(name, Availability::All)
+    }
+
+    /// A register owned by a device which only exists on some models.
+    const fn device_on(name: &'static str, availability: Availability) -> Self {
+        IoRegister {
+            name,
+            storage: None,
+            write_mask: 0xff,
+            read_mask: 0x00,
+            availability,
+            restore_mask: 0xff,
+            power_on: ByModel::all(0x00),
+            post_boot: ByModel::all(0x00),
+        }
+    }
+
+    /// A plain register in the given storage slot.
+    const fn stored(
+        name: &'static str,
+        storage: usize,
+        write_mask: u8,
+        read_mask: u8,
+        availability: Availability,
+    ) -> Self {
+        IoRegister {
+            name,
+            storage: Some(storage),
+            write_mask,
+            read_mask,
+            availability,
+            restore_mask: write_mask,
+            power_on: ByModel::all(0x00),
+            post_boot: ByModel::all(0x00),
+        }
+    }
+
+    /// Changes the bits which are restored by writing them.
+    const fn restoring(mut self, restore_mask: u8) -> Self {
+        self.restore_mask = restore_mask;
+        self
+    }
+
+    /// Changes the power-on value of the register.
+    const fn power_on(mut self, value: u8) -> Self {
+        self.power_on = ByModel::all(value);
+        self
+    }
+
+    /// Changes the post-boot value of the register on every model.
+    const fn post_boot(self, value: u8) -> Self {
+        self.post_boot_by_model(value, value)
+    }
+
+    /// Changes the post-boot value of the register, where it differs between DMG and CGB.
+    const fn post_boot_by_model(mut self, dmg: u8, cgb: u8) -> Self {
+        self.post_boot = ByModel { dmg, cgb };
+        self
+    }
+
+    /// Returns true if the address is used by any model.
+    pub(super) fn is_used(&self) -> bool {
+        !self.name.is_empty()
+    }
+}
+
+/// Names of the audio registers from 0xFF10 to 0xFF27, indexed relative to 0xFF10. Unused
+/// addresses are empty.
+const AUDIO_NAMES: [&str; 0x17] = [
+    "NR10", "NR11", "NR12", "NR13", "NR14", "", "NR21", "NR22", "NR23", "NR24", "NR30", "NR31",
+    "NR32", "NR33", "NR34", "", "NR41", "NR42", "NR43", "NR44", "NR50", "NR51", "NR52",
+];
+
+/// Names of the bytes of wave ram, from 0xFF30 to 0xFF40.
+const WAVE_NAMES: [&str; 0x10] = [
+    "WAVE0", "WAVE1", "WAVE2", "WAVE3", "WAVE4", "WAVE5", "WAVE6", "WAVE7", "WAVE8", "WAVE9",
+    "WAVEA", "WAVEB", "WAVEC", "WAVED", "WAVEE", "WAVEF",
+];
+
+/// Names of the LCD registers from 0xFF40 to 0xFF4C, indexed relative to 0xFF40.
+const LCD_NAMES: [&str; 0x0c] = [
+    "LCDC", "STAT", "SCY", "SCX", "LY", "LYC", "DMA", "BGP", "OBP0", "OBP1", "WY", "WX",
+];
+
+/// Names of the VRAM DMA registers from 0xFF51 to 0xFF56.
+const HDMA_NAMES: [&str; 5] = ["HDMA1", "HDMA2", "HDMA3", "HDMA4", "HDMA5"];
+
+/// Values the boot rom leaves in the audio registers from 0xFF10 to 0xFF27, indexed relative to
+/// 0xFF10. The same on every model.
+const AUDIO_POST_BOOT: [u8; 0x17] = [
+    0x80, 0xbf, 0xf3, 0xff, 0xbf, 0xff, 0x3f, 0x00, 0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff,
+    0xff, 0x00, 0x00, 0xbf, 0x77, 0xf3, 0xf1,
+];
+
+/// Description of every address in the IO block, indexed relative to 0xFF00.
+///
+/// Power-on and post-boot values default to 0. Most registers power on cleared, and the values
+/// the boot rom leaves are from the Pan Docs. Where the Pan Docs list a register as uninitialized
+/// or differing between units, such as OBP0 and OBP1 on the DMG, it is left at 0.
+pub(super) const IO_REGISTERS: [IoRegister; 0x80] = {
+    let mut table = [IoRegister::UNUSED; 0x80];
+    // No joypad column is selected at power on.
+    table[0x00] = IoRegister::device("P1").power_on(0x30).post_boot(0xcf);
+    table[0x01] = IoRegister::device("SB");
+    table[0x02] = IoRegister::device("SC").post_boot_by_model(0x7e, 0x7f);
+    // Writing DIV resets it, so its post-boot value can't be loaded.
+    table[0x04] = IoRegister::device("DIV").restoring(0x00);
+    table[0x05] = IoRegister::device("TIMA");
+    table[0x06] = IoRegister::device("TMA");
+    table[0x07] = IoRegister::device("TAC").post_boot(0xf8);
+    // The boot rom leaves VBlank requested.
+    table[0x0f] = IoRegister::device("IF").post_boot(0xe1);
+    // Audio registers and wave ram. The APU applies the masks of the audio registers and handles
+    // the unused addresses in this range, which are devices without names.
+    let mut i = 0x10;
+    while i < 0x40 {
+        let name = if i < 0x27 {
+            AUDIO_NAMES[i - 0x10]
+        } else if i >= 0x30 {
+            WAVE_NAMES[i - 0x30]
+        } else {
+            ""
+        };
+        table[i] = IoRegister::device(name);
+        if i < 0x27 {
+            table[i] = table[i].post_boot(AUDIO_POST_BOOT[i - 0x10]);
+        }
+        i += 1;
+    }
+    // Bit 7 of NRx4 triggers the channel.
+    table[0x14] = table[0x14].restoring(0x7f);
+    table[0x19] = table[0x19].restoring(0x7f);
+    table[0x1e] = table[0x1e].restoring(0x7f);
+    table[0x23] = table[0x23].restoring(0x7f);
+    // LCD registers and OAM DMA.
+    let mut i = 0x40;
+    while i < 0x4c {
+        table[i] = IoRegister::device(LCD_NAMES[i - 0x40]);
+        i += 1;
+    }
+    table[0x40] = table[0x40].post_boot(0x91);
+    // The boot rom hands over in VBlank, with LY == LYC.
+    table[0x41] = table[0x41].post_boot(0x85);
+    // LY is read-only, and writing DMA starts a transfer, so the post-boot value of DMA is
+    // loaded directly rather than written.
+    table[0x44] = table[0x44].restoring(0x00);
+    table[0x46] = table[0x46].restoring(0x00).post_boot_by_model(0xff, 0x00);
+    table[0x47] = table[0x47].post_boot(0xfc);
+    // KEY0: the CGB compatibility mode. MemMappedIo ignores writes once the bios is disabled. Its
+    // initial value depends on the model rather than the table, as does OPRI's.
+    table[0x4c] = IoRegister::stored("KEY0", KEY0, 0xff, 0x00, Availability::CgbHardware);
+    // KEY1: bit 0 requests a speed switch, and bit 7 is the current speed.
+    table[0x4d] =
+        IoRegister::stored("KEY1", KEY1, 0x01, 0x7e, Availability::CgbFeatures).post_boot(0x7e);
+    // The bios disable register is a registered device, so it is handled before the table is
+    // consulted. It is only described here to give it a name, and it can't be turned back on.
+    table[0x50] = IoRegister {
+        name: "BANK",
+        ..IoRegister::UNUSED
+    };
+    // VRAM DMA. HDMA1..HDMA4 are write-only, and writing HDMA5 starts a transfer.
+    let mut i = 0x51;
+    while i < 0x56 {
+        table[i] =
+            IoRegister::device_on(HDMA_NAMES[i - 0x51], Availability::CgbFeatures).restoring(0x00);
+        i += 1;
+    }
+    table[0x55] = table[0x55].post_boot(0xff);
+    table[0x56] = IoRegister::device_on("RP", Availability::CgbFeatures).post_boot(0x3e);
+    // Color palette index and data registers. Writing a data register stores a color and may
+    // increment the index.
+    table[0x68] = IoRegister::device_on("BCPS", Availability::CgbFeatures);
+    table[0x69] = IoRegister::device_on("BCPD", Availability::CgbFeatures).restoring(0x00);
+    table[0x6a] = IoRegister::device_on("OCPS", Availability::CgbFeatures);
+    table[0x6b] = IoRegister::device_on("OCPD", Availability::CgbFeatures).restoring(0x00);
+    // OPRI: bit 0 selects sprite priority by coordinate. Like KEY0, it is locked once the bios is
+    // disabled.
+    table[0x6c] = IoRegister::stored("OPRI", OPRI, 0x01, 0xfe, Availability::CgbHardware);
+    // Undocumented CGB registers. 0xFF72 and 0xFF73 are plain read/write, 0xFF74 is too but is
+    // locked in DMG compatibility mode, and 0xFF75 only stores bits 4-6.
+    let cgb = Availability::CgbHardware;
+    table[0x72] = IoRegister::stored("FF72", UNDOCUMENTED, 0xff, 0x00, cgb);
+    table[0x73] = IoRegister::stored("FF73", UNDOCUMENTED + 1, 0xff, 0x00, cgb);
+    table[0x74] = IoRegister::stored(
+        "FF74",
+        UNDOCUMENTED + 2,
+        0xff,
+        0x00,
+        Availability::CgbFeatures,
+    );
+    table[0x75] = IoRegister::stored("FF75", UNDOCUMENTED + 3, 0x70, 0x8f, cgb).post_boot(0x8f);
+    // PCM12 and PCM34 report the output of the sound channels, and are read-only.
+    table[0x76] = IoRegister::device_on("PCM12", cgb).restoring(0x00);
+    table[0x77] = IoRegister::device_on("PCM34", cgb).restoring(0x00);
+    table
+};
+
+/// Gets the description of the register at the given index relative to 0xFF00, or `None` if
+/// the index is outside the IO block.
+pub(super) fn io_register(index: usize) -> Option<&'static IoRegister> {
+    IO_REGISTERS.get(index)
+}
@@ -0,0 +1,225 @@
+//! Snapshots of the IO registers, for debugger panels and savestates.
+
+use std::fmt;
+
+use crate::interrupts::Interrupt;
+
+use super::LcdcFlags;
+
+/// The value of one IO register when a snapshot was taken.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IoRegisterValue {
+    /// Address of the register, relative to 0xFF00.
+    pub addr: u8,
+    /// Name of the register, as given in the Pan Docs.
+    pub name: &'static str,
+    /// Value the CPU would read from the register.
+    pub value: u8,
+    /// Human readable breakdown of the value, for registers made of several fields.
+    pub decoded: Option<String>,
+}
+
+/// Snapshot of every IO register which exists on the model the snapshot was taken from, in
+/// address order. Taken with [`MemMappedIo::snapshot`](super::MemMappedIo::snapshot) and
+/// restored with [`MemMappedIo::apply`](super::MemMappedIo::apply).
+///
+/// The `Display` impl prints one register per line, which makes a readable panel for debuggers
+/// and test failures.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IoSnapshot {
+    /// Registers in the snapshot, in address order.
+    registers: Vec<IoRegisterValue>,
+}
+
+impl IoSnapshot {
+    /// Constructs a snapshot from the given register values, which must be in address order.
+    pub(super) fn new(registers: Vec<IoRegisterValue>) -> Self {
+        debug_assert!(registers.windows(2).all(|pair| pair[0].addr < pair[1].addr));
+        IoSnapshot { registers }
+    }
+
+    /// Gets the registers in the snapshot, in address order.
+    pub fn registers(&self) -> &[IoRegisterValue] {
+        &self.registers
+    }
+
+    /// Gets the register at the given address relative to 0xFF00, if it is in the snapshot.
+    pub fn get(&self, addr: u8) -> Option<&IoRegisterValue> {
+        self.registers
+            .binary_search_by_key(&addr, |reg| reg.addr)
+            .ok()
+            .map(|i| &self.registers[i])
+    }
+
+    /// Gets the value of the register with the given name, if it is in the snapshot.
+    pub fn value(&self, name: &str) -> Option<u8> {
+        self.registers
+            .iter()
+            .find(|reg| reg.name == name)
+            .map(|reg| reg.value)
+    }
+}
+
+impl fmt::Display for IoSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for reg in self.registers.iter() {
+            write!(
+                f,
+                "{:04X} {:<5} {:02X}",
+                0xff00 + reg.addr as u16,
+                reg.name,
+                reg.value
+            )?;
+            if let Some(ref decoded) = reg.decoded {
+                write!(f, "  {}", decoded)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Describes the fields of the given value of the register at the given address relative to
+/// 0xFF00, if it is made of several fields.
+pub(super) fn decode(addr: u8, value: u8) -> Option<String> {
+    let on = |bit: u8| if value & bit != 0 { "on" } else { "off" };
+    let decoded = match addr {
+        0x00 => {
+            let selected = match !value & 0x30 {
+                0x00 => "none",
+                0x10 => "d-pad",
+                0x20 => "buttons",
+                _ => "both",
+            };
+            format!("select {}, pressed {:04b}", selected, !value & 0x0f)
+        }
+        0x02 => format!(
+            "transfer {}, {} clock",
+            if value & 0x80 != 0 { "active" } else { "idle" },
+            if value & 0x01 != 0 {
+                "internal"
+            } else {
+                "external"
+            }
+        ),
+        0x07 => {
+            let hz = match value & 0x03 {
+                0 => 4096,
+                1 => 262144,
+                2 => 65536,
+                _ => 16384,
+            };
+            format!("timer {}, {} Hz", on(0x04), hz)
+        }
+        0x0f => format!("pending {}", interrupt_list(value)),
+        0x26 => format!("APU {}", on(0x80)),
+        0x40 => {
+            let lcdc = LcdcFlags::from_bits_truncate(value);
+            let map = |flag| if lcdc.contains(flag) { "9C00" } else { "9800" };
+            format!(
+                "LCD {}, window {} map {}, tiles {}, BG {} map {}, OBJ {} {}",
+                on(LcdcFlags::LCD_ENABLE.bits()),
+                on(LcdcFlags::WINDOW_ENABLE.bits()),
+                map(LcdcFlags::WINDOW_TILE_MAP),
+                if lcdc.contains(LcdcFlags::TILE_DATA) {
+                    "8000"
+                } else {
+                    "8800"
+                },
+                on(LcdcFlags::BG_ENABLE.bits()),
+                map(LcdcFlags::BG_TILE_MAP),
+                on(LcdcFlags::OBJ_ENABLE.bits()),
+                if lcdc.contains(LcdcFlags::OBJ_SIZE) {
+                    "8x16"
+                } else {
+                    "8x8"
+                }
+            )
+        }
+        0x41 => {
+            let sources: Vec<_> = [
+                (0x08, "HBlank"),
+                (0x10, "VBlank"),
+                (0x20, "OAM"),
+                (0x40, "LYC"),
+            ]
+            .iter()
+            .filter(|&&(bit, _)| value & bit != 0)
+            .map(|&(_, name)| name)
+            .collect();
+            format!(
+                "mode {}, LY{}LYC, interrupts {}",
+                value & 0x03,
+                if value & 0x04 != 0 { "==" } else { "!=" },
+                if sources.is_empty() {
+                    "none".to_string()
+                } else {
+                    sources.join(" ")
+                }
+            )
+        }
+        0x4d => format!(
+            "{} speed, switch {}",
+            if value & 0x80 != 0 {
+                "double"
+            } else {
+                "normal"
+            },
+            if value & 0x01 != 0 { "armed" } else { "idle" }
+        ),
+        0x56 => format!(
+            "LED {}, read {}, {}",
+            on(0x01),
+            if value & 0xc0 == 0xc0 {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            if value & 0x02 == 0 {
+                "receiving"
+            } else {
+                "no signal"
+            }
+        ),
+        0x68 | 0x6a => format!("index {:02X}, auto-increment {}", value & 0x3f, on(0x80)),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+/// Lists the interrupts set in the given value of IF, or "none".
+fn interrupt_list(value: u8) -> String {
+    let names: Vec<_> = Interrupt::ALL
+        .iter()
+        .filter(|interrupt| value & interrupt.flag().bits() != 0)
+        .map(|interrupt| format!("{:?}", interrupt))
+        .collect();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_registers() {
+        assert_eq!(
+            decode(0x40, 0x91).unwrap(),
+            "LCD on, window off map 9800, tiles 8000, BG on map 9800, OBJ off 8x8"
+        );
+        assert_eq!(
+            decode(0x41, 0xc6).unwrap(),
+            "mode 2, LY==LYC, interrupts LYC"
+        );
+        assert_eq!(decode(0x07, 0xfd).unwrap(), "timer on, 262144 Hz");
+        assert_eq!(decode(0x0f, 0xe5).unwrap(), "pending VBlank Timer");
+        assert_eq!(decode(0x0f, 0xe0).unwrap(), "pending none");
+        assert_eq!(decode(0x00, 0xdb).unwrap(), "select buttons, pressed 0100");
+        assert_eq!(decode(0x68, 0xc5).unwrap(), "index 05, auto-increment on");
+        assert_eq!(decode(0x42, 0x12), None);
+    }
+}
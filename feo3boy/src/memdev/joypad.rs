@@ -0,0 +1,233 @@
+//! The joypad register (P1/JOYP) at 0xFF00.
+
+use bitflags::bitflags;
+
+use super::sgb::{SgbDecoder, SgbPacket};
+
+bitflags! {
+    /// Set of buttons which are currently held down. Bits are set for pressed buttons, the
+    /// opposite of how the hardware reports them.
+    #[derive(Default)]
+    pub struct ButtonState: u8 {
+        /// The A button.
+        const A = 0b0000_0001;
+
+        /// The B button.
+        const B = 0b0000_0010;
+
+        /// The Select button.
+        const SELECT = 0b0000_0100;
+
+        /// The Start button.
+        const START = 0b0000_1000;
+
+        /// Right on the direction pad.
+        const RIGHT = 0b0001_0000;
+
+        /// Left on the direction pad.
+        const LEFT = 0b0010_0000;
+
+        /// Up on the direction pad.
+        const UP = 0b0100_0000;
+
+        /// Down on the direction pad.
+        const DOWN = 0b1000_0000;
+    }
+}
+
+/// Bit of P1 which selects the direction buttons when cleared.
+const SELECT_DIRECTIONS: u8 = 0x10;
+/// Bit of P1 which selects the action buttons when cleared.
+const SELECT_ACTIONS: u8 = 0x20;
+/// Bits of P1 which are writable by the CPU.
+const SELECT_MASK: u8 = SELECT_DIRECTIONS | SELECT_ACTIONS;
+
+/// The joypad register. The CPU selects the action buttons, the direction buttons, or both by
+/// clearing bit 5 or bit 4 respectively, then reads the selected buttons from the low 4 bits,
+/// where a pressed button reads as 0. If both columns are selected, a bit reads 0 if the button
+/// in either column is pressed. If neither is selected, the low bits read 0xF.
+///
+/// A joypad interrupt is requested whenever one of the four input lines goes from high to low.
+/// That happens when a button in a selected column is pressed, but also when a column is
+/// selected while one of its buttons is held, which some games rely on when scanning the keypad.
+/// Switching columns doesn't fire if every line which was low stays low.
+///
+/// Super Game Boy games also send command packets by toggling the select bits. Decoding them is
+/// off by default, and can be turned on with [`set_sgb_packets`](Joypad::set_sgb_packets).
+#[derive(Clone, Debug)]
+pub struct Joypad {
+    /// Column select bits, as last written to bits 4-5.
+    select: u8,
+    /// Buttons currently held down.
+    buttons: ButtonState,
+    /// Decoder for Super Game Boy packets, if enabled.
+    sgb: Option<SgbDecoder>,
+}
+
+impl Joypad {
+    /// Constructs a new `Joypad` with no buttons pressed and no column selected.
+    pub fn new() -> Self {
+        Joypad {
+            select: SELECT_MASK,
+            buttons: ButtonState::empty(),
+            sgb: None,
+        }
+    }
+
+    /// Enables or disables decoding Super Game Boy packets. Disabling drops any packets which
+    /// have not been taken.
+    pub fn set_sgb_packets(&mut self, enabled: bool) {
+        if enabled != self.sgb.is_some() {
+            self.sgb = if enabled {
+                Some(Default::default())
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Returns true if Super Game Boy packets are being decoded.
+    pub fn sgb_packets_enabled(&self) -> bool {
+        self.sgb.is_some()
+    }
+
+    /// Returns and clears the Super Game Boy packets received since the last call, oldest first.
+    /// Always empty unless decoding is enabled.
+    pub fn take_sgb_packets(&mut self) -> Vec<SgbPacket> {
+        self.sgb
+            .as_mut()
+            .map_or_else(Vec::new, SgbDecoder::take_packets)
+    }
+
+    /// Gets the buttons currently held down.
+    pub fn buttons(&self) -> ButtonState {
+        self.buttons
+    }
+
+    /// Sets the buttons currently held down. Returns true if this pressed a button in a selected
+    /// column, which requests a joypad interrupt.
+    pub fn set_buttons(&mut self, buttons: ButtonState) -> bool {
+        let before = self.lines();
+        self.buttons = buttons;
+        self.pressed_since(before)
+    }
+
+    /// Returns true if a button is held down in a selected column, which pulls one of the input
+    /// lines low.
+    pub fn held(&self) -> bool {
+        self.lines() != 0xf
+    }
+
+    /// Gets the value the CPU reads from the register.
+    pub fn register(&self) -> u8 {
+        // Bits 6 and 7 are unused and always read 1.
+        0xc0 | self.select | self.lines()
+    }
+
+    /// Handles a CPU write to the register, which sets the column select bits. Returns true if
+    /// this selected a column with a button held down, which also requests a joypad interrupt.
+    pub fn write_register(&mut self, value: u8) -> bool {
+        let before = self.lines();
+        self.select = value & SELECT_MASK;
+        if let Some(ref mut sgb) = self.sgb {
+            sgb.write_select(self.select);
+        }
+        self.pressed_since(before)
+    }
+
+    /// Returns true if any of the input lines went low since they read `before`.
+    fn pressed_since(&self, before: u8) -> bool {
+        before & !self.lines() != 0
+    }
+
+    /// Computes the active-low input lines in the low 4 bits.
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & SELECT_ACTIONS == 0 {
+            pressed |= self.buttons.bits() & 0xf;
+        }
+        if self.select & SELECT_DIRECTIONS == 0 {
+            pressed |= self.buttons.bits() >> 4;
+        }
+        !pressed & 0xf
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_selection() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(ButtonState::A | ButtonState::START | ButtonState::DOWN);
+
+        // Neither column selected: nothing reads as pressed.
+        assert_eq!(joypad.register(), 0xff);
+
+        // Action buttons, selected by clearing bit 5. Pressed buttons read 0.
+        joypad.write_register(0x10);
+        assert_eq!(joypad.register(), 0xd0 | 0b0110);
+
+        // Direction buttons, selected by clearing bit 4.
+        joypad.write_register(0x20);
+        assert_eq!(joypad.register(), 0xe0 | 0b0111);
+
+        // Both columns: a line reads 0 if the button in either column is pressed.
+        joypad.write_register(0x00);
+        assert_eq!(joypad.register(), 0xc0 | 0b0110 & 0b0111);
+
+        // Only bits 4-5 are writable, and bits 6-7 always read 1.
+        joypad.write_register(0xcf);
+        assert_eq!(joypad.register(), 0xc0 | 0b0110 & 0b0111);
+    }
+
+    #[test]
+    fn sgb_packets_alongside_input() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(ButtonState::A);
+        joypad.write_register(0x00);
+        assert!(joypad.take_sgb_packets().is_empty());
+
+        joypad.set_sgb_packets(true);
+        joypad.write_register(0x00);
+        joypad.write_register(0x30);
+        // MLT_REQ with one packet, selecting two players: 0x89, 0x01, then zeros.
+        let bits = [0x89u8, 0x01]
+            .iter()
+            .chain([0; 14].iter())
+            .flat_map(|&byte| (0..8).map(move |i| byte >> i & 1))
+            .chain(Some(0));
+        for bit in bits {
+            joypad.write_register(if bit == 0 { 0x20 } else { 0x10 });
+            joypad.write_register(0x30);
+        }
+        // Input still reads normally once the packet is sent.
+        joypad.write_register(0x10);
+        assert_eq!(joypad.register(), 0xd0 | 0b1110);
+        let packets = joypad.take_sgb_packets();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].code(), 0x11);
+        assert_eq!(packets[0].data[0], 0x01);
+    }
+
+    #[test]
+    fn presses_request_interrupts() {
+        let mut joypad = Joypad::new();
+        // Pressing a button in a column which isn't selected doesn't request an interrupt.
+        assert!(!joypad.set_buttons(ButtonState::B));
+        // Selecting the column while it is held does.
+        assert!(joypad.write_register(0x10));
+        assert!(!joypad.write_register(0x10));
+        // Pressing another selected button does, but releasing one doesn't.
+        assert!(joypad.set_buttons(ButtonState::B | ButtonState::SELECT));
+        assert!(!joypad.set_buttons(ButtonState::SELECT));
+        assert!(!joypad.set_buttons(ButtonState::SELECT | ButtonState::UP));
+    }
+}
@@ -0,0 +1,408 @@
+//! The LCD registers at 0xFF40..0xFF46 and 0xFF47..0xFF4C.
+
+use bitflags::bitflags;
+
+use super::DmgPalette;
+
+bitflags! {
+    /// The LCD control register (LCDC).
+    #[derive(Default)]
+    pub struct LcdcFlags: u8 {
+        /// Background and window display. On CGB, this instead gives sprites priority over the
+        /// background when cleared.
+        const BG_ENABLE = 0b0000_0001;
+
+        /// Sprite display.
+        const OBJ_ENABLE = 0b0000_0010;
+
+        /// Sprite size: 8x16 if set, 8x8 otherwise.
+        const OBJ_SIZE = 0b0000_0100;
+
+        /// Background tile map: 0x9C00 if set, 0x9800 otherwise.
+        const BG_TILE_MAP = 0b0000_1000;
+
+        /// Background and window tile data: 0x8000 if set, 0x8800 otherwise.
+        const TILE_DATA = 0b0001_0000;
+
+        /// Window display.
+        const WINDOW_ENABLE = 0b0010_0000;
+
+        /// Window tile map: 0x9C00 if set, 0x9800 otherwise.
+        const WINDOW_TILE_MAP = 0b0100_0000;
+
+        /// LCD and PPU enable.
+        const LCD_ENABLE = 0b1000_0000;
+    }
+}
+
+/// The mode the PPU is in, as reported in the low 2 bits of STAT.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum LcdMode {
+    /// Horizontal blank, at the end of each line.
+    #[default]
+    HBlank = 0,
+    /// Vertical blank, lines 144-153.
+    VBlank = 1,
+    /// Searching OAM for the sprites on the line.
+    OamScan = 2,
+    /// Transferring pixels to the LCD.
+    Drawing = 3,
+}
+
+impl LcdMode {
+    /// Gets the mode from the low 2 bits of STAT.
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & STAT_MODE {
+            0 => LcdMode::HBlank,
+            1 => LcdMode::VBlank,
+            2 => LcdMode::OamScan,
+            3 => LcdMode::Drawing,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// STAT bits which report the PPU mode.
+const STAT_MODE: u8 = 0x03;
+/// STAT bit which is set when LY == LYC.
+pub(super) const STAT_COINCIDENCE: u8 = 0x04;
+/// STAT bit which enables the STAT interrupt in HBlank.
+const STAT_HBLANK_INTERRUPT: u8 = 0x08;
+/// STAT bit which enables the STAT interrupt in VBlank.
+const STAT_VBLANK_INTERRUPT: u8 = 0x10;
+/// STAT bit which enables the STAT interrupt during the OAM scan.
+const STAT_OAM_INTERRUPT: u8 = 0x20;
+/// STAT bit which enables the STAT interrupt when LY == LYC.
+pub(super) const STAT_COINCIDENCE_INTERRUPT: u8 = 0x40;
+/// STAT bits which are writable by the CPU.
+const STAT_WRITABLE: u8 = 0x78;
+
+/// The LCD registers, apart from OAM DMA.
+///
+/// These are what the CPU sees of the PPU. The mode and LY are read-only to the CPU, so whatever
+/// drives the display reports them with [`set_mode`](Lcd::set_mode) and [`set_ly`](Lcd::set_ly).
+/// Even without a real PPU, many games busy-wait on LY, so an embedding loop can step LY to get
+/// them moving.
+///
+/// The STAT interrupt has several sources: LY == LYC and each of the HBlank, VBlank, and OAM scan
+/// modes, each enabled by a bit of STAT. The hardware ORs the enabled sources into a single
+/// line, and only requests an interrupt when that line rises. So when one source becomes true
+/// while another enabled source is already true, no new interrupt is requested. This is known
+/// as STAT blocking.
+#[derive(Copy, Clone, Debug)]
+pub struct Lcd {
+    /// LCD control (LCDC).
+    lcdc: LcdcFlags,
+    /// LCD status (STAT), except for the unused bit 7.
+    stat: u8,
+    /// Background scroll Y (SCY).
+    scy: u8,
+    /// Background scroll X (SCX).
+    scx: u8,
+    /// Current line (LY).
+    ly: u8,
+    /// Line compare (LYC).
+    lyc: u8,
+    /// Window Y position (WY).
+    wy: u8,
+    /// Window X position plus 7 (WX).
+    wx: u8,
+    /// Background palette (BGP).
+    bgp: DmgPalette,
+    /// Sprite palettes (OBP0 and OBP1).
+    obp: [DmgPalette; 2],
+    /// Whether a CPU write to LY resets it to 0, rather than being ignored.
+    ly_write_resets: bool,
+    /// State of the STAT interrupt line, the OR of all enabled STAT interrupt sources.
+    stat_line: bool,
+}
+
+impl Lcd {
+    /// Constructs a new `Lcd` with all registers cleared.
+    pub fn new() -> Self {
+        Lcd {
+            lcdc: LcdcFlags::empty(),
+            // LY and LYC both start at 0, so they start out coincident.
+            stat: STAT_COINCIDENCE,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            wy: 0,
+            wx: 0,
+            bgp: DmgPalette(0),
+            obp: [DmgPalette(0); 2],
+            ly_write_resets: false,
+            stat_line: false,
+        }
+    }
+
+    /// Gets the LCD control register.
+    pub fn lcdc(&self) -> LcdcFlags {
+        self.lcdc
+    }
+
+    /// Gets the PPU mode reported in STAT.
+    pub fn mode(&self) -> LcdMode {
+        LcdMode::from_bits(self.stat)
+    }
+
+    /// Sets the PPU mode reported in STAT. Returns true if this requests a STAT interrupt.
+    pub fn set_mode(&mut self, mode: LcdMode) -> bool {
+        self.stat = (self.stat & !STAT_MODE) | mode as u8;
+        self.update_stat_line()
+    }
+
+    /// Gets the background scroll position as (SCX, SCY).
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scx, self.scy)
+    }
+
+    /// Gets the window position as (WX, WY). Note that WX is offset by 7, so a WX of 7 puts the
+    /// window at the left edge of the screen.
+    pub fn window(&self) -> (u8, u8) {
+        (self.wx, self.wy)
+    }
+
+    /// Gets the background palette (BGP).
+    pub fn bg_palette(&self) -> DmgPalette {
+        self.bgp
+    }
+
+    /// Gets the sprite palette (OBP0 or OBP1) with the given index, which must be 0 or 1.
+    pub fn obj_palette(&self, index: usize) -> DmgPalette {
+        self.obp[index]
+    }
+
+    /// Gets the current line (LY).
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    /// Sets the current line (LY). Returns true if this requests a STAT interrupt.
+    pub fn set_ly(&mut self, ly: u8) -> bool {
+        self.ly = ly;
+        self.update_coincidence()
+    }
+
+    /// Gets the line compare value (LYC).
+    pub fn lyc(&self) -> u8 {
+        self.lyc
+    }
+
+    /// Returns true if LY currently equals LYC, as reported in bit 2 of STAT.
+    pub fn lyc_coincidence(&self) -> bool {
+        self.stat & STAT_COINCIDENCE != 0
+    }
+
+    /// Returns true if CPU writes to LY reset it to 0. Otherwise they are ignored.
+    pub fn ly_write_resets(&self) -> bool {
+        self.ly_write_resets
+    }
+
+    /// Sets whether CPU writes to LY reset it to 0, which differs between models and revisions.
+    pub fn set_ly_write_resets(&mut self, resets: bool) {
+        self.ly_write_resets = resets;
+    }
+
+    /// Gets the value the CPU reads from the register at the given offset from 0xFF40. Offset 6
+    /// (OAM DMA) isn't part of this block.
+    pub fn register(&self, offset: u8) -> u8 {
+        match offset {
+            0x0 => self.lcdc.bits(),
+            // Bit 7 of STAT is unused and always reads 1.
+            0x1 => self.stat | 0x80,
+            0x2 => self.scy,
+            0x3 => self.scx,
+            0x4 => self.ly,
+            0x5 => self.lyc,
+            0x7 => self.bgp.0,
+            0x8 => self.obp[0].0,
+            0x9 => self.obp[1].0,
+            0xa => self.wy,
+            0xb => self.wx,
+            _ => panic!("Offset {:#04X} is not an LCD register", offset),
+        }
+    }
+
+    /// Handles a CPU write to the register at the given offset from 0xFF40. Returns true if this
+    /// requests a STAT interrupt.
+    pub fn write_register(&mut self, offset: u8, value: u8) -> bool {
+        match offset {
+            0x0 => self.lcdc = LcdcFlags::from_bits_truncate(value),
+            0x1 => {
+                self.stat = (self.stat & !STAT_WRITABLE) | (value & STAT_WRITABLE);
+                return self.update_stat_line();
+            }
+            0x2 => self.scy = value,
+            0x3 => self.scx = value,
+            0x4 => {
+                if self.ly_write_resets {
+                    return self.set_ly(0);
+                }
+            }
+            0x5 => {
+                self.lyc = value;
+                return self.update_coincidence();
+            }
+            0x7 => self.bgp = DmgPalette(value),
+            0x8 => self.obp[0] = DmgPalette(value),
+            0x9 => self.obp[1] = DmgPalette(value),
+            0xa => self.wy = value,
+            0xb => self.wx = value,
+            _ => panic!("Offset {:#04X} is not an LCD register", offset),
+        }
+        false
+    }
+
+    /// Recomputes the LY == LYC coincidence bit of STAT after a change to LY or LYC. Returns
+    /// true if this requests a STAT interrupt.
+    fn update_coincidence(&mut self) -> bool {
+        if self.ly == self.lyc {
+            self.stat |= STAT_COINCIDENCE;
+        } else {
+            self.stat &= !STAT_COINCIDENCE;
+        }
+        self.update_stat_line()
+    }
+
+    /// Recomputes the STAT interrupt line after a change to any of its sources. Returns true if
+    /// the line rose, which requests a STAT interrupt.
+    fn update_stat_line(&mut self) -> bool {
+        let mode_source = match self.mode() {
+            LcdMode::HBlank => STAT_HBLANK_INTERRUPT,
+            LcdMode::VBlank => STAT_VBLANK_INTERRUPT,
+            LcdMode::OamScan => STAT_OAM_INTERRUPT,
+            LcdMode::Drawing => 0,
+        };
+        let coincidence_source = if self.lyc_coincidence() {
+            STAT_COINCIDENCE_INTERRUPT
+        } else {
+            0
+        };
+        let line = self.stat & (mode_source | coincidence_source) != 0;
+        let rose = line && !self.stat_line;
+        self.stat_line = line;
+        rose
+    }
+}
+
+impl Default for Lcd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_read_only_bits() {
+        let mut lcd = Lcd::new();
+        lcd.set_mode(LcdMode::OamScan);
+        lcd.write_register(0x1, 0x00);
+        // Mode and coincidence are kept, and bit 7 reads 1.
+        assert_eq!(lcd.register(0x1), 0x86);
+        lcd.write_register(0x1, 0xff);
+        assert_eq!(lcd.register(0x1), 0xfe);
+        assert_eq!(lcd.mode(), LcdMode::OamScan);
+        lcd.set_mode(LcdMode::VBlank);
+        assert_eq!(lcd.register(0x1), 0xfd);
+
+        // LY ignores writes unless configured to reset.
+        lcd.set_ly(42);
+        lcd.write_register(0x4, 0x12);
+        assert_eq!(lcd.register(0x4), 42);
+        lcd.set_ly_write_resets(true);
+        lcd.write_register(0x4, 0x12);
+        assert_eq!(lcd.register(0x4), 0);
+    }
+
+    #[test]
+    fn plain_registers() {
+        let mut lcd = Lcd::new();
+        for &(offset, value) in [
+            (0x2, 0x12),
+            (0x3, 0x34),
+            (0x5, 0x56),
+            (0x7, 0xe4),
+            (0x8, 0x1b),
+            (0x9, 0xd2),
+            (0xa, 0x78),
+            (0xb, 0x9a),
+        ]
+        .iter()
+        {
+            lcd.write_register(offset, value);
+            assert_eq!(lcd.register(offset), value);
+        }
+        assert_eq!(lcd.scroll(), (0x34, 0x12));
+        assert_eq!(lcd.window(), (0x9a, 0x78));
+        assert_eq!(lcd.lyc(), 0x56);
+        assert_eq!(lcd.bg_palette(), DmgPalette(0xe4));
+        assert_eq!(lcd.obj_palette(0), DmgPalette(0x1b));
+        assert_eq!(lcd.obj_palette(1), DmgPalette(0xd2));
+    }
+
+    #[test]
+    fn lyc_sweep() {
+        for &enabled in [false, true].iter() {
+            let mut lcd = Lcd::new();
+            lcd.set_mode(LcdMode::Drawing);
+            lcd.write_register(0x1, if enabled { 0x40 } else { 0x00 });
+            lcd.write_register(0x5, 100);
+            let mut count = 0;
+            for _ in 0..2 {
+                for ly in 0..=153 {
+                    if lcd.set_ly(ly) {
+                        count += 1;
+                        assert_eq!(ly, 100);
+                    }
+                    assert_eq!(lcd.lyc_coincidence(), ly == 100);
+                }
+            }
+            assert_eq!(count, if enabled { 2 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn stat_blocking() {
+        let mut lcd = Lcd::new();
+        lcd.set_mode(LcdMode::Drawing);
+        lcd.set_ly(1);
+        // Coincidence and HBlank interrupts enabled.
+        lcd.write_register(0x1, 0x48);
+        lcd.write_register(0x5, 2);
+        assert!(lcd.set_mode(LcdMode::HBlank));
+        // LY == LYC while the line is already high from HBlank doesn't fire again.
+        assert!(!lcd.set_ly(2));
+        // Leaving HBlank keeps the line high because of the coincidence.
+        assert!(!lcd.set_mode(LcdMode::OamScan));
+        assert!(!lcd.set_mode(LcdMode::Drawing));
+        // Once every source is false the line drops, so the next source fires.
+        assert!(!lcd.set_ly(3));
+        assert!(lcd.set_mode(LcdMode::HBlank));
+
+        // Enabling a source which is already true raises the line.
+        let mut lcd = Lcd::new();
+        lcd.set_mode(LcdMode::VBlank);
+        assert!(lcd.write_register(0x1, 0x10));
+        assert!(!lcd.write_register(0x1, 0x10));
+    }
+
+    #[test]
+    fn lcdc_flags() {
+        let mut lcd = Lcd::new();
+        lcd.write_register(0x0, 0x91);
+        assert_eq!(
+            lcd.lcdc(),
+            LcdcFlags::LCD_ENABLE | LcdcFlags::TILE_DATA | LcdcFlags::BG_ENABLE
+        );
+        for value in 0..=0xff {
+            lcd.write_register(0x0, value);
+            assert_eq!(lcd.register(0x0), value);
+            assert_eq!(lcd.lcdc().bits(), value);
+        }
+    }
+}
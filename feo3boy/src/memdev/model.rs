@@ -0,0 +1,74 @@
+//! Selection of which GameBoy hardware is being emulated.
+
+use super::{CartridgeHeader, CgbSupport};
+
+/// The GameBoy hardware model being emulated. This is the single source of truth for behavior
+/// which differs between models.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum HardwareModel {
+    /// The original GameBoy.
+    #[default]
+    Dmg,
+    /// The GameBoy Pocket. Mostly identical to the DMG.
+    Mgb,
+    /// The GameBoy Color, running a game with CGB support.
+    Cgb,
+    /// The GameBoy Color, running a DMG-only game in compatibility mode. CGB-only features such
+    /// as banking and color palettes are unavailable to the game, but behavior which depends on
+    /// the hardware itself matches the CGB.
+    CgbDmgMode,
+}
+
+/// Whether the PPU and palettes of a GameBoy Color work as on a CGB, or emulate a DMG for a game
+/// without CGB support. The boot rom selects the mode by writing KEY0 (0xFF4C), and it can't be
+/// changed once the boot rom is disabled.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CompatMode {
+    /// Full CGB behavior.
+    Cgb,
+    /// DMG compatibility mode.
+    DmgOnCgb,
+}
+
+impl CompatMode {
+    /// Gets the value the boot rom writes to KEY0 to select this mode.
+    pub(super) fn key0(self) -> u8 {
+        match self {
+            CompatMode::Cgb => 0x80,
+            CompatMode::DmgOnCgb => 0x04,
+        }
+    }
+}
+
+impl HardwareModel {
+    /// Returns true if the hardware is a GameBoy Color, whether or not CGB features are enabled.
+    pub fn is_cgb_hardware(self) -> bool {
+        matches!(self, HardwareModel::Cgb | HardwareModel::CgbDmgMode)
+    }
+
+    /// Returns true if CGB-only features (such as the VRAM and WRAM bank registers, KEY1, HDMA,
+    /// and the color palette registers) are available. On other models, those registers read
+    /// 0xFF and ignore writes.
+    pub fn cgb_features(self) -> bool {
+        self == HardwareModel::Cgb
+    }
+
+    /// Gets the compatibility mode the CGB boot rom selects in KEY0 (0xFF4C) for this model, which
+    /// is also the mode the machine starts in if there is no boot rom to select it. Models which
+    /// aren't CGB hardware always behave as [`CompatMode::DmgOnCgb`] would.
+    pub fn power_on_compat_mode(self) -> CompatMode {
+        match self {
+            HardwareModel::Cgb => CompatMode::Cgb,
+            _ => CompatMode::DmgOnCgb,
+        }
+    }
+
+    /// Suggests the model to run a cartridge on based on its CGB flag: games which support the
+    /// CGB run on a CGB, and everything else runs on a DMG.
+    pub fn preferred_for(header: &CartridgeHeader) -> Self {
+        match header.cgb_support() {
+            CgbSupport::CgbEnhanced | CgbSupport::CgbOnly => HardwareModel::Cgb,
+            CgbSupport::DmgOnly => HardwareModel::Dmg,
+        }
+    }
+}
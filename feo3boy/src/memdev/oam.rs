@@ -0,0 +1,349 @@
+//! Sprite attribute memory (OAM).
+
+use std::fmt::Write;
+
+use bitflags::bitflags;
+
+use super::{Addr, MemDevice};
+
+/// Size of OAM in bytes (4 bytes per sprite).
+const OAM_SIZE: usize = Oam::NUM_SPRITES * 4;
+
+bitflags! {
+    /// Flags in the attribute byte of a sprite.
+    #[derive(Default)]
+    pub struct SpriteFlags: u8 {
+        /// CGB only: which of the 8 object palettes to use.
+        const CGB_PALETTE = 0b0000_0111;
+
+        /// CGB only: which VRAM bank to take the tile from.
+        const CGB_VRAM_BANK = 0b0000_1000;
+
+        /// DMG only: use OBP1 instead of OBP0.
+        const DMG_PALETTE = 0b0001_0000;
+
+        /// Mirror the sprite horizontally.
+        const X_FLIP = 0b0010_0000;
+
+        /// Mirror the sprite vertically.
+        const Y_FLIP = 0b0100_0000;
+
+        /// Draw the sprite behind background colors 1-3.
+        const BEHIND_BG = 0b1000_0000;
+    }
+}
+
+/// How overlapping sprites are prioritized, as selected by the boot rom in OPRI (0xFF6C).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ObjPriorityMode {
+    /// The sprite which comes first in OAM is drawn on top, as on the CGB.
+    OamOrder,
+    /// The sprite with the smaller X coordinate is drawn on top, with ties going to the one
+    /// which comes first in OAM, as on the DMG.
+    Coordinate,
+}
+
+/// Another name for [`ObjPriorityMode`], matching
+/// [`MemMappedIo::object_priority_mode`](super::MemMappedIo::object_priority_mode).
+pub type ObjectPriority = ObjPriorityMode;
+
+/// A single entry in OAM.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Sprite {
+    /// Y position of the sprite, plus 16. A sprite at 0 is entirely above the screen.
+    pub y: u8,
+    /// X position of the sprite, plus 8. A sprite at 0 is entirely left of the screen.
+    pub x: u8,
+    /// Tile index of the sprite. For 8x16 sprites, the low bit is ignored.
+    pub tile: u8,
+    /// Sprite attributes.
+    pub flags: SpriteFlags,
+}
+
+impl Sprite {
+    /// Returns true if this sprite covers the given screen line, for sprites that are 16 pixels
+    /// tall if `tall` is true, and 8 pixels tall otherwise.
+    pub fn covers_line(&self, line: u8, tall: bool) -> bool {
+        let height = if tall { 16 } else { 8 };
+        // Screen coordinates are offset by 16 in OAM, so shift the line rather than the sprite
+        // to avoid underflow.
+        let line = line as u16 + 16;
+        let top = self.y as u16;
+        top <= line && line < top + height
+    }
+
+    /// Returns true if the sprite is entirely above or below the screen, even when it is 16
+    /// pixels tall.
+    pub fn off_screen(&self) -> bool {
+        self.y == 0 || self.y >= 160
+    }
+}
+
+/// Sprite attribute memory. Holds the 40 sprites, mapped to 0xFE00..0xFEA0.
+#[derive(Clone, Debug)]
+pub struct Oam {
+    bytes: [u8; OAM_SIZE],
+}
+
+impl Oam {
+    /// Number of sprites in OAM.
+    pub const NUM_SPRITES: usize = 40;
+
+    /// Maximum number of sprites the hardware will draw on a single line.
+    pub const MAX_SPRITES_PER_LINE: usize = 10;
+
+    /// Construct a new `Oam` with all memory set to 0.
+    pub fn new() -> Self {
+        Oam {
+            bytes: [0; OAM_SIZE],
+        }
+    }
+
+    /// Gets the sprite at the given index. Panics if the index is not less than
+    /// [`Oam::NUM_SPRITES`].
+    pub fn sprite(&self, index: usize) -> Sprite {
+        let entry = &self.bytes[index * 4..index * 4 + 4];
+        Sprite {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            flags: SpriteFlags::from_bits_truncate(entry[3]),
+        }
+    }
+
+    /// Iterates over all sprites along with their indexes, in OAM order.
+    pub fn sprites(&self) -> impl Iterator<Item = (usize, Sprite)> + '_ {
+        (0..Self::NUM_SPRITES).map(move |index| (index, self.sprite(index)))
+    }
+
+    /// Iterates over the sprites which the hardware would draw on the given line, along with
+    /// their indexes. `tall` selects 8x16 sprites, as set by LCDC bit 2.
+    ///
+    /// Like the hardware, this selects at most the first [`Oam::MAX_SPRITES_PER_LINE`] sprites in
+    /// OAM order which cover the line, regardless of their X position. The selected sprites are
+    /// yielded in DMG priority order: lowest X coordinate first, with ties broken by OAM index.
+    /// Use [`sprites_on_line_with_priority`](Oam::sprites_on_line_with_priority) to follow the
+    /// priority mode selected in OPRI instead.
+    pub fn sprites_on_line(&self, line: u8, tall: bool) -> impl Iterator<Item = (usize, Sprite)> {
+        self.sprites_on_line_with_priority(line, tall, ObjectPriority::Coordinate)
+    }
+
+    /// Iterates over the sprites which the hardware would draw on the given line, like
+    /// [`sprites_on_line`](Oam::sprites_on_line), but yields the selected sprites highest
+    /// priority first according to `priority`, which normally comes from
+    /// [`MemMappedIo::object_priority_mode`](super::MemMappedIo::object_priority_mode).
+    pub fn sprites_on_line_with_priority(
+        &self,
+        line: u8,
+        tall: bool,
+        priority: ObjectPriority,
+    ) -> impl Iterator<Item = (usize, Sprite)> {
+        let mut selected: Vec<_> = self
+            .sprites()
+            .filter(|(_, sprite)| sprite.covers_line(line, tall))
+            .take(Self::MAX_SPRITES_PER_LINE)
+            .collect();
+        if priority == ObjectPriority::Coordinate {
+            // Sorting is stable, so equal X coordinates stay in OAM order.
+            selected.sort_by_key(|(_, sprite)| sprite.x);
+        }
+        selected.into_iter()
+    }
+
+    /// Formats the sprites as a table, one per line, for pasting into bug reports. Positions
+    /// are the raw OAM values, so the screen position is Y - 16 and X - 8. Both the DMG and CGB
+    /// palette fields are shown, since OAM doesn't know which model is running. Sprites which
+    /// are entirely above or below the screen are marked "off-screen".
+    pub fn debug_dump(&self) -> String {
+        let mut dump = String::from("  #   Y   X  Tile  Priority  Flip  DMG   CGB\n");
+        for (index, sprite) in self.sprites() {
+            let flags = sprite.flags;
+            let priority = if flags.contains(SpriteFlags::BEHIND_BG) {
+                "behind"
+            } else {
+                "above"
+            };
+            let flip = match (
+                flags.contains(SpriteFlags::X_FLIP),
+                flags.contains(SpriteFlags::Y_FLIP),
+            ) {
+                (false, false) => "--",
+                (true, false) => "X-",
+                (false, true) => "-Y",
+                (true, true) => "XY",
+            };
+            let dmg_palette = if flags.contains(SpriteFlags::DMG_PALETTE) {
+                "OBP1"
+            } else {
+                "OBP0"
+            };
+            // Writing to a String can't fail.
+            let _ = write!(
+                dump,
+                "{:>3} {:>3} {:>3}    {:02X}  {:<8}  {}    {}  pal {} bank {}",
+                index,
+                sprite.y,
+                sprite.x,
+                sprite.tile,
+                priority,
+                flip,
+                dmg_palette,
+                (flags & SpriteFlags::CGB_PALETTE).bits(),
+                flags.contains(SpriteFlags::CGB_VRAM_BANK) as u8,
+            );
+            if sprite.off_screen() {
+                dump.push_str("  off-screen");
+            }
+            dump.push('\n');
+        }
+        dump
+    }
+}
+
+impl Default for Oam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemDevice for Oam {
+    fn size(&self) -> usize {
+        OAM_SIZE
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match self.bytes.get(addr.index()) {
+            Some(val) => *val,
+            None => panic!("Address {} out of range for OAM", addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match self.bytes.get_mut(addr.index()) {
+            Some(val) => *val = value,
+            None => panic!("Address {} out of range for OAM", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a sprite into OAM at the given index.
+    fn set_sprite(oam: &mut Oam, index: usize, sprite: Sprite) {
+        let base = index as u16 * 4;
+        oam.write(base.into(), sprite.y);
+        oam.write((base + 1).into(), sprite.x);
+        oam.write((base + 2).into(), sprite.tile);
+        oam.write((base + 3).into(), sprite.flags.bits());
+    }
+
+    fn at(y: u8, x: u8) -> Sprite {
+        Sprite {
+            y,
+            x,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sprite_height() {
+        let mut oam = Oam::new();
+        // Covers lines 0..8, or 0..16 when tall.
+        set_sprite(&mut oam, 0, at(16, 8));
+        // Partially above the screen, covering lines 0..4 or 0..12.
+        set_sprite(&mut oam, 1, at(12, 8));
+
+        let indexes = |line, tall| {
+            oam.sprites_on_line(line, tall)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(indexes(0, false), [0, 1]);
+        assert_eq!(indexes(4, false), [0]);
+        assert_eq!(indexes(8, false), []);
+        assert_eq!(indexes(8, true), [0, 1]);
+        assert_eq!(indexes(12, true), [0]);
+        assert_eq!(indexes(16, true), []);
+    }
+
+    #[test]
+    fn sprites_on_line_capped_and_ordered() {
+        let mut oam = Oam::new();
+        // 12 sprites on line 0, with X decreasing by index. Sprites 10 and 11 have the lowest X,
+        // but are beyond the cap.
+        for i in 0..12 {
+            set_sprite(&mut oam, i, at(16, 100 - i as u8));
+        }
+        // Equal X coordinates keep OAM order.
+        set_sprite(&mut oam, 3, at(16, 99));
+        let sprites: Vec<_> = oam.sprites_on_line(0, false).collect();
+        assert_eq!(sprites.len(), Oam::MAX_SPRITES_PER_LINE);
+        let indexes: Vec<_> = sprites.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, [9, 8, 7, 6, 5, 4, 2, 1, 3, 0]);
+        // By OAM order, the same sprites are selected but not reordered.
+        let indexes: Vec<_> = oam
+            .sprites_on_line_with_priority(0, false, ObjectPriority::OamOrder)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(indexes, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let indexes: Vec<_> = oam
+            .sprites_on_line_with_priority(0, false, ObjectPriority::Coordinate)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(indexes, [9, 8, 7, 6, 5, 4, 2, 1, 3, 0]);
+
+        // Sprites not on the line don't count towards the cap.
+        for i in 0..5 {
+            set_sprite(&mut oam, i, at(40, 0));
+        }
+        let indexes: Vec<_> = oam.sprites_on_line(0, false).map(|(i, _)| i).collect();
+        assert_eq!(indexes, [11, 10, 9, 8, 7, 6, 5]);
+    }
+
+    #[test]
+    fn debug_dump() {
+        let mut oam = Oam::new();
+        set_sprite(
+            &mut oam,
+            0,
+            Sprite {
+                y: 16,
+                x: 8,
+                tile: 0x2a,
+                flags: SpriteFlags::X_FLIP | SpriteFlags::DMG_PALETTE,
+            },
+        );
+        set_sprite(
+            &mut oam,
+            1,
+            Sprite {
+                y: 160,
+                x: 100,
+                tile: 0xff,
+                flags: SpriteFlags::BEHIND_BG
+                    | SpriteFlags::Y_FLIP
+                    | SpriteFlags::X_FLIP
+                    | SpriteFlags::CGB_VRAM_BANK
+                    | SpriteFlags::from_bits_truncate(5),
+            },
+        );
+        let dump = oam.debug_dump();
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(lines.len(), Oam::NUM_SPRITES + 1);
+        assert_eq!(lines[0], "  #   Y   X  Tile  Priority  Flip  DMG   CGB");
+        assert_eq!(
+            lines[1],
+            "  0  16   8    2A  above     X-    OBP1  pal 0 bank 0"
+        );
+        assert_eq!(
+            lines[2],
+            "  1 160 100    FF  behind    XY    OBP0  pal 5 bank 1  off-screen"
+        );
+        assert_eq!(
+            lines[40],
+            " 39   0   0    00  above     --    OBP0  pal 0 bank 0  off-screen"
+        );
+    }
+}
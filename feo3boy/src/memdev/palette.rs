@@ -0,0 +1,102 @@
+//! The DMG palette registers BGP, OBP0, and OBP1.
+
+use std::fmt;
+
+/// One of the four shades of the DMG display.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Shade {
+    /// The lightest shade.
+    #[default]
+    White = 0,
+    /// Light gray.
+    LightGray = 1,
+    /// Dark gray.
+    DarkGray = 2,
+    /// The darkest shade.
+    Black = 3,
+}
+
+impl Shade {
+    /// Gets the shade from the low 2 bits of the given value.
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & 0x3 {
+            0 => Shade::White,
+            1 => Shade::LightGray,
+            2 => Shade::DarkGray,
+            3 => Shade::Black,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for Shade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Shade::White => "white",
+            Shade::LightGray => "light gray",
+            Shade::DarkGray => "dark gray",
+            Shade::Black => "black",
+        })
+    }
+}
+
+/// A DMG palette register, which maps each of the 4 color indexes of a tile to a shade. Bits 0-1
+/// give the shade of color 0, bits 2-3 color 1, and so on. For sprite palettes, color 0 is
+/// transparent, so its shade is unused.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct DmgPalette(pub u8);
+
+impl DmgPalette {
+    /// Gets the shade for the given color index, which must be 0-3.
+    pub fn shade(self, color: u8) -> Shade {
+        assert!(color < 4, "Color index {} out of range", color);
+        Shade::from_bits(self.0 >> (color * 2))
+    }
+
+    /// Gets the shades for all 4 color indexes, in order.
+    pub fn shades(self) -> [Shade; 4] {
+        [self.shade(0), self.shade(1), self.shade(2), self.shade(3)]
+    }
+}
+
+impl fmt::Display for DmgPalette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [c0, c1, c2, c3] = self.shades();
+        write!(f, "{:#04X} ({}, {}, {}, {})", self.0, c0, c1, c2, c3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        // The standard palette, which maps each color to the matching shade.
+        assert_eq!(
+            DmgPalette(0xe4).shades(),
+            [
+                Shade::White,
+                Shade::LightGray,
+                Shade::DarkGray,
+                Shade::Black
+            ]
+        );
+        // The inverse.
+        assert_eq!(
+            DmgPalette(0x1b).shades(),
+            [
+                Shade::Black,
+                Shade::DarkGray,
+                Shade::LightGray,
+                Shade::White
+            ]
+        );
+        assert_eq!(DmgPalette(0xfc).shade(0), Shade::White);
+        assert_eq!(DmgPalette(0xfc).shade(1), Shade::Black);
+        assert_eq!(
+            DmgPalette(0xe4).to_string(),
+            "0xE4 (white, light gray, dark gray, black)"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Read patches applied over another memory device, as done by the Game Genie.
+
+use thiserror::Error;
+
+use super::regions::ROM_END;
+use super::{Addr, MemDevice};
+
+/// Errors that can result from parsing a Game Genie code.
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum GameGenieParseError {
+    /// Game Genie codes have 6 or 9 hex digits, optionally separated by dashes.
+    #[error("Game Genie codes must have 6 or 9 hex digits, got {0}")]
+    InvalidLength(usize),
+    /// The code contained something other than a hex digit or dash.
+    #[error("Invalid character {0:?} in Game Genie code")]
+    InvalidDigit(char),
+    /// The decoded address is outside of the cartridge rom. The Game Genie can only patch rom.
+    #[error("Game Genie address {0:#06X} is not in cartridge rom")]
+    NotRom(u16),
+}
+
+/// A patch which replaces the value read from an address.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Patch {
+    /// Address to patch, relative to the patched device.
+    pub addr: u16,
+    /// Value to return instead of the real value.
+    pub value: u8,
+    /// If set, the patch only applies when the real value at the address equals this. This lets
+    /// a patch target one bank of a banked rom, since every bank appears at the same address.
+    pub compare: Option<u8>,
+}
+
+impl Patch {
+    /// Decodes a Game Genie code, either `ABC-DEF` or `ABC-DEF-GHI`, where the dashes are
+    /// optional.
+    ///
+    /// `AB` is the new value, and the address is `FCDE` with `F` inverted. If present, `GI` is
+    /// the compare value xored with 0xBA and rotated left by 2, and `H` is ignored.
+    pub fn from_game_genie(code: &str) -> Result<Self, GameGenieParseError> {
+        let mut digits = Vec::with_capacity(9);
+        for c in code.trim().chars().filter(|&c| c != '-') {
+            let digit = c.to_digit(16).ok_or(GameGenieParseError::InvalidDigit(c))?;
+            digits.push(digit as u8);
+        }
+        if digits.len() != 6 && digits.len() != 9 {
+            return Err(GameGenieParseError::InvalidLength(digits.len()));
+        }
+        let d = |i: usize| digits[i] as u16;
+
+        let value = (digits[0] << 4) | digits[1];
+        let addr = ((d(5) ^ 0xf) << 12) | (d(2) << 8) | (d(3) << 4) | d(4);
+        if addr > ROM_END {
+            return Err(GameGenieParseError::NotRom(addr));
+        }
+        let compare = if digits.len() == 9 {
+            let scrambled = (digits[6] << 4) | digits[8];
+            Some(scrambled.rotate_right(2) ^ 0xba)
+        } else {
+            None
+        };
+        Ok(Patch {
+            addr,
+            value,
+            compare,
+        })
+    }
+}
+
+/// Identifies a patch added to a [`Patched`] device.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PatchHandle(u64);
+
+/// Memory device which applies [`Patch`]es to reads from an underlying device. Writes always go
+/// to the underlying device unchanged.
+#[derive(Clone, Debug)]
+pub struct Patched<M> {
+    inner: M,
+    patches: Vec<(PatchHandle, Patch)>,
+    next_handle: u64,
+}
+
+impl<M> Patched<M> {
+    /// Wraps the given device, initially with no patches.
+    pub fn new(inner: M) -> Self {
+        Patched {
+            inner,
+            patches: Vec::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// Gets the underlying device.
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying device.
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    /// Adds a patch. If several patches apply to the same read, the first one added wins.
+    pub fn add_patch(&mut self, patch: Patch) -> PatchHandle {
+        let handle = PatchHandle(self.next_handle);
+        self.next_handle += 1;
+        self.patches.push((handle, patch));
+        handle
+    }
+
+    /// Decodes and adds a Game Genie code.
+    pub fn add_game_genie(&mut self, code: &str) -> Result<PatchHandle, GameGenieParseError> {
+        Ok(self.add_patch(Patch::from_game_genie(code)?))
+    }
+
+    /// Removes the patch with the given handle, returning it if it existed.
+    pub fn remove_patch(&mut self, handle: PatchHandle) -> Option<Patch> {
+        let index = self.patches.iter().position(|(h, _)| *h == handle)?;
+        Some(self.patches.remove(index).1)
+    }
+
+    /// Removes all patches.
+    pub fn clear_patches(&mut self) {
+        self.patches.clear();
+    }
+
+    /// Iterates over the current patches.
+    pub fn patches(&self) -> impl Iterator<Item = (PatchHandle, Patch)> + '_ {
+        self.patches.iter().copied()
+    }
+}
+
+impl<M: MemDevice> MemDevice for Patched<M> {
+    fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        let real = self.inner.read(addr);
+        self.patches
+            .iter()
+            .map(|(_, patch)| patch)
+            .find(|patch| {
+                patch.addr == addr.relative() && patch.compare.is_none_or(|cmp| cmp == real)
+            })
+            .map_or(real, |patch| patch.value)
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        self.inner.write(addr, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_game_genie() {
+        assert_eq!(
+            Patch::from_game_genie("00A-17B-C49").unwrap(),
+            Patch {
+                addr: 0x4a17,
+                value: 0x00,
+                compare: Some(0xc8),
+            }
+        );
+        assert_eq!(
+            Patch::from_game_genie("3ea0f8").unwrap(),
+            Patch {
+                addr: 0x7a0f,
+                value: 0x3e,
+                compare: None,
+            }
+        );
+        assert_eq!(
+            Patch::from_game_genie("00A-17B-C4"),
+            Err(GameGenieParseError::InvalidLength(8))
+        );
+        assert_eq!(
+            Patch::from_game_genie("00A-17B-C4X"),
+            Err(GameGenieParseError::InvalidDigit('X'))
+        );
+        assert_eq!(
+            Patch::from_game_genie("00A-177"),
+            Err(GameGenieParseError::NotRom(0x8a17))
+        );
+    }
+
+    #[test]
+    fn compare_byte() {
+        let mut mem = Patched::new([0u8; 4]);
+        let always = mem.add_patch(Patch {
+            addr: 1,
+            value: 0x11,
+            compare: None,
+        });
+        mem.add_patch(Patch {
+            addr: 2,
+            value: 0x22,
+            compare: Some(0x05),
+        });
+        assert_eq!(mem.read(1.into()), 0x11);
+        assert_eq!(mem.read(2.into()), 0);
+        mem.write(2.into(), 0x05);
+        assert_eq!(mem.read(2.into()), 0x22);
+        assert_eq!(mem.inner()[2], 0x05);
+
+        assert!(mem.remove_patch(always).is_some());
+        assert_eq!(mem.read(1.into()), 0);
+    }
+}
@@ -0,0 +1,104 @@
+//! Boundaries of the regions of the GameBoy memory map, as system addresses.
+//!
+//! Each region has an inclusive start and end, so they can be used directly in match arms, like
+//! `VRAM_START..=VRAM_END`. The regions are checked at compile time to cover the whole address
+//! space without gaps or overlaps.
+
+/// Start of the bios, which is mapped over the start of the cartridge rom until it is disabled.
+pub const BIOS_START: u16 = 0x0000;
+/// Last address of the bios.
+pub const BIOS_END: u16 = 0x00ff;
+
+/// Start of the cartridge rom, which also holds the mapper registers.
+pub const ROM_START: u16 = 0x0000;
+/// Last address of the cartridge rom.
+pub const ROM_END: u16 = 0x7fff;
+
+/// Start of video ram.
+pub const VRAM_START: u16 = 0x8000;
+/// Last address of video ram.
+pub const VRAM_END: u16 = 0x9fff;
+
+/// Start of cartridge ram.
+pub const CART_RAM_START: u16 = 0xa000;
+/// Last address of cartridge ram.
+pub const CART_RAM_END: u16 = 0xbfff;
+
+/// Start of working ram.
+pub const WRAM_START: u16 = 0xc000;
+/// Last address of working ram.
+pub const WRAM_END: u16 = 0xdfff;
+
+/// Start of echo ram, which mirrors working ram.
+pub const ECHO_START: u16 = 0xe000;
+/// Last address of echo ram.
+pub const ECHO_END: u16 = 0xfdff;
+
+/// Start of sprite attribute memory.
+pub const OAM_START: u16 = 0xfe00;
+/// Last address of sprite attribute memory.
+pub const OAM_END: u16 = 0xfe9f;
+
+/// Start of the unusable region above OAM.
+pub const UNUSABLE_START: u16 = 0xfea0;
+/// Last address of the unusable region.
+pub const UNUSABLE_END: u16 = 0xfeff;
+
+/// Start of the IO registers.
+pub const IO_START: u16 = 0xff00;
+/// Last address of the IO registers.
+pub const IO_END: u16 = 0xff7f;
+
+/// Start of high ram.
+pub const HRAM_START: u16 = 0xff80;
+/// Last address of high ram.
+pub const HRAM_END: u16 = 0xfffe;
+
+/// The interrupt enable register (IE).
+pub const IE_REGISTER: u16 = 0xffff;
+
+// Each region must start right after the previous one ends, and the last must end at the top of
+// memory.
+const _: () = assert!(BIOS_START == ROM_START && BIOS_END < ROM_END);
+const _: () = assert!(ROM_START == 0);
+const _: () = assert!(VRAM_START == ROM_END + 1);
+const _: () = assert!(CART_RAM_START == VRAM_END + 1);
+const _: () = assert!(WRAM_START == CART_RAM_END + 1);
+const _: () = assert!(ECHO_START == WRAM_END + 1);
+const _: () = assert!(OAM_START == ECHO_END + 1);
+const _: () = assert!(UNUSABLE_START == OAM_END + 1);
+const _: () = assert!(IO_START == UNUSABLE_END + 1);
+const _: () = assert!(HRAM_START == IO_END + 1);
+const _: () = assert!(IE_REGISTER == HRAM_END + 1 && IE_REGISTER == u16::MAX);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memdev::Region;
+
+    #[test]
+    fn boundaries_match_hardware() {
+        // Start, end, and region, as documented in the Pan Docs memory map.
+        #[rustfmt::skip]
+        let regions = [
+            (ROM_START, ROM_END, 0x0000, 0x7fff, Region::Rom),
+            (VRAM_START, VRAM_END, 0x8000, 0x9fff, Region::Vram),
+            (CART_RAM_START, CART_RAM_END, 0xa000, 0xbfff, Region::CartRam),
+            (WRAM_START, WRAM_END, 0xc000, 0xdfff, Region::Wram),
+            (ECHO_START, ECHO_END, 0xe000, 0xfdff, Region::EchoRam),
+            (OAM_START, OAM_END, 0xfe00, 0xfe9f, Region::Oam),
+            (UNUSABLE_START, UNUSABLE_END, 0xfea0, 0xfeff, Region::Unusable),
+            (IO_START, IO_END, 0xff00, 0xff7f, Region::Io),
+            (HRAM_START, HRAM_END, 0xff80, 0xfffe, Region::HighRam),
+            (IE_REGISTER, IE_REGISTER, 0xffff, 0xffff, Region::InterruptEnable),
+        ];
+        for &(start, end, expected_start, expected_end, region) in regions.iter() {
+            assert_eq!((start, end), (expected_start, expected_end), "{}", region);
+            assert_eq!(Region::of(start, false), region);
+            assert_eq!(Region::of(end, false), region);
+        }
+        assert_eq!((BIOS_START, BIOS_END), (0x0000, 0x00ff));
+        assert_eq!(Region::of(BIOS_END, true), Region::Bios);
+        assert_eq!(Region::of(BIOS_END + 1, true), Region::Rom);
+    }
+}
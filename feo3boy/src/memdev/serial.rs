@@ -0,0 +1,398 @@
+//! The serial port registers (SB and SC) and the link cable on the other end.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// The other end of the link cable.
+pub trait SerialBackend: fmt::Debug {
+    /// Exchanges a byte with the link partner when the first bit of a transfer moves: `out` is
+    /// the byte being shifted out of SB, and the returned byte is shifted in.
+    fn exchange(&mut self, out: u8) -> u8;
+
+    /// Number of clock pulses the link partner supplies over the given number of machine cycles,
+    /// each of which shifts one bit of a transfer running on the external clock. The default
+    /// supplies none, so externally clocked transfers never complete.
+    fn clock_pulses(&mut self, _m_cycles: u32) -> u32 {
+        0
+    }
+
+    /// Called when this Game Boy starts a transfer on the external clock, with the byte in SB
+    /// which will be shifted out. A link partner providing the clock needs it for its own
+    /// exchange, which may happen before this side's first bit moves. The default ignores it.
+    fn armed(&mut self, _out: u8) {}
+
+    /// Clones this backend into a new box, so that types holding boxed backends can still be
+    /// `Clone`.
+    fn box_clone(&self) -> Box<dyn SerialBackend>;
+}
+
+impl Clone for Box<dyn SerialBackend> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Backend for an unplugged link cable. The line floats high, so every transfer receives 0xFF.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Disconnected;
+
+impl SerialBackend for Disconnected {
+    fn exchange(&mut self, _out: u8) -> u8 {
+        0xff
+    }
+
+    fn box_clone(&self) -> Box<dyn SerialBackend> {
+        Box::new(*self)
+    }
+}
+
+/// Backend which records every byte sent, and otherwise behaves like [`Disconnected`].
+///
+/// Test roms such as blargg's print their results over the serial port, so this is enough to
+/// capture their output. Clones share the same buffer, so keep a clone to read the output after
+/// installing the backend.
+#[derive(Clone, Debug, Default)]
+pub struct BufferedSerial {
+    sent: Rc<RefCell<Vec<u8>>>,
+}
+
+impl BufferedSerial {
+    /// Constructs a new `BufferedSerial` with an empty buffer.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Gets a copy of the bytes sent so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.sent.borrow().clone()
+    }
+
+    /// Removes and returns the bytes sent so far.
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.sent.borrow_mut())
+    }
+
+    /// Gets the bytes sent so far as text, replacing invalid UTF-8.
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.sent.borrow()).into_owned()
+    }
+}
+
+impl SerialBackend for BufferedSerial {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.sent.borrow_mut().push(out);
+        0xff
+    }
+
+    fn box_clone(&self) -> Box<dyn SerialBackend> {
+        Box::new(self.clone())
+    }
+}
+
+/// Machine cycles per bit of a transfer on the internal clock, which runs at 8192 Hz.
+const CYCLES_PER_BIT: u32 = 128;
+
+/// How transfers on the internal clock are timed.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum SerialTiming {
+    /// Transfers complete as soon as they are started. Good enough for capturing test rom
+    /// output, and the default.
+    #[default]
+    Instant,
+    /// Transfers shift one bit every 128 machine cycles (8192 Hz), as on hardware, driven by
+    /// [`Serial::tick`].
+    Timed,
+}
+
+/// SC bit which starts a transfer when written, and reads set until it completes.
+const SC_TRANSFER: u8 = 0x80;
+/// SC bit which selects the internal clock.
+const SC_INTERNAL_CLOCK: u8 = 0x01;
+/// SC bits which are writable by the CPU. The rest read 1.
+const SC_WRITABLE: u8 = SC_TRANSFER | SC_INTERNAL_CLOCK;
+
+/// The serial port: the data register SB at 0xFF01 and the control register SC at 0xFF02.
+///
+/// Writing SC with bit 7 set starts a transfer, which shifts the 8 bits of SB out to the link
+/// partner, most significant first, while shifting the partner's bits in. When the last bit has
+/// moved, bit 7 of SC is cleared and a serial interrupt is requested. The backend's
+/// [`exchange`](SerialBackend::exchange) is called with the outgoing byte when the first bit
+/// moves, and provides the incoming byte.
+///
+/// If bit 0 of SC is set, this Game Boy provides the clock. With [`SerialTiming::Instant`], the
+/// default, the whole transfer happens as soon as it is started. With [`SerialTiming::Timed`],
+/// it moves one bit every 128 machine cycles of [`tick`](Serial::tick). If bit 0 is clear, the
+/// link partner provides the clock, so bits only move when the backend reports
+/// [`clock_pulses`](SerialBackend::clock_pulses) or when bits are pushed with
+/// [`push_external_bit`](Serial::push_external_bit). Without a partner, the transfer stalls.
+#[derive(Clone, Debug)]
+pub struct Serial {
+    /// Serial data register (SB).
+    data: u8,
+    /// Writable bits of the serial control register (SC).
+    control: u8,
+    /// The other end of the link cable.
+    backend: Box<dyn SerialBackend>,
+    /// How transfers on the internal clock are timed.
+    timing: SerialTiming,
+    /// Bits left to shift in the current transfer.
+    bits_remaining: u8,
+    /// Byte being shifted in from the backend, once it has been exchanged.
+    incoming: Option<u8>,
+    /// Machine cycles since the last bit moved on the internal clock.
+    elapsed: u32,
+}
+
+impl Serial {
+    /// Constructs a new `Serial` with no transfer in progress and the given backend.
+    pub fn new(backend: Box<dyn SerialBackend>) -> Self {
+        Serial {
+            data: 0x00,
+            control: 0x00,
+            backend,
+            timing: SerialTiming::Instant,
+            bits_remaining: 0,
+            incoming: None,
+            elapsed: 0,
+        }
+    }
+
+    /// Gets how transfers on the internal clock are timed.
+    pub fn timing(&self) -> SerialTiming {
+        self.timing
+    }
+
+    /// Sets how transfers on the internal clock are timed.
+    pub fn set_timing(&mut self, timing: SerialTiming) {
+        self.timing = timing;
+    }
+
+    /// Gets the backend.
+    pub fn backend(&self) -> &dyn SerialBackend {
+        &*self.backend
+    }
+
+    /// Replaces the backend, returning the old one.
+    pub fn set_backend(&mut self, backend: Box<dyn SerialBackend>) -> Box<dyn SerialBackend> {
+        std::mem::replace(&mut self.backend, backend)
+    }
+
+    /// Returns true if a transfer has been started and has not completed.
+    pub fn transfer_in_progress(&self) -> bool {
+        self.control & SC_TRANSFER != 0
+    }
+
+    /// Gets the value the CPU reads from SB.
+    pub fn data(&self) -> u8 {
+        self.data
+    }
+
+    /// Handles a CPU write to SB.
+    pub fn write_data(&mut self, value: u8) {
+        self.data = value;
+    }
+
+    /// Gets the value the CPU reads from SC.
+    pub fn control(&self) -> u8 {
+        self.control | !SC_WRITABLE
+    }
+
+    /// Handles a CPU write to SC. Setting bit 7 starts a new transfer, and clearing it cancels
+    /// any transfer in progress. Returns true if this completed a transfer, which requests a
+    /// serial interrupt.
+    pub fn write_control(&mut self, value: u8) -> bool {
+        self.control = value & SC_WRITABLE;
+        self.bits_remaining = if self.transfer_in_progress() { 8 } else { 0 };
+        self.incoming = None;
+        self.elapsed = 0;
+        if self.control == SC_TRANSFER {
+            self.backend.armed(self.data);
+        }
+        if self.control == SC_WRITABLE && self.timing == SerialTiming::Instant {
+            while self.bits_remaining > 0 {
+                self.shift_bit(None);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Advances a transfer in progress by the given number of machine cycles. On the internal
+    /// clock with [`SerialTiming::Timed`], a bit moves every 128 cycles. On the external clock,
+    /// one bit moves per clock pulse reported by the backend. Returns true if this completed the
+    /// transfer, which requests a serial interrupt.
+    pub fn tick(&mut self, m_cycles: u32) -> bool {
+        if !self.transfer_in_progress() {
+            return false;
+        }
+        let bits = if self.control & SC_INTERNAL_CLOCK != 0 {
+            self.elapsed += m_cycles;
+            let bits = self.elapsed / CYCLES_PER_BIT;
+            self.elapsed %= CYCLES_PER_BIT;
+            bits
+        } else {
+            self.backend.clock_pulses(m_cycles)
+        };
+        (0..bits).any(|_| self.shift_bit(None))
+    }
+
+    /// Shifts in one bit supplied by a link partner providing the external clock, instead of
+    /// taking it from the backend. Ignored unless a transfer on the external clock is in
+    /// progress. Returns true if this completed the transfer, which requests a serial interrupt.
+    pub fn push_external_bit(&mut self, bit: bool) -> bool {
+        if self.control != SC_TRANSFER {
+            return false;
+        }
+        self.shift_bit(Some(bit))
+    }
+
+    /// Shifts one bit of the transfer in progress, taking the incoming bit from the backend if
+    /// it isn't given. Returns true if this was the last bit.
+    fn shift_bit(&mut self, bit: Option<bool>) -> bool {
+        let bit = match bit {
+            Some(bit) => bit as u8,
+            None => {
+                let incoming = match self.incoming {
+                    Some(incoming) => incoming,
+                    None => {
+                        let incoming = self.backend.exchange(self.data);
+                        self.incoming = Some(incoming);
+                        incoming
+                    }
+                };
+                (incoming >> (self.bits_remaining - 1)) & 1
+            }
+        };
+        self.data = (self.data << 1) | bit;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.control &= !SC_TRANSFER;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new(Box::new(Disconnected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_clock_transfer() {
+        let output = BufferedSerial::new();
+        let mut serial = Serial::new(Box::new(output.clone()));
+        assert_eq!(serial.control(), 0x7e);
+
+        for &byte in b"Passed" {
+            serial.write_data(byte);
+            assert!(serial.write_control(0x81));
+            assert!(!serial.transfer_in_progress());
+            // Nothing is connected, so 0xFF is shifted in.
+            assert_eq!(serial.data(), 0xff);
+            assert_eq!(serial.control(), 0x7f);
+        }
+        assert_eq!(output.output_string(), "Passed");
+        assert_eq!(output.take_output(), b"Passed");
+        assert!(output.output().is_empty());
+    }
+
+    /// Backend which returns a fixed byte and supplies a clock pulse every `period` cycles.
+    #[derive(Clone, Debug)]
+    struct Partner {
+        reply: u8,
+        period: u32,
+        elapsed: u32,
+    }
+
+    impl SerialBackend for Partner {
+        fn exchange(&mut self, _out: u8) -> u8 {
+            self.reply
+        }
+
+        fn clock_pulses(&mut self, m_cycles: u32) -> u32 {
+            self.elapsed += m_cycles;
+            let pulses = self.elapsed / self.period;
+            self.elapsed %= self.period;
+            pulses
+        }
+
+        fn box_clone(&self) -> Box<dyn SerialBackend> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn timed_internal_clock() {
+        let mut serial = Serial::new(Box::new(Partner {
+            reply: 0b1010_0000,
+            period: 1,
+            elapsed: 0,
+        }));
+        serial.set_timing(SerialTiming::Timed);
+        serial.write_data(0x0f);
+        assert!(!serial.write_control(0x81));
+        assert!(serial.transfer_in_progress());
+
+        // One bit per 128 cycles, most significant first.
+        assert!(!serial.tick(127));
+        assert_eq!(serial.data(), 0x0f);
+        assert!(!serial.tick(1));
+        assert_eq!(serial.data(), 0x1f);
+        assert!(!serial.tick(128 * 2));
+        assert_eq!(serial.data(), 0x7d);
+        assert!(!serial.tick(128 * 4));
+        assert!(serial.transfer_in_progress());
+        assert!(serial.tick(128));
+        assert!(!serial.transfer_in_progress());
+        assert_eq!(serial.data(), 0b1010_0000);
+        assert!(!serial.tick(1000));
+    }
+
+    #[test]
+    fn external_clock_from_partner() {
+        let mut serial = Serial::new(Box::new(Partner {
+            reply: 0x5a,
+            period: 10,
+            elapsed: 0,
+        }));
+        serial.write_data(0x42);
+        // The timing mode doesn't affect the external clock.
+        assert!(!serial.write_control(0x80));
+        assert!(!serial.tick(79));
+        assert!(serial.transfer_in_progress());
+        assert!(serial.tick(1));
+        assert_eq!(serial.data(), 0x5a);
+
+        // Bits can also be pushed directly.
+        let mut serial = Serial::default();
+        serial.write_data(0x00);
+        assert!(!serial.push_external_bit(true));
+        serial.write_control(0x80);
+        for _ in 0..7 {
+            assert!(!serial.push_external_bit(true));
+        }
+        assert!(serial.push_external_bit(false));
+        assert_eq!(serial.data(), 0xfe);
+    }
+
+    #[test]
+    fn external_clock_waits() {
+        let output = BufferedSerial::new();
+        let mut serial = Serial::new(Box::new(output.clone()));
+        serial.write_data(0x42);
+        assert!(!serial.write_control(0x80));
+        assert!(serial.transfer_in_progress());
+        assert_eq!(serial.control(), 0xfe);
+        assert_eq!(serial.data(), 0x42);
+        assert!(output.output().is_empty());
+    }
+}
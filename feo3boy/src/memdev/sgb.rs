@@ -0,0 +1,178 @@
+//! Decoding of Super Game Boy command packets, which games send through the joypad register.
+
+use std::collections::VecDeque;
+
+/// Number of bytes in a packet.
+const PACKET_BYTES: usize = 16;
+/// Number of data bits in a packet, not counting the stop bit.
+const PACKET_BITS: u8 = 8 * PACKET_BYTES as u8;
+
+/// One 16 byte command packet sent to the Super Game Boy.
+///
+/// The first byte of the first packet of a command holds the command code in bits 3-7 and the
+/// number of packets the command uses in bits 0-2. Packets are delivered as they arrive, without
+/// joining the packets of commands which use several, so for the later packets of such a command
+/// `command` is just the first data byte.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SgbPacket {
+    /// The first byte of the packet.
+    pub command: u8,
+    /// The remaining 15 bytes of the packet.
+    pub data: [u8; 15],
+}
+
+impl SgbPacket {
+    /// Command code, such as 0x11 for MLT_REQ or 0x00 for PAL01. Only meaningful for the first
+    /// packet of a command.
+    pub fn code(&self) -> u8 {
+        self.command >> 3
+    }
+
+    /// Number of packets the command uses. Only meaningful for the first packet of a command.
+    pub fn length(&self) -> u8 {
+        self.command & 0x7
+    }
+
+    /// Builds a packet from its 16 bytes.
+    fn from_bytes(bytes: &[u8; PACKET_BYTES]) -> Self {
+        let mut data = [0; 15];
+        data.copy_from_slice(&bytes[1..]);
+        SgbPacket {
+            command: bytes[0],
+            data,
+        }
+    }
+}
+
+/// Packet currently being received.
+#[derive(Clone, Debug)]
+struct Transfer {
+    /// Bytes received so far, least significant bit first.
+    bytes: [u8; PACKET_BYTES],
+    /// Number of bits received so far.
+    bits: u8,
+    /// Whether both lines have been released since the last bit, so the next pulse is a new bit.
+    released: bool,
+}
+
+/// Decodes packets from the values written to the select bits (4-5) of the joypad register.
+///
+/// A packet starts with a reset pulse, where both lines are pulled low. Each bit is then a pulse
+/// of one line followed by releasing both: P14 (bit 4) low sends a 0, and P15 (bit 5) low sends
+/// a 1. After 128 bits, least significant bit of each byte first, a 0 stop bit ends the packet.
+/// A packet which is cut short by another reset pulse or ends with a 1 is dropped.
+///
+/// Joypad polling between packets selects at most one column at a time, so it is ignored.
+#[derive(Clone, Debug, Default)]
+pub(super) struct SgbDecoder {
+    /// Packet currently being received, if a reset pulse has been seen.
+    transfer: Option<Transfer>,
+    /// Packets which have been received but not yet taken.
+    packets: VecDeque<SgbPacket>,
+}
+
+impl SgbDecoder {
+    /// Handles a write of the given select bits to the joypad register.
+    pub(super) fn write_select(&mut self, select: u8) {
+        let bit = match select {
+            0x00 => {
+                self.transfer = Some(Transfer {
+                    bytes: [0; PACKET_BYTES],
+                    bits: 0,
+                    released: false,
+                });
+                return;
+            }
+            // P14 low.
+            0x20 => 0,
+            // P15 low.
+            0x10 => 1,
+            _ => {
+                if let Some(ref mut transfer) = self.transfer {
+                    transfer.released = true;
+                }
+                return;
+            }
+        };
+        let transfer = match self.transfer {
+            Some(ref mut transfer) if transfer.released => transfer,
+            _ => return,
+        };
+        transfer.released = false;
+        if transfer.bits < PACKET_BITS {
+            let index = transfer.bits as usize;
+            transfer.bytes[index / 8] |= bit << (index % 8);
+            transfer.bits += 1;
+        } else {
+            if bit == 0 {
+                self.packets
+                    .push_back(SgbPacket::from_bytes(&transfer.bytes));
+            }
+            self.transfer = None;
+        }
+    }
+
+    /// Returns and clears the packets which have been received.
+    pub(super) fn take_packets(&mut self) -> Vec<SgbPacket> {
+        self.packets.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends a packet with the given bytes, followed by the given stop bit.
+    fn send(decoder: &mut SgbDecoder, bytes: &[u8; PACKET_BYTES], stop: u8) {
+        decoder.write_select(0x00);
+        decoder.write_select(0x30);
+        let bits = bytes
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |i| byte >> i & 1))
+            .chain(Some(stop));
+        for bit in bits {
+            decoder.write_select(if bit == 0 { 0x20 } else { 0x10 });
+            decoder.write_select(0x30);
+        }
+    }
+
+    #[test]
+    fn decodes_packets() {
+        let mut decoder = SgbDecoder::default();
+        let mut bytes = [0; PACKET_BYTES];
+        bytes[0] = 0x89;
+        bytes[1] = 0x01;
+        bytes[15] = 0xa5;
+        send(&mut decoder, &bytes, 0);
+        let packets = decoder.take_packets();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].command, 0x89);
+        assert_eq!(packets[0].code(), 0x11);
+        assert_eq!(packets[0].length(), 1);
+        assert_eq!(packets[0].data[0], 0x01);
+        assert_eq!(packets[0].data[14], 0xa5);
+        assert!(decoder.take_packets().is_empty());
+    }
+
+    #[test]
+    fn drops_malformed_packets() {
+        let mut decoder = SgbDecoder::default();
+        // A 1 stop bit.
+        send(&mut decoder, &[0xff; PACKET_BYTES], 1);
+        assert!(decoder.take_packets().is_empty());
+
+        // A reset pulse partway through restarts the packet.
+        decoder.write_select(0x00);
+        decoder.write_select(0x30);
+        decoder.write_select(0x10);
+        decoder.write_select(0x30);
+        send(&mut decoder, &[0x42; PACKET_BYTES], 0);
+        assert_eq!(decoder.take_packets()[0].command, 0x42);
+
+        // Polling the joypad between packets is ignored.
+        for &select in [0x10, 0x30, 0x20, 0x30, 0x10, 0x10].iter() {
+            decoder.write_select(select);
+        }
+        assert!(decoder.take_packets().is_empty());
+    }
+}
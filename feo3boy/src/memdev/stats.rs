@@ -0,0 +1,254 @@
+//! Collection of memory access statistics.
+
+use std::cell::Cell;
+use std::fmt;
+
+use super::regions::*;
+
+/// Regions of the GameBoy memory map, as distinguished by access statistics.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Region {
+    /// The bios, while it is mapped over the start of the cartridge rom.
+    Bios,
+    /// Cartridge rom, including the MBC registers.
+    Rom,
+    /// Video ram.
+    Vram,
+    /// Cartridge ram.
+    CartRam,
+    /// Working ram.
+    Wram,
+    /// The mirror of working ram at 0xE000..0xFE00.
+    EchoRam,
+    /// Sprite attribute memory.
+    Oam,
+    /// The unusable area between OAM and IO.
+    Unusable,
+    /// Memory mapped IO registers.
+    Io,
+    /// High ram ("Page Zero").
+    HighRam,
+    /// The interrupt enable register.
+    InterruptEnable,
+}
+
+impl Region {
+    /// Number of distinct regions.
+    pub const COUNT: usize = 11;
+
+    /// All regions, in address order.
+    pub const ALL: [Region; Region::COUNT] = [
+        Region::Bios,
+        Region::Rom,
+        Region::Vram,
+        Region::CartRam,
+        Region::Wram,
+        Region::EchoRam,
+        Region::Oam,
+        Region::Unusable,
+        Region::Io,
+        Region::HighRam,
+        Region::InterruptEnable,
+    ];
+
+    /// Gets the region containing the given system address. The bios overlays the start of the
+    /// cartridge rom only while it is enabled, so that has to be provided.
+    pub fn of(addr: u16, bios_enabled: bool) -> Region {
+        match addr {
+            BIOS_START..=BIOS_END if bios_enabled => Region::Bios,
+            ROM_START..=ROM_END => Region::Rom,
+            VRAM_START..=VRAM_END => Region::Vram,
+            CART_RAM_START..=CART_RAM_END => Region::CartRam,
+            WRAM_START..=WRAM_END => Region::Wram,
+            ECHO_START..=ECHO_END => Region::EchoRam,
+            OAM_START..=OAM_END => Region::Oam,
+            UNUSABLE_START..=UNUSABLE_END => Region::Unusable,
+            IO_START..=IO_END => Region::Io,
+            HRAM_START..=HRAM_END => Region::HighRam,
+            IE_REGISTER => Region::InterruptEnable,
+        }
+    }
+
+    /// Short human readable name of the region.
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Bios => "BIOS",
+            Region::Rom => "ROM",
+            Region::Vram => "VRAM",
+            Region::CartRam => "Cart RAM",
+            Region::Wram => "WRAM",
+            Region::EchoRam => "Echo RAM",
+            Region::Oam => "OAM",
+            Region::Unusable => "Unusable",
+            Region::Io => "IO",
+            Region::HighRam => "HRAM",
+            Region::InterruptEnable => "IE",
+        }
+    }
+
+    /// Index of this region in [`Region::ALL`].
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Snapshot of memory access statistics collected by a [`GbMmu`](super::GbMmu).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AccessStats {
+    /// Reads per region, indexed by `Region::index`.
+    reads: [u64; Region::COUNT],
+    /// Writes per region, indexed by `Region::index`.
+    writes: [u64; Region::COUNT],
+    /// Reads per 256 byte page, if the page histogram is enabled.
+    page_reads: Option<Vec<u64>>,
+    /// Writes per 256 byte page, if the page histogram is enabled.
+    page_writes: Option<Vec<u64>>,
+}
+
+impl AccessStats {
+    /// Number of reads from the given region.
+    pub fn reads(&self, region: Region) -> u64 {
+        self.reads[region.index()]
+    }
+
+    /// Number of writes to the given region.
+    pub fn writes(&self, region: Region) -> u64 {
+        self.writes[region.index()]
+    }
+
+    /// Number of reads from the given page (the high byte of the address), or `None` if the page
+    /// histogram was not enabled.
+    pub fn page_reads(&self, page: u8) -> Option<u64> {
+        self.page_reads.as_ref().map(|pages| pages[page as usize])
+    }
+
+    /// Number of writes to the given page (the high byte of the address), or `None` if the page
+    /// histogram was not enabled.
+    pub fn page_writes(&self, page: u8) -> Option<u64> {
+        self.page_writes.as_ref().map(|pages| pages[page as usize])
+    }
+
+    /// Total number of accesses recorded.
+    pub fn total(&self) -> u64 {
+        self.reads.iter().chain(self.writes.iter()).sum()
+    }
+}
+
+impl fmt::Display for AccessStats {
+    /// Prints a table of accesses per region, sorted from most to least accessed, followed by a
+    /// table of the accessed pages if the page histogram is enabled.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut regions = Region::ALL;
+        regions.sort_by_key(|&r| std::cmp::Reverse(self.reads(r) + self.writes(r)));
+        writeln!(f, "{:<10} {:>12} {:>12}", "Region", "Reads", "Writes")?;
+        for region in regions.iter() {
+            writeln!(
+                f,
+                "{:<10} {:>12} {:>12}",
+                region.name(),
+                self.reads(*region),
+                self.writes(*region)
+            )?;
+        }
+        if let (Some(reads), Some(writes)) = (&self.page_reads, &self.page_writes) {
+            let mut pages: Vec<usize> = (0..256)
+                .filter(|&page| reads[page] + writes[page] > 0)
+                .collect();
+            pages.sort_by_key(|&page| std::cmp::Reverse(reads[page] + writes[page]));
+            writeln!(f)?;
+            writeln!(f, "{:<10} {:>12} {:>12}", "Page", "Reads", "Writes")?;
+            for page in pages {
+                writeln!(
+                    f,
+                    "{:<10} {:>12} {:>12}",
+                    format!("{:02X}xx", page),
+                    reads[page],
+                    writes[page]
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects access statistics. Uses `Cell`s so that reads, which only borrow the memory
+/// immutably, can still be counted.
+#[derive(Clone, Debug)]
+pub(super) struct StatsCollector {
+    reads: [Cell<u64>; Region::COUNT],
+    writes: [Cell<u64>; Region::COUNT],
+    page_reads: Option<Vec<Cell<u64>>>,
+    page_writes: Option<Vec<Cell<u64>>>,
+}
+
+impl StatsCollector {
+    /// Creates a new collector, optionally with a 256 bucket histogram of accesses by page.
+    pub(super) fn new(page_histogram: bool) -> Self {
+        let pages = || {
+            if page_histogram {
+                Some(vec![Cell::new(0); 256])
+            } else {
+                None
+            }
+        };
+        StatsCollector {
+            reads: Default::default(),
+            writes: Default::default(),
+            page_reads: pages(),
+            page_writes: pages(),
+        }
+    }
+
+    /// Records a read of the given address in the given region.
+    pub(super) fn record_read(&self, region: Region, addr: u16) {
+        increment(&self.reads[region.index()]);
+        if let Some(ref pages) = self.page_reads {
+            increment(&pages[(addr >> 8) as usize]);
+        }
+    }
+
+    /// Records a write of the given address in the given region.
+    pub(super) fn record_write(&self, region: Region, addr: u16) {
+        increment(&self.writes[region.index()]);
+        if let Some(ref pages) = self.page_writes {
+            increment(&pages[(addr >> 8) as usize]);
+        }
+    }
+
+    /// Gets a snapshot of the current statistics.
+    pub(super) fn snapshot(&self) -> AccessStats {
+        let get = |cells: &[Cell<u64>]| cells.iter().map(Cell::get).collect::<Vec<_>>();
+        let mut stats = AccessStats::default();
+        for region in Region::ALL.iter() {
+            stats.reads[region.index()] = self.reads[region.index()].get();
+            stats.writes[region.index()] = self.writes[region.index()].get();
+        }
+        stats.page_reads = self.page_reads.as_deref().map(get);
+        stats.page_writes = self.page_writes.as_deref().map(get);
+        stats
+    }
+
+    /// Resets all counters to 0.
+    pub(super) fn reset(&self) {
+        let all = self
+            .reads
+            .iter()
+            .chain(self.writes.iter())
+            .chain(self.page_reads.iter().flatten())
+            .chain(self.page_writes.iter().flatten());
+        for cell in all {
+            cell.set(0);
+        }
+    }
+}
+
+/// Increments a counter cell, wrapping on overflow.
+fn increment(cell: &Cell<u64>) {
+    cell.set(cell.get().wrapping_add(1));
+}
@@ -0,0 +1,527 @@
+//! The timer: DIV, TIMA, TMA, and TAC at 0xFF04..0xFF08.
+
+use super::{Addr, Component, MemDevice};
+use crate::interrupts::InterruptFlags;
+
+/// TAC bit which enables incrementing TIMA.
+const TAC_ENABLE: u8 = 0x04;
+/// TAC bits which select the rate TIMA increments at.
+const TAC_RATE: u8 = 0x03;
+
+/// Divider bit whose falling edge is a DIV-APU event at normal speed: bit 4 of DIV, which gives
+/// 512 Hz.
+const DIV_APU_BIT: u16 = 12;
+/// Divider bit whose falling edge is a DIV-APU event in double speed mode: bit 5 of DIV, which
+/// keeps the events at 512 Hz.
+const DIV_APU_BIT_DOUBLE_SPEED: u16 = 13;
+
+/// Timer component. Covers the timer registers at 0xFF04..0xFF08, relative to 0xFF04.
+///
+/// The timer is built around a 16 bit divider which counts T-cycles, the upper byte of which
+/// is visible as DIV. TIMA doesn't count on its own: it increments whenever the divider bit
+/// selected by TAC, ANDed with the TAC enable bit, goes from 1 to 0. So anything which makes that
+/// signal fall increments TIMA, including resetting the divider by writing DIV while the bit is
+/// set, or changing TAC. Those spurious increments are real hardware behavior which some games
+/// and test roms depend on.
+///
+/// When TIMA overflows, it isn't reloaded right away. For one machine cycle it reads 0x00, and
+/// writing TIMA during that cycle cancels the reload and the interrupt. On the next cycle TIMA is
+/// loaded from TMA and the interrupt is requested. During that reload cycle, writes to TIMA are
+/// overridden by TMA, and writes to TMA go through to TIMA as well.
+///
+/// The APU frame sequencer is clocked the same way, by falling edges of DIV bit 4 (bit 5 in
+/// double speed mode), which are called DIV-APU events. The timer counts them until they are
+/// collected with [`take_div_apu_events`](Timer::take_div_apu_events), so writing DIV while the
+/// bit is set clocks the frame sequencer early.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Timer {
+    /// Internal divider, counting T-cycles. DIV is the upper byte.
+    divider: u16,
+    /// Timer counter (TIMA).
+    tima: u8,
+    /// Timer modulo (TMA), loaded into TIMA when it overflows.
+    tma: u8,
+    /// Timer control (TAC). Only the low 3 bits are stored.
+    tac: u8,
+    /// Whether a timer interrupt has been requested and not yet collected.
+    interrupt_requested: bool,
+    /// Progress of reloading TIMA after an overflow.
+    reload: Reload,
+    /// Whether the CGB is running in double speed mode.
+    double_speed: bool,
+    /// Number of DIV-APU events which haven't been collected yet.
+    div_apu_events: u32,
+}
+
+/// State of reloading TIMA from TMA after an overflow.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+enum Reload {
+    /// No overflow is being handled.
+    #[default]
+    Idle,
+    /// TIMA overflowed during the last cycle and reads 0. It will be reloaded next cycle unless
+    /// TIMA is written first.
+    Pending,
+    /// TIMA was loaded from TMA this cycle.
+    Reloading,
+}
+
+impl Timer {
+    /// Constructs a new `Timer` with all registers cleared.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Gets the full 16 bit internal divider.
+    pub fn divider(&self) -> u16 {
+        self.divider
+    }
+
+    /// Returns true during the machine cycle after TIMA overflows, when it reads 0x00 and hasn't
+    /// been reloaded from TMA yet.
+    pub fn reload_pending(&self) -> bool {
+        self.reload == Reload::Pending
+    }
+
+    /// Advances the timer by the given number of machine cycles (4 T-cycles each). If a timer
+    /// interrupt was requested, returns the number of cycles into this tick at which it was
+    /// requested, where 0 is the first cycle.
+    pub fn tick(&mut self, m_cycles: u32) -> Option<u32> {
+        let mut fired = None;
+        for cycle in 0..m_cycles {
+            self.reload = match self.reload {
+                Reload::Pending => {
+                    self.tima = self.tma;
+                    self.interrupt_requested = true;
+                    fired = fired.or(Some(cycle));
+                    Reload::Reloading
+                }
+                Reload::Idle | Reload::Reloading => Reload::Idle,
+            };
+            self.set_divider(self.divider.wrapping_add(4));
+        }
+        fired
+    }
+
+    /// Returns and clears whether TIMA has overflowed and requested a timer interrupt since the
+    /// last call.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_requested)
+    }
+
+    /// Sets whether the CGB is running in double speed mode, which moves DIV-APU events to the
+    /// next divider bit.
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    /// Returns and clears the number of DIV-APU events since the last call.
+    pub fn take_div_apu_events(&mut self) -> u32 {
+        std::mem::take(&mut self.div_apu_events)
+    }
+
+    /// Whether the divider bit which produces DIV-APU events is set.
+    fn div_apu_signal(&self) -> bool {
+        let bit = if self.double_speed {
+            DIV_APU_BIT_DOUBLE_SPEED
+        } else {
+            DIV_APU_BIT
+        };
+        (self.divider >> bit) & 1 != 0
+    }
+
+    /// The divider bit selected by TAC. This is bit 9, 3, 5, or 7, which gives 4096, 262144,
+    /// 65536, or 16384 Hz.
+    fn selected_bit(&self) -> u16 {
+        match self.tac & TAC_RATE {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            3 => 7,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The signal whose falling edge increments TIMA.
+    fn signal(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && (self.divider >> self.selected_bit()) & 1 != 0
+    }
+
+    /// Sets the divider, incrementing TIMA if that makes the signal fall, and counting a
+    /// DIV-APU event if that makes the frame sequencer bit fall.
+    fn set_divider(&mut self, divider: u16) {
+        let before = self.signal();
+        let div_apu_before = self.div_apu_signal();
+        self.divider = divider;
+        self.check_falling_edge(before);
+        if div_apu_before && !self.div_apu_signal() {
+            self.div_apu_events += 1;
+        }
+    }
+
+    /// Sets TAC, incrementing TIMA if that makes the signal fall.
+    fn set_tac(&mut self, tac: u8) {
+        let before = self.signal();
+        self.tac = tac & (TAC_ENABLE | TAC_RATE);
+        self.check_falling_edge(before);
+    }
+
+    /// Increments TIMA if the signal was high before a change and is now low.
+    fn check_falling_edge(&mut self, before: bool) {
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    /// Increments TIMA. When it overflows, it reads 0 until it is reloaded on the next cycle.
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload = Reload::Pending;
+        }
+    }
+
+    /// Handles a CPU write to TIMA.
+    fn write_tima(&mut self, value: u8) {
+        match self.reload {
+            // Writing during the overflow cycle cancels the reload and the interrupt.
+            Reload::Pending => {
+                self.tima = value;
+                self.reload = Reload::Idle;
+            }
+            // TMA wins over writes during the reload cycle.
+            Reload::Reloading => {}
+            Reload::Idle => self.tima = value,
+        }
+    }
+
+    /// Handles a CPU write to TMA.
+    fn write_tma(&mut self, value: u8) {
+        self.tma = value;
+        // TIMA is being loaded from TMA this cycle, so it gets the new value.
+        if self.reload == Reload::Reloading {
+            self.tima = value;
+        }
+    }
+}
+
+impl MemDevice for Timer {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn read(&self, addr: Addr) -> u8 {
+        match addr.relative() {
+            0 => (self.divider >> 8) as u8,
+            1 => self.tima,
+            2 => self.tma,
+            // The upper 5 bits of TAC are unused and read 1.
+            3 => self.tac | 0xf8,
+            _ => panic!("Address {} out of range for Timer", addr),
+        }
+    }
+
+    fn write(&mut self, addr: Addr, value: u8) {
+        match addr.relative() {
+            // Any write resets the whole divider.
+            0 => self.set_divider(0),
+            1 => self.write_tima(value),
+            2 => self.write_tma(value),
+            3 => self.set_tac(value),
+            _ => panic!("Address {} out of range for Timer", addr),
+        }
+    }
+}
+
+impl Component for Timer {
+    fn box_clone(&self) -> Box<dyn Component> {
+        Box::new(*self)
+    }
+
+    fn tick(&mut self, m_cycles: u32) {
+        Timer::tick(self, m_cycles);
+    }
+
+    fn take_requested_interrupts(&mut self) -> InterruptFlags {
+        if self.take_interrupt() {
+            InterruptFlags::TIMER
+        } else {
+            InterruptFlags::empty()
+        }
+    }
+
+    fn set_double_speed(&mut self, double_speed: bool) {
+        Timer::set_double_speed(self, double_speed);
+    }
+
+    fn take_div_apu_events(&mut self) -> u32 {
+        Timer::take_div_apu_events(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIV: u16 = 0;
+    const TIMA: u16 = 1;
+    const TMA: u16 = 2;
+    const TAC: u16 = 3;
+
+    /// Sets up a timer at 262144 Hz which overflows on the next increment, which happens at the
+    /// end of the next tick.
+    fn about_to_overflow() -> Timer {
+        let mut timer = Timer::new();
+        timer.write(TMA.into(), 0x80);
+        timer.write(TIMA.into(), 0xff);
+        timer.write(TAC.into(), 0x05);
+        timer.tick(3);
+        timer
+    }
+
+    #[test]
+    fn delayed_reload() {
+        let mut timer = about_to_overflow();
+        assert_eq!(timer.tick(1), None);
+        // TIMA reads 0 for one cycle before being reloaded.
+        assert!(timer.reload_pending());
+        assert_eq!(timer.read(TIMA.into()), 0x00);
+        assert!(!timer.take_interrupt());
+        assert_eq!(timer.tick(1), Some(0));
+        assert!(!timer.reload_pending());
+        assert_eq!(timer.read(TIMA.into()), 0x80);
+        assert!(timer.take_interrupt());
+
+        // The interrupt fires on the cycle after the overflow, which is 3 cycles into a tick
+        // starting 2 cycles before the overflow.
+        let mut timer = about_to_overflow();
+        timer.write(TIMA.into(), 0xfe);
+        assert_eq!(timer.tick(2), None);
+        assert_eq!(timer.tick(8), Some(3));
+    }
+
+    #[test]
+    fn write_tima_during_reload_window() {
+        // Writing during the overflow cycle cancels the reload and the interrupt.
+        let mut timer = about_to_overflow();
+        timer.tick(1);
+        timer.write(TIMA.into(), 0x12);
+        assert_eq!(timer.tick(1), None);
+        assert_eq!(timer.read(TIMA.into()), 0x12);
+        assert!(!timer.take_interrupt());
+
+        // Writing during the reload cycle is overridden by TMA.
+        let mut timer = about_to_overflow();
+        timer.tick(2);
+        timer.write(TIMA.into(), 0x12);
+        assert_eq!(timer.read(TIMA.into()), 0x80);
+        assert!(timer.take_interrupt());
+    }
+
+    #[test]
+    fn write_tma_during_reload_window() {
+        // Writing TMA during the reload cycle also loads TIMA.
+        let mut timer = about_to_overflow();
+        timer.tick(2);
+        timer.write(TMA.into(), 0x34);
+        assert_eq!(timer.read(TIMA.into()), 0x34);
+
+        // Writing TMA during the overflow cycle is used by the reload.
+        let mut timer = about_to_overflow();
+        timer.tick(1);
+        timer.write(TMA.into(), 0x56);
+        assert_eq!(timer.read(TIMA.into()), 0x00);
+        timer.tick(1);
+        assert_eq!(timer.read(TIMA.into()), 0x56);
+
+        // Outside of the window, writing TMA doesn't affect TIMA.
+        timer.tick(1);
+        timer.write(TMA.into(), 0x78);
+        assert_eq!(timer.read(TIMA.into()), 0x56);
+    }
+
+    #[test]
+    fn tima_counts_falling_edges() {
+        let mut timer = Timer::new();
+        // 262144 Hz: bit 3 falls every 16 T-cycles, or 4 M-cycles.
+        timer.write(TAC.into(), 0x05);
+        timer.tick(3);
+        assert_eq!(timer.read(TIMA.into()), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(TIMA.into()), 1);
+        timer.tick(40);
+        assert_eq!(timer.read(TIMA.into()), 11);
+        assert_eq!(timer.divider(), 44 * 4);
+        assert_eq!(timer.read(TAC.into()), 0xfd);
+    }
+
+    #[test]
+    fn tac_rates() {
+        // Machine cycles per increment for each TAC rate.
+        for &(tac, period) in [(0x04, 256), (0x05, 4), (0x06, 16), (0x07, 64)].iter() {
+            let mut timer = Timer::new();
+            timer.write(TAC.into(), tac);
+            timer.tick(period - 1);
+            assert_eq!(timer.read(TIMA.into()), 0, "TAC {:#04X}", tac);
+            timer.tick(1);
+            assert_eq!(timer.read(TIMA.into()), 1, "TAC {:#04X}", tac);
+            timer.tick(period * 9);
+            assert_eq!(timer.read(TIMA.into()), 10, "TAC {:#04X}", tac);
+        }
+
+        // Only the low 3 bits of TAC are writable.
+        let mut timer = Timer::new();
+        timer.write(TAC.into(), 0xf2);
+        assert_eq!(timer.read(TAC.into()), 0xfa);
+        timer.tick(1000);
+        assert_eq!(timer.read(TIMA.into()), 0);
+    }
+
+    #[test]
+    fn div_reset_glitch() {
+        let mut timer = Timer::new();
+        timer.write(TAC.into(), 0x05);
+        // Bit 3 is set after 2 M-cycles, so resetting the divider makes it fall.
+        timer.tick(2);
+        assert_eq!(timer.divider(), 8);
+        timer.write(DIV.into(), 0x12);
+        assert_eq!(timer.divider(), 0);
+        assert_eq!(timer.read(TIMA.into()), 1);
+
+        // Resetting while bit 3 is clear has no effect on TIMA.
+        timer.tick(1);
+        timer.write(DIV.into(), 0);
+        assert_eq!(timer.read(TIMA.into()), 1);
+
+        // Resetting DIV restarts the count towards the next increment.
+        timer.tick(1);
+        timer.write(DIV.into(), 0);
+        assert_eq!(timer.read(TIMA.into()), 1);
+        timer.tick(3);
+        assert_eq!(timer.read(TIMA.into()), 1);
+        timer.tick(1);
+        assert_eq!(timer.read(TIMA.into()), 2);
+        assert_eq!(timer.read(DIV.into()), 0);
+    }
+
+    #[test]
+    fn tac_change_glitch() {
+        let mut timer = Timer::new();
+        timer.write(TAC.into(), 0x05);
+        timer.tick(2);
+        // Switching to a rate whose bit is clear makes the signal fall.
+        timer.write(TAC.into(), 0x04);
+        assert_eq!(timer.read(TIMA.into()), 1);
+        // Switching back is a rising edge, which doesn't count.
+        timer.write(TAC.into(), 0x05);
+        assert_eq!(timer.read(TIMA.into()), 1);
+        // Disabling the timer while the bit is set also makes the signal fall.
+        timer.write(TAC.into(), 0x01);
+        assert_eq!(timer.read(TIMA.into()), 2);
+
+        // Switching between two rates whose bits are both set doesn't increment.
+        let mut timer = Timer::new();
+        timer.write(TAC.into(), 0x05);
+        timer.tick(10);
+        assert_eq!(timer.divider(), 0b10_1000);
+        assert_eq!(timer.read(TIMA.into()), 2);
+        timer.write(TAC.into(), 0x06);
+        assert_eq!(timer.read(TIMA.into()), 2);
+    }
+
+    #[test]
+    fn rapid_toggle() {
+        let mut timer = Timer::new();
+        timer.write(TAC.into(), 0x05);
+        timer.tick(2);
+        // Every disable while the selected bit is set counts as an increment.
+        for _ in 0..5 {
+            timer.write(TAC.into(), 0x05);
+            timer.write(TAC.into(), 0x01);
+        }
+        assert_eq!(timer.read(TIMA.into()), 5);
+
+        // Toggling while the bit is clear has no effect.
+        timer.tick(2);
+        for _ in 0..5 {
+            timer.write(TAC.into(), 0x05);
+            timer.write(TAC.into(), 0x01);
+        }
+        assert_eq!(timer.read(TIMA.into()), 5);
+
+        // Toggling can also push TIMA through an overflow.
+        timer.write(TIMA.into(), 0xff);
+        timer.tick(2);
+        timer.write(TAC.into(), 0x05);
+        timer.write(TAC.into(), 0x01);
+        assert!(timer.reload_pending());
+        timer.tick(1);
+        assert!(timer.take_interrupt());
+    }
+
+    #[test]
+    fn div_counts_t_cycles() {
+        let mut timer = Timer::new();
+        timer.tick(64);
+        assert_eq!(timer.read(DIV.into()), 1);
+        timer.tick(64 * 0xff);
+        assert_eq!(timer.read(DIV.into()), 0);
+        // TIMA doesn't count while disabled.
+        assert_eq!(timer.read(TIMA.into()), 0);
+    }
+
+    #[test]
+    fn div_apu_events() {
+        let mut timer = Timer::new();
+        // DIV bit 4 falls every 2048 machine cycles.
+        timer.tick(2047);
+        assert_eq!(timer.take_div_apu_events(), 0);
+        timer.tick(1);
+        assert_eq!(timer.take_div_apu_events(), 1);
+        timer.tick(2048 * 3);
+        assert_eq!(timer.take_div_apu_events(), 3);
+
+        // Writing DIV while bit 4 is set forces an event, and restarts the full period.
+        timer.write(DIV.into(), 0);
+        assert_eq!(timer.take_div_apu_events(), 0);
+        timer.tick(1024);
+        assert_eq!(timer.read(DIV.into()), 0x10);
+        timer.write(DIV.into(), 0);
+        assert_eq!(timer.take_div_apu_events(), 1);
+        timer.tick(2047);
+        assert_eq!(timer.take_div_apu_events(), 0);
+        timer.tick(1);
+        assert_eq!(timer.take_div_apu_events(), 1);
+
+        // Writing DIV while bit 4 is clear skips the event that was coming.
+        timer.tick(1023);
+        timer.write(DIV.into(), 0);
+        timer.tick(1023);
+        assert_eq!(timer.take_div_apu_events(), 0);
+        timer.tick(1025);
+        assert_eq!(timer.take_div_apu_events(), 1);
+    }
+
+    #[test]
+    fn div_apu_events_double_speed() {
+        let mut timer = Timer::new();
+        timer.set_double_speed(true);
+        // DIV runs at the CPU speed, so bit 5 is used to keep the same real rate.
+        timer.tick(2048);
+        assert_eq!(timer.take_div_apu_events(), 0);
+        timer.tick(2048);
+        assert_eq!(timer.take_div_apu_events(), 1);
+
+        // Bit 4 being set doesn't matter, only bit 5.
+        timer.write(DIV.into(), 0);
+        timer.tick(1024);
+        assert_eq!(timer.read(DIV.into()), 0x10);
+        timer.write(DIV.into(), 0);
+        assert_eq!(timer.take_div_apu_events(), 0);
+        timer.tick(2048);
+        assert_eq!(timer.read(DIV.into()), 0x20);
+        timer.write(DIV.into(), 0);
+        assert_eq!(timer.take_div_apu_events(), 1);
+    }
+}
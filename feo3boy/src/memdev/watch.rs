@@ -0,0 +1,140 @@
+//! Watchpoints on memory accesses made through a [`GbMmu`](super::GbMmu).
+
+use std::cell::RefCell;
+
+use super::AddrRange;
+
+/// What a memory access is for. Opcodes and data are read through the same
+/// [`MemDevice::read`](super::MemDevice::read), so the kind has to be set on the
+/// [`GbMmu`](super::GbMmu) by whoever is making the access. The CPU does this for its fetches
+/// through [`CpuMem::fetch`](crate::gbz80core::CpuMem::fetch); other code can use
+/// [`GbMmu::with_access_kind`](super::GbMmu::with_access_kind).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum AccessKind {
+    /// An ordinary read or write of data. This is the default.
+    #[default]
+    Data,
+    /// The CPU fetching an opcode or its immediate operands.
+    Execute,
+    /// A DMA transfer.
+    Dma,
+}
+
+/// Identifies a watchpoint added with [`GbMmu::add_watchpoint`](super::GbMmu::add_watchpoint).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct WatchHandle(u64);
+
+/// Describes which accesses trigger a watchpoint.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Watchpoint {
+    /// System addresses to watch.
+    pub range: AddrRange,
+    /// Whether reads trigger the watchpoint.
+    pub reads: bool,
+    /// Whether writes trigger the watchpoint.
+    pub writes: bool,
+    /// If set, only accesses of this kind trigger the watchpoint.
+    pub kind: Option<AccessKind>,
+}
+
+impl Watchpoint {
+    /// Watchpoint which triggers on any read of the range.
+    pub fn read(range: AddrRange) -> Self {
+        Watchpoint {
+            range,
+            reads: true,
+            writes: false,
+            kind: None,
+        }
+    }
+
+    /// Watchpoint which triggers on any write to the range.
+    pub fn write(range: AddrRange) -> Self {
+        Watchpoint {
+            range,
+            reads: false,
+            writes: true,
+            kind: None,
+        }
+    }
+
+    /// Watchpoint which triggers when the CPU executes from the range.
+    pub fn execute(range: AddrRange) -> Self {
+        Watchpoint::read(range).kind(AccessKind::Execute)
+    }
+
+    /// Restricts this watchpoint to accesses of the given kind.
+    pub fn kind(mut self, kind: AccessKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Returns true if the given access triggers this watchpoint.
+    pub fn matches(&self, addr: u16, write: bool, kind: AccessKind) -> bool {
+        let direction = if write { self.writes } else { self.reads };
+        direction && self.range.contains(&addr) && self.kind.is_none_or(|k| k == kind)
+    }
+}
+
+/// Record of an access which triggered a watchpoint.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WatchHit {
+    /// The watchpoint which was triggered.
+    pub handle: WatchHandle,
+    /// System address which was accessed.
+    pub addr: u16,
+    /// Value which was read or written.
+    pub value: u8,
+    /// True if the access was a write.
+    pub write: bool,
+    /// Kind of access.
+    pub kind: AccessKind,
+}
+
+/// Set of watchpoints, along with the hits not yet collected.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Watchpoints {
+    watchpoints: Vec<(WatchHandle, Watchpoint)>,
+    next_handle: u64,
+    /// Reads are made through a shared reference, so hits need interior mutability.
+    hits: RefCell<Vec<WatchHit>>,
+}
+
+impl Watchpoints {
+    /// Returns true if there are no watchpoints, so accesses don't need to be checked.
+    pub(super) fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub(super) fn add(&mut self, watchpoint: Watchpoint) -> WatchHandle {
+        let handle = WatchHandle(self.next_handle);
+        self.next_handle += 1;
+        self.watchpoints.push((handle, watchpoint));
+        handle
+    }
+
+    pub(super) fn remove(&mut self, handle: WatchHandle) -> Option<Watchpoint> {
+        let index = self.watchpoints.iter().position(|(h, _)| *h == handle)?;
+        Some(self.watchpoints.remove(index).1)
+    }
+
+    /// Records a hit for every watchpoint triggered by the given access.
+    pub(super) fn check(&self, addr: u16, value: u8, write: bool, kind: AccessKind) {
+        for (handle, watchpoint) in self.watchpoints.iter() {
+            if watchpoint.matches(addr, write, kind) {
+                self.hits.borrow_mut().push(WatchHit {
+                    handle: *handle,
+                    addr,
+                    value,
+                    write,
+                    kind,
+                });
+            }
+        }
+    }
+
+    /// Removes and returns all hits recorded so far, oldest first.
+    pub(super) fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(self.hits.get_mut())
+    }
+}
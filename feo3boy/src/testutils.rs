@@ -0,0 +1,103 @@
+//! Helpers for tests of code built on the emulator. Available to other crates with the
+//! `test-utils` feature, and always built for this crate's own tests.
+
+use std::fmt::Write;
+
+use crate::memdev::{Addr, MemDevice};
+
+/// Number of bytes shown on each row of a memory diff.
+const ROW_LEN: usize = 16;
+
+/// Asserts that the memory of `dev` starting at the system address `start` matches `expected`.
+///
+/// On a mismatch, panics with a hex diff of every row of 16 bytes which differs, with the
+/// differing bytes marked, and names the first differing address. Addresses wrap around at the
+/// top of memory.
+#[track_caller]
+pub fn assert_mem_eq<M: MemDevice>(dev: &M, start: u16, expected: &[u8]) {
+    let actual: Vec<u8> = (0..expected.len())
+        .map(|i| dev.read(Addr::from(start.wrapping_add(i as u16))))
+        .collect();
+    if let Some(diff) = mem_diff(start, expected, &actual) {
+        panic!("{}", diff);
+    }
+}
+
+/// Describes the differences between the expected and actual bytes starting at `start`, or
+/// returns `None` if they match.
+fn mem_diff(start: u16, expected: &[u8], actual: &[u8]) -> Option<String> {
+    let first = expected.iter().zip(actual).position(|(e, a)| e != a)?;
+    let count = expected.iter().zip(actual).filter(|(e, a)| e != a).count();
+    let mut diff = format!(
+        "memory differs from expected at {:#06X} ({} of {} bytes differ)\n",
+        start.wrapping_add(first as u16),
+        count,
+        expected.len()
+    );
+    for (row, (expected, actual)) in expected
+        .chunks(ROW_LEN)
+        .zip(actual.chunks(ROW_LEN))
+        .enumerate()
+    {
+        if expected == actual {
+            continue;
+        }
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let markers: String = expected
+            .iter()
+            .zip(actual)
+            .map(|(e, a)| if e == a { "   " } else { "^^ " })
+            .collect();
+        let addr = start.wrapping_add((row * ROW_LEN) as u16);
+        // Writing to a String can't fail.
+        let _ = writeln!(diff, "{:04X} expected: {}", addr, hex(expected));
+        let _ = writeln!(diff, "     actual:   {}", hex(actual));
+        let _ = writeln!(diff, "               {}", markers.trim_end());
+    }
+    Some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_memory() {
+        let mem = [0x10u8, 0x20, 0x30, 0x40];
+        assert_mem_eq(&mem, 1, &[0x20, 0x30, 0x40]);
+        assert_mem_eq(&mem, 2, &[]);
+        assert_eq!(mem_diff(0, &[1, 2], &[1, 2]), None);
+    }
+
+    #[test]
+    fn diff_marks_differing_rows() {
+        let expected: Vec<u8> = (0..40).collect();
+        let mut actual = expected.clone();
+        actual[18] = 0xff;
+        actual[19] = 0xee;
+        let diff = mem_diff(0xc000, &expected, &actual).unwrap();
+        // Only the middle row differs.
+        assert_eq!(
+            diff.lines().collect::<Vec<_>>(),
+            [
+                "memory differs from expected at 0xC012 (2 of 40 bytes differ)",
+                "C010 expected: 10 11 12 13 14 15 16 17 18 19 1A 1B 1C 1D 1E 1F",
+                "     actual:   10 11 FF EE 14 15 16 17 18 19 1A 1B 1C 1D 1E 1F",
+                "                     ^^ ^^",
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "memory differs from expected at 0x0003")]
+    fn mismatch_panics() {
+        let mem = [0u8; 8];
+        assert_mem_eq(&mem, 0, &[0, 0, 0, 1]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::{iter, mem};
 
-use feo3boy::gbz80core::{self, Gbz80State};
+use feo3boy::gbz80core::{self, CpuMem, Gbz80State};
+use feo3boy::interrupts::InterruptController;
 use feo3boy::memdev::{Addr, MemDevice};
 
 #[test]
@@ -107,3 +108,27 @@ impl MemDevice for ExtendMem {
         self.0[addr.index()] = data;
     }
 }
+
+impl CpuMem for ExtendMem {
+    fn interrupt_controller(&self) -> Option<&InterruptController> {
+        None
+    }
+
+    fn interrupt_controller_mut(&mut self) -> Option<&mut InterruptController> {
+        None
+    }
+
+    fn fetch(&mut self, addr: Addr) -> u8 {
+        self.read(addr)
+    }
+
+    fn joypad_held(&self) -> Option<bool> {
+        None
+    }
+
+    fn stop(&mut self) -> bool {
+        false
+    }
+
+    fn yield1m(&mut self) {}
+}